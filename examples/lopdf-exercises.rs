@@ -1,10 +1,9 @@
 use anyhow::{Result, anyhow};
 use clap::{Parser, Subcommand};
 use lopdf::{self, Bookmark, Document, Object, dictionary};
+use pdfunite_tree::base_dirs::BaseDir;
 use std::path::Path;
 
-const DEV_PLAYGROUND_DIR: &str = "dev-playground";
-
 /// Merge together all the PDFs in a folder and its subfolders (max X levels) into a single document
 /// provided with a ToC (Table fo Contents) reflecting the structure of tree of the folder and its descendants.
 #[derive(Parser, Debug)]
@@ -80,12 +79,11 @@ fn experiment_to_equip_trivial_doc(doc_path: &str) -> Result<()> {
         ))?
         .to_string();
 
-    let manifest_dir = env!("CARGO_MANIFEST_DIR");
-    let parent_folder = format!("{manifest_dir}/{DEV_PLAYGROUND_DIR}/equip_with_trivial_toc");
+    let parent_folder = BaseDir::Playground.path()?.join("equip_with_trivial_toc");
     std::fs::create_dir_all(&parent_folder)?;
 
     let doc_name = format!("{}-with-outline.pdf", doc_name);
-    let filepath = format!("{parent_folder}/{doc_name}");
+    let filepath = parent_folder.join(doc_name);
     doc.save(filepath)?;
 
     Ok(())
@@ -210,12 +208,11 @@ fn equip_with_trivial_toc(doc_path: &str) -> Result<()> {
         ))?
         .to_string();
 
-    let manifest_dir = env!("CARGO_MANIFEST_DIR");
-    let parent_folder = format!("{manifest_dir}/{DEV_PLAYGROUND_DIR}/equip_with_trivial_toc");
+    let parent_folder = BaseDir::Playground.path()?.join("equip_with_trivial_toc");
     std::fs::create_dir_all(&parent_folder)?;
 
     let doc_name = format!("{}-with-outline.pdf", doc_name);
-    let filepath = format!("{parent_folder}/{doc_name}");
+    let filepath = parent_folder.join(doc_name);
     doc.save(filepath)?;
 
     Ok(())
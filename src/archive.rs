@@ -0,0 +1,449 @@
+//! Archives (ZIP, tar, possibly compressed) merged as if they were directory trees: their
+//! PDF members are read into memory one at a time, without extracting them.
+
+use crate::ignore_file::IGNORE_FILE_NAME;
+use crate::utils;
+use anyhow::{Result, anyhow};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+
+const PDF_HEADER: &[u8] = b"%PDF-";
+
+/// A PDF file of an archive.
+#[derive(Debug, Clone)]
+pub struct ArchiveMember {
+    /// Path of the member within the archive.
+    pub path: PathBuf,
+    /// Modification time of the member in seconds since the Unix epoch, if known.
+    pub modified: Option<u64>,
+}
+
+/// The PDF members (named `*.pdf` or starting with the `%PDF-` header) and the ignore files
+/// of an archive, the content of the members being read on demand (see `contents`).
+pub struct ArchiveReader {
+    /// In natural order of their paths.
+    members: Vec<ArchiveMember>,
+    /// The content of the ignore files, by the path of their directory within the archive.
+    ignore_files: HashMap<PathBuf, String>,
+    source: MemberSource,
+}
+
+enum MemberSource {
+    Zip {
+        archive: zip::ZipArchive<File>,
+        indices: HashMap<PathBuf, usize>,
+    },
+    /// The stream of the archive opened again after listing the members.
+    Tar(tar::Archive<Box<dyn Read>>),
+}
+
+impl ArchiveReader {
+    pub fn open(archive_path: impl AsRef<Path>) -> Result<Self> {
+        let archive_path = archive_path.as_ref();
+        let format = ArchiveFormat::from_path(archive_path).ok_or(anyhow!(
+            "The format of the archive '{}' is not supported",
+            archive_path.display()
+        ))?;
+
+        let mut reader = match format {
+            ArchiveFormat::Zip => list_zip_members(archive_path)?,
+            ArchiveFormat::Tar | ArchiveFormat::TarGz | ArchiveFormat::TarZst => {
+                let (members, ignore_files) =
+                    list_tar_members(open_tar_stream(archive_path, format)?)?;
+                ArchiveReader {
+                    members,
+                    ignore_files,
+                    source: MemberSource::Tar(tar::Archive::new(open_tar_stream(
+                        archive_path,
+                        format,
+                    )?)),
+                }
+            }
+        };
+        reader.members.sort_by(|member, other_member| {
+            utils::natural_cmp(
+                &member.path.to_string_lossy(),
+                &other_member.path.to_string_lossy(),
+            )
+        });
+
+        Ok(reader)
+    }
+
+    pub fn members(&self) -> &[ArchiveMember] {
+        &self.members
+    }
+
+    pub fn ignore_files(&self) -> &HashMap<PathBuf, String> {
+        &self.ignore_files
+    }
+
+    /// The reader of the contents of the members. The stream of a tar archive is read only
+    /// once: this can be called only once for it.
+    pub fn contents(&mut self) -> Result<MemberContents<'_>> {
+        let source = match &mut self.source {
+            MemberSource::Zip { archive, indices } => ContentsSource::Zip { archive, indices },
+            MemberSource::Tar(archive) => ContentsSource::Tar {
+                entries: archive.entries()?,
+                pending: self
+                    .members
+                    .iter()
+                    .map(|member| member.path.clone())
+                    .collect(),
+                read_ahead: HashMap::new(),
+            },
+        };
+
+        Ok(MemberContents { source })
+    }
+}
+
+/// Reads the content of the PDF members of an archive. The members of a tar archive come in
+/// the order of its stream: the ones met while looking for a later one are kept in memory
+/// until they are read, so that an archive in the order of the merge is read one member at
+/// a time.
+pub struct MemberContents<'a> {
+    source: ContentsSource<'a>,
+}
+
+enum ContentsSource<'a> {
+    Zip {
+        archive: &'a mut zip::ZipArchive<File>,
+        indices: &'a HashMap<PathBuf, usize>,
+    },
+    Tar {
+        entries: tar::Entries<'a, Box<dyn Read>>,
+        /// The PDF members not met yet in the stream.
+        pending: HashSet<PathBuf>,
+        read_ahead: HashMap<PathBuf, Vec<u8>>,
+    },
+}
+
+impl MemberContents<'_> {
+    /// The content of the PDF member, by its path within the archive.
+    pub fn read(&mut self, member_path: &Path) -> Result<Vec<u8>> {
+        let no_member = || {
+            anyhow!(
+                "The archive has no PDF member '{}' left to read",
+                member_path.display()
+            )
+        };
+
+        match &mut self.source {
+            ContentsSource::Zip { archive, indices } => {
+                let index = indices.get(member_path).ok_or_else(no_member)?;
+                let mut content = Vec::new();
+                archive.by_index(*index)?.read_to_end(&mut content)?;
+                Ok(content)
+            }
+            ContentsSource::Tar {
+                entries,
+                pending,
+                read_ahead,
+            } => {
+                if let Some(content) = read_ahead.remove(member_path) {
+                    return Ok(content);
+                }
+                for entry in entries {
+                    let mut entry = entry?;
+                    if !entry.header().entry_type().is_file() {
+                        continue;
+                    }
+                    // The other entries are skipped without reading their content
+                    let Some(path) = enclosed_path(&entry.path()?) else {
+                        continue;
+                    };
+                    if !pending.remove(&path) {
+                        continue;
+                    }
+                    let mut content = Vec::new();
+                    entry.read_to_end(&mut content)?;
+                    if path == member_path {
+                        return Ok(content);
+                    }
+                    read_ahead.insert(path, content);
+                }
+                Err(no_member())
+            }
+        }
+    }
+}
+
+/// Formats of the archives which can be merged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Zip,
+    Tar,
+    TarGz,
+    TarZst,
+}
+
+impl ArchiveFormat {
+    /// The format of the archive, by the extension of its name.
+    pub fn from_path(path: impl AsRef<Path>) -> Option<Self> {
+        let file_name = path.as_ref().file_name()?.to_string_lossy().to_lowercase();
+        if file_name.ends_with(".zip") {
+            Some(ArchiveFormat::Zip)
+        } else if file_name.ends_with(".tar") {
+            Some(ArchiveFormat::Tar)
+        } else if file_name.ends_with(".tar.gz") || file_name.ends_with(".tgz") {
+            Some(ArchiveFormat::TarGz)
+        } else if file_name.ends_with(".tar.zst") || file_name.ends_with(".tzst") {
+            Some(ArchiveFormat::TarZst)
+        } else {
+            None
+        }
+    }
+}
+
+/// The name of the archive without its extension, e.g. `bundle` for `bundle.zip` or
+/// `bundle.tar.gz`.
+pub fn archive_stem(archive_path: impl AsRef<Path>) -> String {
+    let file_name = archive_path
+        .as_ref()
+        .file_name()
+        .map(|file_name| file_name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let file_name = match file_name.rsplit_once('.') {
+        Some((name, "gz" | "zst")) if name.to_lowercase().ends_with(".tar") => name.to_string(),
+        _ => file_name,
+    };
+    match file_name.rfind('.') {
+        Some(extension_start) if extension_start > 0 => file_name[..extension_start].to_string(),
+        _ => file_name,
+    }
+}
+
+fn list_zip_members(archive_path: &Path) -> Result<ArchiveReader> {
+    let mut archive = zip::ZipArchive::new(File::open(archive_path)?)?;
+    let mut members = Vec::new();
+    let mut ignore_files = HashMap::new();
+    let mut indices = HashMap::new();
+
+    for index in 0..archive.len() {
+        let mut zip_file = archive.by_index(index)?;
+        if zip_file.is_dir() {
+            continue;
+        }
+        // Members escaping the archive (`../`, absolute paths) are left out
+        let Some(path) = zip_file.enclosed_name() else {
+            continue;
+        };
+        if is_ignore_file(&path) {
+            ignore_files.insert(parent_dir(&path), read_ignore_file(&mut zip_file, &path)?);
+            continue;
+        }
+        if indices.contains_key(&path) || !is_pdf_member(&path, &mut zip_file)? {
+            continue;
+        }
+        members.push(ArchiveMember {
+            path: path.clone(),
+            modified: None,
+        });
+        indices.insert(path, index);
+    }
+
+    Ok(ArchiveReader {
+        members,
+        ignore_files,
+        source: MemberSource::Zip { archive, indices },
+    })
+}
+
+/// The decompressed stream of the tar archive.
+fn open_tar_stream(archive_path: &Path, format: ArchiveFormat) -> Result<Box<dyn Read>> {
+    let file = File::open(archive_path)?;
+    Ok(match format {
+        ArchiveFormat::TarGz => Box::new(flate2::read::GzDecoder::new(file)),
+        ArchiveFormat::TarZst => Box::new(zstd::Decoder::new(file)?),
+        ArchiveFormat::Tar | ArchiveFormat::Zip => Box::new(file),
+    })
+}
+
+/// List the PDF members of the tar stream, reading only the first bytes of the members
+/// not named `*.pdf`, and read its ignore files.
+fn list_tar_members(reader: impl Read) -> Result<(Vec<ArchiveMember>, HashMap<PathBuf, String>)> {
+    let mut archive = tar::Archive::new(reader);
+    let mut members = Vec::new();
+    let mut ignore_files = HashMap::new();
+    let mut member_paths = HashSet::new();
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let Some(path) = enclosed_path(&entry.path()?) else {
+            continue;
+        };
+        if is_ignore_file(&path) {
+            ignore_files.insert(parent_dir(&path), read_ignore_file(&mut entry, &path)?);
+            continue;
+        }
+        if member_paths.contains(&path) || !is_pdf_member(&path, &mut entry)? {
+            continue;
+        }
+        members.push(ArchiveMember {
+            path: path.clone(),
+            modified: entry.header().mtime().ok(),
+        });
+        member_paths.insert(path);
+    }
+
+    Ok((members, ignore_files))
+}
+
+/// The path without its `.` components, or `None` if it may escape the archive (`../`,
+/// absolute paths).
+fn enclosed_path(path: &Path) -> Option<PathBuf> {
+    let mut enclosed_path = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => enclosed_path.push(name),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+
+    (!enclosed_path.as_os_str().is_empty()).then_some(enclosed_path)
+}
+
+fn has_pdf_extension(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("pdf"))
+}
+
+/// Whether the member is a PDF file, by its name or else by the first bytes of its content.
+fn is_pdf_member(path: &Path, content: &mut impl Read) -> Result<bool> {
+    if has_pdf_extension(path) {
+        return Ok(true);
+    }
+    let mut header = Vec::new();
+    content
+        .take(PDF_HEADER.len() as u64)
+        .read_to_end(&mut header)?;
+
+    Ok(header == PDF_HEADER)
+}
+
+fn is_ignore_file(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|file_name| file_name == IGNORE_FILE_NAME)
+}
+
+fn parent_dir(path: &Path) -> PathBuf {
+    path.parent().map(Path::to_path_buf).unwrap_or_default()
+}
+
+fn read_ignore_file(content: &mut impl Read, path: &Path) -> Result<String> {
+    let mut ignore_file = String::new();
+    content.read_to_string(&mut ignore_file).map_err(|err| {
+        anyhow!(
+            "The ignore file '{}' of the archive could not be read: {err}",
+            path.display()
+        )
+    })?;
+    Ok(ignore_file)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support;
+    use std::io::Write;
+
+    #[test]
+    fn pdf_members_of_zip() -> Result<()> {
+        let test_dir = test_support::get_virgin_test_dir("pdf_members_of_zip")?;
+        let archive_path = format!("{test_dir}/bundle.zip");
+
+        let mut pdf_content = Vec::new();
+        test_support::get_basic_pdf_doc("doc", 1)?.save_to(&mut pdf_content)?;
+        let mut writer = zip::ZipWriter::new(std::fs::File::create(&archive_path)?);
+        let file_options = zip::write::SimpleFileOptions::default();
+        for name in [
+            "week10/b.pdf",
+            "week2/a.pdf",
+            "readme.txt",
+            "week2/.pdfuniteignore",
+        ] {
+            writer.start_file(name, file_options)?;
+            writer.write_all(if name.ends_with(".pdf") {
+                &pdf_content
+            } else {
+                b"old.pdf"
+            })?;
+        }
+        writer.finish()?;
+
+        assert_eq!(archive_stem(&archive_path), "bundle");
+        let mut reader = ArchiveReader::open(&archive_path)?;
+        let member_paths: Vec<PathBuf> = reader
+            .members()
+            .iter()
+            .map(|member| member.path.clone())
+            .collect();
+        assert_eq!(
+            member_paths,
+            vec![PathBuf::from("week2/a.pdf"), PathBuf::from("week10/b.pdf")]
+        );
+        let mut contents = reader.contents()?;
+        assert_eq!(contents.read(Path::new("week2/a.pdf"))?, pdf_content);
+        assert!(contents.read(Path::new("readme.txt")).is_err());
+        assert_eq!(
+            reader
+                .ignore_files()
+                .get(Path::new("week2"))
+                .map(String::as_str),
+            Some("old.pdf")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn pdf_members_of_tar_gz() -> Result<()> {
+        let test_dir = test_support::get_virgin_test_dir("pdf_members_of_tar_gz")?;
+        let archive_path = format!("{test_dir}/bundle.tar.gz");
+
+        let mut pdf_content = Vec::new();
+        test_support::get_basic_pdf_doc("doc", 1)?.save_to(&mut pdf_content)?;
+        let encoder = flate2::write::GzEncoder::new(
+            std::fs::File::create(&archive_path)?,
+            flate2::Compression::default(),
+        );
+        let mut builder = tar::Builder::new(encoder);
+        for name in ["./scans/b.pdf", "a.pdf"] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(pdf_content.len() as u64);
+            header.set_mtime(1_700_000_000);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, name, pdf_content.as_slice())?;
+        }
+        builder.into_inner()?.finish()?;
+
+        assert_eq!(archive_stem(&archive_path), "bundle");
+        let mut reader = ArchiveReader::open(&archive_path)?;
+        let member_paths: Vec<&Path> = reader
+            .members()
+            .iter()
+            .map(|member| member.path.as_path())
+            .collect();
+        assert_eq!(
+            member_paths,
+            vec![Path::new("a.pdf"), Path::new("scans/b.pdf")]
+        );
+        assert_eq!(reader.members()[0].modified, Some(1_700_000_000));
+        // The member before it in the stream is kept until read
+        let mut contents = reader.contents()?;
+        assert_eq!(contents.read(Path::new("a.pdf"))?, pdf_content);
+        assert_eq!(contents.read(Path::new("scans/b.pdf"))?, pdf_content);
+        assert!(contents.read(Path::new("a.pdf")).is_err());
+        assert_eq!(enclosed_path(Path::new("../escape.pdf")), None);
+
+        Ok(())
+    }
+}
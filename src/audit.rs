@@ -0,0 +1,162 @@
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// The entries of the input trees with their size and modification time, to prove that
+/// a merge left them untouched.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeSnapshot {
+    entries: BTreeMap<PathBuf, (u64, Option<SystemTime>)>,
+}
+
+impl TreeSnapshot {
+    /// Record every entry below the roots. Symlinks are recorded, not followed.
+    pub fn take(roots: &[impl AsRef<Path>]) -> Result<Self> {
+        let mut entries = BTreeMap::new();
+        let mut directories: Vec<PathBuf> = roots
+            .iter()
+            .map(|root| root.as_ref().to_path_buf())
+            .collect();
+
+        while let Some(directory) = directories.pop() {
+            let metadata = std::fs::symlink_metadata(&directory)?;
+            entries.insert(
+                directory.clone(),
+                (metadata.len(), metadata.modified().ok()),
+            );
+            // A root may be a file, e.g. an archive
+            if !metadata.is_dir() {
+                continue;
+            }
+
+            for entry in std::fs::read_dir(&directory)? {
+                let entry = entry?;
+                let metadata = entry.metadata()?;
+                if metadata.is_dir() {
+                    directories.push(entry.path());
+                } else {
+                    entries.insert(entry.path(), (metadata.len(), metadata.modified().ok()));
+                }
+            }
+        }
+
+        Ok(TreeSnapshot { entries })
+    }
+
+    /// The entries created, removed or modified since the snapshot, as readable lines.
+    pub fn changes_since(&self, earlier: &TreeSnapshot) -> Vec<String> {
+        let mut changes = Vec::new();
+
+        for (path, fingerprint) in &self.entries {
+            match earlier.entries.get(path) {
+                None => changes.push(format!("'{}' was created", path.display())),
+                Some(earlier_fingerprint) if earlier_fingerprint != fingerprint => {
+                    changes.push(format!("'{}' was modified", path.display()))
+                }
+                Some(_) => {}
+            }
+        }
+        for path in earlier.entries.keys() {
+            if !self.entries.contains_key(path) {
+                changes.push(format!("'{}' was removed", path.display()));
+            }
+        }
+
+        changes
+    }
+}
+
+/// The planned writes (each a description and a path) which would land inside one of
+/// the roots, as readable lines. The paths need not exist: their closest existing
+/// ancestor is resolved, so that symlinks leading into a root are caught.
+pub fn writes_inside_roots(
+    roots: &[impl AsRef<Path>],
+    planned_writes: &[(&str, PathBuf)],
+) -> Result<Vec<String>> {
+    let roots = roots
+        .iter()
+        .map(|root| Ok(root.as_ref().canonicalize()?))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut violations = Vec::new();
+    for (description, path) in planned_writes {
+        let resolved_path = resolve_existing_ancestor(path)?;
+        if let Some(root) = roots.iter().find(|root| resolved_path.starts_with(root)) {
+            violations.push(format!(
+                "{description} '{}' is inside the input directory '{}'",
+                path.display(),
+                root.display()
+            ));
+        }
+    }
+
+    Ok(violations)
+}
+
+/// The absolute path with its closest existing ancestor canonicalised.
+fn resolve_existing_ancestor(path: &Path) -> Result<PathBuf> {
+    let path = std::path::absolute(path)?;
+    let mut missing_components = Vec::new();
+    let mut ancestor = path.as_path();
+
+    loop {
+        match ancestor.canonicalize() {
+            Ok(resolved_ancestor) => {
+                return Ok(missing_components
+                    .into_iter()
+                    .rev()
+                    .fold(resolved_ancestor, |resolved, component| {
+                        resolved.join(component)
+                    }));
+            }
+            Err(_) => match (ancestor.parent(), ancestor.file_name()) {
+                (Some(parent), Some(file_name)) => {
+                    missing_components.push(file_name.to_os_string());
+                    ancestor = parent;
+                }
+                _ => return Ok(path),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support;
+
+    #[test]
+    fn writes_and_changes_in_the_tree() -> Result<()> {
+        let test_dir = test_support::get_virgin_test_dir("writes_and_changes_in_the_tree")?;
+        let root = PathBuf::from(format!("{test_dir}/root_pdfs"));
+        std::fs::create_dir_all(root.join("sub"))?;
+        std::fs::write(root.join("sub").join("a.pdf"), b"%PDF-1.7")?;
+
+        let violations = writes_inside_roots(
+            &[&root],
+            &[
+                ("The report", root.join("new_dir").join("report.json")),
+                ("The output", PathBuf::from(format!("{test_dir}/out.pdf"))),
+            ],
+        )?;
+        assert_eq!(violations.len(), 1);
+        assert!(violations[0].starts_with("The report"));
+
+        let before = TreeSnapshot::take(&[&root])?;
+        assert!(
+            TreeSnapshot::take(&[&root])?
+                .changes_since(&before)
+                .is_empty()
+        );
+        std::fs::write(root.join("sub").join("b.pdf"), b"%PDF-1.7")?;
+        let changes = TreeSnapshot::take(&[&root])?.changes_since(&before);
+        assert!(
+            changes
+                .iter()
+                .any(|change| change.ends_with("b.pdf' was created"))
+        );
+
+        Ok(())
+    }
+}
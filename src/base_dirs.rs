@@ -0,0 +1,118 @@
+use anyhow::{Result, anyhow};
+use std::path::PathBuf;
+
+/// Name of the subdirectory of the platform base directories used by the crate.
+const APP_DIR_NAME: &str = "pdfunite-tree";
+
+/// The kinds of per-user directories, each overridable with its environment variable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BaseDir {
+    /// Scratch outputs of the development tooling and of the tests.
+    Playground,
+}
+
+impl BaseDir {
+    /// Environment variable which, when set, replaces the platform location.
+    pub fn override_var(self) -> &'static str {
+        match self {
+            BaseDir::Playground => "PDFUNITE_TREE_PLAYGROUND_DIR",
+        }
+    }
+
+    /// The directory of this kind for the current user: the override if set, otherwise
+    /// the XDG base directory on Linux and the other Unixes, the Known Folder on Windows
+    /// and the `Library` folder on macOS. The directory is not created.
+    pub fn path(self) -> Result<PathBuf> {
+        resolve(self, |var| std::env::var_os(var).map(PathBuf::from))
+    }
+
+    /// Like `path`, creating the directory if it does not exist.
+    pub fn create(self) -> Result<PathBuf> {
+        let path = self.path()?;
+        std::fs::create_dir_all(&path)?;
+        Ok(path)
+    }
+}
+
+/// The directory of the kind, reading the environment with `env_var`. Relative values of
+/// the variables are ignored, as the XDG specification requires.
+fn resolve(kind: BaseDir, env_var: impl Fn(&str) -> Option<PathBuf>) -> Result<PathBuf> {
+    let absolute_var = |var: &str| env_var(var).filter(|path| path.is_absolute());
+
+    if let Some(path) = absolute_var(kind.override_var()) {
+        return Ok(path);
+    }
+
+    let base_dir = if cfg!(windows) {
+        match kind {
+            BaseDir::Playground => absolute_var("LOCALAPPDATA"),
+        }
+    } else {
+        let home = absolute_var("HOME");
+        if cfg!(target_os = "macos") {
+            home.map(|home| match kind {
+                BaseDir::Playground => home.join("Library").join("Application Support"),
+            })
+        } else {
+            let (xdg_var, home_subdir) = match kind {
+                BaseDir::Playground => ("XDG_DATA_HOME", ".local/share"),
+            };
+            absolute_var(xdg_var).or(home.map(|home| home.join(home_subdir)))
+        }
+    };
+
+    let app_dir = base_dir
+        .ok_or(anyhow!(
+            "Could not find the {kind:?} directory of the user (set {})",
+            kind.override_var()
+        ))?
+        .join(APP_DIR_NAME);
+
+    Ok(match kind {
+        BaseDir::Playground => app_dir.join("dev-playground"),
+    })
+}
+
+#[cfg(all(test, unix, not(target_os = "macos")))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn xdg_directories_and_overrides() -> Result<()> {
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |var: &str| {
+                vars.iter()
+                    .find(|(name, _value)| *name == var)
+                    .map(|(_name, value)| PathBuf::from(value))
+            }
+        };
+
+        let home_only = env(&[("HOME", "/home/ada")]);
+        assert_eq!(
+            resolve(BaseDir::Playground, home_only)?,
+            PathBuf::from("/home/ada/.local/share/pdfunite-tree/dev-playground")
+        );
+
+        let with_xdg = env(&[("HOME", "/home/ada"), ("XDG_DATA_HOME", "/srv/ada")]);
+        assert_eq!(
+            resolve(BaseDir::Playground, with_xdg)?,
+            PathBuf::from("/srv/ada/pdfunite-tree/dev-playground")
+        );
+
+        let relative_xdg = env(&[("HOME", "/home/ada"), ("XDG_DATA_HOME", "share")]);
+        assert_eq!(
+            resolve(BaseDir::Playground, relative_xdg)?,
+            PathBuf::from("/home/ada/.local/share/pdfunite-tree/dev-playground")
+        );
+
+        let overridden = env(&[("PDFUNITE_TREE_PLAYGROUND_DIR", "/tmp/playground")]);
+        assert_eq!(
+            resolve(BaseDir::Playground, overridden)?,
+            PathBuf::from("/tmp/playground")
+        );
+
+        assert!(resolve(BaseDir::Playground, env(&[])).is_err());
+
+        Ok(())
+    }
+}
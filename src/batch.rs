@@ -0,0 +1,287 @@
+use crate::compression::CompressionLevel;
+use crate::merge::MergeOptions;
+use crate::merger::TreeMerger;
+use crate::utils;
+use anyhow::{Result, anyhow};
+use log::info;
+use serde::Deserialize;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// A jobs file, listing the trees to merge in a single run.
+///
+/// ```yaml
+/// jobs:
+///   - input: archive/2023
+///     output: united/2023.pdf
+///   - input: archive/2024
+///     output: united/2024.pdf
+///     exclude: ["**/drafts/**"]
+///     compression: best
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BatchFile {
+    pub jobs: Vec<BatchJob>,
+}
+
+/// A tree to merge, with the settings which differ from the defaults. Relative paths are
+/// relative to the directory of the jobs file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct BatchJob {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    /// Force the presence or the absence of the ToC.
+    pub outlines: Option<bool>,
+    /// Maximum depth of the directory tree, 0 for no limit.
+    pub max_depth: Option<u32>,
+    #[serde(default)]
+    pub include: Vec<String>,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    #[serde(default)]
+    pub skip_errors: bool,
+    #[serde(default)]
+    pub parallel_loading: bool,
+    #[serde(default)]
+    pub compression: CompressionLevel,
+    /// Overwrite the output if it exists.
+    #[serde(default)]
+    pub force: bool,
+}
+
+/// What became of a job.
+#[derive(Debug)]
+pub struct JobOutcome {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    pub duration: Duration,
+    /// The merged files and pages and the skipped files, or why the job failed.
+    pub result: Result<JobSummary>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JobSummary {
+    pub files: usize,
+    pub pages: u32,
+    pub skipped: usize,
+}
+
+impl BatchFile {
+    /// Load the jobs file, resolving the paths of the jobs against its directory.
+    pub fn load(jobs_path: impl AsRef<Path>) -> Result<Self> {
+        let jobs_path = jobs_path.as_ref();
+        let file = std::fs::File::open(jobs_path)?;
+        let mut batch: BatchFile = serde_yaml::from_reader(std::io::BufReader::new(file))
+            .map_err(|err| anyhow!("Invalid jobs file '{}': {err}", jobs_path.display()))?;
+
+        let base_dir = jobs_path.parent().unwrap_or(Path::new(""));
+        for job in &mut batch.jobs {
+            job.input = base_dir.join(&job.input);
+            job.output = base_dir.join(&job.output);
+        }
+
+        Ok(batch)
+    }
+}
+
+impl BatchJob {
+    fn merger(&self) -> TreeMerger {
+        let mut options = MergeOptions {
+            with_outlines: self.outlines,
+            include_patterns: self.include.clone(),
+            exclude_patterns: self.exclude.clone(),
+            skip_errors: self.skip_errors,
+            parallel_loading: self.parallel_loading,
+            ..Default::default()
+        };
+        if self.max_depth.is_some() {
+            options.max_depth = self.max_depth;
+        }
+
+        TreeMerger::new(&self.input)
+            .options(options)
+            .compression(self.compression)
+            .overwrite(self.force)
+    }
+
+    fn run(&self) -> Result<JobSummary> {
+        let report = self.merger().merge_to_file(&self.output)?;
+
+        Ok(JobSummary {
+            files: report.sources.len(),
+            pages: report.sources.iter().map(|source| source.page_count).sum(),
+            skipped: report.skipped.len(),
+        })
+    }
+}
+
+/// Run the jobs with at most `max_parallel_jobs` at a time (one after the other with 1).
+/// A failed job does not stop the others; the outcomes are in the order of the jobs.
+pub fn run_jobs(jobs: &[BatchJob], max_parallel_jobs: usize) -> Vec<JobOutcome> {
+    let num_workers = max_parallel_jobs.min(jobs.len()).max(1);
+    let next_in_queue = AtomicUsize::new(0);
+    let outcomes: Mutex<Vec<Option<JobOutcome>>> =
+        Mutex::new(jobs.iter().map(|_job| None).collect());
+
+    std::thread::scope(|scope| {
+        for _ in 0..num_workers {
+            scope.spawn(|| {
+                loop {
+                    let index = next_in_queue.fetch_add(1, Ordering::Relaxed);
+                    let Some(job) = jobs.get(index) else {
+                        break;
+                    };
+
+                    info!("Job {}: merge '{}'", index + 1, job.input.display());
+                    let start = Instant::now();
+                    let result = job.run();
+                    let outcome = JobOutcome {
+                        input: job.input.clone(),
+                        output: job.output.clone(),
+                        duration: start.elapsed(),
+                        result,
+                    };
+
+                    if let Ok(mut outcomes) = outcomes.lock() {
+                        outcomes[index] = Some(outcome);
+                    }
+                }
+            });
+        }
+    });
+
+    outcomes
+        .into_inner()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .into_iter()
+        .zip(jobs)
+        .map(|(outcome, job)| {
+            outcome.unwrap_or_else(|| JobOutcome {
+                input: job.input.clone(),
+                output: job.output.clone(),
+                duration: Duration::ZERO,
+                result: Err(anyhow!("The job was not run by any worker")),
+            })
+        })
+        .collect()
+}
+
+/// One line per job with its status, counts, duration and output (or error).
+pub fn summary_table(outcomes: &[JobOutcome]) -> String {
+    let rows: Vec<[String; 6]> = outcomes
+        .iter()
+        .map(|outcome| {
+            let input = utils::display_path(&outcome.input);
+            let duration = format!("{:.1}s", outcome.duration.as_secs_f64());
+            match &outcome.result {
+                Ok(summary) => [
+                    "ok".to_string(),
+                    input,
+                    summary.files.to_string(),
+                    summary.pages.to_string(),
+                    duration,
+                    format!(
+                        "{}{}",
+                        utils::display_path(&outcome.output),
+                        match summary.skipped {
+                            0 => String::new(),
+                            skipped => format!(" ({skipped} skipped)"),
+                        }
+                    ),
+                ],
+                Err(err) => [
+                    "FAILED".to_string(),
+                    input,
+                    "-".to_string(),
+                    "-".to_string(),
+                    duration,
+                    err.to_string(),
+                ],
+            }
+        })
+        .collect();
+
+    let header = ["STATUS", "INPUT", "FILES", "PAGES", "TIME", "OUTPUT"].map(str::to_string);
+    let widths: Vec<usize> = (0..header.len())
+        .map(|column| {
+            std::iter::once(&header)
+                .chain(&rows)
+                .map(|row| row[column].chars().count())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+
+    let mut table = String::new();
+    for row in std::iter::once(&header).chain(&rows) {
+        let mut line = String::new();
+        for (column, cell) in row.iter().enumerate() {
+            // The last column is not padded
+            if column + 1 == row.len() {
+                line.push_str(cell);
+            } else {
+                let _ = write!(line, "{cell:<width$}  ", width = widths[column]);
+            }
+        }
+        table.push_str(line.trim_end());
+        table.push('\n');
+    }
+
+    table
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support;
+
+    #[test]
+    fn jobs_run_in_parallel() -> Result<()> {
+        let test_dir = test_support::get_virgin_test_dir("jobs_run_in_parallel")?;
+        for tree in ["first", "second"] {
+            std::fs::create_dir_all(format!("{test_dir}/{tree}"))?;
+            let mut doc = test_support::get_basic_pdf_doc(tree, 2)?;
+            doc.save(format!("{test_dir}/{tree}/{tree}.pdf"))?;
+        }
+
+        let jobs_path = format!("{test_dir}/jobs.yaml");
+        std::fs::write(
+            &jobs_path,
+            "jobs:\n\
+             - input: first\n  output: first.pdf\n\
+             - input: second\n  output: second.pdf\n  compression: best\n\
+             - input: missing\n  output: missing.pdf\n",
+        )?;
+
+        let batch = BatchFile::load(&jobs_path)?;
+        let outcomes = run_jobs(&batch.jobs, 2);
+
+        assert_eq!(
+            outcomes[0].result.as_ref().ok(),
+            Some(&JobSummary {
+                files: 1,
+                pages: 2,
+                skipped: 0
+            })
+        );
+        assert!(outcomes[1].result.is_ok());
+        assert!(outcomes[2].result.is_err());
+        assert!(std::fs::exists(format!("{test_dir}/second.pdf"))?);
+
+        let table = summary_table(&outcomes);
+        assert_eq!(table.lines().count(), 4);
+        assert!(
+            table
+                .lines()
+                .nth(3)
+                .is_some_and(|line| line.starts_with("FAILED"))
+        );
+
+        Ok(())
+    }
+}
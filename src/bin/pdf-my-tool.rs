@@ -1,7 +1,8 @@
 use anyhow::{Result, anyhow};
 use clap::{Parser, Subcommand};
 use lopdf::Document;
-use pdfunite_tree::utils::{get_basic_pdf_doc, get_catalog_children_names};
+use pdfunite_tree::test_support::get_basic_pdf_doc;
+use pdfunite_tree::utils::get_catalog_children_names;
 use std::path::Path;
 
 /// Generate a PDF document with random content. The pages have for title the name of the document and the page number.
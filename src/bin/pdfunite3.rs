@@ -1,36 +1,496 @@
 use pdfunite_tree::*;
 
 use anyhow::{Result, anyhow};
-use clap::Parser;
-use std::path::Path;
+use clap::{Parser, Subcommand};
+use log::warn;
+use pdfunite_tree::archive::{ArchiveFormat, archive_stem};
+use pdfunite_tree::audit::TreeSnapshot;
+use pdfunite_tree::batch::BatchFile;
+use pdfunite_tree::compression::CompressionLevel;
+use pdfunite_tree::encryption::{self, Restriction};
+use pdfunite_tree::info::DocumentInfo;
+use pdfunite_tree::locale::Locale;
+use pdfunite_tree::outline::{BookmarkStyle, BookmarkStyles, DestView, DestViews};
+use pdfunite_tree::output_name::{
+    DEFAULT_OUTPUT_TEMPLATE, OutputNameFields, first_free_numbered_path, output_template_pattern,
+    render_output_template,
+};
+use pdfunite_tree::page_tree::PageTreeLayout;
+use pdfunite_tree::passwords::PasswordMap;
+use pdfunite_tree::plan::MergePlan;
+use pdfunite_tree::progress::{Progress, StallAction};
+use pdfunite_tree::report::{MergeReport, diff_tree_against_report};
+use pdfunite_tree::signatures::SignedPolicy;
+use pdfunite_tree::skip_list::SkipList;
+use pdfunite_tree::stamp::{BatesNumbering, PageNumberStamp, StampPosition, Watermark};
+use pdfunite_tree::text::{PrettifyTitles, TitleEncoding};
+use pdfunite_tree::toc_export::{TocFormat, export_toc};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-const DEFAULT_OUTPUT_SUFFIX: &str = "-united.pdf";
+const DEFAULT_RETRY_BACKOFF_MS: u64 = 500;
 
-/// Merge together all the PDFs in the input directory and its subdirectories (max 5 levels) into a single document.
-/// If the flag `with-outlines`` is activated, the output file will be provided with a ToC (Table of Contents)
+/// Merge together all the PDFs in the input directory and its subdirectories (5 levels by default, see
+/// `--max-depth`) into a single document.
+/// Unless `--no-outlines` is given, the output file is provided with a ToC (Table of Contents)
 /// reflecting the structure of tree of the directory and its descendants. The tool does NOT modify the input
 /// directory and its content.
 ///
 /// Assumptions on the pdf tree:
-/// 1. The tree has not more than 5 levels (the root is considered level 0).
-/// 2. All the files in the input directory and its subdirectories are PDFs and their names are UT8-encoded.
+/// 1. The tree has not more levels than `--max-depth` (5 by default, the root is considered level 0).
+/// 2. The names of the files and directories are UTF-8 encoded. The files which are neither named `.pdf`
+///    nor start with a PDF header are skipped.
 /// 3. The PDFs in the directory and its subdirectories have at most these features:
 ///     * Pages
 ///     * PageMode
+///     * Outlines (kept below the bookmark of the file)
+///     * Metadata (dropped)
 ///
 // (todo: specify rather which features are supported, and add more to them, otherwise is kind of lame).
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, subcommand_negates_reqs = true)]
 pub struct Cli {
-    /// Directory containing the pdfs
-    input_directory: String,
-    /// Output path (must not be among the descendants of the input-directory)
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Directories containing the pdfs (the output is named after the first one), or glob
+    /// patterns of the files to merge, quoted (e.g. `'reports/2024/**/*.pdf'`), nested by
+    /// their directories below the common ancestor of the matches, or a single archive
+    /// (`.zip`, `.tar`, `.tar.gz`, `.tar.zst`) whose PDF members are merged as a directory
+    /// tree, without extracting them
+    #[arg(required_unless_present_any = ["manifest", "files_from"])]
+    input_directories: Vec<String>,
+    /// Merge the files listed in this plan (YAML) with their titles, page ranges and
+    /// nesting, instead of traversing input directories (the output is named after the
+    /// directory of the plan)
+    #[arg(long, value_name = "PLAN", conflicts_with = "input_directories")]
+    manifest: Option<String>,
+    /// Merge the files listed in this file (`-` for the standard input), one per line, in
+    /// order, nested under a bookmark for each directory below their common ancestor
+    #[arg(long, value_name = "LIST", conflicts_with_all = ["input_directories", "manifest"])]
+    files_from: Option<String>,
+    /// The paths of `--files-from` are separated by NUL characters (as by `find -print0`)
+    #[arg(short = '0', long = "null", requires = "files_from")]
+    null_separated: bool,
+    /// With several input directories, whether each one gets its own top-level bookmark
+    /// or their entries are interleaved by name at the top level
+    #[arg(long, value_enum, default_value_t = MergeRootsMode::Separate)]
+    merge_roots: MergeRootsMode,
+    /// Output path (must not be among the descendants of the input-directory), `-` to
+    /// write the output to the standard output (the messages then go to the standard error)
     #[arg(short = 'o')]
     output_path: Option<String>,
-    /// Provide the output file with a ToC (Oulines/Bookmark)
-    /// reflecting the tree structure of the input directory.
-    #[arg(short, long, default_value_t = true)]
-    with_outlines: bool,
+    /// Name of the output placed next to the input directory when no output path is given,
+    /// with the placeholders {dirname}, {date} (YYYY-MM-DD) and {count} (of merged files)
+    #[arg(long, default_value = DEFAULT_OUTPUT_TEMPLATE, conflicts_with = "output_path")]
+    output_template: String,
+    /// Allow the output path to be inside the input directory (also through symlinks):
+    /// the output file is then excluded from the merge
+    #[arg(long)]
+    allow_output_inside: bool,
+    /// Overwrite the output file if it exists (it is replaced only once the new one is
+    /// completely written)
+    #[arg(long)]
+    force: bool,
+    /// Write also the outline of the output to this file, in the format given by its
+    /// extension: .md, .json, .yaml or .opml (repeatable)
+    #[arg(long, value_name = "PATH")]
+    export_toc: Vec<String>,
+    /// Check before the merge that nothing the run writes (output, report, lists, ToC
+    /// files, temporary files of --pre-cmd) lands inside the input directories,
+    /// and after it that their content did not change; fail otherwise
+    #[arg(long, conflicts_with_all = ["allow_output_inside", "post_cmd"])]
+    audit_readonly: bool,
+    /// Print the planned bookmark tree with the page counts of the files and the pages
+    /// they would occupy, without merging nor writing anything
+    #[arg(long, conflicts_with = "manifest")]
+    dry_run: bool,
+    /// If the output file exists, write to the first free name among "name (2).pdf",
+    /// "name (3).pdf", ... instead of failing
+    #[arg(long, conflicts_with = "force")]
+    auto_rename: bool,
+    /// Provide the output file with a ToC (Oulines/Bookmark) reflecting the tree structure
+    /// of the input directory, failing if it would be empty (default: a ToC if not empty)
+    #[arg(
+        short = 'w',
+        long,
+        visible_alias = "with-outlines",
+        overrides_with = "no_outlines"
+    )]
+    outlines: bool,
+    /// Do not provide the output file with a ToC
+    #[arg(long, overrides_with = "outlines")]
+    no_outlines: bool,
+    /// Verify after each imported file that no object was overwritten and that
+    /// every reference resolves (always active in debug builds).
+    #[arg(long)]
+    paranoid: bool,
+    /// Load the PDFs of each directory concurrently
+    #[arg(long)]
+    parallel_loading: bool,
+    /// Maximum number of files opened at once when loading in parallel
+    /// (default: half of the limit of file descriptors of the process)
+    #[arg(long, requires = "parallel_loading")]
+    max_open_files: Option<usize>,
+    /// Merge in bounded memory: load one file at a time and compress its streams (at the
+    /// `--compression` level) as soon as it is imported
+    #[arg(long, conflicts_with = "parallel_loading")]
+    streaming: bool,
+    /// Number of retries of a read failing with a transient I/O error
+    /// (e.g. stale handles on network filesystems)
+    #[arg(long, default_value_t = 0)]
+    io_retries: u32,
+    /// Milliseconds to wait before the first retry, doubled at each further retry
+    #[arg(long, default_value_t = DEFAULT_RETRY_BACKOFF_MS)]
+    retry_backoff_ms: u64,
+    /// Group the PDFs lying directly in the input directory under a bookmark
+    /// with this title, instead of interleaving them with the directory sections
+    #[arg(long)]
+    root_files_section: Option<String>,
+    /// Encoding of the bookmark titles and of the other generated text
+    #[arg(long, value_enum, default_value_t = TitleEncoding::Auto)]
+    title_encoding: TitleEncoding,
+    /// File listing the files to exclude from the merge, one per line, either as path
+    /// relative to the input directory or as `sha256:<hex digest>` of their content
+    #[arg(long)]
+    skip_list: Option<String>,
+    /// TOML file mapping the paths of the encrypted files, relative to the input directory,
+    /// or glob patterns of them, to their passwords (e.g. `"scans/*.pdf" = "s3cret"`)
+    #[arg(long, value_name = "FILE")]
+    passwords: Option<String>,
+    /// What to do with the digitally signed files, whose signatures the merge invalidates:
+    /// abort, strip their signature fields, or merge them and list them in the report
+    #[arg(long, value_enum, default_value_t = SignedPolicy::Error)]
+    signed: SignedPolicy,
+    /// Add named destinations pointing to the first page of each file, named after the
+    /// normalised file name (e.g. `out.pdf#nameddest=invoice-2023-07`)
+    #[arg(long)]
+    dests_index: bool,
+    /// Policy for sibling entries whose names differ only by case or Unicode normalisation
+    #[arg(long, value_enum, default_value_t = CollisionPolicy::Suffix)]
+    collision: CollisionPolicy,
+    /// Order of the files and directories within each directory
+    #[arg(long, value_enum, default_value_t = OrderBy::Name)]
+    order_by: OrderBy,
+    /// How the names compare (also among the entries with the same mtime or size)
+    #[arg(long, value_enum, default_value_t = SortOrder::Natural)]
+    sort: SortOrder,
+    /// Reverse the order of the entries of each directory
+    #[arg(long)]
+    reverse: bool,
+    /// Make the bookmarks keep the zoom of the reader instead of fitting the page, for all
+    /// the levels or only for the given ones (e.g. `--inherit-zoom 2,3`, 1 being the top level)
+    #[arg(long, value_name = "LEVELS", num_args = 0.., value_delimiter = ',')]
+    inherit_zoom: Option<Vec<usize>>,
+    /// View of the bookmark destinations: fit the page, jump to its top-left corner (at
+    /// `--zoom`, if given) or fit its width (overridden by `--inherit-zoom` on its levels)
+    #[arg(long, value_enum, default_value_t = DestView::Fit)]
+    dest_mode: DestView,
+    /// Zoom of the `xyz` destinations (e.g. `1.5` for 150%)
+    #[arg(long, value_parser = parse_zoom)]
+    zoom: Option<f32>,
+    /// Style of the directory bookmarks: comma-separated `bold`, `italic`, `plain` and a
+    /// color `#rrggbb` (e.g. `bold,#1f4e79`)
+    #[arg(long, value_name = "STYLE", value_parser = BookmarkStyle::parse)]
+    dir_style: Option<BookmarkStyle>,
+    /// Style of the file bookmarks, as `--dir-style`
+    #[arg(long, value_name = "STYLE", value_parser = BookmarkStyle::parse)]
+    file_style: Option<BookmarkStyle>,
+    /// Style of the bookmarks of a level, overriding `--dir-style` and `--file-style`
+    /// (e.g. `--level-style 1=bold`, 1 being the top level; repeatable)
+    #[arg(long, value_name = "LEVEL=STYLE", value_parser = parse_level_style)]
+    level_style: Vec<(usize, BookmarkStyle)>,
+    /// Expand at opening only the bookmarks of the first N levels, e.g. 1 to show the
+    /// top-level directories with their content but not deeper (default: all expanded)
+    #[arg(long, value_name = "N")]
+    outline_open_depth: Option<usize>,
+    /// Attach to the output a JSON manifest of the source files (page ranges, SHA-256)
+    #[arg(long)]
+    embed_manifest: bool,
+    /// Record in the manifest (implies --embed-manifest) also owner, permissions,
+    /// timestamps and extended attributes of each source file
+    #[arg(long)]
+    preserve_fs_metadata: bool,
+    /// Insert at the beginning pages showing a miniature of the first page of each file
+    #[arg(long)]
+    contact_sheet: bool,
+    /// Insert at the beginning printed table of contents pages, with the page number of
+    /// each directory and file and links to them
+    #[arg(long)]
+    toc_pages: bool,
+    /// Insert before the content of each directory a page showing its path in large text
+    #[arg(long)]
+    divider_pages: bool,
+    /// Title of the output, shown by the viewers instead of the file name
+    #[arg(long)]
+    title: Option<String>,
+    /// Author of the output
+    #[arg(long)]
+    author: Option<String>,
+    /// Subject of the output
+    #[arg(long)]
+    subject: Option<String>,
+    /// Keywords of the output (e.g. `"invoices, 2024"`)
+    #[arg(long)]
+    keywords: Option<String>,
+    /// Label the pages of each file with its bookmark title and the page number within the
+    /// file (e.g. "Invoice-3 2"), as shown in the page box of the viewers
+    #[arg(long)]
+    page_labels: bool,
+    /// Draw the text across every page of the output (e.g. `CONFIDENTIAL`)
+    #[arg(long, value_name = "TEXT")]
+    watermark: Option<String>,
+    /// Opacity of the watermark, from 0 (invisible) to 1 (opaque)
+    #[arg(long, default_value_t = 0.2, value_parser = parse_opacity, requires = "watermark")]
+    watermark_opacity: f32,
+    /// Counterclockwise angle of the watermark in degrees
+    #[arg(long, default_value_t = 45.0, requires = "watermark")]
+    watermark_angle: f32,
+    /// Font size of the watermark
+    #[arg(long, default_value_t = 60.0, requires = "watermark")]
+    watermark_font_size: f32,
+    /// Stamp on every page the path of its source file and its page number within the file
+    #[arg(long)]
+    source_footer: bool,
+    /// Position of the source footers
+    #[arg(long, value_enum, default_value_t = StampPosition::BottomLeft, requires = "source_footer")]
+    source_footer_position: StampPosition,
+    /// Stamp a running page number on every page of the output
+    #[arg(long)]
+    stamp_page_numbers: bool,
+    /// Position of the page numbers
+    #[arg(long, value_enum, default_value_t = StampPosition::BottomCenter, requires = "stamp_page_numbers")]
+    page_number_position: StampPosition,
+    /// Text of the page numbers, where `{page}` is replaced by the page number and
+    /// `{total}` by the number of pages (e.g. `Page {page} of {total}`)
+    #[arg(long, default_value = "{page}", requires = "stamp_page_numbers")]
+    page_number_format: String,
+    /// Stamp sequential Bates identifiers on every page: the prefix followed by the number
+    /// padded to WIDTH digits, starting from START (e.g. `ACME:1:6` for `ACME000001`, ...)
+    #[arg(long, value_name = "PREFIX:START:WIDTH", value_parser = BatesNumbering::parse)]
+    bates: Option<BatesNumbering>,
+    /// Position of the Bates identifiers
+    #[arg(long, value_enum, default_value_t = StampPosition::BottomRight, requires = "bates")]
+    bates_position: StampPosition,
+    /// Highlight the occurrences of this term in the merged pages, and list the pages
+    /// containing it under a "Search hits" bookmark
+    #[arg(long)]
+    highlight: Option<String>,
+    /// Append to the ToC a "Source map" section listing every file with its page range
+    #[arg(long)]
+    appendix_map: bool,
+    /// File name pattern (with `*` and `?` wildcards) of outputs of previous runs, which
+    /// are skipped if found in the input directory (in addition to `*-united.pdf`)
+    #[arg(long)]
+    output_pattern: Vec<String>,
+    /// Merge also the files looking like outputs of previous runs
+    #[arg(long)]
+    keep_previous_outputs: bool,
+    /// Log the current phase and file every this many seconds
+    #[arg(long)]
+    heartbeat_secs: Option<u64>,
+    /// Consider the merge stalled after this many seconds without progress
+    /// (e.g. because of a hanging network read)
+    #[arg(long)]
+    stall_timeout_secs: Option<u64>,
+    /// What to do when the merge is stalled
+    #[arg(long, value_enum, default_value_t = StallAction::Abort, requires = "stall_timeout_secs")]
+    on_stall: StallAction,
+    /// Do not draw the progress bar of the merged files (it is drawn only on a terminal anyway)
+    #[arg(long)]
+    no_progress: bool,
+    /// Skip (and report) the files whose parsing takes longer than this
+    /// (e.g. `90s`, `2m`, `500ms`; plain numbers are seconds). The parsing of such a file
+    /// goes on in the background until it ends, and the merge is aborted when 8 of them are
+    /// still running
+    #[arg(long, value_parser = parse_duration)]
+    file_timeout: Option<Duration>,
+    /// Prefix the bookmark titles with hierarchical numbers (1, 1.1, 1.1.2, ...)
+    #[arg(long)]
+    number_sections: bool,
+    /// When the output has no ToC, adopt the PageMode (viewer panel shown at opening)
+    /// of the first input, which is otherwise dropped
+    #[arg(long)]
+    inherit_first_pagemode: bool,
+    /// Flate level of the compression of the streams: `fast` speeds up the merge of huge
+    /// archives at the cost of a larger output
+    #[arg(long, value_enum, default_value_t = CompressionLevel::Default)]
+    compression: CompressionLevel,
+    /// Serialise the output in several ways (cross-reference table or streams, with or
+    /// without merging identical streams) and keep the smallest one: slow, for archives
+    #[arg(long)]
+    best_of: bool,
+    /// Encrypt the output, openable without password, withholding these permissions from
+    /// its readers (e.g. `no-print,no-copy`)
+    #[arg(
+        long,
+        value_name = "RESTRICTIONS",
+        value_enum,
+        value_delimiter = ',',
+        num_args = 1..,
+        conflicts_with = "best_of"
+    )]
+    permissions: Vec<Restriction>,
+    /// Split the output into `<output>-part1.pdf`, `<output>-part2.pdf`, ... of at most N
+    /// pages each, cutting only between files (a longer file makes a part on its own)
+    #[arg(long, value_name = "N", conflicts_with = "best_of")]
+    split_pages: Option<u32>,
+    /// Warn about the files with pages of a size or orientation other than the dominant
+    /// one of the tree (the formats of each file are listed in the report)
+    #[arg(long)]
+    warn_mixed_sizes: bool,
+    /// Do not add bookmarks at the top of the ToC for the generated pages placed
+    /// before the files (e.g. the contact sheet)
+    #[arg(long)]
+    no_frontmatter_bookmarks: bool,
+    /// Detect the printed table of contents in the first pages of each file and add a
+    /// bookmark for each of its entries below the one of the file (heuristic, needs a text
+    /// layer: useful for scanned books without outline)
+    #[arg(long)]
+    toc_from_text: bool,
+    /// Add a child bookmark "Page N" for every page below the bookmark of each file
+    /// (useful for scans, where the single pages matter more than the files)
+    #[arg(long)]
+    page_bookmarks: bool,
+    /// Make the bookmark titles readable: all of `--strip-extension`, `--strip-sort-prefix`,
+    /// `--spaces` and `--title-case`
+    #[arg(long)]
+    pretty_titles: bool,
+    /// Drop the `.pdf` extension from the bookmark titles
+    #[arg(long)]
+    strip_extension: bool,
+    /// Drop the numeric sort prefixes like `01_` from the bookmark titles
+    #[arg(long)]
+    strip_sort_prefix: bool,
+    /// Replace underscores and dashes with spaces in the bookmark titles
+    #[arg(long)]
+    spaces: bool,
+    /// Capitalise every word of the bookmark titles
+    #[arg(long)]
+    title_case: bool,
+    /// Title of the bookmarks of the files: their name, or the Title of their metadata
+    /// (falling back to the name when missing)
+    #[arg(long, value_enum, default_value_t = TitleSource::Filename)]
+    title_source: TitleSource,
+    /// Merge every directory containing only JPEG or PNG images as a single document with
+    /// one page per image (in natural order) and a single bookmark
+    #[arg(long)]
+    image_dirs: bool,
+    /// Put every page directly under the root of the page tree, for the downstream tools
+    /// that do not handle the nested page trees of the grafted inputs (same as
+    /// `--page-tree flat`)
+    #[arg(long, conflicts_with = "page_tree")]
+    flat_page_tree: bool,
+    /// Shape of the page tree of the output: `auto` grafts the page tree of each file,
+    /// grouping them 32 per node, but builds a balanced tree for outputs of 50000 pages or more
+    #[arg(long, value_enum, default_value_t = PageTreeLayout::Auto)]
+    page_tree: PageTreeLayout,
+    /// Format the dates and numbers of the generated content (e.g. the page ranges of the
+    /// source map) according to this locale, e.g. `de-DE` (default: ISO dates, plain numbers)
+    #[arg(long, value_parser = Locale::parse)]
+    locale: Option<Locale>,
+    /// Maximum depth of the directory tree below the input directory, 0 for no limit
+    #[arg(long, default_value_t = DEFAULT_MAX_DEPTH)]
+    max_depth: u32,
+    /// Stop after merging this many files, to preview quickly a sample of the output
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    limit_files: Option<u32>,
+    /// Stop once the output has at least this many pages (the last file is merged whole)
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    limit_pages: Option<u32>,
+    /// Write a JSON record of the merge to this path: for each merged file its bookmark
+    /// title, page range and page count, then the skipped files and the warnings
+    #[arg(long)]
+    report: Option<String>,
+    /// Merge only the files whose path relative to the input directory matches this glob
+    /// (repeatable), e.g. `**/report*.pdf`
+    #[arg(long = "include", value_name = "GLOB")]
+    include_patterns: Vec<String>,
+    /// Leave out the files and directories whose path relative to the input directory
+    /// matches this glob (repeatable), e.g. `**/drafts/**`
+    #[arg(long = "exclude", value_name = "GLOB")]
+    exclude_patterns: Vec<String>,
+    /// Leave out the files which cannot be merged (e.g. corrupt or with unsupported
+    /// features) instead of aborting, and list them at the end
+    #[arg(long)]
+    skip_errors: bool,
+    /// Write the skipped files to this path, in the format of --skip-list with the reasons
+    /// as comments
+    #[arg(long)]
+    skipped_list: Option<String>,
+    /// Run this command on each file before merging it, e.g. `qpdf --decrypt {in} {out}`,
+    /// where {in} is the file and {out} the temporary file to write the result to
+    #[arg(long)]
+    pre_cmd: Option<String>,
+    /// Run this command after a successful merge, e.g. `rclone copy {output} remote:archives/`.
+    /// Placeholders: {output}, {root}, {files}, {pages}, {warnings}, {report}. With
+    /// --split-pages it runs once for each part, {output} and {pages} being the ones of the part.
+    /// Not available with `-o -`
+    #[arg(long)]
+    post_cmd: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Show which files a re-merge of the directory would add, remove or update
+    /// with respect to the report of a previous run
+    Changes {
+        /// Report written by a previous run with `--report`
+        #[arg(long)]
+        since: String,
+        /// Do not list again the directories whose modification time did not change, taking
+        /// their files as unchanged (misses files rewritten in place)
+        #[arg(long)]
+        trust_dir_mtimes: bool,
+        /// Directory containing the pdfs
+        input_directory: String,
+    },
+    /// Explode a merged PDF into one file per bookmark of the given level, recreating
+    /// the bookmarks of the levels above as nested directories
+    Split {
+        /// Path of the merged pdf file
+        pdf_path: String,
+        /// Level of the bookmarks at which to split (1 for the top-level bookmarks)
+        #[arg(long, default_value_t = 1)]
+        level: usize,
+        /// Directory in which the files are written
+        #[arg(long)]
+        into: String,
+    },
+    /// Run the merges listed in a jobs file (YAML), each with its input tree, output and
+    /// settings, then print a summary table
+    Batch {
+        /// Path of the jobs file
+        jobs_path: String,
+        /// Number of jobs run at the same time
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+        parallel: u32,
+    },
+    /// Show information about a PDF file
+    Inspect {
+        #[command(subcommand)]
+        what: InspectCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum InspectCommand {
+    /// Print page count, version, encryption, metadata, page sizes and outline size
+    Info {
+        /// Print the information as JSON
+        #[arg(long)]
+        json: bool,
+        /// Path of the pdf file
+        pdf_path: String,
+    },
+    /// Print the outline (ToC) with the page numbers of its entries
+    Toc {
+        /// Output format
+        #[arg(long, value_enum, default_value_t = TocFormat::Md)]
+        format: TocFormat,
+        /// Path of the pdf file
+        pdf_path: String,
+    },
 }
 
 fn main() {
@@ -46,43 +506,746 @@ pub fn run() -> Result<()> {
 
     let cli = Cli::parse();
 
-    let mut target_dir_path = cli.input_directory;
-    let target_dir_path = if target_dir_path.ends_with('/') {
-        target_dir_path.pop();
-        Path::new(&target_dir_path)
+    if let Some(command) = cli.command {
+        return run_command(command);
+    }
+
+    let to_stdout = cli.output_path.as_deref() == Some("-");
+    if to_stdout {
+        check_stdout_output(&cli)?;
+    }
+
+    // `absolute` resolves drive-relative paths (e.g. `D:archive`) against the current
+    // directory of their drive on Windows
+    let merge_plan = match (&cli.manifest, &cli.files_from) {
+        (Some(manifest), _) => Some(MergePlan::load(manifest)?),
+        (None, Some(files_from)) => {
+            let file_paths = if files_from == "-" {
+                plan::read_file_list(std::io::stdin().lock(), cli.null_separated)?
+            } else {
+                plan::read_file_list(std::fs::File::open(files_from)?, cli.null_separated)?
+            };
+            if file_paths.is_empty() {
+                return Err(anyhow!("The list '{files_from}' contains no file"));
+            }
+            Some(MergePlan::from_file_list(&file_paths)?)
+        }
+        (None, None)
+            if cli
+                .input_directories
+                .iter()
+                .any(|input| source::is_glob_pattern(input)) =>
+        {
+            let mut file_paths: Vec<PathBuf> = Vec::new();
+            for pattern in &cli.input_directories {
+                if !source::is_glob_pattern(pattern) {
+                    return Err(anyhow!(
+                        "'{pattern}' is not a glob pattern: directories and patterns cannot be mixed"
+                    ));
+                }
+                for file_path in source::expand_glob(pattern)? {
+                    if !file_paths.contains(&file_path) {
+                        file_paths.push(file_path);
+                    }
+                }
+            }
+            if file_paths.is_empty() {
+                return Err(anyhow!("No file matches the given patterns"));
+            }
+            Some(MergePlan::from_file_list(&file_paths)?)
+        }
+        (None, None) => None,
+    };
+    let input_directories = match &merge_plan {
+        Some(merge_plan) => vec![merge_plan.base_dir.join(".").display().to_string()],
+        None => cli.input_directories,
+    };
+    let target_dir_paths = input_directories
+        .iter()
+        .map(|input_directory| Ok(std::path::absolute(Path::new(input_directory))?.canonicalize()?))
+        .collect::<Result<Vec<PathBuf>>>()?;
+    let target_dir_path = target_dir_paths
+        .first()
+        .ok_or(anyhow!("No input directory was given"))?
+        .clone();
+    let input_archive = match target_dir_paths.as_slice() {
+        [input_path] if input_path.is_file() && ArchiveFormat::from_path(input_path).is_some() => {
+            Some(input_path.clone())
+        }
+        _ => None,
+    };
+    // The skip list paths are relative to the root of the report
+    let report_root = utils::common_ancestor(&target_dir_paths);
+
+    // Fail before the merge on the ToC files whose format is unknown
+    for toc_path in &cli.export_toc {
+        TocFormat::from_path(toc_path)?;
+    }
+
+    let mut skip_list = match &cli.skip_list {
+        Some(skip_list_path) => SkipList::load(skip_list_path)?,
+        None => SkipList::default(),
+    };
+
+    // A templated output lies next to the input directory, an explicit one may be anywhere.
+    // A merge plan lists its files explicitly, therefore it cannot pick up the output
+    if let Some(output_path) = &cli.output_path
+        && merge_plan.is_none()
+        && !to_stdout
+    {
+        let output_path = Path::new(output_path);
+        // The parent is canonicalised as well, as a symlink may lead the output inside the input
+        let resolved_output_path = canonicalize_output_path(output_path)?;
+        if let Some(containing_dir_path) = target_dir_paths
+            .iter()
+            .find(|target_dir_path| resolved_output_path.starts_with(target_dir_path))
+        {
+            if !cli.allow_output_inside {
+                return Err(anyhow!(
+                    "The output file cannot be a descendant of the input directory: \
+                    '{}' resolves to '{}', which is a descendant of '{}' \
+                    (use --allow-output-inside to exclude it from the merge instead)",
+                    output_path.display(),
+                    utils::display_path(&resolved_output_path),
+                    utils::display_path(containing_dir_path)
+                ));
+            }
+            warn!(
+                "The output file '{}' is inside the input directory, therefore it is excluded from the merge",
+                utils::display_path(&resolved_output_path)
+            );
+            skip_list.insert_path(resolved_output_path.strip_prefix(&report_root)?);
+        }
+    }
+
+    let tree_snapshot = if cli.audit_readonly {
+        let mut planned_writes: Vec<(&str, PathBuf)> = Vec::new();
+        planned_writes.extend(
+            cli.output_path
+                .iter()
+                .filter(|_| !to_stdout)
+                .map(|path| ("The output", path.into())),
+        );
+        planned_writes.extend(cli.report.iter().map(|path| ("The report", path.into())));
+        planned_writes.extend(
+            cli.skipped_list
+                .iter()
+                .map(|path| ("The list of the skipped files", path.into())),
+        );
+        planned_writes.extend(
+            cli.export_toc
+                .iter()
+                .map(|path| ("The ToC file", path.into())),
+        );
+        if cli.pre_cmd.is_some() {
+            planned_writes.push((
+                "The directory of the temporary files of --pre-cmd",
+                std::env::temp_dir(),
+            ));
+        }
+        check_no_writes_inside(&target_dir_paths, &planned_writes)?;
+
+        Some(TreeSnapshot::take(&target_dir_paths)?)
+    } else {
+        None
+    };
+
+    let previous_output_patterns = if cli.keep_previous_outputs {
+        Vec::new()
+    } else {
+        let mut patterns = cli.output_pattern;
+        patterns.push(output_template_pattern(DEFAULT_OUTPUT_TEMPLATE));
+        if cli.output_template != DEFAULT_OUTPUT_TEMPLATE {
+            patterns.push(output_template_pattern(&cli.output_template));
+        }
+        patterns
+    };
+
+    let mut options = MergeOptions::default();
+    options.with_outlines = match (cli.outlines, cli.no_outlines) {
+        (true, _) => Some(true),
+        (_, true) => Some(false),
+        _ => None,
+    };
+    options.max_depth = Some(cli.max_depth);
+    options.paranoid = cli.paranoid;
+    options.parallel_loading = cli.parallel_loading;
+    options.streaming = cli.streaming.then_some(cli.compression);
+    options.max_open_files = cli.max_open_files;
+    options.io_retries = cli.io_retries;
+    options.retry_backoff = Duration::from_millis(cli.retry_backoff_ms);
+    options.root_files_section = cli.root_files_section;
+    options.title_encoding = cli.title_encoding;
+    options.skip_list = skip_list;
+    if let Some(passwords_path) = &cli.passwords {
+        options.passwords = PasswordMap::load(passwords_path)?;
+    }
+    options.signed_policy = cli.signed;
+    options.dests_index = cli.dests_index;
+    options.collision_policy = cli.collision;
+    options.order_by = cli.order_by;
+    options.sort_order = cli.sort;
+    options.reverse_order = cli.reverse;
+    options.bookmark_styles = BookmarkStyles {
+        directories: cli.dir_style,
+        files: cli.file_style,
+        per_level: cli.level_style.into_iter().collect(),
+    };
+    options.outline_open_depth = cli.outline_open_depth;
+    options.dest_views = dest_views(cli.dest_mode, cli.zoom, cli.inherit_zoom)?;
+    options.embed_manifest = cli.embed_manifest || cli.preserve_fs_metadata;
+    options.preserve_fs_metadata = cli.preserve_fs_metadata;
+    options.contact_sheet = cli.contact_sheet;
+    options.toc_pages = cli.toc_pages;
+    options.divider_pages = cli.divider_pages;
+    options.document_info = DocumentInfo {
+        title: cli.title,
+        author: cli.author,
+        subject: cli.subject,
+        keywords: cli.keywords,
+    };
+    options.page_labels = cli.page_labels;
+    options.watermark = cli.watermark.map(|text| Watermark {
+        opacity: cli.watermark_opacity,
+        angle: cli.watermark_angle,
+        font_size: cli.watermark_font_size,
+        ..Watermark::new(text)
+    });
+    options.source_footers = cli.source_footer.then_some(cli.source_footer_position);
+    options.page_numbers = cli.stamp_page_numbers.then(|| PageNumberStamp {
+        position: cli.page_number_position,
+        format: cli.page_number_format,
+        ..Default::default()
+    });
+    options.bates = cli.bates.map(|bates| BatesNumbering {
+        position: cli.bates_position,
+        ..bates
+    });
+    options.highlight = cli.highlight;
+    options.appendix_map = cli.appendix_map;
+    options.include_patterns = cli.include_patterns;
+    options.exclude_patterns = cli.exclude_patterns;
+    options.previous_output_patterns = previous_output_patterns;
+    options.number_sections = cli.number_sections;
+    options.inherit_first_page_mode = cli.inherit_first_pagemode;
+    options.warn_mixed_sizes = cli.warn_mixed_sizes;
+    options.skip_frontmatter_bookmarks = cli.no_frontmatter_bookmarks;
+    options.toc_from_text = cli.toc_from_text;
+    options.page_bookmarks = cli.page_bookmarks;
+    options.title_source = cli.title_source;
+    options.prettify_titles = if cli.pretty_titles {
+        PrettifyTitles::all()
+    } else {
+        PrettifyTitles {
+            strip_extension: cli.strip_extension,
+            strip_sort_prefix: cli.strip_sort_prefix,
+            spaces_for_separators: cli.spaces,
+            title_case: cli.title_case,
+        }
+    };
+    options.image_dirs = cli.image_dirs;
+    options.page_tree_layout = if cli.flat_page_tree {
+        PageTreeLayout::Flat
+    } else {
+        cli.page_tree
+    };
+    options.locale = cli.locale.unwrap_or_default();
+    options.limit_files = cli.limit_files;
+    options.limit_pages = cli.limit_pages;
+    options.merge_roots = cli.merge_roots;
+    options.heartbeat_interval = cli.heartbeat_secs.map(Duration::from_secs);
+    options.stall_timeout = cli.stall_timeout_secs.map(Duration::from_secs);
+    options.stall_action = cli.on_stall;
+    options.skip_errors = cli.skip_errors;
+    options.pre_command = cli.pre_cmd;
+    options.file_timeout = cli.file_timeout;
+
+    if cli.dry_run {
+        if input_archive.is_some() {
+            return Err(anyhow!("The dry run does not support archives"));
+        }
+        let (planned_entries, report) = plan_merge(&target_dir_paths, &options)?;
+        print!("{}", plan::format_plan(&planned_entries)?);
+        for warning in &report.warnings {
+            println!("Warning: {warning}");
+        }
+        return Ok(());
+    }
+
+    if !cli.no_progress {
+        options.progress = Progress::with_bar();
+    }
+    let merged = match (&merge_plan, &input_archive) {
+        (Some(merge_plan), _) => merge_plan_to_document(merge_plan, &options),
+        (None, Some(input_archive)) => merge_archive_to_document(input_archive, &options),
+        (None, None) => merge_roots_to_document(&target_dir_paths, &options),
+    };
+    options.progress.finish();
+    let (mut main_doc, report) = merged?;
+
+    compression::compress_streams(&mut main_doc, cli.compression)?;
+
+    let output_path = match cli.output_path {
+        Some(output_path) => PathBuf::from(output_path),
+        None => {
+            let fields = OutputNameFields {
+                dirname: match &input_archive {
+                    Some(input_archive) => archive_stem(input_archive),
+                    None => target_dir_path
+                        .file_name()
+                        .map(|dirname| dirname.to_string_lossy().to_string())
+                        .unwrap_or_default(),
+                },
+                date: output_name::today(),
+                file_count: report.sources.len(),
+            };
+            let output_file_name = render_output_template(&cli.output_template, &fields)?;
+            target_dir_path
+                .parent()
+                .unwrap_or(&target_dir_path)
+                .join(output_file_name)
+        }
+    };
+    let output_path = if cli.auto_rename {
+        first_free_numbered_path(&output_path)?
     } else {
-        Path::new(&target_dir_path)
+        output_path
+    };
+    let output_path = output_path.as_path();
+    if cli.audit_readonly && !to_stdout {
+        check_no_writes_inside(&target_dir_paths, &[("The output", output_path.into())])?;
     }
-    .canonicalize()?;
 
-    let output_path = cli.output_path.unwrap_or(format!(
-        "{}{DEFAULT_OUTPUT_SUFFIX}",
-        target_dir_path.display()
-    ));
-    let output_path = Path::new(&output_path);
+    // The standard output carries the document, if it is written there
+    let mut status: Box<dyn Write> = if to_stdout {
+        Box::new(std::io::stderr())
+    } else {
+        Box::new(std::io::stdout())
+    };
 
-    if output_path.starts_with(&target_dir_path) {
+    // Read before the output is encrypted, which encrypts the titles of the outline too
+    let toc_nodes = if cli.export_toc.is_empty() {
+        Vec::new()
+    } else {
+        outline::outline_tree(&outline::read_outline(&main_doc)?)
+    };
+
+    // The files written, with their number of pages
+    let mut written_outputs = vec![(output_path.to_path_buf(), main_doc.get_pages().len())];
+    if to_stdout {
+        let mut stdout = std::io::stdout().lock();
+        if cli.best_of {
+            let (strategy, serialized) = compression::smallest_serialization(&main_doc)?;
+            writeln!(
+                status,
+                "Smallest output ({} bytes) obtained with the strategy {strategy:?}",
+                serialized.len()
+            )?;
+            stdout.write_all(&serialized)?;
+        } else {
+            encrypt_output(&mut main_doc, &cli.permissions)?;
+            main_doc.save_to(&mut stdout)?;
+        }
+        stdout.flush()?;
+    } else if let Some(max_pages) = cli.split_pages {
+        written_outputs.clear();
+        let parts =
+            split::split_by_page_count(&main_doc, &report, max_pages, options.title_encoding)?;
+        let part_paths: Vec<PathBuf> = (1..=parts.len())
+            .map(|part_number| part_path(output_path, part_number))
+            .collect();
+        if let Some(present_path) = part_paths.iter().find(|part_path| part_path.exists())
+            && !cli.force
+        {
+            return Err(anyhow!(
+                "A file '{}' is already present (use --force to overwrite it)",
+                utils::display_path(present_path)
+            ));
+        }
+        for (mut part, part_path) in parts.into_iter().zip(&part_paths) {
+            encrypt_output(&mut part, &cli.permissions)?;
+            utils::save_atomically(&mut part, part_path)?;
+            let num_part_pages = part.get_pages().len();
+            writeln!(
+                status,
+                "Part of {num_part_pages} pages saved as '{}'",
+                utils::display_path(part_path)
+            )?;
+            written_outputs.push((part_path.clone(), num_part_pages));
+        }
+    } else if std::fs::exists(output_path)? && !cli.force {
         return Err(anyhow!(
-            "The output file cannot be a descendant of the input directory: \
-            '{}' is a descendant of '{}'",
-            output_path.display(),
-            target_dir_path.display()
+            "A file '{}' is already present (use --force to overwrite it or --auto-rename)",
+            utils::display_path(output_path)
         ));
+    } else if cli.best_of {
+        let (strategy, serialized) = compression::smallest_serialization(&main_doc)?;
+        writeln!(
+            status,
+            "Smallest output ({} bytes) obtained with the strategy {strategy:?}",
+            serialized.len()
+        )?;
+        utils::write_atomically(output_path, |writer| Ok(writer.write_all(&serialized)?))?;
+    } else {
+        encrypt_output(&mut main_doc, &cli.permissions)?;
+        utils::save_atomically(&mut main_doc, output_path)?;
+    }
+    if to_stdout {
+        writeln!(status, "Output document written to the standard output")?;
+    } else if cli.split_pages.is_none() {
+        writeln!(
+            status,
+            "Output document saved as '{}'",
+            utils::display_path(output_path)
+        )?;
+    }
+
+    for toc_path in &cli.export_toc {
+        let exported_toc = export_toc(&toc_nodes, TocFormat::from_path(toc_path)?)?;
+        std::fs::write(toc_path, exported_toc)?;
+        writeln!(status, "ToC exported as '{toc_path}'")?;
+    }
+
+    if let Some(report_path) = &cli.report {
+        report.save(report_path)?;
+        writeln!(status, "Report saved as '{report_path}'")?;
+    }
+
+    if !report.skipped.is_empty() {
+        writeln!(status, "{} files skipped:", report.skipped.len())?;
+        for skipped in &report.skipped {
+            writeln!(
+                status,
+                "  '{}': {}",
+                utils::display_path(&skipped.path),
+                skipped.reason
+            )?;
+        }
+    }
+    if let Some(skipped_list_path) = &cli.skipped_list {
+        report.save_skipped_list(skipped_list_path)?;
+        writeln!(
+            status,
+            "List of the skipped files saved as '{skipped_list_path}'"
+        )?;
+    }
+
+    if let Some(tree_snapshot) = tree_snapshot {
+        let changes = TreeSnapshot::take(&target_dir_paths)?.changes_since(&tree_snapshot);
+        if !changes.is_empty() {
+            return Err(anyhow!(
+                "The input directories changed during the merge:\n  {}",
+                changes.join("\n  ")
+            ));
+        }
+        writeln!(status, "Audit: the input directories were left untouched")?;
+    }
+
+    if let Some(post_cmd) = cli.post_cmd {
+        for (written_path, num_pages) in &written_outputs {
+            let placeholders = [
+                ("output", written_path.display().to_string()),
+                ("root", target_dir_path.display().to_string()),
+                ("files", report.sources.len().to_string()),
+                ("pages", num_pages.to_string()),
+                ("warnings", report.warnings.len().to_string()),
+                ("report", cli.report.clone().unwrap_or_default()),
+            ];
+            hooks::run_command_template(&post_cmd, &placeholders)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn run_command(command: Command) -> Result<()> {
+    match command {
+        Command::Changes {
+            since,
+            trust_dir_mtimes,
+            input_directory,
+        } => print_changes_since_report(input_directory, since, trust_dir_mtimes),
+        Command::Split {
+            pdf_path,
+            level,
+            into,
+        } => {
+            let written_paths = split::split_by_outline_level(pdf_path, level, into)?;
+            println!("{} files written", written_paths.len());
+            Ok(())
+        }
+        Command::Batch {
+            jobs_path,
+            parallel,
+        } => {
+            let batch = BatchFile::load(jobs_path)?;
+            let outcomes = batch::run_jobs(&batch.jobs, parallel as usize);
+            print!("{}", batch::summary_table(&outcomes));
+
+            let failed_jobs = outcomes
+                .iter()
+                .filter(|outcome| outcome.result.is_err())
+                .count();
+            if failed_jobs > 0 {
+                return Err(anyhow!("{failed_jobs} of {} jobs failed", outcomes.len()));
+            }
+            Ok(())
+        }
+        Command::Inspect { what } => match what {
+            InspectCommand::Info { json, pdf_path } => {
+                let doc = lopdf::Document::load(pdf_path)?;
+                let pdf_info = info::pdf_info(&doc)?;
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&pdf_info)?);
+                } else {
+                    print!("{pdf_info}");
+                }
+                Ok(())
+            }
+            InspectCommand::Toc { format, pdf_path } => {
+                let doc = lopdf::Document::load(pdf_path)?;
+                let entries = outline::read_outline(&doc)?;
+                print!("{}", export_toc(&outline::outline_tree(&entries), format)?);
+                Ok(())
+            }
+        },
+    }
+}
+
+/// The views of the bookmark destinations chosen by `--dest-mode`, `--zoom` and `--inherit-zoom`.
+fn dest_views(
+    dest_mode: DestView,
+    zoom: Option<f32>,
+    inherit_zoom: Option<Vec<usize>>,
+) -> Result<DestViews> {
+    if zoom.is_some() && dest_mode != DestView::TopLeft {
+        return Err(anyhow!("--zoom applies only to --dest-mode xyz"));
     }
+    Ok(match inherit_zoom {
+        None => DestViews {
+            default: dest_mode,
+            zoom,
+            ..Default::default()
+        },
+        Some(levels) if levels.is_empty() => DestViews {
+            default: DestView::InheritZoom,
+            ..Default::default()
+        },
+        Some(levels) => DestViews {
+            default: dest_mode,
+            per_level: levels
+                .into_iter()
+                .map(|level| (level, DestView::InheritZoom))
+                .collect(),
+            zoom,
+        },
+    })
+}
 
-    let mut main_doc = get_merged_tree_doc(target_dir_path, cli.with_outlines)?;
+fn parse_level_style(level_style: &str) -> Result<(usize, BookmarkStyle)> {
+    let (level, style) = level_style
+        .split_once('=')
+        .ok_or(anyhow!("'{level_style}' is not in the form LEVEL=STYLE"))?;
+    let level = level
+        .trim()
+        .parse()
+        .map_err(|_| anyhow!("'{level}' is not a bookmark level"))?;
+    Ok((level, BookmarkStyle::parse(style)?))
+}
 
-    main_doc.compress();
+fn parse_zoom(zoom: &str) -> Result<f32> {
+    match zoom.trim().parse::<f32>() {
+        Ok(zoom) if zoom > 0.0 && zoom.is_finite() => Ok(zoom),
+        _ => Err(anyhow!("'{zoom}' is not a positive zoom factor")),
+    }
+}
 
-    if std::fs::exists(output_path)? {
+fn parse_opacity(opacity: &str) -> Result<f32> {
+    match opacity.trim().parse::<f32>() {
+        Ok(opacity) if (0.0..=1.0).contains(&opacity) => Ok(opacity),
+        _ => Err(anyhow!("'{opacity}' is not an opacity between 0 and 1")),
+    }
+}
+
+fn parse_duration(duration: &str) -> Result<Duration> {
+    let number_len = duration
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(duration.len());
+    let (number, unit) = duration.split_at(number_len);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| anyhow!("'{duration}' does not start with a number"))?;
+
+    match unit {
+        "ms" => Ok(Duration::from_millis(number)),
+        "" | "s" => Ok(Duration::from_secs(number)),
+        "m" => Ok(Duration::from_secs(number * 60)),
+        "h" => Ok(Duration::from_secs(number * 3600)),
+        _ => Err(anyhow!("Unknown unit '{unit}' (use ms, s, m or h)")),
+    }
+}
+
+/// Fail on the options which need an output file, when the output goes to the standard output.
+fn check_stdout_output(cli: &Cli) -> Result<()> {
+    if cli.split_pages.is_some() || cli.auto_rename {
         return Err(anyhow!(
-            "A file '{}' is already present",
-            output_path.display()
+            "The output cannot be split or renamed when written to the standard output"
         ));
-    } else {
-        main_doc.save(output_path)?;
-        println!("Output document saved as '{}'", output_path.display());
+    }
+    // The command would write to the standard output too, after the document
+    if cli.post_cmd.is_some() {
+        return Err(anyhow!(
+            "--post-cmd cannot be used when the output is written to the standard output"
+        ));
+    }
+    Ok(())
+}
+
+/// Fail, listing them, if any of the planned writes lands inside the input directories.
+fn check_no_writes_inside(roots: &[PathBuf], planned_writes: &[(&str, PathBuf)]) -> Result<()> {
+    let violations = audit::writes_inside_roots(roots, planned_writes)?;
+    if violations.is_empty() {
+        return Ok(());
+    }
+
+    Err(anyhow!(
+        "The read-only audit failed:\n  {}",
+        violations.join("\n  ")
+    ))
+}
+
+/// Encrypt the document to save if some permissions are withheld.
+fn encrypt_output(doc: &mut lopdf::Document, restrictions: &[Restriction]) -> Result<()> {
+    if restrictions.is_empty() {
+        return Ok(());
+    }
+    encryption::encrypt_with_restrictions(doc, restrictions)
+}
+
+/// The path of a part of a split output: `out.pdf` gives `out-part1.pdf`, ...
+fn part_path(output_path: &Path, part_number: usize) -> PathBuf {
+    let stem = output_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let extension = output_path
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+    output_path.with_file_name(format!("{stem}-part{part_number}{extension}"))
+}
+
+/// The output path with its parent directory canonicalised (the file itself may not exist yet).
+fn canonicalize_output_path(output_path: &Path) -> Result<PathBuf> {
+    let file_name = output_path.file_name().ok_or(anyhow!(
+        "The output path '{}' does not name a file",
+        output_path.display()
+    ))?;
+    let parent_dir = match output_path.parent() {
+        Some(parent_dir) if !parent_dir.as_os_str().is_empty() => parent_dir,
+        _ => Path::new("."),
+    };
+
+    Ok(parent_dir.canonicalize()?.join(file_name))
+}
+
+fn print_changes_since_report(
+    input_directory: impl AsRef<Path>,
+    report_path: impl AsRef<Path>,
+    trust_directory_mtimes: bool,
+) -> Result<()> {
+    let previous_report = MergeReport::load(report_path)?;
+    let changes =
+        diff_tree_against_report(input_directory, &previous_report, trust_directory_mtimes)?;
+
+    if changes.is_empty() {
+        println!("No changes: a re-merge would produce the same document");
+        return Ok(());
     }
 
+    for added in &changes.added {
+        println!("+ {}", added.display());
+    }
+    for removed in &changes.removed {
+        println!("- {}", removed.display());
+    }
+    for modified in &changes.modified {
+        println!("~ {}", modified.display());
+    }
+    println!(
+        "A re-merge would add {}, remove {} and update {} files",
+        changes.added.len(),
+        changes.removed.len(),
+        changes.modified.len()
+    );
+
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pdfunite_tree::base_dirs::BaseDir;
+
+    #[test]
+    fn post_cmd_needs_an_output_file() -> Result<()> {
+        let cli = Cli::try_parse_from([
+            "pdfunite3",
+            "lectures",
+            "-o",
+            "-",
+            "--post-cmd",
+            "ls {output}",
+        ])?;
+        assert!(check_stdout_output(&cli).is_err());
+
+        let cli = Cli::try_parse_from(["pdfunite3", "lectures", "-o", "-"])?;
+        check_stdout_output(&cli)?;
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn output_path_is_resolved_through_symlinks() -> Result<()> {
+        let test_dir = BaseDir::Playground
+            .path()?
+            .join("test")
+            .join("output_path_is_resolved_through_symlinks");
+        if std::fs::exists(&test_dir)? {
+            std::fs::remove_dir_all(&test_dir)?;
+        }
+        let input_dir = test_dir.join("lectures");
+        std::fs::create_dir_all(&input_dir)?;
+        std::os::unix::fs::symlink(&input_dir, test_dir.join("link"))?;
+
+        let resolved_output_path =
+            canonicalize_output_path(&test_dir.join("link").join("out.pdf"))?;
+        assert_eq!(
+            resolved_output_path,
+            input_dir.canonicalize()?.join("out.pdf")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn dest_mode_and_zoom() -> Result<()> {
+        let views_of = |args: &[&str]| -> Result<DestViews> {
+            let cli = Cli::try_parse_from(["pdfunite3", "lectures"].iter().chain(args))?;
+            dest_views(cli.dest_mode, cli.zoom, cli.inherit_zoom)
+        };
+
+        assert_eq!(views_of(&[])?, DestViews::default());
+        let views = views_of(&["--dest-mode", "xyz", "--zoom", "1.5"])?;
+        assert_eq!((views.default, views.zoom), (DestView::TopLeft, Some(1.5)));
+        let views = views_of(&["--dest-mode", "fith", "--inherit-zoom", "2"])?;
+        assert_eq!(views.for_level(1), DestView::FitWidth);
+        assert_eq!(views.for_level(2), DestView::InheritZoom);
+
+        assert!(views_of(&["--zoom", "1.5"]).is_err());
+        assert!(views_of(&["--dest-mode", "xyz", "--zoom", "0"]).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,247 @@
+use anyhow::{Result, anyhow};
+use flate2::Compression;
+use flate2::write::ZlibEncoder;
+use log::{info, warn};
+use lopdf::{Document, Object, ObjectId, Stream};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::Write;
+
+/// Flate level of the streams compressed on save.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CompressionLevel {
+    /// Fastest compression, larger output
+    Fast,
+    /// Balance between speed and size
+    #[default]
+    Default,
+    /// Smallest output, slowest compression
+    Best,
+}
+
+impl CompressionLevel {
+    fn flate_compression(self) -> Compression {
+        match self {
+            CompressionLevel::Fast => Compression::fast(),
+            CompressionLevel::Default => Compression::default(),
+            CompressionLevel::Best => Compression::best(),
+        }
+    }
+}
+
+/// Compress with Flate at the given level the streams which are not already encoded,
+/// keeping the plain content where compressing would not make it smaller.
+pub fn compress_streams(doc: &mut Document, level: CompressionLevel) -> Result<()> {
+    for object in doc.objects.values_mut() {
+        if let Object::Stream(stream) = object {
+            compress_stream(stream, level)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Compress the stream as `compress_streams` does.
+pub fn compress_stream(stream: &mut Stream, level: CompressionLevel) -> Result<()> {
+    if !stream.allows_compression || stream.dict.has(b"Filter") {
+        return Ok(());
+    }
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), level.flate_compression());
+    encoder.write_all(&stream.content)?;
+    let compressed = encoder.finish()?;
+
+    if compressed.len() < stream.content.len() {
+        stream.dict.set("Filter", "FlateDecode");
+        stream.set_content(compressed);
+    }
+
+    Ok(())
+}
+
+/// A way of serialising the document tried by `smallest_serialization`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SaveStrategy {
+    /// Classic cross-reference table, every object written plainly
+    Classic,
+    /// Cross-reference stream, with the small objects packed in compressed object streams
+    Modern,
+    /// As `Classic`, after merging the identical streams
+    ClassicDeduplicated,
+    /// As `Modern`, after merging the identical streams
+    ModernDeduplicated,
+}
+
+impl SaveStrategy {
+    const ALL: [SaveStrategy; 4] = [
+        SaveStrategy::Classic,
+        SaveStrategy::Modern,
+        SaveStrategy::ClassicDeduplicated,
+        SaveStrategy::ModernDeduplicated,
+    ];
+
+    fn deduplicates(self) -> bool {
+        matches!(
+            self,
+            SaveStrategy::ClassicDeduplicated | SaveStrategy::ModernDeduplicated
+        )
+    }
+}
+
+/// Serialise the document with every strategy and return the smallest output which parses
+/// back with the same number of pages, along with the strategy giving it. Slow and memory
+/// hungry, as the document is copied for each strategy.
+pub fn smallest_serialization(doc: &Document) -> Result<(SaveStrategy, Vec<u8>)> {
+    let page_count = doc.get_pages().len();
+    let mut smallest: Option<(SaveStrategy, Vec<u8>)> = None;
+
+    for strategy in SaveStrategy::ALL {
+        let mut candidate_doc = doc.clone();
+        if strategy.deduplicates() {
+            deduplicate_streams(&mut candidate_doc);
+        }
+
+        let mut buffer = Vec::new();
+        match strategy {
+            SaveStrategy::Classic | SaveStrategy::ClassicDeduplicated => {
+                candidate_doc.save_to(&mut buffer)?
+            }
+            SaveStrategy::Modern | SaveStrategy::ModernDeduplicated => {
+                candidate_doc.save_modern(&mut buffer)?
+            }
+        }
+
+        let is_valid = Document::load_mem(&buffer)
+            .is_ok_and(|reloaded| reloaded.get_pages().len() == page_count);
+        if !is_valid {
+            warn!("The output serialised with the strategy {strategy:?} is not valid");
+            continue;
+        }
+        info!("Strategy {strategy:?}: {} bytes", buffer.len());
+
+        if smallest
+            .as_ref()
+            .is_none_or(|(_strategy, smallest_buffer)| buffer.len() < smallest_buffer.len())
+        {
+            smallest = Some((strategy, buffer));
+        }
+    }
+
+    smallest.ok_or(anyhow!("No serialisation strategy gave a valid output"))
+}
+
+/// Keep a single copy of the streams with the same dictionary and content (e.g. a logo or
+/// a font embedded by several source files), pointing the references to it. Returns the
+/// number of removed copies.
+pub fn deduplicate_streams(doc: &mut Document) -> usize {
+    let mut first_copies: HashMap<(String, Vec<u8>), ObjectId> = HashMap::new();
+    let mut replacements: HashMap<ObjectId, ObjectId> = HashMap::new();
+
+    for (&object_id, object) in &doc.objects {
+        let Object::Stream(stream) = object else {
+            continue;
+        };
+        let key = (
+            format!("{:?}", stream.dict),
+            Sha256::digest(&stream.content).to_vec(),
+        );
+        match first_copies.get(&key) {
+            Some(&first_copy_id) => {
+                replacements.insert(object_id, first_copy_id);
+            }
+            None => {
+                first_copies.insert(key, object_id);
+            }
+        }
+    }
+
+    if replacements.is_empty() {
+        return 0;
+    }
+
+    fn replace_references(object: &mut Object, replacements: &HashMap<ObjectId, ObjectId>) {
+        match object {
+            Object::Reference(id) => {
+                if let Some(&replacement) = replacements.get(id) {
+                    *id = replacement;
+                }
+            }
+            Object::Array(array) => array
+                .iter_mut()
+                .for_each(|child| replace_references(child, replacements)),
+            Object::Dictionary(dict) => dict
+                .iter_mut()
+                .for_each(|(_key, child)| replace_references(child, replacements)),
+            Object::Stream(stream) => stream
+                .dict
+                .iter_mut()
+                .for_each(|(_key, child)| replace_references(child, replacements)),
+            _ => {}
+        }
+    }
+
+    for duplicate_id in replacements.keys() {
+        doc.objects.remove(duplicate_id);
+    }
+    for object in doc.objects.values_mut() {
+        replace_references(object, &replacements);
+    }
+
+    replacements.len()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support;
+
+    #[test]
+    fn compressed_streams_decode_to_the_original() -> Result<()> {
+        let original = test_support::get_basic_pdf_doc("doc_name", 3)?;
+        let mut doc = original.clone();
+
+        compress_streams(&mut doc, CompressionLevel::Best)?;
+
+        for (object_id, object) in &doc.objects {
+            if let Object::Stream(stream) = object
+                && stream.dict.has(b"Filter")
+            {
+                let original_stream = original.get_object(*object_id)?.as_stream()?;
+                assert_eq!(stream.decompressed_content()?, original_stream.content);
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn identical_streams_are_merged() -> Result<()> {
+        let mut doc = test_support::get_basic_pdf_doc("doc_name", 2)?;
+        let page_ids: Vec<ObjectId> = doc.get_pages().into_values().collect();
+
+        let logo_content = b"0 0 10 10 re f".to_vec();
+        for page_id in &page_ids {
+            let logo_id = doc.add_object(lopdf::Stream::new(
+                lopdf::dictionary! {"Type" => "XObject", "Subtype" => "Form"},
+                logo_content.clone(),
+            ));
+            doc.get_object_mut(*page_id)?
+                .as_dict_mut()?
+                .set("Logo", Object::Reference(logo_id));
+        }
+
+        assert_eq!(deduplicate_streams(&mut doc), 1);
+        let logo_ids: Vec<ObjectId> = page_ids
+            .iter()
+            .map(|page_id| Ok(doc.get_dictionary(*page_id)?.get(b"Logo")?.as_reference()?))
+            .collect::<Result<_>>()?;
+        assert_eq!(logo_ids[0], logo_ids[1]);
+        assert!(doc.get_object(logo_ids[0]).is_ok());
+
+        let (_strategy, smallest) = smallest_serialization(&doc)?;
+        assert_eq!(Document::load_mem(&smallest)?.get_pages().len(), 2);
+
+        Ok(())
+    }
+}
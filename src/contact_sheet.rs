@@ -0,0 +1,207 @@
+use crate::page_tree;
+use crate::report::MergeReport;
+use crate::text::transliterate_to_ascii;
+use anyhow::Result;
+use lopdf::content::{Content, Operation};
+use lopdf::{Dictionary, Document, Object, ObjectId, Stream, dictionary};
+
+const SHEET_WIDTH: f32 = 595.0;
+const SHEET_HEIGHT: f32 = 842.0;
+const MARGIN: f32 = 36.0;
+const GAP: f32 = 12.0;
+const COLUMNS: usize = 3;
+const ROWS: usize = 4;
+const CAPTION_HEIGHT: f32 = 14.0;
+const CAPTION_FONT_SIZE: f32 = 8.0;
+/// Rough width of a Helvetica character relative to the font size, to truncate captions.
+const AVERAGE_CHAR_WIDTH: f32 = 0.5;
+const FONT_NAME: &str = "F1";
+
+/// Insert at the beginning of the document pages showing a grid of miniatures of the first
+/// page of each source document, each one captioned with the file name. The miniatures are
+/// the first pages themselves, embedded as Form XObjects and scaled down. Returns the number
+/// of pages added.
+pub fn prepend_contact_sheet(doc: &mut Document, report: &MergeReport) -> Result<u32> {
+    let root_pages_id = page_tree::root_pages_id(doc)?;
+
+    let font_id = doc.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type1",
+        "BaseFont" => "Helvetica",
+    });
+
+    let mut thumbnails = Vec::with_capacity(report.sources.len());
+    for source in &report.sources {
+        let first_page_id = page_tree::page_id_by_number(doc, source.first_page)?;
+        let caption = source
+            .path
+            .file_name()
+            .map(|file_name| file_name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        thumbnails.push((page_as_form_xobject(doc, first_page_id)?, caption));
+    }
+
+    let sheet_ids: Vec<ObjectId> = thumbnails
+        .chunks(COLUMNS * ROWS)
+        .map(|sheet_thumbnails| add_sheet_page(doc, sheet_thumbnails, font_id, root_pages_id))
+        .collect::<Result<_>>()?;
+
+    page_tree::prepend_pages(doc, &sheet_ids)?;
+
+    Ok(sheet_ids.len() as u32)
+}
+
+struct FormXObject {
+    id: ObjectId,
+    bbox: [f32; 4],
+}
+
+fn page_as_form_xobject(doc: &mut Document, page_id: ObjectId) -> Result<FormXObject> {
+    let bbox = page_tree::media_box(doc, page_id);
+    let content = doc.get_page_content(page_id)?;
+
+    let mut form_dict = dictionary! {
+        "Type" => "XObject",
+        "Subtype" => "Form",
+        "BBox" => bbox.iter().map(|&coordinate| Object::Real(coordinate)).collect::<Vec<_>>(),
+    };
+    if let Some(resources) = page_tree::inherited_page_attribute(doc, page_id, b"Resources") {
+        form_dict.set("Resources", resources);
+    }
+
+    let id = doc.add_object(Stream::new(form_dict, content));
+    Ok(FormXObject { id, bbox })
+}
+
+fn add_sheet_page(
+    doc: &mut Document,
+    thumbnails: &[(FormXObject, String)],
+    font_id: ObjectId,
+    root_pages_id: ObjectId,
+) -> Result<ObjectId> {
+    let cell_width = (SHEET_WIDTH - 2.0 * MARGIN - (COLUMNS - 1) as f32 * GAP) / COLUMNS as f32;
+    let cell_height = (SHEET_HEIGHT - 2.0 * MARGIN - (ROWS - 1) as f32 * GAP) / ROWS as f32;
+    let thumbnail_height = cell_height - CAPTION_HEIGHT;
+    let max_caption_chars = (cell_width / (CAPTION_FONT_SIZE * AVERAGE_CHAR_WIDTH)) as usize;
+
+    let mut operations = Vec::new();
+    let mut xobjects = Dictionary::new();
+
+    for (index, (form, caption)) in thumbnails.iter().enumerate() {
+        let xobject_name = format!("Thumb{}", index + 1);
+        xobjects.set(xobject_name.clone(), Object::Reference(form.id));
+
+        let cell_left = MARGIN + (index % COLUMNS) as f32 * (cell_width + GAP);
+        let cell_top = SHEET_HEIGHT - MARGIN - (index / COLUMNS) as f32 * (cell_height + GAP);
+
+        let [llx, lly, urx, ury] = form.bbox;
+        let (form_width, form_height) = ((urx - llx).max(1.0), (ury - lly).max(1.0));
+        let scale = (cell_width / form_width).min(thumbnail_height / form_height);
+        let (width, height) = (form_width * scale, form_height * scale);
+        let left = cell_left + (cell_width - width) / 2.0;
+        let bottom = cell_top - height;
+
+        operations.extend([
+            Operation::new("q", vec![]),
+            Operation::new(
+                "cm",
+                vec![
+                    Object::Real(scale),
+                    0.into(),
+                    0.into(),
+                    Object::Real(scale),
+                    Object::Real(left - llx * scale),
+                    Object::Real(bottom - lly * scale),
+                ],
+            ),
+            Operation::new("Do", vec![Object::Name(xobject_name.into_bytes())]),
+            Operation::new("Q", vec![]),
+            Operation::new("w", vec![Object::Real(0.5)]),
+            Operation::new(
+                "re",
+                vec![
+                    Object::Real(left),
+                    Object::Real(bottom),
+                    Object::Real(width),
+                    Object::Real(height),
+                ],
+            ),
+            Operation::new("S", vec![]),
+        ]);
+
+        let caption: String = transliterate_to_ascii(caption)
+            .chars()
+            .take(max_caption_chars)
+            .collect();
+        operations.extend([
+            Operation::new("BT", vec![]),
+            Operation::new(
+                "Tf",
+                vec![FONT_NAME.into(), Object::Real(CAPTION_FONT_SIZE)],
+            ),
+            Operation::new(
+                "Td",
+                vec![
+                    Object::Real(cell_left),
+                    Object::Real(cell_top - cell_height + CAPTION_FONT_SIZE / 2.0),
+                ],
+            ),
+            Operation::new("Tj", vec![Object::string_literal(caption)]),
+            Operation::new("ET", vec![]),
+        ]);
+    }
+
+    let content_id = doc.add_object(Stream::new(
+        dictionary! {},
+        Content { operations }.encode()?,
+    ));
+
+    Ok(doc.add_object(dictionary! {
+        "Type" => "Page",
+        "Parent" => Object::Reference(root_pages_id),
+        "MediaBox" => vec![0.into(), 0.into(), Object::Real(SHEET_WIDTH), Object::Real(SHEET_HEIGHT)],
+        "Contents" => Object::Reference(content_id),
+        "Resources" => dictionary! {
+            "Font" => dictionary! {
+                FONT_NAME => Object::Reference(font_id),
+            },
+            "XObject" => xobjects,
+        },
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support;
+
+    #[test]
+    fn miniatures_of_the_first_pages() -> Result<()> {
+        let num_sources = COLUMNS * ROWS + 1;
+        let mut doc = test_support::get_basic_pdf_doc("doc", num_sources as u8)?;
+        let mut report = MergeReport::new("archive");
+        for index in 1..=num_sources {
+            report.record_source_with_fingerprint(format!("archive/doc{index}.pdf"), 1, 0, None);
+        }
+
+        assert_eq!(prepend_contact_sheet(&mut doc, &report)?, 2);
+
+        let pages: Vec<ObjectId> = doc.get_pages().into_values().collect();
+        assert_eq!(pages.len(), num_sources + 2);
+        let first_sheet = doc.get_dictionary(pages[0])?;
+        let xobjects = first_sheet
+            .get(b"Resources")?
+            .as_dict()?
+            .get(b"XObject")?
+            .as_dict()?;
+        assert_eq!(xobjects.len(), COLUMNS * ROWS);
+        let first_sheet_content =
+            String::from_utf8_lossy(&doc.get_page_content(pages[0])?).to_string();
+        assert!(first_sheet_content.contains("(doc1.pdf)"));
+        let second_sheet_content =
+            String::from_utf8_lossy(&doc.get_page_content(pages[1])?).to_string();
+        assert!(second_sheet_content.contains(&format!("(doc{num_sources}.pdf)")));
+
+        Ok(())
+    }
+}
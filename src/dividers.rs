@@ -0,0 +1,197 @@
+use crate::outline::OutlineItem;
+use crate::page_tree;
+use crate::report::MergeReport;
+use crate::text::transliterate_to_ascii;
+use anyhow::Result;
+use lopdf::content::{Content, Operation};
+use lopdf::{Document, Object, ObjectId, Stream, dictionary};
+use std::collections::HashMap;
+use std::path::Path;
+
+const MARGIN: f32 = 56.0;
+const MAX_FONT_SIZE: f32 = 28.0;
+const MIN_FONT_SIZE: f32 = 10.0;
+/// Rough width of a Helvetica character relative to the font size, to fit the path.
+const AVERAGE_CHAR_WIDTH: f32 = 0.55;
+const FONT_NAME: &str = "F1";
+
+/// Insert before the content of each directory of the outline items a page showing the
+/// path of the directory (relative to the root of the report) in large text, with the
+/// size of the page following it. The root itself gets no divider. Returns the number of
+/// pages added.
+pub fn insert_divider_pages(
+    doc: &mut Document,
+    items: &[OutlineItem],
+    report: &mut MergeReport,
+) -> Result<u32> {
+    let mut directories = Vec::new();
+    collect_directories(items, &report.root, &mut directories);
+    if directories.is_empty() {
+        return Ok(0);
+    }
+
+    let page_numbers: HashMap<ObjectId, u32> = doc
+        .get_pages()
+        .into_iter()
+        .map(|(page_number, page_id)| (page_id, page_number))
+        .collect();
+
+    let font_id = doc.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type1",
+        "BaseFont" => "Helvetica-Bold",
+        "Encoding" => "WinAnsiEncoding",
+    });
+
+    let mut shifted_page_numbers: Vec<u32> = Vec::with_capacity(directories.len());
+    for (label, next_page_id) in &directories {
+        let media_box = page_tree::media_box(doc, *next_page_id);
+        let divider_id = add_divider_page(doc, label, media_box, font_id)?;
+        // Dividers of nested directories starting on the same page stay in outline order
+        page_tree::insert_page_before(doc, divider_id, *next_page_id)?;
+        shifted_page_numbers.extend(page_numbers.get(next_page_id));
+    }
+
+    // From the last page back, so that the numbers still refer to the pages before the insertions
+    shifted_page_numbers.sort_unstable_by(|number, other_number| other_number.cmp(number));
+    for page_number in shifted_page_numbers {
+        report.shift_pages_from(page_number, 1);
+    }
+
+    Ok(directories.len() as u32)
+}
+
+/// The label and the first page of each directory, in outline order.
+fn collect_directories(
+    items: &[OutlineItem],
+    root: &Path,
+    directories: &mut Vec<(String, ObjectId)>,
+) {
+    for item in items {
+        if item.dest_page.is_some() {
+            continue;
+        }
+        if let (Some(source), Some(first_page_id)) = (&item.source, item.first_page()) {
+            let relative_path = source.strip_prefix(root).unwrap_or(source);
+            if !relative_path.as_os_str().is_empty() {
+                let label = relative_path
+                    .components()
+                    .map(|component| component.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join(" / ");
+                directories.push((transliterate_to_ascii(&label), first_page_id));
+            }
+        }
+        collect_directories(&item.children, root, directories);
+    }
+}
+
+fn add_divider_page(
+    doc: &mut Document,
+    label: &str,
+    media_box: [f32; 4],
+    font_id: ObjectId,
+) -> Result<ObjectId> {
+    let [llx, lly, urx, ury] = media_box;
+    let text_width = (urx - llx - 2.0 * MARGIN).max(MARGIN);
+    let label_chars = label.chars().count().max(1) as f32;
+    let font_size =
+        (text_width / (label_chars * AVERAGE_CHAR_WIDTH)).clamp(MIN_FONT_SIZE, MAX_FONT_SIZE);
+
+    // Keep the end of the path, which names the directory, if it does not fit
+    let max_chars = (text_width / (font_size * AVERAGE_CHAR_WIDTH)) as usize;
+    let label = if label.chars().count() > max_chars {
+        let kept: String = label
+            .chars()
+            .skip(label.chars().count() - max_chars.saturating_sub(3))
+            .collect();
+        format!("...{kept}")
+    } else {
+        label.to_string()
+    };
+
+    let operations = vec![
+        Operation::new("BT", vec![]),
+        Operation::new("Tf", vec![FONT_NAME.into(), Object::Real(font_size)]),
+        Operation::new(
+            "Td",
+            vec![
+                Object::Real(llx + MARGIN),
+                Object::Real(lly + (ury - lly) / 2.0),
+            ],
+        ),
+        Operation::new("Tj", vec![Object::string_literal(label)]),
+        Operation::new("ET", vec![]),
+    ];
+    let content_id = doc.add_object(Stream::new(
+        dictionary! {},
+        Content { operations }.encode()?,
+    ));
+
+    let media_box: Vec<Object> = media_box
+        .iter()
+        .map(|&coordinate| Object::Real(coordinate))
+        .collect();
+    // Explicit attributes, as the parent may have inheritable ones meant for its other pages
+    Ok(doc.add_object(dictionary! {
+        "Type" => "Page",
+        "MediaBox" => media_box.clone(),
+        "CropBox" => media_box,
+        "Rotate" => 0,
+        "Contents" => Object::Reference(content_id),
+        "Resources" => dictionary! {
+            "Font" => dictionary! {
+                FONT_NAME => Object::Reference(font_id),
+            },
+        },
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support;
+
+    #[test]
+    fn dividers_before_the_directories() -> Result<()> {
+        let mut doc = test_support::get_basic_pdf_doc("doc", 3)?;
+        let page_ids: Vec<ObjectId> = doc.get_pages().into_values().collect();
+        let root = Path::new("archive");
+
+        let mut inner_item = OutlineItem::new("inner".to_string(), None)
+            .with_source(root.join("outer").join("inner"));
+        inner_item
+            .children
+            .push(OutlineItem::new("b.pdf".to_string(), Some(page_ids[1])));
+        let mut outer_item =
+            OutlineItem::new("outer".to_string(), None).with_source(root.join("outer"));
+        outer_item.children.push(inner_item);
+        let mut root_item = OutlineItem::new("archive".to_string(), None).with_source(root);
+        root_item.children = vec![
+            OutlineItem::new("a.pdf".to_string(), Some(page_ids[0])),
+            outer_item,
+        ];
+
+        let mut report = MergeReport::new(root);
+        assert_eq!(
+            insert_divider_pages(&mut doc, &[root_item], &mut report)?,
+            2
+        );
+
+        let pages: Vec<ObjectId> = doc.get_pages().into_values().collect();
+        assert_eq!(pages.len(), 5);
+        assert_eq!(pages[0], page_ids[0]);
+        assert_eq!(pages[3], page_ids[1]);
+        let outer_divider = String::from_utf8_lossy(&doc.get_page_content(pages[1])?).to_string();
+        assert!(outer_divider.contains("(outer)"));
+        let inner_divider = String::from_utf8_lossy(&doc.get_page_content(pages[2])?).to_string();
+        assert!(inner_divider.contains("(outer / inner)"));
+        let root_pages_id = page_tree::root_pages_id(&doc)?;
+        assert_eq!(
+            doc.get_dictionary(root_pages_id)?.get(b"Count")?.as_i64()?,
+            5
+        );
+
+        Ok(())
+    }
+}
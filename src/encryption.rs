@@ -0,0 +1,85 @@
+use anyhow::{Result, anyhow};
+use lopdf::encryption::{EncryptionState, EncryptionVersion, Permissions};
+use lopdf::{Document, Object, StringFormat};
+use sha2::{Digest, Sha256};
+use std::time::SystemTime;
+
+/// A permission withheld from the readers of the output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Restriction {
+    /// Printing, in any quality
+    NoPrint,
+    /// Copying or extracting the text and the images (except for accessibility)
+    NoCopy,
+    /// Changing the content and inserting, deleting or rotating pages
+    NoModify,
+}
+
+impl Restriction {
+    fn withheld_permissions(self) -> Permissions {
+        match self {
+            Restriction::NoPrint => Permissions::PRINTABLE | Permissions::PRINTABLE_IN_HIGH_QUALITY,
+            Restriction::NoCopy => Permissions::COPYABLE,
+            Restriction::NoModify => Permissions::MODIFIABLE | Permissions::ASSEMBLABLE,
+        }
+    }
+}
+
+/// Encrypt the document (RC4, 128 bits) with an empty user password, so that it opens
+/// without one, and the permissions left after the restrictions. The owner password,
+/// which would lift them, is random and not kept.
+pub fn encrypt_with_restrictions(doc: &mut Document, restrictions: &[Restriction]) -> Result<()> {
+    let permissions = restrictions
+        .iter()
+        .fold(Permissions::all(), |permissions, restriction| {
+            permissions - restriction.withheld_permissions()
+        });
+
+    let seed = Sha256::new()
+        .chain_update(format!("{:?}", SystemTime::now()))
+        .chain_update(std::process::id().to_le_bytes())
+        .chain_update(doc.max_id.to_le_bytes())
+        .finalize();
+    // The key of the encryption is derived from the first identifier of the file
+    if !doc.trailer.has(b"ID") {
+        let file_id = Object::String(seed[..16].to_vec(), StringFormat::Hexadecimal);
+        doc.trailer.set("ID", vec![file_id.clone(), file_id]);
+    }
+    let owner_password: String = seed[16..]
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+
+    let encryption_state = EncryptionState::try_from(EncryptionVersion::V2 {
+        document: doc,
+        owner_password: &owner_password,
+        user_password: "",
+        key_length: 128,
+        permissions,
+    })
+    .map_err(|err| anyhow!("The encryption of the output could not be set up: {err}"))?;
+    doc.encrypt(&encryption_state)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support;
+
+    #[test]
+    fn restrictions_clear_the_permission_bits() -> Result<()> {
+        let mut doc = test_support::get_basic_pdf_doc("doc", 2)?;
+        encrypt_with_restrictions(&mut doc, &[Restriction::NoPrint, Restriction::NoCopy])?;
+
+        assert!(doc.is_encrypted());
+        let (_, encrypt_dict) = doc.dereference(doc.trailer.get(b"Encrypt")?)?;
+        let permission_bits = encrypt_dict.as_dict()?.get(b"P")?.as_i64()?;
+        assert_eq!(permission_bits & (1 << 2), 0);
+        assert_eq!(permission_bits & (1 << 4), 0);
+        assert_ne!(permission_bits & (1 << 3), 0);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,90 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Why a merge failed, for the callers of the library which react differently to each
+/// class of failure. Inside the crate errors travel as `anyhow::Error` and are classified
+/// when they leave it.
+#[derive(Debug, thiserror::Error)]
+pub enum MergeError {
+    #[error(
+        "The document '{path}' contains the non supported feature '{feature}' among the Catalog children"
+    )]
+    UnsupportedCatalogFeature { path: PathBuf, feature: String },
+    #[error("The document '{path}' has 0 pages")]
+    EmptyDocument { path: PathBuf },
+    #[error(
+        "The document '{path}' is digitally signed: merging it would invalidate its signatures"
+    )]
+    SignedDocument { path: PathBuf },
+    #[error(
+        "The document '{path}' has form fields besides its signatures, which are not supported"
+    )]
+    FormFieldsLeft { path: PathBuf },
+    #[error("The Outlines object for the document obtained is empty")]
+    EmptyOutline,
+    #[error("A file '{path}' is already present")]
+    OutputExists { path: PathBuf },
+    #[error(transparent)]
+    ParseTimeout(#[from] ParseTimeout),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid PDF: {0}")]
+    Pdf(#[from] lopdf::Error),
+    #[error(transparent)]
+    Other(anyhow::Error),
+}
+
+impl From<anyhow::Error> for MergeError {
+    fn from(err: anyhow::Error) -> Self {
+        let err = match err.downcast::<MergeError>() {
+            Ok(merge_error) => return merge_error,
+            Err(err) => err,
+        };
+        let err = match err.downcast::<ParseTimeout>() {
+            Ok(parse_timeout) => return MergeError::ParseTimeout(parse_timeout),
+            Err(err) => err,
+        };
+        let err = match err.downcast::<std::io::Error>() {
+            Ok(io_error) => return MergeError::Io(io_error),
+            Err(err) => err,
+        };
+        match err.downcast::<lopdf::Error>() {
+            Ok(pdf_error) => MergeError::Pdf(pdf_error),
+            Err(err) => MergeError::Other(err),
+        }
+    }
+}
+
+/// The parsing of a file took longer than the timeout.
+#[derive(Debug, thiserror::Error)]
+#[error("The parsing of '{path}' took longer than {timeout:?}, therefore the file is skipped")]
+pub struct ParseTimeout {
+    pub path: PathBuf,
+    pub timeout: Duration,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[test]
+    fn errors_are_classified() {
+        let empty_document: anyhow::Error = MergeError::EmptyDocument {
+            path: PathBuf::from("empty.pdf"),
+        }
+        .into();
+        assert!(matches!(
+            MergeError::from(empty_document),
+            MergeError::EmptyDocument { .. }
+        ));
+
+        let io_error: anyhow::Error = std::io::Error::from(std::io::ErrorKind::NotFound).into();
+        assert!(matches!(MergeError::from(io_error), MergeError::Io(_)));
+
+        assert!(matches!(
+            MergeError::from(anyhow!("something else")),
+            MergeError::Other(_)
+        ));
+    }
+}
@@ -0,0 +1,223 @@
+use anyhow::Result;
+use lopdf::content::Content;
+use lopdf::{Document, Object, ObjectId, dictionary};
+
+const IDENTITY_MATRIX: [f32; 6] = [1.0, 0.0, 0.0, 1.0, 0.0, 0.0];
+const HIGHLIGHT_COLOR_RGB: [f32; 3] = [1.0, 1.0, 0.0];
+/// Rough width of a character relative to the font size, to place the highlights.
+const AVERAGE_CHAR_WIDTH: f32 = 0.5;
+/// Portion of the font size below the baseline covered by the highlight.
+const DESCENT: f32 = 0.2;
+
+/// The pages of the document containing the term, with the number of occurrences.
+#[derive(Debug, Clone)]
+pub struct SearchHit {
+    pub page_id: ObjectId,
+    pub page_number: u32,
+    pub occurrences: usize,
+}
+
+/// Search the term (case-insensitively) in the text shown by the pages and add a highlight
+/// annotation on every occurrence.
+///
+/// The position of the occurrences is estimated from the text state operators of the content
+/// streams, without reading the metrics of the fonts and ignoring the transformations of the
+/// graphics state: the highlights are therefore approximate, especially for proportional fonts.
+pub fn highlight_term(doc: &mut Document, term: &str) -> Result<Vec<SearchHit>> {
+    let term: Vec<char> = term.chars().collect();
+    let mut hits = Vec::new();
+
+    if term.is_empty() {
+        return Ok(hits);
+    }
+
+    for (page_number, page_id) in doc.get_pages() {
+        let content = Content::decode(&doc.get_page_content(page_id)?)?;
+        let rects = find_term_rects(&content, &term);
+
+        if rects.is_empty() {
+            continue;
+        }
+
+        for rect in &rects {
+            add_highlight_annotation(doc, page_id, *rect, &term)?;
+        }
+        hits.push(SearchHit {
+            page_id,
+            page_number,
+            occurrences: rects.len(),
+        });
+    }
+
+    Ok(hits)
+}
+
+/// Follow the text state of the content stream, returning the estimated rectangle
+/// (`[llx, lly, urx, ury]`) of every occurrence of the term in the shown strings.
+fn find_term_rects(content: &Content, term: &[char]) -> Vec<[f32; 4]> {
+    let mut rects = Vec::new();
+    let mut text_matrix = IDENTITY_MATRIX;
+    let mut line_matrix = IDENTITY_MATRIX;
+    let mut font_size = 0.0;
+    let mut leading = 0.0;
+
+    let operand = |operands: &[Object], index: usize| -> f32 {
+        operands
+            .get(index)
+            .and_then(crate::page_tree::object_to_f32)
+            .unwrap_or(0.0)
+    };
+
+    for operation in &content.operations {
+        let operands = operation.operands.as_slice();
+        let mut shown_strings: Vec<&[u8]> = Vec::new();
+
+        match operation.operator.as_str() {
+            "BT" => {
+                text_matrix = IDENTITY_MATRIX;
+                line_matrix = IDENTITY_MATRIX;
+            }
+            "Tf" => font_size = operand(operands, 1),
+            "TL" => leading = operand(operands, 0),
+            "Td" | "TD" => {
+                if operation.operator == "TD" {
+                    leading = -operand(operands, 1);
+                }
+                line_matrix = translate(line_matrix, operand(operands, 0), operand(operands, 1));
+                text_matrix = line_matrix;
+            }
+            "Tm" => {
+                line_matrix = [0, 1, 2, 3, 4, 5].map(|index| operand(operands, index));
+                text_matrix = line_matrix;
+            }
+            "T*" => {
+                line_matrix = translate(line_matrix, 0.0, -leading);
+                text_matrix = line_matrix;
+            }
+            "Tj" | "'" | "\"" => {
+                if operation.operator != "Tj" {
+                    line_matrix = translate(line_matrix, 0.0, -leading);
+                    text_matrix = line_matrix;
+                }
+                if let Some(Ok(string)) = operands.last().map(Object::as_str) {
+                    shown_strings.push(string);
+                }
+            }
+            "TJ" => {
+                if let Some(Ok(array)) = operands.first().map(Object::as_array) {
+                    shown_strings.extend(array.iter().filter_map(|item| item.as_str().ok()));
+                }
+            }
+            _ => {}
+        }
+
+        for string in shown_strings {
+            let effective_size = font_size * text_matrix[3].abs().max(text_matrix[0].abs());
+            let char_width = effective_size * AVERAGE_CHAR_WIDTH;
+            let (x, y) = (text_matrix[4], text_matrix[5]);
+
+            // Simple fonts map one byte to one glyph; Latin-1 is the best guess without the encoding.
+            let text: Vec<char> = string.iter().map(|&byte| byte as char).collect();
+            for offset in find_occurrences(&text, term) {
+                let left = x + offset as f32 * char_width;
+                rects.push([
+                    left,
+                    y - DESCENT * effective_size,
+                    left + term.len() as f32 * char_width,
+                    y + (1.0 - DESCENT) * effective_size,
+                ]);
+            }
+
+            text_matrix[4] += text.len() as f32 * char_width;
+        }
+    }
+
+    rects
+}
+
+fn translate(matrix: [f32; 6], tx: f32, ty: f32) -> [f32; 6] {
+    let [a, b, c, d, e, f] = matrix;
+    [a, b, c, d, tx * a + ty * c + e, tx * b + ty * d + f]
+}
+
+/// The offsets of the case-insensitive occurrences of the term in the text.
+fn find_occurrences(text: &[char], term: &[char]) -> Vec<usize> {
+    if term.len() > text.len() {
+        return Vec::new();
+    }
+
+    (0..=text.len() - term.len())
+        .filter(|&offset| {
+            text[offset..offset + term.len()]
+                .iter()
+                .zip(term)
+                .all(|(text_char, term_char)| text_char.to_lowercase().eq(term_char.to_lowercase()))
+        })
+        .collect()
+}
+
+fn add_highlight_annotation(
+    doc: &mut Document,
+    page_id: ObjectId,
+    rect: [f32; 4],
+    term: &[char],
+) -> Result<()> {
+    let [llx, lly, urx, ury] = rect;
+
+    let annotation_id = doc.add_object(dictionary! {
+        "Type" => "Annot",
+        "Subtype" => "Highlight",
+        "P" => Object::Reference(page_id),
+        "Rect" => rect.iter().map(|&coordinate| Object::Real(coordinate)).collect::<Vec<_>>(),
+        "QuadPoints" => [llx, ury, urx, ury, llx, lly, urx, lly]
+            .iter()
+            .map(|&coordinate| Object::Real(coordinate))
+            .collect::<Vec<_>>(),
+        "C" => HIGHLIGHT_COLOR_RGB.iter().map(|&c| Object::Real(c)).collect::<Vec<_>>(),
+        "Contents" => Object::string_literal(term.iter().collect::<String>()),
+    });
+
+    append_annotation(doc, page_id, annotation_id)
+}
+
+/// Add the annotation to the `/Annots` of the page, which may be missing, inline or indirect.
+pub fn append_annotation(
+    doc: &mut Document,
+    page_id: ObjectId,
+    annotation_id: ObjectId,
+) -> Result<()> {
+    let annots = doc.get_dictionary(page_id)?.get(b"Annots").ok().cloned();
+
+    match annots {
+        Some(Object::Reference(annots_id)) => {
+            doc.get_object_mut(annots_id)?
+                .as_array_mut()?
+                .push(Object::Reference(annotation_id));
+        }
+        Some(Object::Array(mut annots)) => {
+            annots.push(Object::Reference(annotation_id));
+            doc.get_object_mut(page_id)?
+                .as_dict_mut()?
+                .set("Annots", annots);
+        }
+        _ => {
+            doc.get_object_mut(page_id)?
+                .as_dict_mut()?
+                .set("Annots", vec![Object::Reference(annotation_id)]);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn occurrences_are_case_insensitive() {
+        let text: Vec<char> = "Theorem 1, see theorem 2".chars().collect();
+        let term: Vec<char> = "THEOREM".chars().collect();
+        assert_eq!(find_occurrences(&text, &term), vec![0, 15]);
+    }
+}
@@ -0,0 +1,112 @@
+use anyhow::{Result, anyhow};
+use log::info;
+use std::process::Command;
+
+/// Run the command line of the template after filling its `{name}` placeholders. The
+/// template is split into arguments before the substitution, as a shell would split it
+/// (with single and double quotes), so that the values never need quoting; no shell is
+/// involved. Fails if the command cannot be started or exits unsuccessfully.
+pub fn run_command_template(template: &str, placeholders: &[(&str, String)]) -> Result<()> {
+    let arguments = split_command_line(template)?
+        .into_iter()
+        .map(|argument| fill_placeholders(&argument, placeholders))
+        .collect::<Result<Vec<_>>>()?;
+    let (program, arguments) = arguments
+        .split_first()
+        .ok_or(anyhow!("The command '{template}' is empty"))?;
+
+    info!("Run the command '{program}' with the arguments {arguments:?}");
+    let status = Command::new(program)
+        .args(arguments)
+        .status()
+        .map_err(|err| anyhow!("The command '{program}' could not be started: {err}"))?;
+
+    if !status.success() {
+        return Err(anyhow!("The command '{program}' failed ({status})"));
+    }
+    Ok(())
+}
+
+/// Split the command line into arguments at the unquoted whitespace, removing the quotes.
+fn split_command_line(command_line: &str) -> Result<Vec<String>> {
+    let mut arguments = Vec::new();
+    let mut argument: Option<String> = None;
+    let mut quote: Option<char> = None;
+
+    for c in command_line.chars() {
+        match (quote, c) {
+            (Some(open_quote), c) if c == open_quote => quote = None,
+            (Some(_), c) => argument.get_or_insert_default().push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                argument.get_or_insert_default();
+            }
+            (None, c) if c.is_whitespace() => arguments.extend(argument.take()),
+            (None, c) => argument.get_or_insert_default().push(c),
+        }
+    }
+
+    if let Some(open_quote) = quote {
+        return Err(anyhow!(
+            "The quote {open_quote} of the command '{command_line}' is not closed"
+        ));
+    }
+    arguments.extend(argument);
+
+    Ok(arguments)
+}
+
+fn fill_placeholders(argument: &str, placeholders: &[(&str, String)]) -> Result<String> {
+    let mut filled = String::with_capacity(argument.len());
+    let mut rest = argument;
+
+    while let Some(start) = rest.find('{') {
+        filled.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find('}').map(|end| end + start) else {
+            break;
+        };
+
+        let name = &rest[start + 1..end];
+        let value = placeholders
+            .iter()
+            .find(|(placeholder, _value)| *placeholder == name)
+            .map(|(_placeholder, value)| value)
+            .ok_or(anyhow!(
+                "Unknown placeholder '{{{name}}}' in the command (available: {})",
+                placeholders
+                    .iter()
+                    .map(|(placeholder, _value)| format!("{{{placeholder}}}"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ))?;
+        filled.push_str(value);
+        rest = &rest[end + 1..];
+    }
+    filled.push_str(rest);
+
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn split_and_fill_command_lines() -> Result<()> {
+        let arguments = split_command_line(r#"rclone copy  "{output}" 'remote:my archives/'"#)?;
+        assert_eq!(
+            arguments,
+            vec!["rclone", "copy", "{output}", "remote:my archives/"]
+        );
+
+        let placeholders = [("output", "lectures united.pdf".to_string())];
+        assert_eq!(
+            fill_placeholders("{output}", &placeholders)?,
+            "lectures united.pdf"
+        );
+        assert!(fill_placeholders("{pages}", &placeholders).is_err());
+        assert!(split_command_line("echo 'unclosed").is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,128 @@
+use crate::utils;
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+
+/// Name of the files listing, in gitignore style, the entries of their directory (and
+/// of its subdirectories) to leave out of the merge.
+pub const IGNORE_FILE_NAME: &str = ".pdfuniteignore";
+
+/// The rules of the ignore files found from the input directory down to the directory
+/// being merged. As with gitignore, the last rule matching a path decides whether it is
+/// ignored, so that the rules of a subdirectory can override (with `!`) the ones above.
+#[derive(Debug, Clone, Default)]
+pub struct IgnoreRules {
+    rules: Vec<IgnoreRule>,
+}
+
+#[derive(Debug, Clone)]
+struct IgnoreRule {
+    /// The directory of the ignore file, to which the pattern is relative.
+    base_dir: PathBuf,
+    /// Path glob (see `utils::matches_path_glob`).
+    pattern: String,
+    negated: bool,
+    directory_only: bool,
+}
+
+impl IgnoreRules {
+    /// These rules followed by the ones of the ignore file of the directory, if any.
+    pub fn with_directory(&self, directory: impl AsRef<Path>) -> Result<IgnoreRules> {
+        let directory = directory.as_ref();
+        let ignore_file_path = directory.join(IGNORE_FILE_NAME);
+
+        if !std::fs::exists(&ignore_file_path)? {
+            return Ok(self.clone());
+        }
+        let content = std::fs::read_to_string(&ignore_file_path)?;
+
+        Ok(self.with_content(directory, &content))
+    }
+
+    /// These rules followed by the ones of an ignore file of the directory with the content,
+    /// e.g. read from an archive.
+    pub fn with_content(&self, directory: impl AsRef<Path>, content: &str) -> IgnoreRules {
+        let mut rules = self.clone();
+        rules.rules.extend(parse_rules(directory.as_ref(), content));
+        rules
+    }
+
+    /// Whether the path (of a file or of a directory, which is then skipped with all its
+    /// content) is excluded by the rules.
+    pub fn is_ignored(&self, path: impl AsRef<Path>, is_dir: bool) -> bool {
+        let path = path.as_ref();
+
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| {
+                (is_dir || !rule.directory_only)
+                    && path
+                        .strip_prefix(&rule.base_dir)
+                        .is_ok_and(|relative_path| {
+                            utils::matches_path_glob(&rule.pattern, relative_path)
+                        })
+            })
+            .is_some_and(|rule| !rule.negated)
+    }
+}
+
+/// Parse the lines of an ignore file: blank lines and `#` comments are skipped, `!`
+/// re-includes what an earlier rule ignored, a trailing `/` restricts the rule to
+/// directories and a pattern without `/` (other than a trailing one) matches at any depth.
+fn parse_rules(base_dir: &Path, content: &str) -> Vec<IgnoreRule> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let (negated, line) = match line.strip_prefix('!') {
+                Some(line) => (true, line),
+                None => (false, line.strip_prefix('\\').unwrap_or(line)),
+            };
+            let (directory_only, line) = match line.strip_suffix('/') {
+                Some(line) => (true, line),
+                None => (false, line),
+            };
+            let pattern = if line.contains('/') {
+                line.trim_start_matches('/').to_string()
+            } else {
+                format!("**/{line}")
+            };
+
+            IgnoreRule {
+                base_dir: base_dir.to_path_buf(),
+                pattern,
+                negated,
+                directory_only,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn gitignore_style_rules() {
+        let root = Path::new("archive");
+        let rules = IgnoreRules {
+            rules: parse_rules(
+                root,
+                "# scratch material\n\
+                 drafts/\n\
+                 *-old.pdf\n\
+                 !keep-old.pdf\n\
+                 /notes.pdf\n",
+            ),
+        };
+
+        assert!(rules.is_ignored(root.join("2024").join("drafts"), true));
+        assert!(!rules.is_ignored(root.join("2024").join("drafts"), false));
+        assert!(rules.is_ignored(root.join("2024").join("report-old.pdf"), false));
+        assert!(!rules.is_ignored(root.join("2024").join("keep-old.pdf"), false));
+        assert!(rules.is_ignored(root.join("notes.pdf"), false));
+        assert!(!rules.is_ignored(root.join("2024").join("notes.pdf"), false));
+        assert!(!rules.is_ignored(Path::new("elsewhere").join("report-old.pdf"), false));
+    }
+}
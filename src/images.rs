@@ -0,0 +1,389 @@
+use anyhow::{Result, anyhow};
+use lopdf::content::{Content, Operation};
+use lopdf::{Dictionary, Document, Object, ObjectId, Stream, dictionary};
+use std::path::{Path, PathBuf};
+
+const IMAGE_EXTENSIONS: [&str; 3] = ["jpg", "jpeg", "png"];
+/// Resolution assumed for the images which do not declare one.
+const DEFAULT_DPI: f32 = 72.0;
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+const INCHES_PER_METRE: f32 = 39.370_08;
+
+pub fn is_image_file(path: impl AsRef<Path>) -> bool {
+    path.as_ref()
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_ascii_lowercase())
+        .is_some_and(|extension| IMAGE_EXTENSIONS.contains(&extension.as_str()))
+}
+
+/// The images of the directory, sorted naturally, if it contains only (and at least one)
+/// image files.
+pub fn image_dir_contents(dir_path: impl AsRef<Path>) -> Result<Option<Vec<PathBuf>>> {
+    let mut image_paths = Vec::new();
+
+    for entry in std::fs::read_dir(dir_path.as_ref())? {
+        let entry = entry?;
+        if !entry.file_type()?.is_file() || !is_image_file(entry.path()) {
+            return Ok(None);
+        }
+        image_paths.push(entry.path());
+    }
+
+    if image_paths.is_empty() {
+        return Ok(None);
+    }
+
+    image_paths.sort_by(|path, other_path| {
+        crate::utils::natural_cmp(
+            &path.file_name().unwrap_or_default().to_string_lossy(),
+            &other_path.file_name().unwrap_or_default().to_string_lossy(),
+        )
+    });
+    Ok(Some(image_paths))
+}
+
+/// A document with one page per image, each page as large as the image at its resolution.
+/// JPEG files are embedded as they are; PNG files only if they are not interlaced and have
+/// no alpha channel.
+pub fn images_to_document(image_paths: &[impl AsRef<Path>]) -> Result<Document> {
+    let mut doc = Document::with_version("1.7");
+    let pages_root_id = doc.new_object_id();
+
+    let page_ids = image_paths
+        .iter()
+        .map(|image_path| {
+            let image_path = image_path.as_ref();
+            let content = std::fs::read(image_path)?;
+            let image = parse_image(&content).map_err(|err| {
+                anyhow!(
+                    "Could not embed the image '{}': {err}",
+                    image_path.display()
+                )
+            })?;
+            add_image_page(&mut doc, image, content, pages_root_id)
+        })
+        .collect::<Result<Vec<ObjectId>>>()?;
+
+    doc.objects.insert(
+        pages_root_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => page_ids.iter().map(|&page_id| page_id.into()).collect::<Vec<Object>>(),
+            "Count" => page_ids.len() as i64,
+        }),
+    );
+
+    let catalog_id = doc.add_object(dictionary! {
+        "Type" => "Catalog",
+        "Pages" => pages_root_id,
+    });
+    doc.trailer.set("Root", catalog_id);
+
+    Ok(doc)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct ImageInfo {
+    width: u32,
+    height: u32,
+    /// Horizontal and vertical resolution, if declared.
+    dpi: Option<(f32, f32)>,
+    encoding: ImageEncoding,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum ImageEncoding {
+    Jpeg {
+        components: u8,
+    },
+    Png {
+        bit_depth: u8,
+        colors: u8,
+        /// The PLTE chunk of indexed images.
+        palette: Option<Vec<u8>>,
+        /// The concatenated IDAT chunks, a zlib stream with the PNG predictors.
+        data: Vec<u8>,
+    },
+}
+
+fn parse_image(content: &[u8]) -> Result<ImageInfo> {
+    if content.starts_with(&PNG_SIGNATURE) {
+        parse_png(content)
+    } else if content.starts_with(&[0xff, 0xd8]) {
+        parse_jpeg(content)
+    } else {
+        Err(anyhow!("Only JPEG and PNG images are supported"))
+    }
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Result<u16> {
+    Ok(u16::from_be_bytes(
+        bytes
+            .get(offset..offset + 2)
+            .ok_or(anyhow!("Truncated image"))?
+            .try_into()?,
+    ))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32> {
+    Ok(u32::from_be_bytes(
+        bytes
+            .get(offset..offset + 4)
+            .ok_or(anyhow!("Truncated image"))?
+            .try_into()?,
+    ))
+}
+
+fn parse_jpeg(content: &[u8]) -> Result<ImageInfo> {
+    let mut offset = 2;
+    let mut dpi = None;
+
+    while offset + 4 <= content.len() {
+        if content[offset] != 0xff {
+            return Err(anyhow!("Malformed JPEG marker at byte {offset}"));
+        }
+        let marker = content[offset + 1];
+        let segment_len = read_u16(content, offset + 2)? as usize;
+        let segment = content
+            .get(offset + 4..offset + 2 + segment_len)
+            .ok_or(anyhow!("Truncated image"))?;
+
+        match marker {
+            // APP0 with the JFIF resolution: units (1 = dots per inch, 2 = per cm), x, y
+            0xe0 if segment.starts_with(b"JFIF\0") && segment.len() >= 12 => {
+                let (x_density, y_density) =
+                    (read_u16(segment, 8)? as f32, read_u16(segment, 10)? as f32);
+                dpi = match segment[7] {
+                    1 if x_density > 0.0 && y_density > 0.0 => Some((x_density, y_density)),
+                    2 if x_density > 0.0 && y_density > 0.0 => {
+                        Some((x_density * 2.54, y_density * 2.54))
+                    }
+                    _ => None,
+                };
+            }
+            // Start of frame (except the DHT, JPG and DAC markers sharing the range)
+            0xc0..=0xcf if !matches!(marker, 0xc4 | 0xc8 | 0xcc) => {
+                return Ok(ImageInfo {
+                    height: read_u16(segment, 1)? as u32,
+                    width: read_u16(segment, 3)? as u32,
+                    dpi,
+                    encoding: ImageEncoding::Jpeg {
+                        components: *segment.get(5).ok_or(anyhow!("Truncated image"))?,
+                    },
+                });
+            }
+            _ => {}
+        }
+
+        offset += 2 + segment_len;
+    }
+
+    Err(anyhow!("No frame header found in the JPEG image"))
+}
+
+fn parse_png(content: &[u8]) -> Result<ImageInfo> {
+    let mut offset = PNG_SIGNATURE.len();
+    let mut header = None;
+    let mut dpi = None;
+    let mut palette = None;
+    let mut data = Vec::new();
+
+    while offset + 8 <= content.len() {
+        let chunk_len = read_u32(content, offset)? as usize;
+        let chunk_type = &content[offset + 4..offset + 8];
+        let chunk = content
+            .get(offset + 8..offset + 8 + chunk_len)
+            .ok_or(anyhow!("Truncated image"))?;
+
+        match chunk_type {
+            b"IHDR" => header = Some(chunk),
+            b"PLTE" => palette = Some(chunk.to_vec()),
+            b"IDAT" => data.extend_from_slice(chunk),
+            // Pixels per unit (1 = metre) in the two directions
+            b"pHYs" if chunk.len() >= 9 && chunk[8] == 1 => {
+                let (x_ppm, y_ppm) = (read_u32(chunk, 0)? as f32, read_u32(chunk, 4)? as f32);
+                if x_ppm > 0.0 && y_ppm > 0.0 {
+                    dpi = Some((x_ppm / INCHES_PER_METRE, y_ppm / INCHES_PER_METRE));
+                }
+            }
+            b"IEND" => break,
+            _ => {}
+        }
+
+        // length, type, data and CRC
+        offset += 12 + chunk_len;
+    }
+
+    let header = header.ok_or(anyhow!("No IHDR chunk found in the PNG image"))?;
+    let (bit_depth, color_type) = (
+        *header.get(8).ok_or(anyhow!("Truncated image"))?,
+        *header.get(9).ok_or(anyhow!("Truncated image"))?,
+    );
+    if header.get(12) != Some(&0) {
+        return Err(anyhow!("Interlaced PNG images are not supported"));
+    }
+    let colors = match color_type {
+        0 | 3 => 1,
+        2 => 3,
+        _ => return Err(anyhow!("PNG images with alpha channel are not supported")),
+    };
+    if color_type == 3 && palette.is_none() {
+        return Err(anyhow!("Indexed PNG image without palette"));
+    }
+
+    Ok(ImageInfo {
+        width: read_u32(header, 0)?,
+        height: read_u32(header, 4)?,
+        dpi,
+        encoding: ImageEncoding::Png {
+            bit_depth,
+            colors,
+            palette: palette.filter(|_| color_type == 3),
+            data,
+        },
+    })
+}
+
+fn add_image_page(
+    doc: &mut Document,
+    image: ImageInfo,
+    content: Vec<u8>,
+    pages_root_id: ObjectId,
+) -> Result<ObjectId> {
+    let mut image_dict = dictionary! {
+        "Type" => "XObject",
+        "Subtype" => "Image",
+        "Width" => image.width as i64,
+        "Height" => image.height as i64,
+    };
+
+    let image_data = match image.encoding {
+        ImageEncoding::Jpeg { components } => {
+            let color_space = match components {
+                1 => "DeviceGray",
+                4 => "DeviceCMYK",
+                _ => "DeviceRGB",
+            };
+            image_dict.set("ColorSpace", color_space);
+            image_dict.set("BitsPerComponent", 8);
+            image_dict.set("Filter", "DCTDecode");
+            content
+        }
+        ImageEncoding::Png {
+            bit_depth,
+            colors,
+            palette,
+            data,
+        } => {
+            let color_space: Object = match (palette, colors) {
+                (Some(palette), _) => vec![
+                    "Indexed".into(),
+                    "DeviceRGB".into(),
+                    Object::Integer((palette.len() / 3) as i64 - 1),
+                    Object::String(palette, lopdf::StringFormat::Hexadecimal),
+                ]
+                .into(),
+                (None, 1) => "DeviceGray".into(),
+                (None, _) => "DeviceRGB".into(),
+            };
+            image_dict.set("ColorSpace", color_space);
+            image_dict.set("BitsPerComponent", bit_depth as i64);
+            image_dict.set("Filter", "FlateDecode");
+            image_dict.set(
+                "DecodeParms",
+                dictionary! {
+                    "Predictor" => 15,
+                    "Colors" => colors as i64,
+                    "BitsPerComponent" => bit_depth as i64,
+                    "Columns" => image.width as i64,
+                },
+            );
+            data
+        }
+    };
+
+    let mut image_stream = Stream::new(image_dict, image_data);
+    image_stream.allows_compression = false;
+    let image_id = doc.add_object(image_stream);
+
+    let (x_dpi, y_dpi) = image.dpi.unwrap_or((DEFAULT_DPI, DEFAULT_DPI));
+    let width = image.width as f32 * 72.0 / x_dpi;
+    let height = image.height as f32 * 72.0 / y_dpi;
+
+    let operations = vec![
+        Operation::new("q", vec![]),
+        Operation::new(
+            "cm",
+            vec![
+                Object::Real(width),
+                0.into(),
+                0.into(),
+                Object::Real(height),
+                0.into(),
+                0.into(),
+            ],
+        ),
+        Operation::new("Do", vec!["Im1".into()]),
+        Operation::new("Q", vec![]),
+    ];
+    let content_id = doc.add_object(Stream::new(
+        dictionary! {},
+        Content { operations }.encode()?,
+    ));
+
+    let mut xobjects = Dictionary::new();
+    xobjects.set("Im1", image_id);
+
+    Ok(doc.add_object(dictionary! {
+        "Type" => "Page",
+        "Parent" => pages_root_id,
+        "MediaBox" => vec![0.into(), 0.into(), Object::Real(width), Object::Real(height)],
+        "Contents" => content_id,
+        "Resources" => dictionary! {
+            "XObject" => xobjects,
+        },
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn png_header_and_resolution() -> Result<()> {
+        let chunk = |chunk_type: &[u8], data: &[u8]| {
+            let mut chunk = (data.len() as u32).to_be_bytes().to_vec();
+            chunk.extend_from_slice(chunk_type);
+            chunk.extend_from_slice(data);
+            // The CRC is not verified
+            chunk.extend_from_slice(&[0; 4]);
+            chunk
+        };
+
+        let mut header = Vec::new();
+        header.extend_from_slice(&2u32.to_be_bytes());
+        header.extend_from_slice(&1u32.to_be_bytes());
+        header.extend_from_slice(&[8, 2, 0, 0, 0]);
+        let mut physical = Vec::new();
+        physical.extend_from_slice(&11_811u32.to_be_bytes());
+        physical.extend_from_slice(&11_811u32.to_be_bytes());
+        physical.push(1);
+
+        let mut png = PNG_SIGNATURE.to_vec();
+        png.extend(chunk(b"IHDR", &header));
+        png.extend(chunk(b"pHYs", &physical));
+        png.extend(chunk(b"IDAT", b"data"));
+        png.extend(chunk(b"IEND", b""));
+
+        let image = parse_image(&png)?;
+        assert_eq!((image.width, image.height), (2, 1));
+        let (x_dpi, _y_dpi) = image.dpi.ok_or(anyhow!("No resolution"))?;
+        assert!((x_dpi - 300.0).abs() < 0.1);
+        assert!(matches!(
+            image.encoding,
+            ImageEncoding::Png { colors: 3, ref data, .. } if data == b"data"
+        ));
+
+        Ok(())
+    }
+}
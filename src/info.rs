@@ -0,0 +1,266 @@
+use crate::outline::read_outline;
+use crate::page_sizes::page_format_counts;
+use crate::text::{TitleEncoding, decode_text_string, encode_text_string};
+use anyhow::Result;
+use lazy_static::lazy_static;
+use lopdf::{Dictionary, Document, Object};
+use regex::Regex;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+
+lazy_static! {
+    static ref XMP_TITLE: Regex =
+        Regex::new(r"(?s)<dc:title>.*?<rdf:li[^>]*>(.*?)</rdf:li>").unwrap();
+}
+
+/// Summary of a PDF document, with the fields usually read from `pdfinfo`.
+#[derive(Debug, Clone, Serialize)]
+pub struct PdfInfo {
+    pub version: String,
+    pub page_count: u32,
+    pub encrypted: bool,
+    /// The text entries of the Info dictionary (Title, Author, Producer, ...).
+    pub metadata: BTreeMap<String, String>,
+    /// The XMP metadata stream of the catalog, if any.
+    pub xmp_metadata: Option<String>,
+    /// Number of pages for each format (e.g. "A4 portrait").
+    pub page_formats: BTreeMap<String, u32>,
+    pub outline_item_count: usize,
+}
+
+pub fn pdf_info(doc: &Document) -> Result<PdfInfo> {
+    let mut metadata = BTreeMap::new();
+    if let Ok((_id, Object::Dictionary(info))) = doc
+        .trailer
+        .get(b"Info")
+        .and_then(|info| doc.dereference(info))
+    {
+        for (key, value) in info.iter() {
+            if let Ok((_id, Object::String(bytes, _format))) = doc.dereference(value) {
+                metadata.insert(
+                    String::from_utf8_lossy(key).to_string(),
+                    decode_text_string(bytes),
+                );
+            }
+        }
+    }
+
+    let xmp_metadata = doc
+        .catalog()?
+        .get(b"Metadata")
+        .and_then(|metadata| doc.dereference(metadata))
+        .and_then(|(_id, metadata)| metadata.as_stream())
+        .ok()
+        .map(|stream| {
+            let content = stream
+                .decompressed_content()
+                .unwrap_or_else(|_| stream.content.clone());
+            String::from_utf8_lossy(&content).to_string()
+        });
+
+    Ok(PdfInfo {
+        version: doc.version.clone(),
+        page_count: doc.get_pages().len() as u32,
+        encrypted: doc.trailer.has(b"Encrypt"),
+        metadata,
+        xmp_metadata,
+        page_formats: page_format_counts(doc),
+        outline_item_count: read_outline(doc)?.len(),
+    })
+}
+
+/// The title of the document in its metadata: the Title of the Info dictionary, else the
+/// `dc:title` of the XMP metadata. `None` if both are missing or blank.
+pub fn document_title(doc: &Document) -> Option<String> {
+    let info_title = doc
+        .trailer
+        .get(b"Info")
+        .and_then(|info| doc.dereference(info))
+        .and_then(|(_id, info)| info.as_dict())
+        .and_then(|info| info.get(b"Title"))
+        .and_then(|title| doc.dereference(title))
+        .and_then(|(_id, title)| title.as_str())
+        .map(decode_text_string)
+        .ok();
+
+    info_title
+        .or_else(|| {
+            let metadata = doc
+                .catalog()
+                .and_then(|catalog| catalog.get(b"Metadata"))
+                .and_then(|metadata| doc.dereference(metadata))
+                .and_then(|(_id, metadata)| metadata.as_stream())
+                .ok()?;
+            let content = metadata
+                .decompressed_content()
+                .unwrap_or_else(|_| metadata.content.clone());
+            xmp_title(&String::from_utf8_lossy(&content))
+        })
+        .map(|title| title.trim().to_string())
+        .filter(|title| !title.is_empty())
+}
+
+/// The first `dc:title` alternative of the XMP packet.
+fn xmp_title(xmp_metadata: &str) -> Option<String> {
+    let captures = XMP_TITLE.captures(xmp_metadata)?;
+    Some(
+        captures[1]
+            .replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&quot;", "\"")
+            .replace("&apos;", "'")
+            .replace("&amp;", "&"),
+    )
+}
+
+/// The entries of the Info dictionary of the output given by the user.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DocumentInfo {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub subject: Option<String>,
+    pub keywords: Option<String>,
+}
+
+impl DocumentInfo {
+    pub fn is_empty(&self) -> bool {
+        *self == DocumentInfo::default()
+    }
+}
+
+/// Add to the document an Info dictionary with the given entries. With a title, the
+/// viewers are also asked to show it instead of the file name (`DisplayDocTitle`).
+pub fn set_document_info(
+    doc: &mut Document,
+    document_info: &DocumentInfo,
+    encoding: TitleEncoding,
+) -> Result<()> {
+    let mut info = Dictionary::new();
+    for (key, value) in [
+        ("Title", &document_info.title),
+        ("Author", &document_info.author),
+        ("Subject", &document_info.subject),
+        ("Keywords", &document_info.keywords),
+    ] {
+        if let Some(value) = value {
+            info.set(key, encode_text_string(value, encoding));
+        }
+    }
+    if info.is_empty() {
+        return Ok(());
+    }
+
+    let info_id = doc.add_object(info);
+    doc.trailer.set("Info", Object::Reference(info_id));
+
+    if document_info.title.is_some() {
+        let catalog = doc.catalog_mut()?;
+        let mut viewer_preferences = catalog
+            .get(b"ViewerPreferences")
+            .and_then(Object::as_dict)
+            .cloned()
+            .unwrap_or_default();
+        viewer_preferences.set("DisplayDocTitle", true);
+        catalog.set("ViewerPreferences", viewer_preferences);
+    }
+
+    Ok(())
+}
+
+impl fmt::Display for PdfInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (key, value) in &self.metadata {
+            writeln!(f, "{:<16}{value}", format!("{key}:"))?;
+        }
+        writeln!(f, "{:<16}{}", "Pages:", self.page_count)?;
+        writeln!(
+            f,
+            "{:<16}{}",
+            "Encrypted:",
+            if self.encrypted { "yes" } else { "no" }
+        )?;
+        writeln!(f, "{:<16}{}", "PDF version:", self.version)?;
+        for (format, count) in &self.page_formats {
+            writeln!(f, "{:<16}{count} x {format}", "Page size:")?;
+        }
+        writeln!(f, "{:<16}{}", "Outline items:", self.outline_item_count)?;
+        writeln!(
+            f,
+            "{:<16}{}",
+            "XMP metadata:",
+            if self.xmp_metadata.is_some() {
+                "yes"
+            } else {
+                "no"
+            }
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support;
+    use lopdf::dictionary;
+
+    #[test]
+    fn info_of_basic_doc() -> Result<()> {
+        let doc = test_support::get_basic_pdf_doc("doc_name", 3)?;
+        let info = pdf_info(&doc)?;
+
+        assert_eq!(info.page_count, 3);
+        assert!(!info.encrypted);
+        assert_eq!(info.page_formats.values().sum::<u32>(), 3);
+        assert_eq!(info.outline_item_count, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn title_from_info_or_xmp() -> Result<()> {
+        let mut doc = test_support::get_basic_pdf_doc("doc_name", 1)?;
+        assert_eq!(document_title(&doc), None);
+
+        let metadata_id = doc.add_object(lopdf::Stream::new(
+            dictionary! {"Type" => "Metadata", "Subtype" => "XML"},
+            b"<x:xmpmeta><rdf:RDF><rdf:Description><dc:title><rdf:Alt>\
+              <rdf:li xml:lang=\"x-default\">Notes &amp; Exercises</rdf:li>\
+              </rdf:Alt></dc:title></rdf:Description></rdf:RDF></x:xmpmeta>"
+                .to_vec(),
+        ));
+        doc.catalog_mut()?.set("Metadata", metadata_id);
+        assert_eq!(document_title(&doc).as_deref(), Some("Notes & Exercises"));
+
+        let info_id = doc.add_object(dictionary! {
+            "Title" => Object::string_literal("Stochastic Differential Equations"),
+        });
+        doc.trailer.set("Info", info_id);
+        assert_eq!(
+            document_title(&doc).as_deref(),
+            Some("Stochastic Differential Equations")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn document_info_is_set() -> Result<()> {
+        let mut doc = test_support::get_basic_pdf_doc("doc_name", 1)?;
+        let document_info = DocumentInfo {
+            title: Some("Course notes".to_string()),
+            keywords: Some("probability, SDE".to_string()),
+            ..Default::default()
+        };
+        set_document_info(&mut doc, &document_info, TitleEncoding::Auto)?;
+
+        let info = pdf_info(&doc)?;
+        assert_eq!(info.metadata["Title"], "Course notes");
+        assert_eq!(info.metadata["Keywords"], "probability, SDE");
+        assert!(!info.metadata.contains_key("Author"));
+        let viewer_preferences = doc.catalog()?.get(b"ViewerPreferences")?.as_dict()?;
+        assert!(viewer_preferences.get(b"DisplayDocTitle")?.as_bool()?);
+
+        Ok(())
+    }
+}
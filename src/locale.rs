@@ -0,0 +1,161 @@
+use anyhow::{Result, anyhow};
+
+/// Conventions for writing dates and numbers in the generated content (bookmark titles,
+/// stamps, ...).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Locale {
+    pub tag: &'static str,
+    /// Order of the fields of a date, with the `{d}`, `{m}` and `{y}` placeholders.
+    date_pattern: &'static str,
+    /// Put between the groups of three digits of the integer part of numbers.
+    thousands_separator: &'static str,
+    decimal_separator: char,
+}
+
+/// The conventions used when no locale is given: ISO dates and plain numbers.
+const NEUTRAL_LOCALE: Locale = Locale {
+    tag: "C",
+    date_pattern: "{y}-{m}-{d}",
+    thousands_separator: "",
+    decimal_separator: '.',
+};
+
+const LOCALES: [Locale; 9] = [
+    NEUTRAL_LOCALE,
+    Locale {
+        tag: "en-US",
+        date_pattern: "{m}/{d}/{y}",
+        thousands_separator: ",",
+        decimal_separator: '.',
+    },
+    Locale {
+        tag: "en-GB",
+        date_pattern: "{d}/{m}/{y}",
+        thousands_separator: ",",
+        decimal_separator: '.',
+    },
+    Locale {
+        tag: "de-DE",
+        date_pattern: "{d}.{m}.{y}",
+        thousands_separator: ".",
+        decimal_separator: ',',
+    },
+    Locale {
+        tag: "de-CH",
+        date_pattern: "{d}.{m}.{y}",
+        thousands_separator: "\u{2019}",
+        decimal_separator: '.',
+    },
+    Locale {
+        tag: "fr-FR",
+        date_pattern: "{d}/{m}/{y}",
+        thousands_separator: "\u{202f}",
+        decimal_separator: ',',
+    },
+    Locale {
+        tag: "it-IT",
+        date_pattern: "{d}/{m}/{y}",
+        thousands_separator: ".",
+        decimal_separator: ',',
+    },
+    Locale {
+        tag: "es-ES",
+        date_pattern: "{d}/{m}/{y}",
+        thousands_separator: ".",
+        decimal_separator: ',',
+    },
+    Locale {
+        tag: "nl-NL",
+        date_pattern: "{d}-{m}-{y}",
+        thousands_separator: ".",
+        decimal_separator: ',',
+    },
+];
+
+impl Default for Locale {
+    fn default() -> Self {
+        NEUTRAL_LOCALE
+    }
+}
+
+impl Locale {
+    /// The locale with the BCP 47 tag (e.g. `de-DE`, case and `_` in place of `-` accepted).
+    pub fn parse(tag: &str) -> Result<Self> {
+        let normalised_tag = tag.replace('_', "-");
+
+        LOCALES
+            .iter()
+            .find(|locale| locale.tag.eq_ignore_ascii_case(&normalised_tag))
+            .copied()
+            .ok_or(anyhow!(
+                "Unsupported locale '{tag}' (available: {})",
+                LOCALES.map(|locale| locale.tag).join(", ")
+            ))
+    }
+
+    pub fn format_integer(&self, number: i64) -> String {
+        let digits = number.unsigned_abs().to_string();
+        let mut formatted = String::with_capacity(digits.len() * 2);
+        if number < 0 {
+            formatted.push('-');
+        }
+
+        for (index, digit) in digits.chars().enumerate() {
+            if index > 0 && (digits.len() - index).is_multiple_of(3) {
+                formatted.push_str(self.thousands_separator);
+            }
+            formatted.push(digit);
+        }
+
+        formatted
+    }
+
+    pub fn format_decimal(&self, number: f64, decimals: usize) -> String {
+        let rounded = format!("{:.decimals$}", number.abs());
+        let (integer_part, fractional_part) = rounded.split_once('.').unwrap_or((&rounded, ""));
+        let sign = if number < 0.0 && rounded.chars().any(|c| c.is_ascii_digit() && c != '0') {
+            "-"
+        } else {
+            ""
+        };
+
+        let mut formatted = format!(
+            "{sign}{}",
+            self.format_integer(integer_part.parse().unwrap_or(0))
+        );
+        if !fractional_part.is_empty() {
+            formatted.push(self.decimal_separator);
+            formatted.push_str(fractional_part);
+        }
+        formatted
+    }
+
+    pub fn format_date(&self, year: i64, month: u32, day: u32) -> String {
+        self.date_pattern
+            .replace("{y}", &format!("{year:04}"))
+            .replace("{m}", &format!("{month:02}"))
+            .replace("{d}", &format!("{day:02}"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn german_and_neutral_formats() -> Result<()> {
+        let german = Locale::parse("de_de")?;
+        assert_eq!(german.format_date(2024, 2, 9), "09.02.2024");
+        assert_eq!(german.format_integer(1_234_567), "1.234.567");
+        assert_eq!(german.format_decimal(-1234.567, 2), "-1.234,57");
+
+        let neutral = Locale::default();
+        assert_eq!(neutral.format_date(2024, 2, 9), "2024-02-09");
+        assert_eq!(neutral.format_integer(12_345), "12345");
+        assert_eq!(neutral.format_decimal(0.5, 1), "0.5");
+
+        assert!(Locale::parse("xx-XX").is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,292 @@
+use crate::report::MergeReport;
+use crate::skip_list::sha256_hex;
+use anyhow::Result;
+use lopdf::{Dictionary, Document, Object, Stream, dictionary};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Name of the manifest attached to the output.
+pub const MANIFEST_FILE_NAME: &str = "pdfunite-tree-manifest.json";
+
+/// Provenance of the merged document: which file each page range comes from, with the
+/// hash of its content and optionally its file system metadata.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Name and version of the tool which wrote the manifest.
+    pub generator: String,
+    pub entries: Vec<ManifestEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// Path of the file relative to the input directory.
+    pub path: PathBuf,
+    pub sha256: String,
+    pub size: u64,
+    /// Number (starting from 1) of the first page of the file in the output.
+    pub first_page: u32,
+    pub page_count: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fs_metadata: Option<FsMetadata>,
+}
+
+/// File system metadata of a source file, as found when it was merged. Timestamps are in
+/// seconds since the Unix epoch; the fields a platform does not provide are left empty.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FsMetadata {
+    pub modified: Option<u64>,
+    pub accessed: Option<u64>,
+    pub created: Option<u64>,
+    pub readonly: bool,
+    /// Permission bits (Unix).
+    pub mode: Option<u32>,
+    /// User id of the owner (Unix).
+    pub owner: Option<u32>,
+    /// Group id of the owner (Unix).
+    pub group: Option<u32>,
+    /// Extended attributes (Linux), with the values hex-encoded.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub xattrs: BTreeMap<String, String>,
+}
+
+/// Describe the merged files of the report, reading them again to hash their content.
+pub fn build_manifest(report: &MergeReport, preserve_fs_metadata: bool) -> Result<Manifest> {
+    let entries = report
+        .sources
+        .iter()
+        .map(|source| {
+            let source_path = report.root.join(&source.path);
+            let content = std::fs::read(&source_path)?;
+
+            Ok(ManifestEntry {
+                path: source.path.clone(),
+                sha256: sha256_hex(&content),
+                size: content.len() as u64,
+                first_page: source.first_page,
+                page_count: source.page_count,
+                fs_metadata: preserve_fs_metadata
+                    .then(|| fs_metadata(&source_path))
+                    .transpose()?,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(Manifest {
+        generator: format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+        entries,
+    })
+}
+
+pub fn fs_metadata(path: impl AsRef<Path>) -> Result<FsMetadata> {
+    let path = path.as_ref();
+    let metadata = std::fs::metadata(path)?;
+    let seconds_since_epoch = |time: std::io::Result<SystemTime>| {
+        time.ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|since_epoch| since_epoch.as_secs())
+    };
+
+    #[allow(unused_mut)]
+    let mut fs_metadata = FsMetadata {
+        modified: seconds_since_epoch(metadata.modified()),
+        accessed: seconds_since_epoch(metadata.accessed()),
+        created: seconds_since_epoch(metadata.created()),
+        readonly: metadata.permissions().readonly(),
+        ..Default::default()
+    };
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        fs_metadata.mode = Some(metadata.mode() & 0o7777);
+        fs_metadata.owner = Some(metadata.uid());
+        fs_metadata.group = Some(metadata.gid());
+    }
+    #[cfg(target_os = "linux")]
+    {
+        fs_metadata.xattrs = extended_attributes(path)?;
+    }
+
+    Ok(fs_metadata)
+}
+
+/// The extended attributes of the file which can be read, none if the file system does
+/// not support them.
+#[cfg(target_os = "linux")]
+fn extended_attributes(path: &Path) -> Result<BTreeMap<String, String>> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    // SAFETY: with a null buffer `listxattr` only returns the size of the list.
+    let list_len = unsafe { libc::listxattr(c_path.as_ptr(), std::ptr::null_mut(), 0) };
+    if list_len <= 0 {
+        return Ok(BTreeMap::new());
+    }
+    let mut names = vec![0u8; list_len as usize];
+    // SAFETY: `listxattr` writes at most `names.len()` bytes into the buffer.
+    let list_len =
+        unsafe { libc::listxattr(c_path.as_ptr(), names.as_mut_ptr().cast(), names.len()) };
+    if list_len < 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    names.truncate(list_len as usize);
+
+    let mut xattrs = BTreeMap::new();
+    for name in names
+        .split(|byte| *byte == 0)
+        .filter(|name| !name.is_empty())
+    {
+        let c_name = CString::new(name)?;
+        // SAFETY: as above, first the size of the value, then at most `value.len()` bytes.
+        let value_len =
+            unsafe { libc::getxattr(c_path.as_ptr(), c_name.as_ptr(), std::ptr::null_mut(), 0) };
+        if value_len < 0 {
+            continue;
+        }
+        let mut value = vec![0u8; value_len as usize];
+        let value_len = unsafe {
+            libc::getxattr(
+                c_path.as_ptr(),
+                c_name.as_ptr(),
+                value.as_mut_ptr().cast(),
+                value.len(),
+            )
+        };
+        if value_len < 0 {
+            continue;
+        }
+        value.truncate(value_len as usize);
+
+        xattrs.insert(
+            String::from_utf8_lossy(name).to_string(),
+            value.iter().map(|byte| format!("{byte:02x}")).collect(),
+        );
+    }
+
+    Ok(xattrs)
+}
+
+/// Attach the manifest to the document as an embedded JSON file, listed in the
+/// `/EmbeddedFiles` name tree of the catalog.
+pub fn embed_manifest(doc: &mut Document, manifest: &Manifest) -> Result<()> {
+    let content = serde_json::to_vec_pretty(manifest)?;
+    let content_len = content.len() as i64;
+
+    let embedded_file_id = doc.add_object(Stream::new(
+        dictionary! {
+            "Type" => "EmbeddedFile",
+            "Subtype" => Object::Name(b"application/json".to_vec()),
+            "Params" => dictionary! { "Size" => content_len },
+        },
+        content,
+    ));
+    let filespec_id = doc.add_object(dictionary! {
+        "Type" => "Filespec",
+        "F" => Object::string_literal(MANIFEST_FILE_NAME),
+        "UF" => Object::string_literal(MANIFEST_FILE_NAME),
+        "Desc" => Object::string_literal("Source files of the merged document"),
+        "AFRelationship" => "Supplement",
+        "EF" => dictionary! { "F" => Object::Reference(embedded_file_id) },
+    });
+    let embedded_files_id = doc.add_object(dictionary! {
+        "Names" => Object::Array(vec![
+            Object::string_literal(MANIFEST_FILE_NAME),
+            Object::Reference(filespec_id),
+        ]),
+    });
+
+    let names_id = match doc.catalog()?.get(b"Names") {
+        Ok(Object::Reference(names_id)) => Some(*names_id),
+        _ => None,
+    };
+    let names = match names_id {
+        Some(names_id) => doc.get_dictionary_mut(names_id)?,
+        None => {
+            let catalog = doc.catalog_mut()?;
+            if !matches!(catalog.get(b"Names"), Ok(Object::Dictionary(_))) {
+                catalog.set("Names", Dictionary::new());
+            }
+            catalog.get_mut(b"Names")?.as_dict_mut()?
+        }
+    };
+    names.set("EmbeddedFiles", Object::Reference(embedded_files_id));
+
+    let catalog = doc.catalog_mut()?;
+    catalog.set("AF", Object::Array(vec![Object::Reference(filespec_id)]));
+
+    Ok(())
+}
+
+/// The manifest attached to the document by `embed_manifest`, if any.
+pub fn read_manifest(doc: &Document) -> Result<Option<Manifest>> {
+    let Ok(names) = doc
+        .catalog()?
+        .get_deref(b"Names", doc)
+        .and_then(Object::as_dict)
+    else {
+        return Ok(None);
+    };
+    let Ok(embedded_files) = names
+        .get_deref(b"EmbeddedFiles", doc)
+        .and_then(Object::as_dict)
+    else {
+        return Ok(None);
+    };
+    let Ok(entries) = embedded_files.get(b"Names").and_then(Object::as_array) else {
+        return Ok(None);
+    };
+
+    for pair in entries.chunks(2) {
+        let [name, filespec] = pair else {
+            continue;
+        };
+        if name.as_str().ok() != Some(MANIFEST_FILE_NAME.as_bytes()) {
+            continue;
+        }
+        let filespec = doc.dereference(filespec)?.1.as_dict()?;
+        let ef = filespec.get_deref(b"EF", doc)?.as_dict()?;
+        let stream = ef.get_deref(b"F", doc)?.as_stream()?;
+        let content = stream
+            .decompressed_content()
+            .unwrap_or_else(|_| stream.content.clone());
+        return Ok(Some(serde_json::from_slice(&content)?));
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support;
+
+    #[test]
+    fn manifest_round_trip() -> Result<()> {
+        let test_dir = test_support::get_virgin_test_dir("manifest_round_trip")?;
+
+        let mut source_doc = test_support::get_basic_pdf_doc("source", 2)?;
+        source_doc.save(format!("{test_dir}/source.pdf"))?;
+        let mut report = MergeReport::new(&test_dir);
+        report.record_source(format!("{test_dir}/source.pdf"), 2)?;
+
+        let manifest = build_manifest(&report, true)?;
+        let fs_metadata = manifest.entries[0].fs_metadata.as_ref();
+        assert!(fs_metadata.is_some_and(|fs_metadata| fs_metadata.modified.is_some()));
+
+        let mut doc = test_support::get_basic_pdf_doc("merged", 2)?;
+        embed_manifest(&mut doc, &manifest)?;
+        let mut saved = Vec::new();
+        doc.save_to(&mut saved)?;
+
+        let read_back = read_manifest(&Document::load_mem(&saved)?)?;
+        assert_eq!(
+            read_back.map(|manifest| manifest.entries[0].sha256.clone()),
+            Some(manifest.entries[0].sha256.clone())
+        );
+
+        Ok(())
+    }
+}
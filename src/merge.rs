@@ -0,0 +1,3604 @@
+//! Merge of directory trees (or of an explicit plan) into one document with a bookmark for
+//! every file and directory: the options, the traversal of the trees and the import of each
+//! source file.
+
+use crate::compression::CompressionLevel;
+use crate::error::{MergeError, ParseTimeout};
+use crate::ignore_file::{IGNORE_FILE_NAME, IgnoreRules};
+use crate::outline::OutlineItem;
+use crate::passwords::PasswordMap;
+use crate::report::MergeReport;
+use crate::signatures::{self, SignedPolicy};
+use crate::skip_list::SkipList;
+use crate::text::TitleEncoding;
+use crate::{
+    archive, compression, contact_sheet, dividers, highlight, hooks, images, info, locale,
+    manifest, outline, page_labels, page_sizes, page_tree, plan, progress, source, stamp, text,
+    toc_pages, toc_text, utils, validate,
+};
+use anyhow::{Result, anyhow};
+use log::{info, trace, warn};
+use lopdf::{Document, Object, ObjectId, dictionary};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::DirEntry;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::Duration;
+use unicode_normalization::UnicodeNormalization;
+
+/// Maximum depth of the directory tree when `MergeOptions::max_depth` is `None`.
+pub const DEFAULT_MAX_DEPTH: u32 = 5;
+const FALLBACK_MAX_OPEN_FILES: usize = 256;
+/// Number of timed out parsers left running at which the merge gives up.
+const MAX_ABANDONED_PARSERS: usize = 8;
+
+/// Options steering the merging process. Build them from `MergeOptions::default()`: new
+/// options may be added in minor releases.
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct MergeOptions {
+    /// Maximum depth of the directory tree below the root: `None` for `DEFAULT_MAX_DEPTH`,
+    /// `Some(0)` for no limit. Deeper trees make the merge fail.
+    pub max_depth: Option<u32>,
+    /// Provide the merged document with a ToC (Outlines) reflecting the tree structure:
+    /// `None` for the default (a ToC if there is anything to put in it), `Some(_)` to force
+    /// the choice, failing if a forced ToC would be empty.
+    pub with_outlines: Option<bool>,
+    /// After each leaf import, verify that no object of the main document was overwritten
+    /// and that every reference of the imported objects resolves. Always active in debug builds.
+    pub paranoid: bool,
+    /// Load the PDFs of each directory concurrently before merging them.
+    pub parallel_loading: bool,
+    /// Merge in bounded memory: hold a single source document at a time (`parallel_loading`
+    /// is ignored) and compress at the given level the streams of each source as soon as it
+    /// is imported, so that the merged document grows by the compressed size of the sources.
+    pub streaming: Option<CompressionLevel>,
+    /// Maximum number of files opened at the same time by the parallel loader.
+    /// If `None`, it is derived from the limit of file descriptors of the process.
+    pub max_open_files: Option<usize>,
+    /// How many times a read failing with a transient I/O error (e.g. a stale NFS handle)
+    /// is retried before giving up.
+    pub io_retries: u32,
+    /// Waiting time before the first retry, doubled at each further retry.
+    pub retry_backoff: Duration,
+    /// Group the PDFs lying directly in the input root under a bookmark with this title,
+    /// placed before the sections of the subdirectories.
+    pub root_files_section: Option<String>,
+    /// Encoding of the text strings written in the output (e.g. bookmark titles).
+    pub title_encoding: TitleEncoding,
+    /// Files excluded from the merge.
+    pub skip_list: SkipList,
+    /// Passwords of the encrypted source files.
+    pub passwords: PasswordMap,
+    /// What to do with the digitally signed source files.
+    pub signed_policy: SignedPolicy,
+    /// Add to the output a name tree of named destinations pointing to the first page
+    /// of each source file, named after the normalised file name.
+    pub dests_index: bool,
+    /// What to do with sibling entries whose names differ only by case or Unicode
+    /// normalisation (e.g. `Report.pdf` and `report.pdf`).
+    pub collision_policy: CollisionPolicy,
+    /// Order of the files and directories within each directory.
+    pub order_by: OrderBy,
+    /// How the names compare, for the ordering by name and among the entries with the same
+    /// modification time or size.
+    pub sort_order: SortOrder,
+    /// Reverse the order of the entries of each directory.
+    pub reverse_order: bool,
+    /// How the view changes when a bookmark of each level is clicked.
+    pub dest_views: outline::DestViews,
+    /// Color and text format of the bookmarks.
+    pub bookmark_styles: outline::BookmarkStyles,
+    /// Expand in the viewer only the bookmarks of the first levels (1 for the top-level
+    /// bookmarks, 0 to collapse them all): `None` to expand them all.
+    pub outline_open_depth: Option<usize>,
+    /// Attach to the output a JSON manifest of the source files, with their page ranges
+    /// and content hashes (see `manifest::Manifest`).
+    pub embed_manifest: bool,
+    /// Record also the file system metadata (timestamps, permissions, owner, extended
+    /// attributes) of each source file in the manifest.
+    pub preserve_fs_metadata: bool,
+    /// Insert at the beginning pages with miniatures of the first page of each source file.
+    pub contact_sheet: bool,
+    /// Insert at the beginning printed table of contents pages, listing the directories
+    /// and files with their page numbers and links to them.
+    pub toc_pages: bool,
+    /// Insert before the content of each directory a page showing its path in large text.
+    pub divider_pages: bool,
+    /// Entries of the Info dictionary of the output (title, author, ...).
+    pub document_info: info::DocumentInfo,
+    /// Label the pages of each source file with the title of its bookmark and the number of
+    /// the page within the file, as shown by the viewers in their page box.
+    pub page_labels: bool,
+    /// Draw a text across every page of the output.
+    pub watermark: Option<stamp::Watermark>,
+    /// Stamp on every page of the source files, at this position, the path of the file and
+    /// the number of the page within it.
+    pub source_footers: Option<stamp::StampPosition>,
+    /// Stamp a running page number on every page of the output.
+    pub page_numbers: Option<stamp::PageNumberStamp>,
+    /// Stamp a Bates identifier on every page of the output, recording the range of each
+    /// source file in the report.
+    pub bates: Option<stamp::BatesNumbering>,
+    /// Highlight the occurrences of this term in the merged pages and list the pages
+    /// containing it under a "Search hits" bookmark.
+    pub highlight: Option<String>,
+    /// Append to the outline a "Source map" section listing every source file with its
+    /// page range, regardless of the shape of the rest of the outline.
+    pub appendix_map: bool,
+    /// If not empty, merge only the files whose path relative to the input directory
+    /// matches one of these glob patterns (`*`, `?` and `**` for any number of directories).
+    pub include_patterns: Vec<String>,
+    /// Leave out the files and the whole directories whose path relative to the input
+    /// directory matches one of these glob patterns.
+    pub exclude_patterns: Vec<String>,
+    /// File name patterns (with `*` and `?` wildcards) of the outputs of previous runs:
+    /// matching files found in the tree are skipped with a warning, so that an old output
+    /// lying in the input is not merged into the new one.
+    pub previous_output_patterns: Vec<String>,
+    /// Prefix the bookmark titles with hierarchical numbers (`1`, `1.1`, `1.1.2`, ...).
+    pub number_sections: bool,
+    /// The PageMode of the inputs is dropped: with this flag, the output adopts the one of
+    /// the first input instead, unless it has outlines (which are then shown).
+    pub inherit_first_page_mode: bool,
+    /// Warn about the source files with pages deviating from the dominant format of the tree.
+    pub warn_mixed_sizes: bool,
+    /// Do not add bookmarks for the generated pages placed before the source files
+    /// (e.g. "Contact sheet").
+    pub skip_frontmatter_bookmarks: bool,
+    /// For the files containing a printed table of contents (detected heuristically in the
+    /// text of the first pages), add a child bookmark for each of its entries.
+    pub toc_from_text: bool,
+    /// Add below the bookmark of each file a child bookmark for each of its pages
+    /// ("Page 1", "Page 2", ...), after the bookmarks of the file itself.
+    pub page_bookmarks: bool,
+    /// How the bookmark titles are derived from the file and directory names.
+    pub prettify_titles: text::PrettifyTitles,
+    /// Where the bookmark titles of the files come from.
+    pub title_source: TitleSource,
+    /// Merge every directory containing only images (JPEG or PNG) as a single document
+    /// with one page per image, in natural order of the file names.
+    pub image_dirs: bool,
+    /// Shape of the page tree of the output.
+    pub page_tree_layout: page_tree::PageTreeLayout,
+    /// Conventions for the dates and numbers written in the generated content.
+    pub locale: locale::Locale,
+    /// Stop the merge once this many files were merged, to get a quick sample of the output.
+    pub limit_files: Option<u32>,
+    /// Stop the merge once the output has at least this many pages (the file crossing the
+    /// limit is merged whole).
+    pub limit_pages: Option<u32>,
+    /// Arrangement of the outline when several roots are merged.
+    pub merge_roots: MergeRootsMode,
+    /// Log the current phase and file of the merge at this interval.
+    pub heartbeat_interval: Option<Duration>,
+    /// Consider the merge stalled (e.g. on a hanging network read) after this time
+    /// without progress, and react according to `stall_action`.
+    pub stall_timeout: Option<Duration>,
+    pub stall_action: progress::StallAction,
+    /// Leave out (and report) the files which cannot be loaded or merged, e.g. because they
+    /// are corrupt or use unsupported features, instead of failing.
+    pub skip_errors: bool,
+    /// Command run on each file before its import, e.g. to decrypt or repair it, with the
+    /// placeholders `{in}` (the file) and `{out}` (the temporary file to write the result to).
+    pub pre_command: Option<String>,
+    /// Abandon (and report) the files whose parsing takes longer than this. Their parsing
+    /// goes on in the background, and the merge fails when too many are still running.
+    pub file_timeout: Option<Duration>,
+    /// Current phase and file of the merge, watched for heartbeats and stalls.
+    pub progress: progress::Progress,
+}
+
+/// How several input trees are arranged in the outline.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum MergeRootsMode {
+    /// Interleave the entries of all the roots at the top level, ordered by name
+    Flatten,
+    /// Give each root its own top-level bookmark, named after the directory
+    #[default]
+    Separate,
+}
+
+/// Criterion by which the entries of each directory are ordered.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OrderBy {
+    #[default]
+    Name,
+    /// Oldest modification first
+    Mtime,
+    /// Smallest first (for a directory, the total size of the files below it)
+    Size,
+}
+
+/// How the names of the entries of each directory compare.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum SortOrder {
+    /// By name, with the numbers compared by value (`chapter2` before `chapter10`)
+    #[default]
+    Natural,
+    /// By name, character by character (`chapter10` before `chapter2`)
+    Lexicographic,
+}
+
+/// Where the bookmark titles of the files come from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum TitleSource {
+    /// The file name
+    #[default]
+    Filename,
+    /// The Title of the document metadata (Info dictionary, else XMP), else the file name
+    Metadata,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum CollisionPolicy {
+    /// Abort the merge
+    Fail,
+    /// Merge all the colliding entries, appending " (2)", " (3)", ... to the titles of all but the first
+    #[default]
+    Suffix,
+    /// Merge only the first of the colliding entries (in sorting order)
+    Skip,
+}
+
+impl MergeOptions {
+    fn consistency_checks_enabled(&self) -> bool {
+        self.paranoid || cfg!(debug_assertions)
+    }
+
+    fn max_open_files(&self) -> usize {
+        self.max_open_files
+            .unwrap_or_else(default_max_open_files)
+            .max(1)
+    }
+}
+
+/// Half of the soft limit of file descriptors of the process, leaving the rest to the
+/// other open files (output, logs, standard streams, ...).
+fn default_max_open_files() -> usize {
+    #[cfg(unix)]
+    {
+        let mut rlimit = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        // SAFETY: `getrlimit` only writes into the struct it is given.
+        if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut rlimit) } == 0 {
+            return usize::try_from(rlimit.rlim_cur / 2).unwrap_or(usize::MAX);
+        }
+    }
+
+    FALLBACK_MAX_OPEN_FILES
+}
+
+pub fn get_merged_tree_doc(
+    target_dir_path: impl AsRef<Path>,
+    options: &MergeOptions,
+) -> Result<Document, MergeError> {
+    let (main_doc, _report) = merge_to_document(target_dir_path, options)?;
+    Ok(main_doc)
+}
+
+/// Merge the tree into a document without saving it, returning it together with the record
+/// of the merged files. The document is not compressed, so that it can be post-processed
+/// (signed, encrypted, extended with further pages, ...) before being serialised.
+pub fn merge_to_document(
+    target_dir_path: impl AsRef<Path>,
+    options: &MergeOptions,
+) -> Result<(Document, MergeReport), MergeError> {
+    merge_roots_to_document(&[target_dir_path], options)
+}
+
+/// As `merge_to_document`, but merging several trees, arranged according to
+/// `options.merge_roots`. The paths in the report are relative to the closest common
+/// ancestor of the roots.
+pub fn merge_roots_to_document(
+    roots: &[impl AsRef<Path>],
+    options: &MergeOptions,
+) -> Result<(Document, MergeReport), MergeError> {
+    let roots: Vec<PathBuf> = roots
+        .iter()
+        .map(|root| root.as_ref().to_path_buf())
+        .collect();
+
+    progress::watch(
+        &options.progress,
+        options.heartbeat_interval,
+        options.stall_timeout,
+        options.stall_action,
+        || merge_tree(&roots, options).map_err(MergeError::from),
+    )
+}
+
+fn merge_tree(roots: &[PathBuf], options: &MergeOptions) -> Result<(Document, MergeReport)> {
+    let mut report = MergeReport::new(match roots {
+        [root] => root.clone(),
+        _ => utils::common_ancestor(roots),
+    });
+
+    info!("Initialising main document");
+    let mut main_doc = Document::with_version("1.7");
+    initialise_doc_with_null_pages(&mut main_doc)?;
+
+    info!("Start the merging process");
+    let root_outline_items = match (roots, options.merge_roots) {
+        ([], _) => return Err(anyhow!("No input directory was given")),
+        ([_], _) | (_, MergeRootsMode::Separate) => {
+            let mut root_outline_items = Vec::with_capacity(roots.len());
+            for root in roots {
+                if let Some(mut root_outline_item) = merge_from_internal_nodes(
+                    &mut main_doc,
+                    std::slice::from_ref(root),
+                    0,
+                    &IgnoreRules::default(),
+                    options,
+                    &mut report,
+                )? {
+                    if options.number_sections {
+                        root_outline_item.number_descendants();
+                    }
+                    root_outline_items.push(root_outline_item);
+                }
+            }
+            root_outline_items
+        }
+        (_, MergeRootsMode::Flatten) => {
+            match merge_from_internal_nodes(
+                &mut main_doc,
+                roots,
+                0,
+                &IgnoreRules::default(),
+                options,
+                &mut report,
+            )? {
+                Some(mut roots_outline_item) => {
+                    if options.number_sections {
+                        roots_outline_item.number_descendants();
+                    }
+                    roots_outline_item.children
+                }
+                None => Vec::new(),
+            }
+        }
+    };
+
+    finish_merge(main_doc, report, root_outline_items, options)
+}
+
+/// As `merge_to_document`, but merging the files listed by the plan, under its titles and
+/// nesting, instead of traversing a tree. The paths in the report are relative to the
+/// directory of the plan.
+pub fn merge_plan_to_document(
+    plan: &plan::MergePlan,
+    options: &MergeOptions,
+) -> Result<(Document, MergeReport), MergeError> {
+    progress::watch(
+        &options.progress,
+        options.heartbeat_interval,
+        options.stall_timeout,
+        options.stall_action,
+        || {
+            let mut report = MergeReport::new(&plan.base_dir);
+
+            info!("Initialising main document");
+            let mut main_doc = Document::with_version("1.7");
+            initialise_doc_with_null_pages(&mut main_doc)?;
+
+            info!("Start the merging process of the plan");
+            let root_outline_items = merge_plan_entries(
+                &mut main_doc,
+                &plan.entries,
+                &plan.base_dir,
+                &mut |path| Ok((load_document(path, options)?, None)),
+                options,
+                &mut report,
+            )?;
+
+            finish_merge(main_doc, report, root_outline_items, options)
+        },
+    )
+    .map_err(MergeError::from)
+}
+
+/// As `merge_to_document`, but merging the PDF members of the archive as if it were the
+/// root directory, reading them in memory one at a time (see `archive::MemberContents`)
+/// instead of extracting them. The paths in the report are relative to the archive.
+pub fn merge_archive_to_document(
+    archive_path: impl AsRef<Path>,
+    options: &MergeOptions,
+) -> Result<(Document, MergeReport), MergeError> {
+    let archive_path = archive_path.as_ref();
+
+    progress::watch(
+        &options.progress,
+        options.heartbeat_interval,
+        options.stall_timeout,
+        options.stall_action,
+        || {
+            // Both read the source files from the file system
+            if options.embed_manifest || options.pre_command.is_some() {
+                return Err(anyhow!(
+                    "The manifest and the pre-command are not supported for archives"
+                ));
+            }
+
+            let archive_root = std::path::absolute(archive_path)?;
+            let mut report = MergeReport::new(&archive_root);
+
+            info!(
+                "Read the PDF files of the archive '{}'",
+                archive_path.display()
+            );
+            options.progress.step("load", Some(archive_path));
+            let mut reader = archive::ArchiveReader::open(archive_path)?;
+            let (member_paths, collision_suffixes) = select_archive_members(
+                reader.members(),
+                reader.ignore_files(),
+                &archive_root,
+                options,
+                &mut report,
+            )?;
+            let modified_times: HashMap<PathBuf, Option<u64>> = reader
+                .members()
+                .iter()
+                .map(|member| (member.path.clone(), member.modified))
+                .collect();
+            let plan = plan::MergePlan::from_relative_paths(archive_root.clone(), &member_paths);
+
+            info!("Initialising main document");
+            let mut main_doc = Document::with_version("1.7");
+            initialise_doc_with_null_pages(&mut main_doc)?;
+
+            info!("Start the merging process of the archive");
+            let mut contents = reader.contents()?;
+            let mut load_member = |path: &Path| {
+                let relative_path = path.strip_prefix(&archive_root)?;
+                let content = contents.read(relative_path)?;
+                let fingerprint = (
+                    content.len() as u64,
+                    modified_times.get(relative_path).copied().flatten(),
+                );
+                Ok((parse_document(path, content, options)?, Some(fingerprint)))
+            };
+            let mut root_outline_item = OutlineItem::new(
+                options
+                    .prettify_titles
+                    .apply(&archive::archive_stem(archive_path)),
+                None,
+            )
+            .with_source(&archive_root);
+            root_outline_item.children = merge_plan_entries(
+                &mut main_doc,
+                &plan.entries,
+                &plan.base_dir,
+                &mut load_member,
+                options,
+                &mut report,
+            )?;
+            append_collision_suffixes(
+                &mut root_outline_item.children,
+                &archive_root,
+                &collision_suffixes,
+            );
+            if options.number_sections {
+                root_outline_item.number_descendants();
+            }
+            let root_outline_items = if root_outline_item.first_page().is_some() {
+                vec![root_outline_item]
+            } else {
+                Vec::new()
+            };
+
+            finish_merge(main_doc, report, root_outline_items, options)
+        },
+    )
+    .map_err(MergeError::from)
+}
+
+/// The paths of the PDF members of the archive to merge, in merging order, with the suffixes
+/// of the titles of the colliding entries: the ignore files, the filters, the skip list, the
+/// maximum depth, the ordering and the collision policy apply as to a directory tree. The
+/// ordering by modification time or size and the hashes of the skip list are not supported.
+fn select_archive_members(
+    members: &[archive::ArchiveMember],
+    ignore_files: &HashMap<PathBuf, String>,
+    archive_root: &Path,
+    options: &MergeOptions,
+    report: &mut MergeReport,
+) -> Result<(Vec<PathBuf>, HashMap<PathBuf, usize>)> {
+    if options.order_by != OrderBy::Name {
+        return Err(anyhow!(
+            "The ordering by {:?} is not supported for archives",
+            options.order_by
+        ));
+    }
+    if options.skip_list.has_hashes() {
+        return Err(anyhow!(
+            "The hashes of the skip list are not supported for archives"
+        ));
+    }
+    let max_depth = options.max_depth.unwrap_or(DEFAULT_MAX_DEPTH);
+
+    let mut member_paths = Vec::with_capacity(members.len());
+    'members: for member in members {
+        // The entries are checked from the top, each one against the ignore files above it
+        let num_components = member.path.components().count();
+        let mut ignore_rules = IgnoreRules::default();
+        let mut relative_path = PathBuf::new();
+        for (index, name) in member.path.iter().enumerate() {
+            if let Some(content) = ignore_files.get(&relative_path) {
+                ignore_rules =
+                    ignore_rules.with_content(archive_root.join(&relative_path), content);
+            }
+            relative_path.push(name);
+            let path = archive_root.join(&relative_path);
+            let is_file = index + 1 == num_components;
+            if ignore_rules.is_ignored(&path, !is_file)
+                || is_filtered_out(&path, is_file, options, report)
+            {
+                trace!("Skip the member '{}' of the archive", member.path.display());
+                continue 'members;
+            }
+        }
+
+        let path = archive_root.join(&member.path);
+        if looks_like_previous_output(&path, options, report)
+            || options.skip_list.excludes(&path, archive_root)?
+        {
+            continue;
+        }
+        let level = num_components.saturating_sub(1) as u32;
+        if max_depth != 0 && level > max_depth {
+            return Err(anyhow!(
+                "The number of levels achieved is higher than the maximum \
+                allowed (={max_depth}): {level}"
+            ));
+        }
+        member_paths.push(member.path.clone());
+    }
+    member_paths.sort_by(|path, other_path| compare_member_paths(path, other_path, options));
+
+    // The entries of each directory of the archive, files and subdirectories
+    let mut directory_entries: BTreeMap<PathBuf, Vec<PathBuf>> = BTreeMap::new();
+    for member_path in &member_paths {
+        let mut entry_path = archive_root.to_path_buf();
+        for name in member_path {
+            let siblings = directory_entries.entry(entry_path.clone()).or_default();
+            entry_path.push(name);
+            if !siblings.contains(&entry_path) {
+                siblings.push(entry_path.clone());
+            }
+        }
+    }
+    let mut collision_suffixes = HashMap::new();
+    let mut skipped = HashSet::new();
+    for entry_paths in directory_entries.values() {
+        let (suffixes, skipped_paths) =
+            find_name_collisions(entry_paths, options.collision_policy, report)?;
+        collision_suffixes.extend(suffixes);
+        skipped.extend(skipped_paths);
+    }
+    member_paths.retain(|member_path| {
+        !archive_root
+            .join(member_path)
+            .ancestors()
+            .any(|path| skipped.contains(path))
+    });
+
+    Ok((member_paths, collision_suffixes))
+}
+
+/// Compare the paths of two members of an archive as `sort_entries` orders the entries of
+/// each directory, by the first of their components which differs.
+fn compare_member_paths(
+    path: &Path,
+    other_path: &Path,
+    options: &MergeOptions,
+) -> std::cmp::Ordering {
+    for (name, other_name) in path.iter().zip(other_path) {
+        if name == other_name {
+            continue;
+        }
+        let name_ordering = match options.sort_order {
+            SortOrder::Natural => {
+                utils::natural_cmp(&name.to_string_lossy(), &other_name.to_string_lossy())
+            }
+            SortOrder::Lexicographic => std::cmp::Ordering::Equal,
+        }
+        .then_with(|| name.cmp(other_name));
+        return if options.reverse_order {
+            name_ordering.reverse()
+        } else {
+            name_ordering
+        };
+    }
+
+    path.iter().count().cmp(&other_path.iter().count())
+}
+
+/// Append the collision suffixes to the titles of the items of the members of the archive,
+/// and of the sections of their directories, which are titled with the directory name.
+fn append_collision_suffixes(
+    outline_items: &mut [OutlineItem],
+    directory: &Path,
+    suffixes: &HashMap<PathBuf, usize>,
+) {
+    for outline_item in outline_items {
+        let path = outline_item
+            .source
+            .clone()
+            .unwrap_or_else(|| directory.join(&outline_item.title));
+        append_collision_suffixes(&mut outline_item.children, &path, suffixes);
+        if let Some(suffix) = suffixes.get(&path) {
+            outline_item.title = format!("{} ({suffix})", outline_item.title);
+        }
+    }
+}
+
+/// Merge the files of the entries and their children, returning their outline items. The
+/// files are loaded by `load_leaf`, which also gives their fingerprint when they are not on
+/// the file system.
+fn merge_plan_entries(
+    main_doc: &mut Document,
+    entries: &[plan::PlanEntry],
+    base_dir: &Path,
+    load_leaf: &mut dyn FnMut(&Path) -> Result<LoadedLeaf>,
+    options: &MergeOptions,
+    report: &mut MergeReport,
+) -> Result<Vec<OutlineItem>> {
+    let mut outline_items = Vec::with_capacity(entries.len());
+
+    for entry in entries {
+        if sample_limit_reached(options, report) {
+            break;
+        }
+
+        let outline_item = match &entry.path {
+            Some(path) => {
+                let path = base_dir.join(path);
+                options.progress.files_found(1);
+                let num_objects_before = main_doc.objects.len();
+                let merged_leaf = load_leaf(&path).and_then(|(mut doc_to_merge, fingerprint)| {
+                    if let Some(pages) = &entry.pages {
+                        plan::keep_pages(&mut doc_to_merge, pages)?;
+                    }
+                    merge_loaded_leaf(main_doc, &path, doc_to_merge, fingerprint, options, report)
+                });
+                match merged_leaf {
+                    Err(err)
+                        if is_skippable_error(&err, options)
+                            && main_doc.objects.len() == num_objects_before =>
+                    {
+                        report.record_skipped(&path, &err);
+                        options.progress.file_skipped();
+                        None
+                    }
+                    merged_leaf => Some(merged_leaf?),
+                }
+            }
+            None => Some(OutlineItem::new(
+                entry.title.clone().unwrap_or_default(),
+                None,
+            )),
+        };
+        let Some(mut outline_item) = outline_item else {
+            continue;
+        };
+
+        if let Some(title) = &entry.title {
+            outline_item.title = title.clone();
+        }
+        outline_item.children.extend(merge_plan_entries(
+            main_doc,
+            &entry.children,
+            base_dir,
+            load_leaf,
+            options,
+            report,
+        )?);
+
+        // A section whose files were all skipped
+        if outline_item.first_page().is_none() {
+            continue;
+        }
+        outline_items.push(outline_item);
+    }
+
+    Ok(outline_items)
+}
+
+/// The steps common to all the merges once the source files are in the main document:
+/// generated pages, outline, catalog entries and page tree.
+fn finish_merge(
+    mut main_doc: Document,
+    mut report: MergeReport,
+    root_outline_items: Vec<OutlineItem>,
+    options: &MergeOptions,
+) -> Result<(Document, MergeReport)> {
+    if sample_limit_reached(options, &report) {
+        let warning = format!(
+            "The output is a sample: the merge stopped after {} files and {} pages",
+            report.sources.len(),
+            main_doc.get_pages().len()
+        );
+        warn!("{warning}");
+        report.warnings.push(warning);
+    }
+
+    // Sibling bookmarks with the same title would be confusing in the ToC
+    let mut top_outline_item = OutlineItem::new(String::new(), None);
+    top_outline_item.children = root_outline_items;
+    for title_mapping in top_outline_item.disambiguate_descendant_titles() {
+        warn!(
+            "Bookmark '{}' renamed '{}' as a sibling has the same title",
+            title_mapping.original_title, title_mapping.title
+        );
+        report.title_mappings.push(title_mapping);
+    }
+    let mut source_titles = Vec::new();
+    top_outline_item.collect_source_titles(&mut source_titles);
+    report.record_titles(source_titles);
+    let root_outline_items = top_outline_item.children;
+
+    if options.divider_pages {
+        info!("Add a divider page before each directory");
+        options.progress.step("divider pages", None);
+        let num_dividers =
+            dividers::insert_divider_pages(&mut main_doc, &root_outline_items, &mut report)?;
+        info!("{num_dividers} divider pages added");
+    }
+
+    if options.warn_mixed_sizes {
+        for warning in page_sizes::mixed_format_warnings(&report) {
+            warn!("{warning}");
+            report.warnings.push(warning);
+        }
+    }
+
+    let search_hits_outline_item = match &options.highlight {
+        Some(term) => {
+            info!("Highlight the occurrences of '{term}'");
+            options.progress.step("highlight", None);
+            let hits = highlight::highlight_term(&mut main_doc, term)?;
+            info!("'{term}' found in {} pages", hits.len());
+            search_hits_outline_item(&hits, &report, &options.locale)
+        }
+        None => None,
+    };
+
+    // Bookmarks of the generated pages placed before the source files, in page order
+    let mut front_matter_outline_items: Vec<OutlineItem> = Vec::new();
+
+    if options.contact_sheet {
+        info!("Add the contact sheet of the source files");
+        options.progress.step("contact sheet", None);
+        let num_sheet_pages = contact_sheet::prepend_contact_sheet(&mut main_doc, &report)?;
+        report.shift_pages(num_sheet_pages);
+        front_matter_outline_items.insert(
+            0,
+            OutlineItem::new(
+                "Contact sheet".to_string(),
+                Some(page_tree::page_id_by_number(&main_doc, 1)?),
+            ),
+        );
+    }
+
+    if options.toc_pages {
+        info!("Add the table of contents pages");
+        options.progress.step("table of contents", None);
+        let num_toc_pages = toc_pages::prepend_toc_pages(&mut main_doc, &root_outline_items)?;
+        if num_toc_pages > 0 {
+            report.shift_pages(num_toc_pages);
+            front_matter_outline_items.insert(
+                0,
+                OutlineItem::new(
+                    "Contents".to_string(),
+                    Some(page_tree::page_id_by_number(&main_doc, 1)?),
+                ),
+            );
+        }
+    }
+
+    let appendix_map_outline_item = if options.appendix_map {
+        appendix_map_outline_item(&main_doc, &report, &options.locale)?
+    } else {
+        None
+    };
+
+    let outlines_id = if options.with_outlines != Some(false) {
+        info!("Build the Outline of the main document and append it to the catalog");
+        options.progress.step("outline", None);
+        if options.skip_frontmatter_bookmarks {
+            front_matter_outline_items.clear();
+        }
+        let mut top_outline_item = OutlineItem::new(String::new(), None);
+        top_outline_item.children = front_matter_outline_items
+            .into_iter()
+            .chain(root_outline_items)
+            .chain(search_hits_outline_item)
+            .chain(appendix_map_outline_item)
+            .collect();
+        top_outline_item.set_descendant_dest_views(&options.dest_views, 1);
+        top_outline_item.set_descendant_styles(&options.bookmark_styles, 1);
+        if let Some(open_depth) = options.outline_open_depth {
+            top_outline_item.set_descendant_open_depth(open_depth, 1);
+        }
+        let outline_items = top_outline_item.children;
+        let built_outlines = outline::build_outlines_with_ids(
+            &mut main_doc,
+            &outline_items,
+            options.title_encoding,
+        )?;
+        match built_outlines {
+            Some((outlines_id, bookmark_ids)) => {
+                for (source, bookmark_id) in bookmark_ids {
+                    let relative_source = source
+                        .strip_prefix(&report.root)
+                        .map(Path::to_path_buf)
+                        .unwrap_or(source);
+                    report.bookmark_ids.insert(relative_source, bookmark_id);
+                }
+                Some(outlines_id)
+            }
+            None if options.with_outlines == Some(true) => {
+                return Err(MergeError::EmptyOutline.into());
+            }
+            None => None,
+        }
+    } else {
+        None
+    };
+
+    if let Some(outlines_id) = outlines_id {
+        let catalog = main_doc.catalog_mut()?;
+        catalog.set("Outlines", Object::Reference(outlines_id));
+        catalog.set("PageMode", Object::Name(b"UseOutlines".to_vec()));
+    } else if options.inherit_first_page_mode
+        && let Some(page_mode) = report
+            .sources
+            .first()
+            .and_then(|source| source.page_mode.clone())
+    {
+        info!("Adopt the PageMode '{page_mode}' of the first source file");
+        main_doc
+            .catalog_mut()?
+            .set("PageMode", Object::Name(page_mode.into_bytes()));
+    }
+
+    if options.dests_index {
+        info!("Add the named destinations of the source files to the catalog");
+        options.progress.step("named destinations", None);
+        add_dests_index(&mut main_doc, &report)?;
+    }
+
+    if options.embed_manifest {
+        info!("Attach the manifest of the source files");
+        options.progress.step("manifest", None);
+        let manifest = manifest::build_manifest(&report, options.preserve_fs_metadata)?;
+        manifest::embed_manifest(&mut main_doc, &manifest)?;
+    }
+
+    if let Some(watermark) = &options.watermark {
+        info!("Draw the watermark '{}'", watermark.text);
+        options.progress.step("watermark", None);
+        stamp::stamp_watermark(&mut main_doc, watermark)?;
+    }
+
+    if let Some(position) = options.source_footers {
+        info!("Stamp the source file of each page");
+        options.progress.step("source footers", None);
+        stamp::stamp_source_footers(&mut main_doc, &report, position)?;
+    }
+
+    if let Some(page_number_stamp) = &options.page_numbers {
+        info!("Stamp the page numbers");
+        options.progress.step("page numbers", None);
+        stamp::stamp_page_numbers(&mut main_doc, page_number_stamp, &options.locale)?;
+    }
+
+    if let Some(bates) = &options.bates {
+        info!("Stamp the Bates numbers");
+        options.progress.step("Bates numbers", None);
+        stamp::stamp_bates_numbers(&mut main_doc, bates, &mut report)?;
+    }
+
+    if !options.document_info.is_empty() {
+        info!("Set the Info dictionary of the output");
+        info::set_document_info(
+            &mut main_doc,
+            &options.document_info,
+            options.title_encoding,
+        )?;
+    }
+
+    if options.page_labels {
+        info!("Label the pages after their source file");
+        page_labels::set_page_labels(&mut main_doc, &report, options.title_encoding)?;
+    }
+
+    info!("Arrange the page tree ({:?})", options.page_tree_layout);
+    options.progress.step("page tree", None);
+    page_tree::arrange_page_tree(&mut main_doc, options.page_tree_layout)?;
+
+    info!("Check the counts and parents of the page tree");
+    options.progress.step("page tree", None);
+    for fix in page_tree::fix_page_tree(&mut main_doc)? {
+        let warning = format!("Page tree: {fix}");
+        warn!("{warning}");
+        report.warnings.push(warning);
+    }
+
+    Ok((main_doc, report))
+}
+
+/// The "Search hits" section of the outline, with an entry for each page containing the term.
+fn search_hits_outline_item(
+    hits: &[highlight::SearchHit],
+    report: &MergeReport,
+    locale: &locale::Locale,
+) -> Option<OutlineItem> {
+    if hits.is_empty() {
+        return None;
+    }
+
+    let mut section_item = OutlineItem::new("Search hits".to_string(), None);
+    section_item.children = hits
+        .iter()
+        .map(|hit| {
+            let source = report.sources.iter().find(|source| {
+                (source.first_page..source.first_page + source.page_count)
+                    .contains(&hit.page_number)
+            });
+            let occurrences = locale.format_integer(hit.occurrences as i64);
+            let title = match source {
+                Some(source) => format!(
+                    "{}, p. {} ({occurrences} hits)",
+                    source.path.display(),
+                    locale.format_integer((hit.page_number - source.first_page + 1).into()),
+                ),
+                None => format!(
+                    "p. {} ({occurrences} hits)",
+                    locale.format_integer(hit.page_number.into())
+                ),
+            };
+            OutlineItem::new(title, Some(hit.page_id))
+        })
+        .collect();
+
+    Some(section_item)
+}
+
+/// The "Source map" section of the outline, with a flat list of the source files and their pages.
+fn appendix_map_outline_item(
+    main_doc: &Document,
+    report: &MergeReport,
+    locale: &locale::Locale,
+) -> Result<Option<OutlineItem>> {
+    if report.sources.is_empty() {
+        return Ok(None);
+    }
+
+    let pages = main_doc.get_pages();
+    let mut section_item = OutlineItem::new("Source map".to_string(), None);
+
+    for source in &report.sources {
+        let first_page_id = *pages.get(&source.first_page).ok_or(anyhow!(
+            "The first page ({}) of '{}' is not in the merged document",
+            source.first_page,
+            source.path.display()
+        ))?;
+        let last_page = source.first_page + source.page_count - 1;
+        let title = format!(
+            "{} (pages {}\u{2013}{})",
+            source.path.display(),
+            locale.format_integer(source.first_page.into()),
+            locale.format_integer(last_page.into())
+        );
+        section_item
+            .children
+            .push(OutlineItem::new(title, Some(first_page_id)));
+    }
+
+    Ok(Some(section_item))
+}
+
+/// Add the `/Dests` name tree to the catalog, mapping the normalised name of each source
+/// file to its first page. Names occurring more than once get a numeric suffix.
+fn add_dests_index(main_doc: &mut Document, report: &MergeReport) -> Result<()> {
+    let pages = main_doc.get_pages();
+    let mut dests = BTreeMap::new();
+
+    for source in &report.sources {
+        let file_name = source
+            .path
+            .file_name()
+            .map(|file_name| file_name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let base_name = text::normalize_dest_name(&file_name);
+
+        let mut dest_name = base_name.clone();
+        let mut suffix = 2;
+        while dests.contains_key(&dest_name) {
+            dest_name = format!("{base_name}-{suffix}");
+            suffix += 1;
+        }
+
+        let page_id = *pages.get(&source.first_page).ok_or(anyhow!(
+            "The first page ({}) of '{}' is not in the merged document",
+            source.first_page,
+            source.path.display()
+        ))?;
+        dests.insert(dest_name, page_id);
+    }
+
+    let names_array = dests
+        .into_iter()
+        .flat_map(|(dest_name, page_id)| {
+            [
+                Object::string_literal(dest_name),
+                Object::Array(vec![
+                    Object::Reference(page_id),
+                    Object::Name(b"Fit".to_vec()),
+                ]),
+            ]
+        })
+        .collect::<Vec<_>>();
+
+    let dests_tree_id = main_doc.add_object(dictionary! {
+        "Names" => Object::Array(names_array),
+    });
+    main_doc.catalog_mut()?.set(
+        "Names",
+        dictionary! {
+            "Dests" => Object::Reference(dests_tree_id),
+        },
+    );
+
+    Ok(())
+}
+
+fn initialise_doc_with_null_pages(doc: &mut Document) -> Result<()> {
+    let main_pages_root = dictionary!(
+        b"Type" => Object::Name(b"Pages".to_vec()),
+        b"Kids" => Object::Array(vec![]),
+        b"Count" => Object::Integer(0)
+    );
+
+    let main_root_pages_id = doc.add_object(Object::Dictionary(main_pages_root));
+
+    let catalog_id = doc.add_object(dictionary! {
+        "Type" => "Catalog",
+        "Pages" => Object::Reference(main_root_pages_id)
+    });
+    doc.trailer.set("Root", catalog_id);
+
+    Ok(())
+}
+
+/// Traverse the trees as `merge_roots_to_document` would, without merging: the planned
+/// bookmark tree with the page counts of the files, read cheaply from their page trees.
+pub fn plan_merge(
+    roots: &[impl AsRef<Path>],
+    options: &MergeOptions,
+) -> Result<(Vec<plan::PlannedEntry>, MergeReport), MergeError> {
+    let roots: Vec<PathBuf> = roots
+        .iter()
+        .map(|root| root.as_ref().to_path_buf())
+        .collect();
+    let mut report = MergeReport::new(match roots.as_slice() {
+        [root] => root.clone(),
+        _ => utils::common_ancestor(&roots),
+    });
+
+    let planned_entries = match (roots.as_slice(), options.merge_roots) {
+        ([], _) => return Err(anyhow!("No input directory was given").into()),
+        ([_], _) | (_, MergeRootsMode::Separate) => {
+            let mut planned_entries = Vec::with_capacity(roots.len());
+            for root in &roots {
+                planned_entries.extend(plan_internal_nodes(
+                    std::slice::from_ref(root),
+                    0,
+                    &IgnoreRules::default(),
+                    options,
+                    &mut report,
+                )?);
+            }
+            planned_entries
+        }
+        (_, MergeRootsMode::Flatten) => {
+            plan_internal_nodes(&roots, 0, &IgnoreRules::default(), options, &mut report)?
+                .map(|planned_roots| planned_roots.children)
+                .unwrap_or_default()
+        }
+    };
+
+    Ok((planned_entries, report))
+}
+
+/// The planned entry of the directories merged as a single node (see
+/// `merge_from_internal_nodes`), `None` if nothing in them would be merged.
+fn plan_internal_nodes(
+    directories: &[PathBuf],
+    parent_level: u32,
+    ignore_rules: &IgnoreRules,
+    options: &MergeOptions,
+    report: &mut MergeReport,
+) -> Result<Option<plan::PlannedEntry>> {
+    let directory = directories
+        .first()
+        .ok_or(anyhow!("No directory to plan was given"))?;
+
+    let max_depth = options.max_depth.unwrap_or(DEFAULT_MAX_DEPTH);
+    if max_depth != 0 && parent_level > max_depth {
+        return Err(anyhow!(
+            "The number of levels achieved is higher than the maximum \
+            allowed (={max_depth}): {parent_level}"
+        ));
+    }
+
+    let (mut entries, ignore_rules) =
+        list_kept_entries(directories, ignore_rules, options, report)?;
+    sort_entries(&mut entries, options)?;
+    let grouped_directories = group_same_named_directories(&mut entries, options)?;
+    let collision_suffixes =
+        resolve_name_collisions(&mut entries, options.collision_policy, report)?;
+
+    let mut children = Vec::with_capacity(entries.len());
+    for entry in entries {
+        let mut planned_entry = if source::is_file(entry.path()) {
+            plan::PlannedEntry::file(entry.path(), source::quick_page_count(entry.path()))
+        } else if let Some(image_paths) = options
+            .image_dirs
+            .then(|| images::image_dir_contents(entry.path()))
+            .transpose()?
+            .flatten()
+        {
+            plan::PlannedEntry::file(entry.path(), Ok(image_paths.len() as u32))
+        } else {
+            match plan_internal_nodes(
+                &directories_of_entry(&entry, &grouped_directories),
+                parent_level + 1,
+                &ignore_rules,
+                options,
+                report,
+            )? {
+                Some(planned_directory) => planned_directory,
+                None => continue,
+            }
+        };
+        if let Some(suffix) = collision_suffixes.get(&entry.path()) {
+            planned_entry.title = format!("{} ({suffix})", planned_entry.title);
+        }
+        children.push(planned_entry);
+    }
+
+    Ok((!children.is_empty()).then(|| plan::PlannedEntry::directory(directory, children)))
+}
+
+/// The entries of the directories which are merged, i.e. not left out by the ignore files,
+/// the filters or the skip list, nor special files, together with the ignore rules which
+/// apply to their descendants.
+fn list_kept_entries(
+    directories: &[PathBuf],
+    ignore_rules: &IgnoreRules,
+    options: &MergeOptions,
+    report: &mut MergeReport,
+) -> Result<(Vec<DirEntry>, IgnoreRules)> {
+    let mut entries = Vec::new();
+    let mut ignore_rules = ignore_rules.clone();
+    for directory in directories {
+        ignore_rules = ignore_rules.with_directory(directory)?;
+        options.progress.step("read directory", Some(directory));
+        entries.extend(retry_transient_io(directory, options, || {
+            std::fs::read_dir(directory)?.collect::<std::io::Result<Vec<_>>>()
+        })?);
+        report.record_directory(directory)?;
+    }
+
+    let mut kept_entries = Vec::with_capacity(entries.len());
+    let mut kept_files = 0;
+    for entry in entries {
+        if entry.file_name() == IGNORE_FILE_NAME {
+            continue;
+        }
+        let is_file = match source::entry_kind(entry.path()) {
+            source::EntryKind::File => true,
+            source::EntryKind::Directory => false,
+            source::EntryKind::Special(kind) => {
+                let warning = format!("Skip '{}' as it is a {kind}", entry.path().display());
+                warn!("{warning}");
+                report.warnings.push(warning);
+                continue;
+            }
+        };
+        if ignore_rules.is_ignored(entry.path(), !is_file) {
+            trace!(
+                "Skip '{}' as it is ignored by an ignore file",
+                entry.path().display()
+            );
+            continue;
+        }
+        if is_filtered_out(&entry.path(), is_file, options, report)
+            || (is_file && is_excluded_file(&entry, options, report)?)
+        {
+            continue;
+        }
+        kept_files += usize::from(is_file);
+        kept_entries.push(entry);
+    }
+    options.progress.files_found(kept_files);
+
+    Ok((kept_entries, ignore_rules))
+}
+
+/// Sort the entries by the chosen criterion, then by name (so that the entries of several
+/// directories are interleaved). The sort is stable: entries with the same name in several
+/// directories stay in the order of the directories.
+fn sort_entries(entries: &mut [DirEntry], options: &MergeOptions) -> Result<()> {
+    let order_keys = entries
+        .iter()
+        .map(|entry| Ok((entry.path(), order_key(&entry.path(), options.order_by)?)))
+        .collect::<Result<HashMap<PathBuf, u128>>>()?;
+
+    entries.sort_by(|entry, other_entry| {
+        let name_ordering = match options.sort_order {
+            SortOrder::Natural => utils::natural_cmp(
+                &entry.file_name().to_string_lossy(),
+                &other_entry.file_name().to_string_lossy(),
+            ),
+            SortOrder::Lexicographic => std::cmp::Ordering::Equal,
+        };
+        order_keys[&entry.path()]
+            .cmp(&order_keys[&other_entry.path()])
+            .then(name_ordering)
+            .then_with(|| entry.file_name().cmp(&other_entry.file_name()))
+    });
+    if options.reverse_order {
+        entries.reverse();
+    }
+
+    Ok(())
+}
+
+/// The value of the entry by which it is ordered before its name: the modification time
+/// in nanoseconds since the Unix epoch, or the size in bytes (of all the files below, for
+/// a directory).
+fn order_key(path: &Path, order_by: OrderBy) -> Result<u128> {
+    Ok(match order_by {
+        OrderBy::Name => 0,
+        OrderBy::Mtime => std::fs::metadata(path)?
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|since_epoch| since_epoch.as_nanos())
+            .unwrap_or(0),
+        OrderBy::Size => u128::from(source::tree_size(path)?),
+    })
+}
+
+/// A directory (or group of directories merged as one) whose entries are being merged by
+/// `merge_from_internal_nodes`.
+struct NodeFrame {
+    level: u32,
+    outline_item: OutlineItem,
+    /// Bookmark gathering the files of the root, see `MergeOptions::root_files_section`.
+    root_files_section_item: Option<OutlineItem>,
+    entries: std::vec::IntoIter<DirEntry>,
+    ignore_rules: IgnoreRules,
+    /// The directories merged as the entry, by its path, see `group_same_named_directories`.
+    grouped_directories: HashMap<PathBuf, Vec<PathBuf>>,
+    collision_suffixes: HashMap<PathBuf, usize>,
+    preloaded_docs: HashMap<PathBuf, Result<Document>>,
+}
+
+impl NodeFrame {
+    /// List and sort the entries of the directories, `None` if there is nothing to merge in them.
+    fn open(
+        directories: &[PathBuf],
+        level: u32,
+        ignore_rules: &IgnoreRules,
+        options: &MergeOptions,
+        report: &mut MergeReport,
+    ) -> Result<Option<Self>> {
+        let directory = directories
+            .first()
+            .ok_or(anyhow!("No directory to merge was given"))?;
+        trace!(
+            "Merge the node (=symlink or directory) '{}' and add its bookmark",
+            directory.display()
+        );
+
+        let max_depth = options.max_depth.unwrap_or(DEFAULT_MAX_DEPTH);
+        if max_depth != 0 && level > max_depth {
+            return Err(anyhow!(
+                "The number of levels achieved is higher than the maximum \
+                allowed (={max_depth}): {level}"
+            ));
+        }
+
+        let (mut entries, ignore_rules) =
+            list_kept_entries(directories, ignore_rules, options, report)?;
+
+        if entries.is_empty() {
+            trace!(
+                "The node (=symlink or directory) '{}' is empty, therefore its bookmark is not added",
+                directory.display()
+            );
+            return Ok(None);
+        }
+
+        let outline_item = {
+            let dir_name = directory
+                .file_name()
+                .ok_or(anyhow!(
+                    "Could not get name of the directory '{}'",
+                    directory.display()
+                ))?
+                .to_string_lossy();
+
+            OutlineItem::new(options.prettify_titles.apply(&dir_name), None).with_source(directory)
+        };
+
+        sort_entries(&mut entries, options)?;
+        let grouped_directories = group_same_named_directories(&mut entries, options)?;
+
+        let collision_suffixes =
+            resolve_name_collisions(&mut entries, options.collision_policy, report)?;
+
+        let root_files_section_item = options
+            .root_files_section
+            .as_ref()
+            .filter(|_| level == 0)
+            .map(|section_title| OutlineItem::new(section_title.clone(), None));
+        if root_files_section_item.is_some() {
+            // the sort is stable: files first, then directories, each group still sorted by path
+            entries.sort_by_key(|entry| !source::is_file(entry.path()));
+        }
+
+        let preloaded_docs = if options.parallel_loading && options.streaming.is_none() {
+            let leaf_paths: Vec<PathBuf> = entries
+                .iter()
+                .filter(|entry| source::is_file(entry.path()))
+                .map(|entry| entry.path())
+                .collect();
+            let loaded_docs = load_documents_in_parallel(&leaf_paths, options);
+            leaf_paths.into_iter().zip(loaded_docs).collect()
+        } else {
+            HashMap::new()
+        };
+
+        Ok(Some(NodeFrame {
+            level,
+            outline_item,
+            root_files_section_item,
+            entries: entries.into_iter(),
+            ignore_rules,
+            grouped_directories,
+            collision_suffixes,
+            preloaded_docs,
+        }))
+    }
+
+    fn title_with_suffix(&self, entry: &DirEntry, mut outline_item: OutlineItem) -> OutlineItem {
+        if let Some(suffix) = self.collision_suffixes.get(&entry.path()) {
+            outline_item.title = format!("{} ({suffix})", outline_item.title);
+        }
+        outline_item
+    }
+
+    /// The outline item of the merged node, `None` if nothing in it was merged.
+    fn close(self) -> Option<OutlineItem> {
+        let mut outline_item = self.outline_item;
+        if let Some(section_item) = self
+            .root_files_section_item
+            .filter(|item| !item.children.is_empty())
+        {
+            outline_item.children.insert(0, section_item);
+        }
+
+        (!outline_item.children.is_empty()).then_some(outline_item)
+    }
+}
+
+/// Merge the directories as a single node, interleaving their entries by name as if they
+/// were in the same directory: their subdirectories with the same name are merged as one
+/// node too, their files with the same name all kept in the order of the directories. The
+/// bookmark of the node is named after the first directory.
+/// The tree is walked depth-first with an explicit stack of the open directories, so that
+/// deep trees cannot overflow the call stack.
+fn merge_from_internal_nodes(
+    main_doc: &mut Document,
+    directories: &[PathBuf],
+    parent_level: u32,
+    ignore_rules: &IgnoreRules,
+    options: &MergeOptions,
+    report: &mut MergeReport,
+) -> Result<Option<OutlineItem>> {
+    let Some(root_frame) =
+        NodeFrame::open(directories, parent_level, ignore_rules, options, report)?
+    else {
+        return Ok(None);
+    };
+    let mut stack = vec![root_frame];
+
+    while let Some(frame) = stack.last_mut() {
+        let next_entry = if sample_limit_reached(options, report) {
+            None
+        } else {
+            frame.entries.next()
+        };
+        let Some(entry) = next_entry else {
+            let Some(closed_frame) = stack.pop() else {
+                break;
+            };
+            let closed_outline_item = closed_frame.close();
+            match stack.last_mut() {
+                None => return Ok(closed_outline_item),
+                Some(parent_frame) => parent_frame
+                    .outline_item
+                    .children
+                    .extend(closed_outline_item),
+            }
+            continue;
+        };
+
+        if source::is_file(entry.path()) {
+            let num_objects_before = main_doc.objects.len();
+            let merged_leaf = match frame.preloaded_docs.remove(&entry.path()) {
+                Some(doc_to_merge) => doc_to_merge.and_then(|doc_to_merge| {
+                    merge_loaded_leaf(main_doc, entry.path(), doc_to_merge, None, options, report)
+                }),
+                None => merge_from_leaf(main_doc, entry.path(), options, report),
+            };
+            let leaf_outline_item = match merged_leaf {
+                Err(err)
+                    if is_skippable_error(&err, options)
+                        && main_doc.objects.len() == num_objects_before =>
+                {
+                    report.record_skipped(entry.path(), &err);
+                    options.progress.file_skipped();
+                    continue;
+                }
+                merged_leaf => merged_leaf?,
+            };
+            let leaf_outline_item = frame.title_with_suffix(&entry, leaf_outline_item);
+            match frame.root_files_section_item.as_mut() {
+                Some(section_item) => section_item.children.push(leaf_outline_item),
+                None => frame.outline_item.children.push(leaf_outline_item),
+            }
+        } else if let Some(image_paths) = options
+            .image_dirs
+            .then(|| images::image_dir_contents(entry.path()))
+            .transpose()?
+            .flatten()
+        {
+            trace!(
+                "Merge the directory of images '{}' as a single document",
+                entry.path().display()
+            );
+            options.progress.files_found(1);
+            let num_objects_before = main_doc.objects.len();
+            let merged_images = images::images_to_document(&image_paths).and_then(|images_doc| {
+                merge_loaded_leaf(main_doc, entry.path(), images_doc, None, options, report)
+            });
+            let leaf_outline_item = match merged_images {
+                Err(err)
+                    if is_skippable_error(&err, options)
+                        && main_doc.objects.len() == num_objects_before =>
+                {
+                    report.record_skipped(entry.path(), &err);
+                    options.progress.file_skipped();
+                    continue;
+                }
+                merged_images => merged_images?,
+            };
+            let leaf_outline_item = frame.title_with_suffix(&entry, leaf_outline_item);
+            frame.outline_item.children.push(leaf_outline_item);
+        } else if let Some(mut child_frame) = NodeFrame::open(
+            &directories_of_entry(&entry, &frame.grouped_directories),
+            frame.level + 1,
+            &frame.ignore_rules,
+            options,
+            report,
+        )? {
+            // The suffix goes on the title now, as the directory is added to its parent when closed
+            child_frame.outline_item = frame.title_with_suffix(&entry, child_frame.outline_item);
+            stack.push(child_frame);
+        }
+    }
+
+    Ok(None)
+}
+
+/// Keep only the first of the directories with the same name among the entries (coming from
+/// several directories merged as one), so that they are merged as a single node too. The
+/// directories of images, merged as files, are not grouped. Returns the directories grouped
+/// under each kept entry, by its path.
+fn group_same_named_directories(
+    entries: &mut Vec<DirEntry>,
+    options: &MergeOptions,
+) -> Result<HashMap<PathBuf, Vec<PathBuf>>> {
+    let mut first_paths: HashMap<std::ffi::OsString, PathBuf> = HashMap::new();
+    let mut grouped_directories: HashMap<PathBuf, Vec<PathBuf>> = HashMap::new();
+    let mut grouped_paths = HashSet::new();
+
+    for entry in entries.iter() {
+        let is_image_dir =
+            options.image_dirs && images::image_dir_contents(entry.path())?.is_some();
+        if source::is_file(entry.path()) || is_image_dir {
+            continue;
+        }
+        match first_paths.get(&entry.file_name()) {
+            Some(first_path) => {
+                grouped_directories
+                    .entry(first_path.clone())
+                    .or_insert_with(|| vec![first_path.clone()])
+                    .push(entry.path());
+                grouped_paths.insert(entry.path());
+            }
+            None => {
+                first_paths.insert(entry.file_name(), entry.path());
+            }
+        }
+    }
+    entries.retain(|entry| !grouped_paths.contains(&entry.path()));
+
+    Ok(grouped_directories)
+}
+
+/// The directories to merge as the directory entry (see `group_same_named_directories`).
+fn directories_of_entry(
+    entry: &DirEntry,
+    grouped_directories: &HashMap<PathBuf, Vec<PathBuf>>,
+) -> Vec<PathBuf> {
+    grouped_directories
+        .get(&entry.path())
+        .cloned()
+        .unwrap_or_else(|| vec![entry.path()])
+}
+
+/// Find the sibling entries whose names differ only by case or Unicode normalisation, which
+/// collide on case-insensitive filesystems, and apply the policy to them. Returns the numeric
+/// suffix to append to the title of each entry colliding with a previous sibling.
+fn resolve_name_collisions(
+    entries: &mut Vec<DirEntry>,
+    policy: CollisionPolicy,
+    report: &mut MergeReport,
+) -> Result<HashMap<PathBuf, usize>> {
+    let paths: Vec<PathBuf> = entries.iter().map(DirEntry::path).collect();
+    let (suffixes, skipped) = find_name_collisions(&paths, policy, report)?;
+    entries.retain(|entry| !skipped.contains(&entry.path()));
+
+    Ok(suffixes)
+}
+
+/// As `resolve_name_collisions`, for the paths of sibling entries: returns the suffixes and
+/// the paths of the entries to skip.
+fn find_name_collisions(
+    paths: &[PathBuf],
+    policy: CollisionPolicy,
+    report: &mut MergeReport,
+) -> Result<(HashMap<PathBuf, usize>, HashSet<PathBuf>)> {
+    let mut groups: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+    for path in paths {
+        let collision_key = path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .nfc()
+            .flat_map(char::to_lowercase)
+            .collect();
+        groups.entry(collision_key).or_default().push(path.clone());
+    }
+
+    let mut suffixes = HashMap::new();
+    let mut skipped = HashSet::new();
+
+    for colliding_paths in groups.values() {
+        // Files with exactly the same name come from several directories merged as one:
+        // they are all kept, their bookmarks being told apart with the other repeated titles
+        let mut distinct_names: Vec<&std::ffi::OsStr> = Vec::new();
+        for path in colliding_paths {
+            if let Some(name) = path.file_name()
+                && !distinct_names.contains(&name)
+            {
+                distinct_names.push(name);
+            }
+        }
+        if distinct_names.len() < 2 {
+            continue;
+        }
+        let name_index = |path: &PathBuf| {
+            distinct_names
+                .iter()
+                .position(|name| path.file_name() == Some(*name))
+                .unwrap_or(0)
+        };
+
+        let listed_paths = colliding_paths
+            .iter()
+            .map(|path| format!("'{}'", path.display()))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        match policy {
+            CollisionPolicy::Fail => {
+                return Err(anyhow!(
+                    "The entries {listed_paths} have names differing only by case or Unicode normalisation"
+                ));
+            }
+            CollisionPolicy::Suffix => {
+                for path in colliding_paths {
+                    let index = name_index(path);
+                    if index > 0 {
+                        suffixes.insert(path.clone(), index + 1);
+                    }
+                }
+            }
+            CollisionPolicy::Skip => skipped.extend(
+                colliding_paths
+                    .iter()
+                    .filter(|path| name_index(path) > 0)
+                    .cloned(),
+            ),
+        }
+
+        let warning =
+            format!("Name collision among {listed_paths}, resolved with policy '{policy:?}'");
+        warn!("{warning}");
+        report.warnings.push(warning);
+    }
+
+    Ok((suffixes, skipped))
+}
+
+/// Load the documents with a pool of workers, each one keeping at most one file open.
+/// The files not yet picked up by a worker wait in the queue. The results are returned
+/// in the same order as the paths.
+fn load_documents_in_parallel(paths: &[PathBuf], options: &MergeOptions) -> Vec<Result<Document>> {
+    let num_workers = std::thread::available_parallelism()
+        .map(|num| num.get())
+        .unwrap_or(1)
+        .min(options.max_open_files())
+        .min(paths.len())
+        .max(1);
+
+    trace!("Load {} documents with {num_workers} workers", paths.len());
+
+    let next_in_queue = AtomicUsize::new(0);
+    let loaded_docs: Mutex<Vec<Option<Result<Document>>>> =
+        Mutex::new(paths.iter().map(|_path| None).collect());
+
+    std::thread::scope(|scope| {
+        for _ in 0..num_workers {
+            scope.spawn(|| {
+                loop {
+                    let index = next_in_queue.fetch_add(1, Ordering::Relaxed);
+                    let Some(path) = paths.get(index) else {
+                        break;
+                    };
+
+                    let loaded_doc = load_document(path, options).map_err(|err| {
+                        if err.is::<ParseTimeout>() {
+                            err
+                        } else {
+                            anyhow!("Could not load the document '{}': {err}", path.display())
+                        }
+                    });
+
+                    if let Ok(mut loaded_docs) = loaded_docs.lock() {
+                        loaded_docs[index] = Some(loaded_doc);
+                    }
+                }
+            });
+        }
+    });
+
+    loaded_docs
+        .into_inner()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .into_iter()
+        .zip(paths)
+        .map(|(loaded_doc, path)| {
+            loaded_doc.unwrap_or_else(|| {
+                Err(anyhow!(
+                    "The document '{}' was not loaded by any worker",
+                    path.display()
+                ))
+            })
+        })
+        .collect()
+}
+
+/// Read the file (retrying on transient I/O errors) and parse it as a PDF document.
+fn load_document(path: &Path, options: &MergeOptions) -> Result<Document> {
+    options.progress.step("load", Some(path));
+    let buffer = match &options.pre_command {
+        Some(pre_command) => run_pre_command(path, pre_command)?,
+        None => retry_transient_io(path, options, || std::fs::read(path))?,
+    };
+    parse_document(path, buffer, options)
+}
+
+/// Parse the content of the file (within the timeout of the options, if any).
+fn parse_document(path: &Path, buffer: Vec<u8>, options: &MergeOptions) -> Result<Document> {
+    match options.file_timeout {
+        Some(timeout) => parse_with_timeout(path, buffer, timeout),
+        None => Ok(Document::load_mem(&buffer)?),
+    }
+}
+
+/// Run the command on the file, filling `{in}` with its path and `{out}` with the path of
+/// a temporary file, and return the content written to the temporary file.
+fn run_pre_command(path: &Path, pre_command: &str) -> Result<Vec<u8>> {
+    static NEXT_TEMP_FILE_NUMBER: AtomicUsize = AtomicUsize::new(0);
+    let temp_path = std::env::temp_dir().join(format!(
+        "pdfunite-tree-{}-{}.pdf",
+        std::process::id(),
+        NEXT_TEMP_FILE_NUMBER.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let placeholders = [
+        ("in", path.display().to_string()),
+        ("out", temp_path.display().to_string()),
+    ];
+    let processed = hooks::run_command_template(pre_command, &placeholders)
+        .and_then(|()| Ok(std::fs::read(&temp_path)?))
+        .map_err(|err| anyhow!("The pre-command on '{}' failed: {err}", path.display()));
+
+    if std::fs::exists(&temp_path).unwrap_or(false) {
+        let _ = std::fs::remove_file(&temp_path);
+    }
+    processed
+}
+
+/// Parse the document in a worker thread, giving up after the timeout. A worker which does
+/// not finish cannot be stopped: it is left running detached until it completes or the
+/// process exits, holding the content of the file. Fails once `MAX_ABANDONED_PARSERS` of
+/// them are still running, instead of piling up more.
+fn parse_with_timeout(path: &Path, buffer: Vec<u8>, timeout: Duration) -> Result<Document> {
+    static ABANDONED_PARSERS: AtomicUsize = AtomicUsize::new(0);
+
+    let abandoned_parsers = ABANDONED_PARSERS.load(Ordering::Relaxed);
+    if abandoned_parsers >= MAX_ABANDONED_PARSERS {
+        return Err(anyhow!(
+            "Could not parse '{}': the parsing of {abandoned_parsers} files timed out and is \
+            still running",
+            path.display()
+        ));
+    }
+
+    // Without a buffer the result is handed over only while waiting for it
+    let (parsed_sender, parsed_receiver) = std::sync::mpsc::sync_channel(0);
+
+    std::thread::Builder::new()
+        .name(format!("parse {}", path.display()))
+        .spawn(move || {
+            // The receiver is gone only after the timeout expired and the worker was counted
+            if parsed_sender.send(Document::load_mem(&buffer)).is_err() {
+                ABANDONED_PARSERS.fetch_sub(1, Ordering::Relaxed);
+            }
+        })?;
+
+    match parsed_receiver.recv_timeout(timeout) {
+        Ok(parsed) => Ok(parsed?),
+        Err(RecvTimeoutError::Timeout) => {
+            ABANDONED_PARSERS.fetch_add(1, Ordering::Relaxed);
+            Err(ParseTimeout {
+                path: path.to_path_buf(),
+                timeout,
+            }
+            .into())
+        }
+        Err(RecvTimeoutError::Disconnected) => Err(anyhow!(
+            "The parser of '{}' terminated without a result",
+            path.display()
+        )),
+    }
+}
+
+/// Run the I/O operation on the path, retrying it with exponential backoff as long as
+/// it fails with a transient error and retries are left.
+fn retry_transient_io<T>(
+    path: &Path,
+    options: &MergeOptions,
+    mut operation: impl FnMut() -> std::io::Result<T>,
+) -> std::io::Result<T> {
+    let mut attempt = 0;
+    loop {
+        match operation() {
+            Err(err) if attempt < options.io_retries && is_transient_io_error(&err) => {
+                let backoff = options.retry_backoff * 2u32.saturating_pow(attempt);
+                warn!(
+                    "Transient I/O error on '{}' (attempt {} of {}), retrying in {backoff:?}: {err}",
+                    path.display(),
+                    attempt + 1,
+                    options.io_retries + 1
+                );
+                std::thread::sleep(backoff);
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+fn is_transient_io_error(err: &std::io::Error) -> bool {
+    use std::io::ErrorKind;
+
+    matches!(
+        err.kind(),
+        ErrorKind::Interrupted
+            | ErrorKind::TimedOut
+            | ErrorKind::WouldBlock
+            | ErrorKind::UnexpectedEof
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::NotConnected
+            | ErrorKind::BrokenPipe
+            | ErrorKind::ResourceBusy
+            | ErrorKind::StaleNetworkFileHandle
+    )
+}
+
+/// Whether the file failing with the error can be left out of the merge (provided that
+/// nothing of it was imported yet): always for a parsing timeout, and for any error with
+/// `skip_errors`.
+fn is_skippable_error(err: &anyhow::Error, options: &MergeOptions) -> bool {
+    options.skip_errors || err.is::<ParseTimeout>()
+}
+
+/// Whether the merged files or pages reached the limits of a sample output.
+fn sample_limit_reached(options: &MergeOptions, report: &MergeReport) -> bool {
+    let files_limit_reached = options
+        .limit_files
+        .is_some_and(|limit_files| report.sources.len() >= limit_files as usize);
+    let pages_limit_reached = options.limit_pages.is_some_and(|limit_pages| {
+        report
+            .sources
+            .iter()
+            .map(|source| source.page_count)
+            .sum::<u32>()
+            >= limit_pages
+    });
+
+    files_limit_reached || pages_limit_reached
+}
+
+/// Whether the path is left out by the include and exclude patterns, which are matched
+/// against the path relative to the input directory. An excluded directory is not
+/// traversed at all, while the include patterns only select files.
+fn is_filtered_out(
+    path: &Path,
+    is_file: bool,
+    options: &MergeOptions,
+    report: &MergeReport,
+) -> bool {
+    let relative_path = path.strip_prefix(&report.root).unwrap_or(path);
+
+    if let Some(pattern) = options
+        .exclude_patterns
+        .iter()
+        .find(|pattern| utils::matches_path_glob(pattern, relative_path))
+    {
+        trace!(
+            "Skip '{}' as it matches the exclude pattern '{pattern}'",
+            path.display()
+        );
+        return true;
+    }
+
+    is_file
+        && !options.include_patterns.is_empty()
+        && !options
+            .include_patterns
+            .iter()
+            .any(|pattern| utils::matches_path_glob(pattern, relative_path))
+}
+
+/// Whether the name of the file matches one of the patterns of the outputs of previous
+/// runs, which is reported as a warning.
+fn looks_like_previous_output(
+    path: &Path,
+    options: &MergeOptions,
+    report: &mut MergeReport,
+) -> bool {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+
+    let Some(pattern) = options
+        .previous_output_patterns
+        .iter()
+        .find(|pattern| utils::matches_file_name_pattern(pattern, &file_name))
+    else {
+        return false;
+    };
+    let warning = format!(
+        "Skip '{}' as it looks like a previous output (it matches '{pattern}')",
+        path.display()
+    );
+    warn!("{warning}");
+    report.warnings.push(warning);
+    true
+}
+
+/// Whether the file is in the skip list, looks like the output of a previous run or is not
+/// a PDF file at all.
+fn is_excluded_file(
+    entry: &DirEntry,
+    options: &MergeOptions,
+    report: &mut MergeReport,
+) -> Result<bool> {
+    if looks_like_previous_output(&entry.path(), options, report) {
+        return Ok(true);
+    }
+
+    if options.skip_list.excludes(entry.path(), &report.root)? {
+        info!(
+            "Skip '{}' as it is in the skip list",
+            entry.path().display()
+        );
+        return Ok(true);
+    }
+
+    if !source::is_pdf_file(entry.path())? {
+        info!("Skip '{}' as it is not a PDF file", entry.path().display());
+        return Ok(true);
+    }
+
+    Ok(false)
+}
+
+/// Merge the PDF file into the main document, returning its outline item.
+fn merge_from_leaf(
+    main_doc: &mut Document,
+    path_doc_to_merge: impl AsRef<Path>,
+    options: &MergeOptions,
+    report: &mut MergeReport,
+) -> Result<OutlineItem> {
+    let doc_to_merge = load_document(path_doc_to_merge.as_ref(), options)?;
+    merge_loaded_leaf(
+        main_doc,
+        path_doc_to_merge,
+        doc_to_merge,
+        None,
+        options,
+        report,
+    )
+}
+
+/// Size and modification time of a source file which is not on the file system.
+type Fingerprint = (u64, Option<u64>);
+
+/// A source file loaded by the caller of `merge_plan_entries`, with its fingerprint when it
+/// is not on the file system.
+type LoadedLeaf = (Document, Option<Fingerprint>);
+
+/// Merge the already loaded PDF file into the main document, returning its outline item.
+/// Its fingerprint is read from the file system unless given.
+fn merge_loaded_leaf(
+    main_doc: &mut Document,
+    path_doc_to_merge: impl AsRef<Path>,
+    mut doc_to_merge: Document,
+    fingerprint: Option<Fingerprint>,
+    options: &MergeOptions,
+    report: &mut MergeReport,
+) -> Result<OutlineItem> {
+    trace!(
+        "Merge the leaf (=PDF file) '{}' and add its bookmark",
+        path_doc_to_merge.as_ref().display()
+    );
+    options
+        .progress
+        .step("merge", Some(path_doc_to_merge.as_ref()));
+
+    options
+        .passwords
+        .decrypt(&mut doc_to_merge, path_doc_to_merge.as_ref(), &report.root)?;
+    let signed = signatures::is_signed(&doc_to_merge)?;
+    if signed {
+        match options.signed_policy {
+            SignedPolicy::Error => {
+                return Err(MergeError::SignedDocument {
+                    path: path_doc_to_merge.as_ref().to_path_buf(),
+                }
+                .into());
+            }
+            SignedPolicy::Strip => {
+                let num_fields = signatures::strip_signature_fields(&mut doc_to_merge, false)?;
+                info!(
+                    "{num_fields} signature fields stripped from '{}'",
+                    path_doc_to_merge.as_ref().display()
+                );
+            }
+            SignedPolicy::Warn => {
+                signatures::strip_signature_fields(&mut doc_to_merge, true)?;
+            }
+        }
+        if doc_to_merge.catalog()?.has(b"AcroForm") {
+            return Err(MergeError::FormFieldsLeft {
+                path: path_doc_to_merge.as_ref().to_path_buf(),
+            }
+            .into());
+        }
+    }
+    validate::check_supported_catalog(&doc_to_merge, path_doc_to_merge.as_ref())?;
+    let catalog_to_merge = doc_to_merge.catalog()?;
+
+    // Some writers store the PageMode as a string instead of a name
+    let page_mode = match catalog_to_merge.get(b"PageMode") {
+        Ok(Object::Name(page_mode)) | Ok(Object::String(page_mode, _)) => {
+            Some(String::from_utf8_lossy(page_mode).to_string())
+        }
+        _ => None,
+    };
+
+    let page_formats = page_sizes::page_format_counts(&doc_to_merge);
+
+    materialize_object_streams(&mut doc_to_merge)?;
+
+    // The counts of the input are added up into the main document: do not trust them
+    let declared_page_count = doc_to_merge
+        .get_dictionary(page_tree::root_pages_id(&doc_to_merge)?)?
+        .get(b"Count")
+        .and_then(Object::as_i64)
+        .ok();
+    let actual_page_count = doc_to_merge.get_pages().len() as i64;
+    if declared_page_count != Some(actual_page_count) {
+        let warning = format!(
+            "'{}' declares {} pages but has {actual_page_count}: the page counts were fixed",
+            path_doc_to_merge.as_ref().display(),
+            declared_page_count.map_or("no count of".to_string(), |count| count.to_string())
+        );
+        warn!("{warning}");
+        report.warnings.push(warning);
+        page_tree::fix_page_tree(&mut doc_to_merge)?;
+    }
+
+    let metadata_title = match options.title_source {
+        TitleSource::Filename => None,
+        TitleSource::Metadata => info::document_title(&doc_to_merge),
+    };
+
+    // The bookmarks of the source go below its own bookmark, keeping the page numbers of the entries
+    let source_outline = if doc_to_merge.catalog()?.has(b"Outlines") {
+        outline::read_outline(&doc_to_merge)
+            .map(|entries| outline::outline_tree(&entries))
+            .unwrap_or_else(|err| {
+                let warning = format!(
+                    "The bookmarks of '{}' were dropped as they could not be read: {err}",
+                    path_doc_to_merge.as_ref().display()
+                );
+                warn!("{warning}");
+                report.warnings.push(warning);
+                Vec::new()
+            })
+    } else {
+        Vec::new()
+    };
+
+    // The outline (rebuilt with the others) and the XMP metadata are dropped with the
+    // catalog of the source: do not import their objects
+    let catalog_to_merge = doc_to_merge.catalog_mut()?;
+    let has_outline = catalog_to_merge.remove(b"Outlines").is_some();
+    let has_xmp_metadata = catalog_to_merge.remove(b"Metadata").is_some();
+    if has_outline || has_xmp_metadata {
+        doc_to_merge.prune_objects();
+    }
+
+    let toc_entries = if options.toc_from_text && source_outline.is_empty() {
+        toc_text::detect_toc_entries(&doc_to_merge)
+    } else {
+        Vec::new()
+    };
+
+    doc_to_merge.renumber_objects_with(main_doc.max_id + 1);
+
+    let main_doc_pages_root_reference = main_doc.catalog()?.get(b"Pages")?.as_reference()?;
+    let imported_max_id = doc_to_merge.max_id;
+    let mut imported_object_ids = Vec::new();
+    let (first_page_id, page_count, child_outline_items) = {
+        let pages = doc_to_merge.get_pages();
+        let first_page_id = *pages.get(&1).ok_or(MergeError::EmptyDocument {
+            path: path_doc_to_merge.as_ref().to_path_buf(),
+        })?;
+        let toc_outline_items: Vec<OutlineItem> = toc_entries
+            .into_iter()
+            .filter_map(|entry| {
+                let page_id = *pages.get(&entry.page_number)?;
+                Some(OutlineItem::new(entry.title, Some(page_id)))
+            })
+            .collect();
+        if !toc_outline_items.is_empty() {
+            info!(
+                "{} bookmarks detected in the printed table of contents of '{}'",
+                toc_outline_items.len(),
+                path_doc_to_merge.as_ref().display()
+            );
+        }
+        let page_outline_items: Vec<OutlineItem> = if options.page_bookmarks {
+            pages
+                .iter()
+                .map(|(page_number, &page_id)| {
+                    OutlineItem::new(format!("Page {page_number}"), Some(page_id))
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        let child_outline_items = OutlineItem::from_toc_nodes(source_outline, &pages)
+            .into_iter()
+            .chain(toc_outline_items)
+            .chain(page_outline_items)
+            .collect::<Vec<_>>();
+        (first_page_id, pages.len() as u32, child_outline_items)
+    };
+
+    for (object_id, mut object) in doc_to_merge.objects {
+        if options.consistency_checks_enabled() && main_doc.objects.contains_key(&object_id) {
+            return Err(anyhow!(
+                "Importing '{}' would overwrite the object {:?} already present in the main document",
+                path_doc_to_merge.as_ref().display(),
+                object_id
+            ));
+        }
+
+        match object.type_name().unwrap_or(b"") {
+            b"Catalog" => {}
+            b"Pages" => {
+                let pages_dict = object.as_dict_mut()?;
+                if !pages_dict.has(b"Parent") {
+                    // Graft the root of the imported page tree under the one of the main document
+                    pages_dict.set(b"Parent", main_doc_pages_root_reference);
+                    let imported_pages_count = pages_dict.get(b"Count")?.as_i64()?;
+
+                    let main_doc_pages_root_dictionary = main_doc
+                        .get_object_mut(main_doc_pages_root_reference)?
+                        .as_dict_mut()?;
+                    let actual_count = main_doc_pages_root_dictionary.get(b"Count")?.as_i64()?
+                        + imported_pages_count;
+                    main_doc_pages_root_dictionary.set(b"Count", Object::Integer(actual_count));
+                    main_doc_pages_root_dictionary
+                        .get_mut(b"Kids")?
+                        .as_array_mut()?
+                        .push(Object::Reference(object_id));
+                }
+
+                imported_object_ids.push(object_id);
+                main_doc.objects.insert(object_id, object);
+            }
+            _ => {
+                if let (Some(level), Object::Stream(stream)) = (options.streaming, &mut object) {
+                    compression::compress_stream(stream, level)?;
+                }
+                imported_object_ids.push(object_id);
+                main_doc.objects.insert(object_id, object);
+            }
+        }
+    }
+
+    // The ids of the imported objects follow the ones of the main document without gaps
+    main_doc.max_id = main_doc.max_id.max(imported_max_id);
+
+    if options.consistency_checks_enabled() {
+        validate::check_references_resolve(main_doc, &imported_object_ids).map_err(|err| {
+            anyhow!(
+                "Inconsistent import of '{}': {err}",
+                path_doc_to_merge.as_ref().display()
+            )
+        })?;
+    }
+
+    let name_doc_to_merge = path_doc_to_merge
+        .as_ref()
+        .file_name()
+        .ok_or(anyhow!(
+            "The given path '{}' does not contain a filename",
+            path_doc_to_merge.as_ref().display()
+        ))?
+        .to_string_lossy();
+    let name_doc_to_merge =
+        metadata_title.unwrap_or_else(|| options.prettify_titles.apply(&name_doc_to_merge));
+
+    match fingerprint {
+        Some((size, modified)) => report.record_source_with_fingerprint(
+            path_doc_to_merge.as_ref(),
+            page_count,
+            size,
+            modified,
+        ),
+        None => report.record_source(path_doc_to_merge.as_ref(), page_count)?,
+    }
+    options.progress.file_merged(page_count);
+    if let Some(source) = report.sources.last_mut() {
+        source.page_mode = page_mode;
+        source.page_formats = page_formats;
+        source.signed = signed;
+    }
+    if signed && options.signed_policy == SignedPolicy::Warn {
+        let warning = format!(
+            "The signatures of '{}' are invalid in the output",
+            path_doc_to_merge.as_ref().display()
+        );
+        warn!("{warning}");
+        report.warnings.push(warning);
+    }
+
+    let mut leaf_outline_item =
+        OutlineItem::new(name_doc_to_merge, Some(first_page_id)).with_source(path_doc_to_merge);
+    leaf_outline_item.children = child_outline_items;
+    Ok(leaf_outline_item)
+}
+
+/// Make sure that the objects stored in object streams are plain objects of the document,
+/// and drop the object and cross-reference streams: their raw content refers to the object
+/// numbers of the input, which are changed by the renumbering.
+fn materialize_object_streams(doc: &mut Document) -> Result<()> {
+    let container_ids: Vec<ObjectId> = doc
+        .objects
+        .iter()
+        .filter(|(_id, object)| matches!(object.type_name(), Ok(b"ObjStm") | Ok(b"XRef")))
+        .map(|(&id, _object)| id)
+        .collect();
+
+    for container_id in container_ids {
+        let Some(Object::Stream(mut stream)) = doc.objects.remove(&container_id) else {
+            continue;
+        };
+        if stream.dict.has_type(b"ObjStm") {
+            let object_stream = lopdf::ObjectStream::new(&mut stream)?;
+            for (member_id, member) in object_stream.objects {
+                doc.objects.entry(member_id).or_insert(member);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::test_support::{self, get_virgin_test_dir};
+
+    #[test]
+    fn modern_leaf_is_imported_without_object_streams() -> Result<()> {
+        let test_dir = get_virgin_test_dir("modern_leaf_is_imported_without_object_streams")?;
+        let leaf_path = format!("{test_dir}/leaf.pdf");
+
+        let mut leaf_doc = test_support::get_basic_pdf_doc("leaf", 4)?;
+        let mut buffer = Vec::new();
+        leaf_doc.save_modern(&mut buffer)?;
+        std::fs::write(&leaf_path, buffer)?;
+
+        let mut main_doc = Document::with_version("1.7");
+        initialise_doc_with_null_pages(&mut main_doc)?;
+        merge_from_leaf(
+            &mut main_doc,
+            &leaf_path,
+            &MergeOptions::default(),
+            &mut MergeReport::new(&test_dir),
+        )?;
+
+        assert_eq!(main_doc.get_pages().len(), 4);
+        assert!(
+            main_doc
+                .objects
+                .values()
+                .all(|object| !matches!(object.type_name(), Ok(b"ObjStm") | Ok(b"XRef")))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn merge_10_pages_leaf_in_main_doc() -> Result<()> {
+        println!("Test 'merge_10_pages_leaf_in_main_doc'");
+        let test_dir = get_virgin_test_dir("merge_10_pages_leaf_in_main_doc")?;
+
+        let main_doc_name = "main_doc";
+
+        let leaf_name = "leaf";
+        let leaf_path = format!("{test_dir}/{leaf_name}");
+
+        let mut main_doc = test_support::get_basic_pdf_doc(main_doc_name, 15)?;
+        let previous_max_id_main_doc = main_doc.max_id;
+        let mut previous_pages_main_doc = main_doc.get_pages();
+        let previous_len_main_doc = previous_pages_main_doc.len();
+
+        let mut leaf_doc = test_support::get_basic_pdf_doc(leaf_name, 10)?;
+
+        let mut buffer = Vec::new();
+        leaf_doc.save_modern(&mut buffer)?;
+        std::fs::write(&leaf_path, buffer)?;
+
+        let expected_page_ids_leaf_post_merge: BTreeMap<u32, (u32, u16)> = leaf_doc
+            .get_pages()
+            .iter()
+            .map(|(&page_num, &page_id)| {
+                let (id, version) = page_id;
+                (
+                    page_num + previous_len_main_doc as u32,
+                    (id + previous_max_id_main_doc as u32, version),
+                )
+            })
+            .collect();
+
+        merge_from_leaf(
+            &mut main_doc,
+            &leaf_path,
+            &MergeOptions::default(),
+            &mut MergeReport::new(&test_dir),
+        )?;
+
+        previous_pages_main_doc.extend(expected_page_ids_leaf_post_merge.iter());
+
+        let expected_pages_after_merge = previous_pages_main_doc;
+        let pages_main_doc = main_doc.get_pages();
+        assert_eq!(pages_main_doc, expected_pages_after_merge);
+
+        Ok(())
+    }
+
+    #[test]
+    fn import_over_an_existing_object_fails() -> Result<()> {
+        let test_dir = get_virgin_test_dir("import_over_an_existing_object_fails")?;
+        let leaf_path = format!("{test_dir}/leaf.pdf");
+        test_support::get_basic_pdf_doc("leaf", 2)?.save(&leaf_path)?;
+
+        // An object beyond the max id of the main document gets the id of an imported one
+        let mut main_doc = test_support::get_basic_pdf_doc("main_doc", 1)?;
+        main_doc
+            .objects
+            .insert((main_doc.max_id + 1, 0), Object::Null);
+
+        let options = MergeOptions {
+            paranoid: true,
+            ..Default::default()
+        };
+        let err = merge_from_leaf(
+            &mut main_doc,
+            &leaf_path,
+            &options,
+            &mut MergeReport::new(&test_dir),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("would overwrite the object"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn leaf_with_a_dangling_reference_fails() -> Result<()> {
+        let test_dir = get_virgin_test_dir("leaf_with_a_dangling_reference_fails")?;
+        let leaf_path = format!("{test_dir}/leaf.pdf");
+
+        let mut leaf_doc = test_support::get_basic_pdf_doc("leaf", 2)?;
+        let page_id = *leaf_doc.get_pages().values().next().unwrap();
+        let missing_id = (leaf_doc.max_id + 100, 0);
+        leaf_doc
+            .get_object_mut(page_id)?
+            .as_dict_mut()?
+            .set("Thumb", Object::Reference(missing_id));
+        leaf_doc.save(&leaf_path)?;
+
+        let mut main_doc = test_support::get_basic_pdf_doc("main_doc", 1)?;
+        let options = MergeOptions {
+            paranoid: true,
+            ..Default::default()
+        };
+        let err = merge_from_leaf(
+            &mut main_doc,
+            &leaf_path,
+            &options,
+            &mut MergeReport::new(&test_dir),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("Inconsistent import"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn merged_with_outline_and_save_modern_is_faulty_pdf() -> Result<()> {
+        let test_dir = get_virgin_test_dir("merged_with_outline_and_save_modern_is_faulty_pdf")?;
+        let target_dir_path = format!("{test_dir}/root_pdfs");
+        let output_path = format!("{target_dir_path}.pdf");
+        let compressed_output_path = format!("{target_dir_path}-compressed.pdf");
+        let options = MergeOptions {
+            with_outlines: Some(true),
+            ..Default::default()
+        };
+
+        let minus_one = |n: u8| n - 1;
+        test_support::generate_fn_tree_with_levels(&target_dir_path, 3, 4, 2, 4, &minus_one)?;
+
+        let mut main_doc = get_merged_tree_doc(target_dir_path, &options)?;
+
+        {
+            let mut buffer = Vec::new();
+            main_doc.save_modern(&mut buffer)?;
+            std::fs::write(&output_path, buffer)?;
+
+            main_doc.compress();
+
+            let mut buffer = Vec::new();
+            main_doc.save_modern(&mut buffer)?;
+            std::fs::write(&compressed_output_path, buffer)?;
+        }
+
+        assert!(test_support::validate_pdf(&output_path).is_err());
+        assert!(test_support::validate_pdf(&compressed_output_path).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn merged_without_outline_and_save_modern_is_faulty_pdf() -> Result<()> {
+        let test_dir = get_virgin_test_dir("merged_without_outline_and_save_modern_is_faulty_pdf")?;
+        let target_dir_path = format!("{test_dir}/root_pdfs");
+        let output_path = format!("{target_dir_path}.pdf");
+        let compressed_output_path = format!("{target_dir_path}-compressed.pdf");
+        let options = MergeOptions {
+            with_outlines: Some(false),
+            ..Default::default()
+        };
+
+        let minus_one = |n: u8| n - 1;
+        test_support::generate_fn_tree_with_levels(&target_dir_path, 3, 4, 2, 4, &minus_one)?;
+
+        let mut main_doc = get_merged_tree_doc(target_dir_path, &options)?;
+
+        {
+            let mut buffer = Vec::new();
+            main_doc.save_modern(&mut buffer)?;
+            std::fs::write(&output_path, buffer)?;
+
+            main_doc.compress();
+
+            let mut buffer = Vec::new();
+            main_doc.save_modern(&mut buffer)?;
+            std::fs::write(&compressed_output_path, buffer)?;
+        }
+
+        assert!(test_support::validate_pdf(&output_path).is_err());
+        assert!(test_support::validate_pdf(&compressed_output_path).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn merged_with_outline_and_save_is_ok() -> Result<()> {
+        let test_dir = get_virgin_test_dir("merged_with_outline_and_save_is_ok")?;
+        let target_dir_path = format!("{test_dir}/root_pdfs");
+        let output_path = format!("{target_dir_path}.pdf");
+        let compressed_output_path = format!("{target_dir_path}-compressed.pdf");
+        let options = MergeOptions {
+            with_outlines: Some(true),
+            ..Default::default()
+        };
+
+        let minus_one = |n: u8| n - 1;
+        test_support::generate_fn_tree_with_levels(&target_dir_path, 3, 4, 2, 4, &minus_one)?;
+
+        let mut main_doc = get_merged_tree_doc(target_dir_path, &options)?;
+
+        main_doc.save(&output_path)?;
+
+        main_doc.compress();
+
+        main_doc.save(&compressed_output_path)?;
+
+        test_support::validate_pdf(&output_path)?;
+        test_support::validate_pdf(&compressed_output_path)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn merged_without_outline_and_save_is_ok() -> Result<()> {
+        let test_dir = get_virgin_test_dir("merged_without_outline_and_save_is_ok")?;
+        let target_dir_path = format!("{test_dir}/root_pdfs");
+        let output_path = format!("{target_dir_path}.pdf");
+        let compressed_output_path = format!("{target_dir_path}-compressed.pdf");
+        let options = MergeOptions {
+            with_outlines: Some(false),
+            ..Default::default()
+        };
+
+        let minus_one = |n: u8| n - 1;
+        test_support::generate_fn_tree_with_levels(&target_dir_path, 3, 4, 2, 4, &minus_one)?;
+
+        let mut main_doc = get_merged_tree_doc(target_dir_path, &options)?;
+
+        main_doc.save(&output_path)?;
+
+        main_doc.compress();
+
+        main_doc.save(&compressed_output_path)?;
+
+        test_support::validate_pdf(&output_path)?;
+        test_support::validate_pdf(&compressed_output_path)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn outlines_of_the_sources_are_kept() -> Result<()> {
+        let test_dir = get_virgin_test_dir("outlines_of_the_sources_are_kept")?;
+        let root_tree = format!("{test_dir}/root_pdfs");
+
+        let identity_function = |n: u8| n;
+        test_support::generate_fn_tree_with_levels(&root_tree, 3, 2, 0, 4, &identity_function)?;
+
+        let output_path = format!("{root_tree}.pdf");
+        let options = MergeOptions {
+            with_outlines: Some(true),
+            ..Default::default()
+        };
+        let mut main_doc = get_merged_tree_doc(root_tree, &options)?;
+        main_doc.compress();
+        main_doc.save(&output_path)?;
+        let source_entries = outline::read_outline(&main_doc)?;
+
+        let main_doc = get_merged_tree_doc(test_dir, &options)?;
+        let entries = outline::read_outline(&main_doc)?;
+        let file_index = entries
+            .iter()
+            .position(|entry| entry.title == "root_pdfs.pdf")
+            .unwrap();
+        let file_entry = &entries[file_index];
+        let kept_entries: Vec<&outline::OutlineEntry> = entries[file_index + 1..]
+            .iter()
+            .take_while(|entry| entry.level > file_entry.level)
+            .collect();
+
+        assert_eq!(kept_entries.len(), source_entries.len());
+        assert_eq!(kept_entries[0].title, source_entries[0].title);
+        assert_eq!(kept_entries[0].level, file_entry.level + 1);
+        assert_eq!(kept_entries[0].page, file_entry.page);
+
+        Ok(())
+    }
+
+    #[test]
+    fn page_bookmarks_below_file_bookmarks() -> Result<()> {
+        let test_dir = get_virgin_test_dir("page_bookmarks_below_file_bookmarks")?;
+        let root_tree = format!("{test_dir}/root_pdfs");
+        std::fs::create_dir_all(&root_tree)?;
+        test_support::get_basic_pdf_doc("scan", 3)?.save(format!("{root_tree}/scan.pdf"))?;
+
+        let options = MergeOptions {
+            page_bookmarks: true,
+            ..Default::default()
+        };
+        let main_doc = get_merged_tree_doc(&root_tree, &options)?;
+
+        let entries = outline::read_outline(&main_doc)?;
+        let file_index = entries
+            .iter()
+            .position(|entry| entry.title == "scan.pdf")
+            .unwrap();
+        let page_level = entries[file_index].level + 1;
+        let page_entries: Vec<(usize, &str, Option<u32>)> = entries[file_index + 1..]
+            .iter()
+            .map(|entry| (entry.level, entry.title.as_str(), entry.page))
+            .collect();
+        assert_eq!(
+            page_entries,
+            vec![
+                (page_level, "Page 1", Some(1)),
+                (page_level, "Page 2", Some(2)),
+                (page_level, "Page 3", Some(3))
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn non_pdf_files_are_skipped() -> Result<()> {
+        let test_dir = get_virgin_test_dir("non_pdf_files_are_skipped")?;
+        let root_tree = format!("{test_dir}/root_pdfs");
+
+        let identity_function = |n: u8| n;
+        test_support::generate_fn_tree_with_levels(&root_tree, 3, 2, 0, 4, &identity_function)?;
+
+        let options = MergeOptions {
+            with_outlines: Some(true),
+            ..Default::default()
+        };
+        let pdf_page_count = get_merged_tree_doc(&root_tree, &options)?.get_pages().len();
+
+        let text_file_path = format!("{root_tree}/text_file.txt");
+        let random_text = test_support::craft_random_text_of_len(20);
+        std::fs::write(text_file_path, random_text.as_bytes())?;
+        // Recognised by its header despite the missing extension
+        let mut scan = test_support::get_basic_pdf_doc("scan", 2)?;
+        scan.save(format!("{root_tree}/scan"))?;
+
+        let main_doc = get_merged_tree_doc(&root_tree, &options)?;
+        assert_eq!(main_doc.get_pages().len(), pdf_page_count + 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn case_only_collisions_follow_policy() -> Result<()> {
+        let test_dir = get_virgin_test_dir("case_only_collisions_follow_policy")?;
+        let root_tree = format!("{test_dir}/root_pdfs");
+        std::fs::create_dir_all(&root_tree)?;
+
+        for doc_name in ["Report.pdf", "report.pdf"] {
+            let mut doc = test_support::get_basic_pdf_doc(doc_name, 2)?;
+            doc.save(format!("{root_tree}/{doc_name}"))?;
+        }
+
+        let failing_options = MergeOptions {
+            collision_policy: CollisionPolicy::Fail,
+            ..Default::default()
+        };
+        assert!(get_merged_tree_doc(&root_tree, &failing_options).is_err());
+
+        let skipping_options = MergeOptions {
+            collision_policy: CollisionPolicy::Skip,
+            ..Default::default()
+        };
+        let (main_doc, report) = merge_to_document(&root_tree, &skipping_options)?;
+        assert_eq!(main_doc.get_pages().len(), 2);
+        assert_eq!(report.warnings.len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn signed_files_follow_policy() -> Result<()> {
+        let test_dir = get_virgin_test_dir("signed_files_follow_policy")?;
+        let signed_tree = format!("{test_dir}/signed");
+        let mixed_tree = format!("{test_dir}/mixed");
+        for (root_tree, with_text_field) in [(&signed_tree, false), (&mixed_tree, true)] {
+            std::fs::create_dir_all(root_tree)?;
+            let mut doc = test_support::get_basic_pdf_doc("signed.pdf", 2)?;
+            test_support::add_signed_form(&mut doc, with_text_field)?;
+            doc.save(format!("{root_tree}/signed.pdf"))?;
+            test_support::get_basic_pdf_doc("plain.pdf", 1)?
+                .save(format!("{root_tree}/plain.pdf"))?;
+        }
+
+        assert!(matches!(
+            merge_to_document(&signed_tree, &MergeOptions::default()),
+            Err(MergeError::SignedDocument { .. })
+        ));
+
+        let warning_options = MergeOptions {
+            signed_policy: SignedPolicy::Warn,
+            ..Default::default()
+        };
+        let (main_doc, report) = merge_to_document(&signed_tree, &warning_options)?;
+        assert_eq!(main_doc.get_pages().len(), 3);
+        assert_eq!(report.warnings.len(), 1);
+        assert!(report.warnings[0].contains("signed.pdf"));
+        let signed_sources: Vec<(&Path, bool)> = report
+            .sources
+            .iter()
+            .map(|source| (source.path.as_path(), source.signed))
+            .collect();
+        assert_eq!(
+            signed_sources,
+            vec![
+                (Path::new("plain.pdf"), false),
+                (Path::new("signed.pdf"), true)
+            ]
+        );
+
+        let stripping_options = MergeOptions {
+            signed_policy: SignedPolicy::Strip,
+            ..Default::default()
+        };
+        let (main_doc, report) = merge_to_document(&signed_tree, &stripping_options)?;
+        assert_eq!(main_doc.get_pages().len(), 3);
+        assert!(report.warnings.is_empty());
+        assert!(!main_doc.catalog()?.has(b"AcroForm"));
+
+        // The text field stays after the signature is stripped, and forms are not merged
+        assert!(matches!(
+            merge_to_document(&mixed_tree, &stripping_options),
+            Err(MergeError::FormFieldsLeft { path }) if path.ends_with("signed.pdf")
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn encrypted_files_need_their_password() -> Result<()> {
+        let test_dir = get_virgin_test_dir("encrypted_files_need_their_password")?;
+        let root_tree = format!("{test_dir}/root_pdfs");
+        std::fs::create_dir_all(&root_tree)?;
+        test_support::get_basic_pdf_doc("plain.pdf", 1)?.save(format!("{root_tree}/plain.pdf"))?;
+        let mut doc = test_support::get_basic_pdf_doc("locked.pdf", 2)?;
+        test_support::encrypt_with_password(&mut doc, "s3cret")?;
+        doc.save(format!("{root_tree}/locked.pdf"))?;
+
+        let options_with_password =
+            |password: Option<&str>, skip_errors: bool| -> Result<MergeOptions> {
+                Ok(MergeOptions {
+                    passwords: match password {
+                        Some(password) => {
+                            PasswordMap::parse(&format!("\"locked.pdf\" = \"{password}\""))?
+                        }
+                        None => PasswordMap::default(),
+                    },
+                    skip_errors,
+                    ..Default::default()
+                })
+            };
+
+        let (main_doc, report) =
+            merge_to_document(&root_tree, &options_with_password(Some("s3cret"), false)?)?;
+        assert_eq!(main_doc.get_pages().len(), 3);
+        assert!(report.skipped.is_empty());
+
+        for password in [Some("wrong"), None] {
+            let err = merge_to_document(&root_tree, &options_with_password(password, false)?)
+                .err()
+                .ok_or(anyhow!(
+                    "The merge succeeded with the password {password:?}"
+                ))?;
+            assert!(err.to_string().contains("locked.pdf"));
+
+            let (main_doc, report) =
+                merge_to_document(&root_tree, &options_with_password(password, true)?)?;
+            assert_eq!(main_doc.get_pages().len(), 1);
+            assert_eq!(report.skipped.len(), 1);
+            assert_eq!(report.skipped[0].path, Path::new("locked.pdf"));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn flattened_roots_share_same_named_entries() -> Result<()> {
+        let test_dir = get_virgin_test_dir("flattened_roots_share_same_named_entries")?;
+        let roots = [format!("{test_dir}/first"), format!("{test_dir}/second")];
+        for (root, doc_name) in roots.iter().zip(["x.pdf", "y.pdf"]) {
+            std::fs::create_dir_all(format!("{root}/docs"))?;
+            test_support::get_basic_pdf_doc(doc_name, 1)?
+                .save(format!("{root}/docs/{doc_name}"))?;
+            test_support::get_basic_pdf_doc("same.pdf", 1)?.save(format!("{root}/same.pdf"))?;
+        }
+
+        for collision_policy in [
+            CollisionPolicy::Suffix,
+            CollisionPolicy::Fail,
+            CollisionPolicy::Skip,
+        ] {
+            let options = MergeOptions {
+                merge_roots: MergeRootsMode::Flatten,
+                collision_policy,
+                ..Default::default()
+            };
+            let (main_doc, report) = merge_roots_to_document(&roots, &options)?;
+
+            assert!(report.warnings.is_empty());
+            // The directories are merged as one, the files with the same name follow the roots
+            let source_paths: Vec<&Path> = report
+                .sources
+                .iter()
+                .map(|source| source.path.as_path())
+                .collect();
+            assert_eq!(
+                source_paths,
+                vec![
+                    Path::new("first/docs/x.pdf"),
+                    Path::new("second/docs/y.pdf"),
+                    Path::new("first/same.pdf"),
+                    Path::new("second/same.pdf"),
+                ]
+            );
+            let entries = outline::read_outline(&main_doc)?;
+            let titles: Vec<(usize, &str)> = entries
+                .iter()
+                .map(|entry| (entry.level, entry.title.as_str()))
+                .collect();
+            assert_eq!(
+                titles,
+                vec![
+                    (1, "docs"),
+                    (2, "x.pdf"),
+                    (2, "y.pdf"),
+                    (1, "same.pdf"),
+                    (1, "same.pdf (2)"),
+                ]
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn wrong_declared_page_count_is_fixed() -> Result<()> {
+        let test_dir = get_virgin_test_dir("wrong_declared_page_count_is_fixed")?;
+        let root_tree = format!("{test_dir}/root_pdfs");
+        std::fs::create_dir_all(&root_tree)?;
+
+        let mut doc = test_support::get_basic_pdf_doc("doc_name", 3)?;
+        let root_pages_id = page_tree::root_pages_id(&doc)?;
+        doc.get_object_mut(root_pages_id)?
+            .as_dict_mut()?
+            .set("Count", Object::Integer(30));
+        doc.save(format!("{root_tree}/doc.pdf"))?;
+
+        let (main_doc, report) = merge_to_document(&root_tree, &Default::default())?;
+        let main_root_pages = main_doc.get_dictionary(page_tree::root_pages_id(&main_doc)?)?;
+        assert_eq!(main_root_pages.get(b"Count")?.as_i64()?, 3);
+        assert_eq!(report.warnings.len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn streaming_merge_compresses_the_imported_streams() -> Result<()> {
+        let test_dir = get_virgin_test_dir("streaming_merge_compresses_the_imported_streams")?;
+        let root_tree = format!("{test_dir}/root_pdfs");
+        std::fs::create_dir_all(&root_tree)?;
+
+        let mut doc = test_support::get_basic_pdf_doc("doc_name", 2)?;
+        let logo_id = doc.add_object(lopdf::Stream::new(
+            dictionary! {"Type" => "XObject", "Subtype" => "Form"},
+            b"0 0 10 10 re f\n".repeat(100),
+        ));
+        let first_page_id = doc.page_iter().next().unwrap();
+        doc.get_object_mut(first_page_id)?
+            .as_dict_mut()?
+            .set("Logo", Object::Reference(logo_id));
+        doc.save(format!("{root_tree}/doc.pdf"))?;
+
+        let options = MergeOptions {
+            parallel_loading: true,
+            streaming: Some(CompressionLevel::Fast),
+            ..Default::default()
+        };
+        let (main_doc, _report) = merge_to_document(&root_tree, &options)?;
+        assert_eq!(main_doc.get_pages().len(), 2);
+        let first_page = main_doc.get_dictionary(main_doc.page_iter().next().unwrap())?;
+        let logo = main_doc
+            .get_object(first_page.get(b"Logo")?.as_reference()?)?
+            .as_stream()?;
+        assert!(logo.dict.has(b"Filter"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn sample_limits_stop_the_merge() -> Result<()> {
+        let test_dir = get_virgin_test_dir("sample_limits_stop_the_merge")?;
+        let root_tree = format!("{test_dir}/root_pdfs");
+        std::fs::create_dir_all(&root_tree)?;
+
+        for doc_name in ["a.pdf", "b.pdf", "c.pdf", "d.pdf"] {
+            let mut doc = test_support::get_basic_pdf_doc(doc_name, 3)?;
+            doc.save(format!("{root_tree}/{doc_name}"))?;
+        }
+
+        let files_options = MergeOptions {
+            limit_files: Some(2),
+            ..Default::default()
+        };
+        let (main_doc, report) = merge_to_document(&root_tree, &files_options)?;
+        assert_eq!(report.sources.len(), 2);
+        assert_eq!(main_doc.get_pages().len(), 6);
+
+        let pages_options = MergeOptions {
+            limit_pages: Some(7),
+            ..Default::default()
+        };
+        let (main_doc, report) = merge_to_document(&root_tree, &pages_options)?;
+        assert_eq!(report.sources.len(), 3);
+        assert_eq!(main_doc.get_pages().len(), 9);
+        assert_eq!(report.warnings.len(), 1);
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn pre_command_output_is_merged() -> Result<()> {
+        let test_dir = get_virgin_test_dir("pre_command_output_is_merged")?;
+        let root_tree = format!("{test_dir}/root_pdfs");
+        std::fs::create_dir_all(&root_tree)?;
+
+        let mut doc = test_support::get_basic_pdf_doc("doc_name", 2)?;
+        doc.save(format!("{root_tree}/doc.pdf"))?;
+
+        let copying_options = MergeOptions {
+            pre_command: Some("cp {in} {out}".to_string()),
+            ..Default::default()
+        };
+        let main_doc = get_merged_tree_doc(&root_tree, &copying_options)?;
+        assert_eq!(main_doc.get_pages().len(), 2);
+
+        let failing_options = MergeOptions {
+            pre_command: Some("false {in} {out}".to_string()),
+            ..Default::default()
+        };
+        assert!(get_merged_tree_doc(&root_tree, &failing_options).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn max_depth_limits_the_tree() -> Result<()> {
+        let test_dir = get_virgin_test_dir("max_depth_limits_the_tree")?;
+        let root_tree = format!("{test_dir}/root_pdfs");
+
+        let one_sibling = |_n: u8| 1;
+        test_support::generate_fn_tree_with_levels(&root_tree, 8, 1, 1, 1, &one_sibling)?;
+
+        assert!(get_merged_tree_doc(&root_tree, &MergeOptions::default()).is_err());
+
+        for max_depth in [0, 10] {
+            let options = MergeOptions {
+                max_depth: Some(max_depth),
+                ..Default::default()
+            };
+            assert!(get_merged_tree_doc(&root_tree, &options).is_ok());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn broken_files_are_skipped_on_request() -> Result<()> {
+        let test_dir = get_virgin_test_dir("broken_files_are_skipped_on_request")?;
+        let root_tree = format!("{test_dir}/root_pdfs");
+        std::fs::create_dir_all(&root_tree)?;
+
+        let mut doc = test_support::get_basic_pdf_doc("doc_name", 2)?;
+        doc.save(format!("{root_tree}/a.pdf"))?;
+        std::fs::write(format!("{root_tree}/b.pdf"), b"%PDF-1.7 truncated")?;
+
+        assert!(get_merged_tree_doc(&root_tree, &MergeOptions::default()).is_err());
+
+        let options = MergeOptions {
+            skip_errors: true,
+            ..Default::default()
+        };
+        let (main_doc, report) = merge_to_document(&root_tree, &options)?;
+        assert_eq!(main_doc.get_pages().len(), 2);
+        assert_eq!(report.skipped.len(), 1);
+        assert_eq!(report.skipped[0].path, Path::new("b.pdf"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn slow_parsing_is_reported_as_a_timeout() -> Result<()> {
+        let mut buffer = Vec::new();
+        test_support::get_basic_pdf_doc("doc_name", 50)?.save_to(&mut buffer)?;
+
+        let options = MergeOptions {
+            file_timeout: Some(Duration::from_nanos(1)),
+            ..Default::default()
+        };
+        let err = parse_document(Path::new("slow.pdf"), buffer, &options).unwrap_err();
+        assert!(err.is::<ParseTimeout>());
+        assert!(is_skippable_error(&err, &options));
+
+        Ok(())
+    }
+
+    #[test]
+    fn include_and_exclude_patterns() -> Result<()> {
+        let test_dir = get_virgin_test_dir("include_and_exclude_patterns")?;
+        let root_tree = format!("{test_dir}/root_pdfs");
+
+        for doc_path in [
+            "report-q1.pdf",
+            "notes.pdf",
+            "drafts/report-q2.pdf",
+            "q3/report-q3.pdf",
+        ] {
+            let doc_path = Path::new(&root_tree).join(doc_path);
+            std::fs::create_dir_all(doc_path.parent().unwrap_or(Path::new(&root_tree)))?;
+            let mut doc = test_support::get_basic_pdf_doc("doc_name", 1)?;
+            doc.save(doc_path)?;
+        }
+
+        let options = MergeOptions {
+            include_patterns: vec!["**/report*.pdf".to_string()],
+            exclude_patterns: vec!["**/drafts/**".to_string()],
+            ..Default::default()
+        };
+        let (main_doc, report) = merge_to_document(&root_tree, &options)?;
+
+        assert_eq!(main_doc.get_pages().len(), 2);
+        let merged_paths: Vec<&Path> = report
+            .sources
+            .iter()
+            .map(|source| source.path.as_path())
+            .collect();
+        assert_eq!(
+            merged_paths,
+            vec![Path::new("q3/report-q3.pdf"), Path::new("report-q1.pdf")]
+        );
+        assert!(!report.bookmark_ids.contains_key(Path::new("drafts")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn ignore_files_exclude_entries() -> Result<()> {
+        let test_dir = get_virgin_test_dir("ignore_files_exclude_entries")?;
+        let root_tree = format!("{test_dir}/root_pdfs");
+
+        for doc_path in ["a.pdf", "drafts/b.pdf", "misc/c.pdf", "misc/keep.pdf"] {
+            let doc_path = Path::new(&root_tree).join(doc_path);
+            std::fs::create_dir_all(doc_path.parent().unwrap_or(Path::new(&root_tree)))?;
+            let mut doc = test_support::get_basic_pdf_doc("doc_name", 1)?;
+            doc.save(doc_path)?;
+        }
+        std::fs::write(
+            format!("{root_tree}/{IGNORE_FILE_NAME}"),
+            "# work in progress\ndrafts/\n",
+        )?;
+        std::fs::write(
+            format!("{root_tree}/misc/{IGNORE_FILE_NAME}"),
+            "*.pdf\n!keep.pdf\n",
+        )?;
+
+        let (main_doc, report) = merge_to_document(&root_tree, &MergeOptions::default())?;
+
+        assert_eq!(main_doc.get_pages().len(), 2);
+        let merged_paths: Vec<&Path> = report
+            .sources
+            .iter()
+            .map(|source| source.path.as_path())
+            .collect();
+        assert_eq!(
+            merged_paths,
+            vec![Path::new("a.pdf"), Path::new("misc/keep.pdf")]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn entries_are_sorted_naturally() -> Result<()> {
+        let test_dir = get_virgin_test_dir("entries_are_sorted_naturally")?;
+        let root_tree = format!("{test_dir}/root_pdfs");
+        std::fs::create_dir_all(&root_tree)?;
+
+        for doc_name in ["chapter10.pdf", "chapter2.pdf", "chapter1.pdf"] {
+            let mut doc = test_support::get_basic_pdf_doc(doc_name, 1)?;
+            doc.save(format!("{root_tree}/{doc_name}"))?;
+        }
+
+        let merged_paths = |sort_order| -> Result<Vec<PathBuf>> {
+            let options = MergeOptions {
+                sort_order,
+                ..Default::default()
+            };
+            let (_main_doc, report) = merge_to_document(&root_tree, &options)?;
+            Ok(report
+                .sources
+                .into_iter()
+                .map(|source| source.path)
+                .collect())
+        };
+
+        assert_eq!(
+            merged_paths(SortOrder::Natural)?,
+            ["chapter1.pdf", "chapter2.pdf", "chapter10.pdf"].map(PathBuf::from)
+        );
+        assert_eq!(
+            merged_paths(SortOrder::Lexicographic)?,
+            ["chapter1.pdf", "chapter10.pdf", "chapter2.pdf"].map(PathBuf::from)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn entries_are_ordered_by_size() -> Result<()> {
+        let test_dir = get_virgin_test_dir("entries_are_ordered_by_size")?;
+        let root_tree = format!("{test_dir}/root_pdfs");
+
+        for (doc_path, num_pages) in [
+            ("a.pdf", 10),
+            ("b.pdf", 1),
+            ("c/c1.pdf", 2),
+            ("c/c2.pdf", 3),
+        ] {
+            let doc_path = Path::new(&root_tree).join(doc_path);
+            std::fs::create_dir_all(doc_path.parent().unwrap_or(Path::new(&root_tree)))?;
+            let mut doc = test_support::get_basic_pdf_doc("doc_name", num_pages)?;
+            doc.save(doc_path)?;
+        }
+
+        let merged_paths = |reverse_order| -> Result<Vec<PathBuf>> {
+            let options = MergeOptions {
+                order_by: OrderBy::Size,
+                reverse_order,
+                ..Default::default()
+            };
+            let (_main_doc, report) = merge_to_document(&root_tree, &options)?;
+            Ok(report
+                .sources
+                .into_iter()
+                .map(|source| source.path)
+                .collect())
+        };
+
+        assert_eq!(
+            merged_paths(false)?,
+            ["b.pdf", "c/c1.pdf", "c/c2.pdf", "a.pdf"].map(PathBuf::from)
+        );
+        assert_eq!(
+            merged_paths(true)?,
+            ["a.pdf", "c/c2.pdf", "c/c1.pdf", "b.pdf"].map(PathBuf::from)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn merge_following_a_plan() -> Result<()> {
+        let test_dir = get_virgin_test_dir("merge_following_a_plan")?;
+        std::fs::create_dir_all(format!("{test_dir}/scans"))?;
+
+        for (doc_path, num_pages) in [("intro.pdf", 1), ("scans/chapter1.pdf", 6)] {
+            let mut doc = test_support::get_basic_pdf_doc("doc_name", num_pages)?;
+            doc.save(format!("{test_dir}/{doc_path}"))?;
+        }
+        let plan_path = format!("{test_dir}/merge.yaml");
+        std::fs::write(
+            &plan_path,
+            "entries:\n\
+             - title: Part I\n  children:\n\
+             \x20 - path: intro.pdf\n    title: Introduction\n\
+             \x20 - path: scans/chapter1.pdf\n    pages: 2-3,6\n",
+        )?;
+
+        let plan = plan::MergePlan::load(&plan_path)?;
+        let (main_doc, report) = merge_plan_to_document(&plan, &MergeOptions::default())?;
+
+        assert_eq!(main_doc.get_pages().len(), 4);
+        assert_eq!(report.sources[1].path, Path::new("scans/chapter1.pdf"));
+        assert_eq!(report.sources[1].page_count, 3);
+
+        let entries = outline::read_outline(&main_doc)?;
+        let titles: Vec<(usize, &str)> = entries
+            .iter()
+            .map(|entry| (entry.level, entry.title.as_str()))
+            .collect();
+        assert_eq!(
+            titles,
+            vec![(1, "Part I"), (2, "Introduction"), (2, "chapter1.pdf")]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn merge_a_zip_archive() -> Result<()> {
+        use std::io::Write;
+
+        let test_dir = get_virgin_test_dir("merge_a_zip_archive")?;
+        let archive_path = format!("{test_dir}/bundle.zip");
+        let mut writer = zip::ZipWriter::new(std::fs::File::create(&archive_path)?);
+        for (member_path, num_pages) in [("scans/chapter1.pdf", 3), ("intro.pdf", 1)] {
+            let mut content = Vec::new();
+            test_support::get_basic_pdf_doc("doc_name", num_pages)?.save_to(&mut content)?;
+            writer.start_file(member_path, zip::write::SimpleFileOptions::default())?;
+            writer.write_all(&content)?;
+        }
+        writer.finish()?;
+
+        let (main_doc, report) =
+            merge_archive_to_document(&archive_path, &MergeOptions::default())?;
+
+        assert_eq!(main_doc.get_pages().len(), 4);
+        assert_eq!(report.sources[0].path, Path::new("intro.pdf"));
+        assert_eq!(report.sources[1].path, Path::new("scans/chapter1.pdf"));
+
+        let entries = outline::read_outline(&main_doc)?;
+        let titles: Vec<(usize, &str)> = entries
+            .iter()
+            .map(|entry| (entry.level, entry.title.as_str()))
+            .collect();
+        assert_eq!(
+            titles,
+            vec![
+                (1, "bundle"),
+                (2, "intro.pdf"),
+                (2, "scans"),
+                (3, "chapter1.pdf")
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn archive_members_follow_the_tree_options() -> Result<()> {
+        use std::io::Write;
+
+        let test_dir = get_virgin_test_dir("archive_members_follow_the_tree_options")?;
+        let archive_path = format!("{test_dir}/bundle.zip");
+        let mut writer = zip::ZipWriter::new(std::fs::File::create(&archive_path)?);
+        for member_path in [
+            "intro.pdf",
+            "Intro.pdf",
+            "drafts/old.pdf",
+            "notes/a.pdf",
+            "notes/skip-me.pdf",
+        ] {
+            let mut content = Vec::new();
+            test_support::get_basic_pdf_doc("member", 1)?.save_to(&mut content)?;
+            writer.start_file(member_path, zip::write::SimpleFileOptions::default())?;
+            writer.write_all(&content)?;
+        }
+        writer.start_file(IGNORE_FILE_NAME, zip::write::SimpleFileOptions::default())?;
+        writer.write_all(b"drafts/\n")?;
+        writer.finish()?;
+
+        let options = MergeOptions {
+            exclude_patterns: vec!["**/skip-*.pdf".to_string()],
+            reverse_order: true,
+            collision_policy: CollisionPolicy::Skip,
+            ..Default::default()
+        };
+        let (main_doc, report) = merge_archive_to_document(&archive_path, &options)?;
+
+        assert_eq!(main_doc.get_pages().len(), 2);
+        let source_paths: Vec<&Path> = report
+            .sources
+            .iter()
+            .map(|source| source.path.as_path())
+            .collect();
+        assert_eq!(
+            source_paths,
+            vec![Path::new("notes/a.pdf"), Path::new("intro.pdf")]
+        );
+        assert_eq!(report.warnings.len(), 1);
+
+        // By default the colliding names are told apart by a suffix
+        let (main_doc, _) = merge_archive_to_document(&archive_path, &MergeOptions::default())?;
+        let entries = outline::read_outline(&main_doc)?;
+        let titles: Vec<&str> = entries.iter().map(|entry| entry.title.as_str()).collect();
+        assert_eq!(
+            titles,
+            vec![
+                "bundle",
+                "Intro.pdf",
+                "intro.pdf (2)",
+                "notes",
+                "a.pdf",
+                "skip-me.pdf"
+            ]
+        );
+
+        let mtime_options = MergeOptions {
+            order_by: OrderBy::Mtime,
+            ..Default::default()
+        };
+        assert!(merge_archive_to_document(&archive_path, &mtime_options).is_err());
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn special_files_are_skipped() -> Result<()> {
+        let test_dir = get_virgin_test_dir("special_files_are_skipped")?;
+        let root_tree = format!("{test_dir}/root_pdfs");
+        std::fs::create_dir_all(&root_tree)?;
+
+        let mut doc = test_support::get_basic_pdf_doc("a.pdf", 2)?;
+        doc.save(format!("{root_tree}/a.pdf"))?;
+        let fifo_path = std::ffi::CString::new(format!("{root_tree}/pipe.pdf"))?;
+        // SAFETY: `mkfifo` only reads the nul-terminated path.
+        assert_eq!(unsafe { libc::mkfifo(fifo_path.as_ptr(), 0o644) }, 0);
+        std::os::unix::fs::symlink("missing.pdf", format!("{root_tree}/dangling.pdf"))?;
+
+        let (main_doc, report) = merge_to_document(&root_tree, &MergeOptions::default())?;
+
+        assert_eq!(main_doc.get_pages().len(), 2);
+        assert!(
+            report
+                .warnings
+                .iter()
+                .any(|warning| warning.ends_with("is a FIFO"))
+        );
+        assert!(
+            report
+                .warnings
+                .iter()
+                .any(|warning| warning.ends_with("is a broken symbolic link"))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn report_records_titles_and_page_ranges() -> Result<()> {
+        let test_dir = get_virgin_test_dir("report_records_titles_and_page_ranges")?;
+        let root_tree = format!("{test_dir}/root_pdfs");
+        std::fs::create_dir_all(&root_tree)?;
+
+        for (doc_name, num_pages) in [("a.pdf", 2), ("b.pdf", 3)] {
+            let mut doc = test_support::get_basic_pdf_doc(doc_name, num_pages)?;
+            doc.save(format!("{root_tree}/{doc_name}"))?;
+        }
+
+        let options = MergeOptions {
+            number_sections: true,
+            ..Default::default()
+        };
+        let (_main_doc, report) = merge_to_document(&root_tree, &options)?;
+
+        let b_source = &report.sources[1];
+        assert_eq!((b_source.first_page, b_source.last_page), (3, 5));
+        assert!(
+            b_source
+                .title
+                .as_deref()
+                .is_some_and(|title| title.ends_with("b.pdf"))
+        );
+
+        let saved_report = format!("{test_dir}/report.json");
+        report.save(&saved_report)?;
+        let loaded_report = MergeReport::load(&saved_report)?;
+        assert_eq!(loaded_report.sources[1].title, b_source.title);
+
+        Ok(())
+    }
+
+    #[test]
+    fn source_footers_follow_the_front_matter() -> Result<()> {
+        let test_dir = get_virgin_test_dir("source_footers_follow_the_front_matter")?;
+        let root_tree = format!("{test_dir}/root_pdfs");
+        std::fs::create_dir_all(&root_tree)?;
+
+        for (doc_name, num_pages) in [("a.pdf", 1), ("b.pdf", 2)] {
+            let mut doc = test_support::get_basic_pdf_doc(doc_name, num_pages)?;
+            doc.save(format!("{root_tree}/{doc_name}"))?;
+        }
+
+        let options = MergeOptions {
+            toc_pages: true,
+            source_footers: Some(stamp::StampPosition::BottomLeft),
+            ..Default::default()
+        };
+        let (main_doc, report) = merge_to_document(&root_tree, &options)?;
+
+        let pages = main_doc.get_pages();
+        assert_eq!(pages.len(), 4);
+        let toc_content =
+            String::from_utf8_lossy(&main_doc.get_page_content(pages[&1])?).to_string();
+        assert!(!toc_content.contains(", p. "));
+        assert_eq!(report.sources[1].first_page, 3);
+        let last_content =
+            String::from_utf8_lossy(&main_doc.get_page_content(pages[&4])?).to_string();
+        assert!(last_content.contains("(b.pdf, p. 2/2)"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn dry_run_plans_without_merging() -> Result<()> {
+        let test_dir = get_virgin_test_dir("dry_run_plans_without_merging")?;
+        let root_tree = format!("{test_dir}/root_pdfs");
+        std::fs::create_dir_all(format!("{root_tree}/sub"))?;
+
+        for (doc_path, num_pages) in [("a.pdf", 2), ("sub/b.pdf", 3)] {
+            let mut doc = test_support::get_basic_pdf_doc("doc_name", num_pages)?;
+            doc.save(format!("{root_tree}/{doc_path}"))?;
+        }
+
+        let (planned_entries, _report) = plan_merge(&[&root_tree], &MergeOptions::default())?;
+
+        assert_eq!(planned_entries.len(), 1);
+        assert_eq!(planned_entries[0].page_count, 5);
+        let titles: Vec<&str> = planned_entries[0]
+            .children
+            .iter()
+            .map(|planned_entry| planned_entry.title.as_str())
+            .collect();
+        assert_eq!(titles, vec!["a.pdf", "sub"]);
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn max_open_files_defaults_to_half_the_limit() {
+        let mut rlimit = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        // SAFETY: `getrlimit` only writes into the struct it is given.
+        assert_eq!(
+            unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut rlimit) },
+            0
+        );
+        let half_limit = usize::try_from(rlimit.rlim_cur / 2).unwrap_or(usize::MAX);
+
+        assert_eq!(MergeOptions::default().max_open_files(), half_limit.max(1));
+        let options = MergeOptions {
+            max_open_files: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(options.max_open_files(), 1);
+    }
+
+    #[test]
+    fn transient_io_errors_are_retried() {
+        use std::io::{Error, ErrorKind};
+
+        let options = MergeOptions {
+            io_retries: 2,
+            retry_backoff: Duration::from_millis(1),
+            ..Default::default()
+        };
+        let path = Path::new("remote.pdf");
+
+        let mut attempts = 0;
+        let result = retry_transient_io(path, &options, || {
+            attempts += 1;
+            match attempts {
+                1 | 2 => Err(Error::from(ErrorKind::TimedOut)),
+                _ => Ok(attempts),
+            }
+        });
+        assert_eq!(result.unwrap(), 3);
+
+        // Give up once the retries are exhausted
+        let mut attempts = 0;
+        let result: std::io::Result<()> = retry_transient_io(path, &options, || {
+            attempts += 1;
+            Err(Error::from(ErrorKind::ConnectionReset))
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts, 3);
+
+        // Do not retry the permanent errors
+        let mut attempts = 0;
+        let result: std::io::Result<()> = retry_transient_io(path, &options, || {
+            attempts += 1;
+            Err(Error::from(ErrorKind::NotFound))
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn root_files_are_grouped_under_a_section() -> Result<()> {
+        let test_dir = get_virgin_test_dir("root_files_are_grouped_under_a_section")?;
+        let root_tree = format!("{test_dir}/root_pdfs");
+
+        for doc_path in ["z.pdf", "a/x.pdf"] {
+            let doc_path = Path::new(&root_tree).join(doc_path);
+            std::fs::create_dir_all(doc_path.parent().unwrap_or(Path::new(&root_tree)))?;
+            test_support::get_basic_pdf_doc("doc_name", 1)?.save(doc_path)?;
+        }
+
+        let options = MergeOptions {
+            root_files_section: Some("Loose files".to_string()),
+            ..Default::default()
+        };
+        let (main_doc, _report) = merge_to_document(&root_tree, &options)?;
+        let entries: Vec<(usize, String)> = outline::read_outline(&main_doc)?
+            .into_iter()
+            .map(|entry| (entry.level, entry.title))
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                (1, "root_pdfs"),
+                (2, "Loose files"),
+                (3, "z.pdf"),
+                (2, "a"),
+                (3, "x.pdf"),
+            ]
+            .map(|(level, title)| (level, title.to_string()))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn appendix_map_lists_the_page_ranges() -> Result<()> {
+        let test_dir = get_virgin_test_dir("appendix_map_lists_the_page_ranges")?;
+        let root_tree = format!("{test_dir}/root_pdfs");
+
+        for (doc_path, num_pages) in [("a.pdf", 2), ("sub/b.pdf", 3)] {
+            let doc_path = Path::new(&root_tree).join(doc_path);
+            std::fs::create_dir_all(doc_path.parent().unwrap_or(Path::new(&root_tree)))?;
+            test_support::get_basic_pdf_doc("doc_name", num_pages)?.save(doc_path)?;
+        }
+
+        let options = MergeOptions {
+            appendix_map: true,
+            ..Default::default()
+        };
+        let (main_doc, _report) = merge_to_document(&root_tree, &options)?;
+        let entries = outline::read_outline(&main_doc)?;
+        let source_map: Vec<(usize, &str, Option<u32>)> = entries
+            .iter()
+            .skip_while(|entry| entry.title != "Source map")
+            .map(|entry| (entry.level, entry.title.as_str(), entry.page))
+            .collect();
+        assert_eq!(
+            source_map,
+            vec![
+                (1, "Source map", Some(1)),
+                (2, "a.pdf (pages 1\u{2013}2)", Some(1)),
+                (2, "sub/b.pdf (pages 3\u{2013}5)", Some(3)),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn outlines_are_built_unless_disabled() -> Result<()> {
+        let test_dir = get_virgin_test_dir("outlines_are_built_unless_disabled")?;
+        let root_tree = format!("{test_dir}/root_pdfs");
+        std::fs::create_dir_all(&root_tree)?;
+        test_support::get_basic_pdf_doc("doc_name", 1)?.save(format!("{root_tree}/a.pdf"))?;
+
+        for (with_outlines, expected_outlines) in
+            [(None, true), (Some(true), true), (Some(false), false)]
+        {
+            let options = MergeOptions {
+                with_outlines,
+                ..Default::default()
+            };
+            let (main_doc, _report) = merge_to_document(&root_tree, &options)?;
+            assert_eq!(
+                main_doc.catalog()?.has(b"Outlines"),
+                expected_outlines,
+                "with_outlines: {with_outlines:?}"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn front_matter_is_bookmarked_first() -> Result<()> {
+        let test_dir = get_virgin_test_dir("front_matter_is_bookmarked_first")?;
+        let root_tree = format!("{test_dir}/root_pdfs");
+        std::fs::create_dir_all(&root_tree)?;
+        test_support::get_basic_pdf_doc("doc_name", 2)?.save(format!("{root_tree}/a.pdf"))?;
+
+        let top_level_entries = |skip_frontmatter_bookmarks| -> Result<Vec<(String, u32)>> {
+            let options = MergeOptions {
+                contact_sheet: true,
+                toc_pages: true,
+                skip_frontmatter_bookmarks,
+                ..Default::default()
+            };
+            let (main_doc, _report) = merge_to_document(&root_tree, &options)?;
+            Ok(outline::read_outline(&main_doc)?
+                .into_iter()
+                .filter(|entry| entry.level == 1)
+                .map(|entry| (entry.title, entry.page.unwrap_or(0)))
+                .collect())
+        };
+
+        assert_eq!(
+            top_level_entries(false)?,
+            [("Contents", 1), ("Contact sheet", 2), ("root_pdfs", 3)]
+                .map(|(title, page)| (title.to_string(), page))
+        );
+        assert_eq!(top_level_entries(true)?, vec![("root_pdfs".to_string(), 3)]);
+
+        Ok(())
+    }
+}
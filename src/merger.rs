@@ -0,0 +1,175 @@
+use crate::compression::{self, CompressionLevel};
+use crate::error::MergeError;
+use crate::locale::Locale;
+use crate::merge::{MergeOptions, MergeRootsMode, merge_roots_to_document};
+use crate::page_tree::PageTreeLayout;
+use crate::report::MergeReport;
+use crate::skip_list::SkipList;
+use crate::text::TitleEncoding;
+use crate::utils;
+use lopdf::Document;
+use std::path::{Path, PathBuf};
+
+/// Entry point for merging trees from other programs: set up the roots and the options,
+/// then `merge` into a document or `merge_to_file`.
+///
+/// ```no_run
+/// # use pdfunite_tree::merger::TreeMerger;
+/// let report = TreeMerger::new("lectures")
+///     .with_outlines(true)
+///     .parallel_loading(true)
+///     .merge_to_file("lectures-united.pdf")?;
+/// # Ok::<(), pdfunite_tree::error::MergeError>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct TreeMerger {
+    roots: Vec<PathBuf>,
+    options: MergeOptions,
+    compression: CompressionLevel,
+    overwrite: bool,
+}
+
+impl TreeMerger {
+    pub fn new(root: impl AsRef<Path>) -> Self {
+        TreeMerger {
+            roots: vec![root.as_ref().to_path_buf()],
+            options: MergeOptions::default(),
+            compression: CompressionLevel::default(),
+            overwrite: false,
+        }
+    }
+
+    /// Merge also this tree, arranged with the others according to `merge_roots`.
+    pub fn add_root(mut self, root: impl AsRef<Path>) -> Self {
+        self.roots.push(root.as_ref().to_path_buf());
+        self
+    }
+
+    /// Replace all the options, for the settings without a dedicated method.
+    pub fn options(mut self, options: MergeOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Force the presence (failing if it would be empty) or the absence of the ToC.
+    pub fn with_outlines(mut self, with_outlines: bool) -> Self {
+        self.options.with_outlines = Some(with_outlines);
+        self
+    }
+
+    /// Maximum depth of the directory tree, 0 for no limit.
+    pub fn max_depth(mut self, max_depth: u32) -> Self {
+        self.options.max_depth = Some(max_depth);
+        self
+    }
+
+    pub fn paranoid(mut self, paranoid: bool) -> Self {
+        self.options.paranoid = paranoid;
+        self
+    }
+
+    pub fn parallel_loading(mut self, parallel_loading: bool) -> Self {
+        self.options.parallel_loading = parallel_loading;
+        self
+    }
+
+    pub fn skip_list(mut self, skip_list: SkipList) -> Self {
+        self.options.skip_list = skip_list;
+        self
+    }
+
+    pub fn title_encoding(mut self, title_encoding: TitleEncoding) -> Self {
+        self.options.title_encoding = title_encoding;
+        self
+    }
+
+    pub fn merge_roots(mut self, merge_roots: MergeRootsMode) -> Self {
+        self.options.merge_roots = merge_roots;
+        self
+    }
+
+    pub fn page_tree_layout(mut self, page_tree_layout: PageTreeLayout) -> Self {
+        self.options.page_tree_layout = page_tree_layout;
+        self
+    }
+
+    pub fn locale(mut self, locale: Locale) -> Self {
+        self.options.locale = locale;
+        self
+    }
+
+    /// Compression of the streams applied by `merge_to_file`.
+    pub fn compression(mut self, compression: CompressionLevel) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Let `merge_to_file` replace an existing file (only once the new one is completely
+    /// written).
+    pub fn overwrite(mut self, overwrite: bool) -> Self {
+        self.overwrite = overwrite;
+        self
+    }
+
+    /// Merge the trees into an uncompressed document.
+    pub fn merge(&self) -> Result<Document, MergeError> {
+        let (main_doc, _report) = self.merge_with_report()?;
+        Ok(main_doc)
+    }
+
+    /// Merge the trees into an uncompressed document, with the record of the merged files.
+    pub fn merge_with_report(&self) -> Result<(Document, MergeReport), MergeError> {
+        merge_roots_to_document(&self.roots, &self.options)
+    }
+
+    /// Merge the trees, then compress and save the document to the path, which must not
+    /// exist yet unless `overwrite` is set.
+    pub fn merge_to_file(&self, output_path: impl AsRef<Path>) -> Result<MergeReport, MergeError> {
+        let output_path = output_path.as_ref();
+        if !self.overwrite && std::fs::exists(output_path)? {
+            return Err(MergeError::OutputExists {
+                path: output_path.to_path_buf(),
+            });
+        }
+
+        let (mut main_doc, report) = self.merge_with_report()?;
+        compression::compress_streams(&mut main_doc, self.compression)?;
+        utils::save_atomically(&mut main_doc, output_path)?;
+
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support;
+
+    #[test]
+    fn builder_merges_to_file() -> anyhow::Result<()> {
+        let test_dir = test_support::get_virgin_test_dir("builder_merges_to_file")?;
+        let root_tree = format!("{test_dir}/root_pdfs");
+        let output_path = format!("{test_dir}/root_pdfs.pdf");
+        std::fs::create_dir_all(&root_tree)?;
+
+        for doc_name in ["a.pdf", "b.pdf"] {
+            let mut doc = test_support::get_basic_pdf_doc(doc_name, 2)?;
+            doc.save(format!("{root_tree}/{doc_name}"))?;
+        }
+
+        let merger = TreeMerger::new(&root_tree)
+            .with_outlines(true)
+            .compression(CompressionLevel::Fast);
+        assert_eq!(merger.merge()?.get_pages().len(), 4);
+
+        let report = merger.merge_to_file(&output_path)?;
+        assert_eq!(report.sources.len(), 2);
+        assert_eq!(Document::load(&output_path)?.get_pages().len(), 4);
+        assert!(matches!(
+            merger.merge_to_file(&output_path),
+            Err(MergeError::OutputExists { .. })
+        ));
+
+        Ok(())
+    }
+}
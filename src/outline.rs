@@ -0,0 +1,858 @@
+use crate::page_tree;
+use crate::text::{TitleEncoding, decode_text_string, encode_text_string};
+use anyhow::{Result, anyhow};
+use lopdf::{Dictionary, Document, Object, ObjectId, dictionary};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+pub const BLACK_COLOR_RGB: [f32; 3] = [0f32; 3];
+pub const DEFAULT_TEXT_FORMAT: u32 = 0;
+
+/// How the view changes when a bookmark is clicked.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum DestView {
+    /// Fit the whole page in the window
+    #[default]
+    Fit,
+    /// Keep the zoom (and position) of the reader (`/XYZ null null null`)
+    #[value(skip)]
+    InheritZoom,
+    /// Jump to the top-left corner of the page, at the given zoom or keeping the one of
+    /// the reader (`/XYZ left top zoom`)
+    #[value(name = "xyz")]
+    TopLeft,
+    /// Fit the width of the page, from its top (`/FitH top`)
+    #[value(name = "fith")]
+    FitWidth,
+}
+
+impl DestView {
+    /// The explicit destination of the page with this view. `zoom` (1 for 100%) is used
+    /// only by `TopLeft`.
+    pub fn dest(self, page_id: ObjectId, media_box: [f32; 4], zoom: Option<f32>) -> Object {
+        let [left, _bottom, _right, top] = media_box;
+        let view = match self {
+            DestView::Fit => vec![Object::Name(b"Fit".to_vec())],
+            DestView::InheritZoom => vec![
+                Object::Name(b"XYZ".to_vec()),
+                Object::Null,
+                Object::Null,
+                Object::Null,
+            ],
+            DestView::TopLeft => vec![
+                Object::Name(b"XYZ".to_vec()),
+                Object::Real(left),
+                Object::Real(top),
+                zoom.map_or(Object::Null, Object::Real),
+            ],
+            DestView::FitWidth => vec![Object::Name(b"FitH".to_vec()), Object::Real(top)],
+        };
+
+        Object::Array(
+            std::iter::once(Object::Reference(page_id))
+                .chain(view)
+                .collect(),
+        )
+    }
+}
+
+/// The view of the bookmarks of each level (1 for the top-level bookmarks).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DestViews {
+    pub default: DestView,
+    pub per_level: BTreeMap<usize, DestView>,
+    /// Zoom of the `TopLeft` views (1 for 100%), `None` keeping the one of the reader.
+    pub zoom: Option<f32>,
+}
+
+impl DestViews {
+    pub fn for_level(&self, level: usize) -> DestView {
+        self.per_level.get(&level).copied().unwrap_or(self.default)
+    }
+}
+
+/// Bit of the text format of a bookmark for italic (see the PDF reference, table 153).
+pub const ITALIC_TEXT_FORMAT: u32 = 1;
+/// Bit of the text format of a bookmark for bold.
+pub const BOLD_TEXT_FORMAT: u32 = 2;
+
+/// Color and text format of a bookmark.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BookmarkStyle {
+    pub color: [f32; 3],
+    pub format: u32,
+}
+
+impl Default for BookmarkStyle {
+    fn default() -> Self {
+        BookmarkStyle {
+            color: BLACK_COLOR_RGB,
+            format: DEFAULT_TEXT_FORMAT,
+        }
+    }
+}
+
+impl BookmarkStyle {
+    /// Parse a style given as comma-separated `bold`, `italic`, `plain` and a color
+    /// `#rrggbb`, e.g. `bold,#1f4e79`.
+    pub fn parse(style: &str) -> Result<Self> {
+        let mut parsed = BookmarkStyle::default();
+
+        for token in style.split(',').map(str::trim) {
+            match token {
+                "bold" => parsed.format |= BOLD_TEXT_FORMAT,
+                "italic" => parsed.format |= ITALIC_TEXT_FORMAT,
+                "plain" => parsed.format = DEFAULT_TEXT_FORMAT,
+                _ => {
+                    let hex = token
+                        .strip_prefix('#')
+                        .filter(|hex| hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit()))
+                        .ok_or(anyhow!(
+                            "Unknown bookmark style '{token}' (use bold, italic, plain or #rrggbb)"
+                        ))?;
+                    for (component, channel) in parsed.color.iter_mut().enumerate() {
+                        let value = u8::from_str_radix(&hex[2 * component..2 * component + 2], 16)?;
+                        *channel = f32::from(value) / 255.0;
+                    }
+                }
+            }
+        }
+
+        Ok(parsed)
+    }
+}
+
+/// The style of the bookmarks: by level (1 for the top-level bookmarks) if given, else by
+/// kind, else black and plain. The bookmarks without an own destination (directories,
+/// sections) count as directories, the others as files.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BookmarkStyles {
+    pub directories: Option<BookmarkStyle>,
+    pub files: Option<BookmarkStyle>,
+    pub per_level: BTreeMap<usize, BookmarkStyle>,
+}
+
+impl BookmarkStyles {
+    pub fn for_item(&self, item: &OutlineItem, level: usize) -> Option<BookmarkStyle> {
+        let kind_style = match item.dest_page {
+            None => self.directories,
+            Some(_page_id) => self.files,
+        };
+        self.per_level.get(&level).copied().or(kind_style)
+    }
+}
+
+/// A node of the outline tree of the merged document. Nodes without an explicit
+/// destination (e.g. directories) point to the first page of their first descendant.
+#[derive(Debug, Clone)]
+pub struct OutlineItem {
+    pub title: String,
+    pub dest_page: Option<ObjectId>,
+    pub color: [f32; 3],
+    /// Bit 1 is italic, bit 2 is bold (see the PDF reference, table 153).
+    pub format: u32,
+    /// Whether the children of the item are shown when the document is opened.
+    pub open: bool,
+    pub dest_view: DestView,
+    /// Zoom of the destination, for the views which have one.
+    pub dest_zoom: Option<f32>,
+    pub children: Vec<OutlineItem>,
+    /// The file or directory the item was made from, if any.
+    pub source: Option<PathBuf>,
+}
+
+/// A bookmark title changed to tell it apart from a sibling with the same title.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TitleMapping {
+    pub source: Option<PathBuf>,
+    pub original_title: String,
+    pub title: String,
+}
+
+impl OutlineItem {
+    pub fn new(title: String, dest_page: Option<ObjectId>) -> Self {
+        OutlineItem {
+            title,
+            dest_page,
+            color: BLACK_COLOR_RGB,
+            format: DEFAULT_TEXT_FORMAT,
+            open: true,
+            dest_view: DestView::default(),
+            dest_zoom: None,
+            children: Vec::new(),
+            source: None,
+        }
+    }
+
+    /// The page the item jumps to: its own destination, or the one of its first descendant.
+    pub fn first_page(&self) -> Option<ObjectId> {
+        self.dest_page
+            .or_else(|| self.children.iter().find_map(|child| child.first_page()))
+    }
+
+    /// The items of an outline read from a document (see `outline_tree`), pointing to the
+    /// pages with the given numbers. The items whose page is unknown get no destination.
+    pub fn from_toc_nodes(
+        nodes: Vec<TocNode>,
+        page_ids: &BTreeMap<u32, ObjectId>,
+    ) -> Vec<OutlineItem> {
+        nodes
+            .into_iter()
+            .map(|node| {
+                let dest_page = node.page.and_then(|page| page_ids.get(&page).copied());
+                let mut item = OutlineItem::new(node.title, dest_page);
+                item.children = OutlineItem::from_toc_nodes(node.children, page_ids);
+                item
+            })
+            .collect()
+    }
+
+    pub fn with_source(mut self, source: impl AsRef<Path>) -> Self {
+        self.source = Some(source.as_ref().to_path_buf());
+        self
+    }
+
+    /// The source and the title of the item and of its descendants made from a file or
+    /// directory, in document order.
+    pub fn collect_source_titles<'a>(&'a self, source_titles: &mut Vec<(&'a Path, &'a str)>) {
+        if let Some(source) = &self.source {
+            source_titles.push((source, &self.title));
+        }
+        for child in &self.children {
+            child.collect_source_titles(source_titles);
+        }
+    }
+
+    /// Set the view of the destinations of the descendants according to their level (the
+    /// children of this item being at `children_level`).
+    pub fn set_descendant_dest_views(&mut self, dest_views: &DestViews, children_level: usize) {
+        for child in &mut self.children {
+            child.dest_view = dest_views.for_level(children_level);
+            child.dest_zoom = dest_views.zoom;
+            child.set_descendant_dest_views(dest_views, children_level + 1);
+        }
+    }
+
+    /// Show the children of the descendants up to `open_depth` levels (the children of this
+    /// item being at `children_level`), collapsing the deeper ones.
+    pub fn set_descendant_open_depth(&mut self, open_depth: usize, children_level: usize) {
+        for child in &mut self.children {
+            child.open = children_level <= open_depth;
+            child.set_descendant_open_depth(open_depth, children_level + 1);
+        }
+    }
+
+    /// Style the descendants according to their kind and level (the children of this item
+    /// being at `children_level`). The items without a style given keep theirs.
+    pub fn set_descendant_styles(&mut self, styles: &BookmarkStyles, children_level: usize) {
+        for child in &mut self.children {
+            if let Some(style) = styles.for_item(child, children_level) {
+                child.color = style.color;
+                child.format = style.format;
+            }
+            child.set_descendant_styles(styles, children_level + 1);
+        }
+    }
+
+    /// Make the titles of the siblings unique among the descendants, appending " (2)",
+    /// " (3)", ... to the repetitions of a title, in order. Returns the renamed items.
+    pub fn disambiguate_descendant_titles(&mut self) -> Vec<TitleMapping> {
+        let mut renamed = Vec::new();
+        let sibling_titles: HashSet<String> = self
+            .children
+            .iter()
+            .map(|child| child.title.clone())
+            .collect();
+        let mut used_titles = HashSet::new();
+
+        for child in &mut self.children {
+            if !used_titles.insert(child.title.clone()) {
+                let mut suffix = 2;
+                let new_title = loop {
+                    let candidate = format!("{} ({suffix})", child.title);
+                    if !used_titles.contains(&candidate) && !sibling_titles.contains(&candidate) {
+                        break candidate;
+                    }
+                    suffix += 1;
+                };
+                used_titles.insert(new_title.clone());
+                renamed.push(TitleMapping {
+                    source: child.source.clone(),
+                    original_title: std::mem::replace(&mut child.title, new_title.clone()),
+                    title: new_title,
+                });
+            }
+            renamed.extend(child.disambiguate_descendant_titles());
+        }
+
+        renamed
+    }
+
+    /// Prefix the titles of the descendants with their hierarchical number (`1`, `1.1`,
+    /// `1.1.2`, ...), counted from the children of this item.
+    pub fn number_descendants(&mut self) {
+        fn number_children(item: &mut OutlineItem, prefix: &str) {
+            for (index, child) in item.children.iter_mut().enumerate() {
+                let number = format!("{prefix}{}", index + 1);
+                child.title = format!("{number} {}", child.title);
+                number_children(child, &format!("{number}."));
+            }
+        }
+
+        number_children(self, "");
+    }
+
+    /// Number of descendants visible when the document is opened.
+    fn visible_descendants(&self) -> i64 {
+        if self.open {
+            self.descendants_if_opened()
+        } else {
+            0
+        }
+    }
+
+    /// Number of descendants which would be visible if this item were opened.
+    fn descendants_if_opened(&self) -> i64 {
+        self.children
+            .iter()
+            .map(|child| 1 + child.visible_descendants())
+            .sum()
+    }
+
+    /// The `/Count` entry of the item: positive if open, negative if closed.
+    fn count(&self) -> i64 {
+        if self.open {
+            self.descendants_if_opened()
+        } else {
+            -self.descendants_if_opened()
+        }
+    }
+}
+
+/// Write the Outlines dictionary and the linked lists of outline items into the document,
+/// returning the id of the Outlines dictionary (or `None` if there are no items at all).
+/// The Catalog is left untouched.
+pub fn build_outlines(
+    doc: &mut Document,
+    items: &[OutlineItem],
+    encoding: TitleEncoding,
+) -> Result<Option<ObjectId>> {
+    Ok(build_outlines_with_ids(doc, items, encoding)?
+        .map(|(outlines_id, _bookmark_ids)| outlines_id))
+}
+
+/// As `build_outlines`, returning also the id of the bookmark made from each source file
+/// or directory, to which further bookmarks can be attached with `append_child_bookmark`.
+pub fn build_outlines_with_ids(
+    doc: &mut Document,
+    items: &[OutlineItem],
+    encoding: TitleEncoding,
+) -> Result<Option<(ObjectId, BTreeMap<PathBuf, ObjectId>)>> {
+    if items.is_empty() {
+        return Ok(None);
+    }
+
+    let mut bookmark_ids = BTreeMap::new();
+    let outlines_id = doc.new_object_id();
+    let (first_id, last_id) =
+        add_outline_siblings(doc, items, outlines_id, encoding, &mut bookmark_ids)?;
+
+    let visible_count: i64 = items
+        .iter()
+        .map(|item| 1 + item.visible_descendants())
+        .sum();
+
+    let outlines = dictionary! {
+        "Type" => "Outlines",
+        "First" => Object::Reference(first_id),
+        "Last" => Object::Reference(last_id),
+        "Count" => Object::Integer(visible_count),
+    };
+    doc.objects
+        .insert(outlines_id, Object::Dictionary(outlines));
+
+    Ok(Some((outlines_id, bookmark_ids)))
+}
+
+/// Add the items as a linked list of siblings under the given parent, and recursively their
+/// children. Returns the ids of the first and last sibling.
+fn add_outline_siblings(
+    doc: &mut Document,
+    items: &[OutlineItem],
+    parent_id: ObjectId,
+    encoding: TitleEncoding,
+    bookmark_ids: &mut BTreeMap<PathBuf, ObjectId>,
+) -> Result<(ObjectId, ObjectId)> {
+    let item_ids: Vec<ObjectId> = items.iter().map(|_item| doc.new_object_id()).collect();
+
+    for (index, item) in items.iter().enumerate() {
+        let page_id = item.first_page().ok_or(anyhow!(
+            "The outline item '{}' does not point to any page",
+            item.title
+        ))?;
+
+        let mut item_dict = dictionary! {
+            "Title" => encode_text_string(&item.title, encoding),
+            "Parent" => Object::Reference(parent_id),
+            "Dest" => item.dest_view.dest(page_id, page_tree::media_box(doc, page_id), item.dest_zoom),
+        };
+
+        if index > 0 {
+            item_dict.set("Prev", Object::Reference(item_ids[index - 1]));
+        }
+        if let Some(&next_id) = item_ids.get(index + 1) {
+            item_dict.set("Next", Object::Reference(next_id));
+        }
+
+        if !item.children.is_empty() {
+            let (first_id, last_id) =
+                add_outline_siblings(doc, &item.children, item_ids[index], encoding, bookmark_ids)?;
+            item_dict.set("First", Object::Reference(first_id));
+            item_dict.set("Last", Object::Reference(last_id));
+            item_dict.set("Count", Object::Integer(item.count()));
+        }
+
+        set_style(&mut item_dict, item);
+
+        if let Some(source) = &item.source {
+            bookmark_ids.insert(source.clone(), item_ids[index]);
+        }
+        doc.objects
+            .insert(item_ids[index], Object::Dictionary(item_dict));
+    }
+
+    Ok((item_ids[0], item_ids[item_ids.len() - 1]))
+}
+
+/// Add a bookmark to the page as the last child of the bookmark (or of the Outlines
+/// dictionary), updating the counts of the visible items above it. A bookmark getting its
+/// first child is left open. Returns the id of the new bookmark.
+pub fn append_child_bookmark(
+    doc: &mut Document,
+    parent_id: ObjectId,
+    title: &str,
+    page_id: ObjectId,
+    encoding: TitleEncoding,
+) -> Result<ObjectId> {
+    let previous_last_id = doc
+        .get_dictionary(parent_id)?
+        .get(b"Last")
+        .and_then(Object::as_reference)
+        .ok();
+
+    let mut item_dict = dictionary! {
+        "Title" => encode_text_string(title, encoding),
+        "Parent" => Object::Reference(parent_id),
+        "Dest" => Object::Array(vec![Object::Reference(page_id), Object::Name(b"Fit".to_vec())]),
+    };
+    if let Some(previous_last_id) = previous_last_id {
+        item_dict.set("Prev", Object::Reference(previous_last_id));
+    }
+    let item_id = doc.add_object(item_dict);
+
+    match previous_last_id {
+        Some(previous_last_id) => doc
+            .get_object_mut(previous_last_id)?
+            .as_dict_mut()?
+            .set("Next", Object::Reference(item_id)),
+        None => doc
+            .get_object_mut(parent_id)?
+            .as_dict_mut()?
+            .set("First", Object::Reference(item_id)),
+    }
+    doc.get_object_mut(parent_id)?
+        .as_dict_mut()?
+        .set("Last", Object::Reference(item_id));
+
+    // The new item is visible up to the first closed ancestor, whose hidden count grows
+    let mut ancestor_id = Some(parent_id);
+    let mut visited = HashSet::new();
+    while let Some(node_id) = ancestor_id
+        && visited.insert(node_id)
+    {
+        let node = doc.get_object_mut(node_id)?.as_dict_mut()?;
+        let count = node.get(b"Count").and_then(Object::as_i64).unwrap_or(0);
+        if count < 0 {
+            node.set("Count", Object::Integer(count - 1));
+            break;
+        }
+        node.set("Count", Object::Integer(count + 1));
+        ancestor_id = node.get(b"Parent").and_then(Object::as_reference).ok();
+    }
+
+    Ok(item_id)
+}
+
+fn set_style(item_dict: &mut Dictionary, item: &OutlineItem) {
+    if item.color != BLACK_COLOR_RGB {
+        item_dict.set(
+            "C",
+            Object::Array(item.color.iter().map(|&c| Object::Real(c)).collect()),
+        );
+    }
+    if item.format != DEFAULT_TEXT_FORMAT {
+        item_dict.set("F", Object::Integer(item.format as i64));
+    }
+}
+
+/// An entry of the outline of an existing document, as read by `read_outline`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutlineEntry {
+    /// Nesting level, 1 for the top-level entries.
+    pub level: usize,
+    pub title: String,
+    /// Number (starting from 1) of the destination page, if it could be resolved.
+    pub page: Option<u32>,
+}
+
+/// An entry of the outline of an existing document with its descendants, as exported
+/// by `inspect toc`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TocNode {
+    pub title: String,
+    pub page: Option<u32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<TocNode>,
+}
+
+/// Rebuild the tree of the outline from the flat list of entries returned by `read_outline`.
+pub fn outline_tree(entries: &[OutlineEntry]) -> Vec<TocNode> {
+    fn build_siblings(entries: &[OutlineEntry], index: &mut usize, level: usize) -> Vec<TocNode> {
+        let mut siblings = Vec::new();
+
+        while let Some(entry) = entries.get(*index) {
+            if entry.level < level {
+                break;
+            }
+            *index += 1;
+
+            let children = build_siblings(entries, index, entry.level + 1);
+            siblings.push(TocNode {
+                title: entry.title.clone(),
+                page: entry.page,
+                children,
+            });
+        }
+
+        siblings
+    }
+
+    build_siblings(entries, &mut 0, 1)
+}
+
+/// Read the outline of the document as a flat list of entries in document order (each
+/// entry followed by its descendants). Returns an empty list if the document has no outline.
+pub fn read_outline(doc: &Document) -> Result<Vec<OutlineEntry>> {
+    let Ok(outlines) = doc.catalog()?.get(b"Outlines") else {
+        return Ok(Vec::new());
+    };
+    let outlines = doc.dereference(outlines)?.1.as_dict()?;
+
+    let page_numbers: HashMap<ObjectId, u32> = doc
+        .get_pages()
+        .into_iter()
+        .map(|(page_number, page_id)| (page_id, page_number))
+        .collect();
+
+    let mut entries = Vec::new();
+    let mut visited = HashSet::new();
+    let first_id = outlines.get(b"First").and_then(Object::as_reference).ok();
+    read_outline_siblings(doc, first_id, 1, &page_numbers, &mut entries, &mut visited)?;
+
+    Ok(entries)
+}
+
+fn read_outline_siblings(
+    doc: &Document,
+    first_id: Option<ObjectId>,
+    level: usize,
+    page_numbers: &HashMap<ObjectId, u32>,
+    entries: &mut Vec<OutlineEntry>,
+    visited: &mut HashSet<ObjectId>,
+) -> Result<()> {
+    let mut next_id = first_id;
+
+    while let Some(item_id) = next_id {
+        if !visited.insert(item_id) {
+            return Err(anyhow!(
+                "The outline contains a cycle through the object {item_id:?}"
+            ));
+        }
+
+        let item = doc.get_dictionary(item_id)?;
+        let title = item
+            .get(b"Title")
+            .and_then(|title| doc.dereference(title))
+            .and_then(|(_id, title)| title.as_str())
+            .map(decode_text_string)
+            .unwrap_or_default();
+        let page =
+            destination_page_id(doc, item).and_then(|page_id| page_numbers.get(&page_id).copied());
+        entries.push(OutlineEntry { level, title, page });
+
+        let first_child_id = item.get(b"First").and_then(Object::as_reference).ok();
+        read_outline_siblings(
+            doc,
+            first_child_id,
+            level + 1,
+            page_numbers,
+            entries,
+            visited,
+        )?;
+
+        next_id = item.get(b"Next").and_then(Object::as_reference).ok();
+    }
+
+    Ok(())
+}
+
+/// The page of an explicit destination, given either directly by `/Dest` or by a GoTo action.
+/// Named destinations are not resolved.
+fn destination_page_id(doc: &Document, item: &Dictionary) -> Option<ObjectId> {
+    let dest = match item.get(b"Dest") {
+        Ok(dest) => dest,
+        Err(_no_dest) => {
+            let action = doc
+                .dereference(item.get(b"A").ok()?)
+                .ok()?
+                .1
+                .as_dict()
+                .ok()?;
+            action.get(b"D").ok()?
+        }
+    };
+
+    let dest = doc.dereference(dest).ok()?.1;
+    dest.as_array().ok()?.first()?.as_reference().ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support;
+
+    #[test]
+    fn repeated_sibling_titles_get_suffixes() {
+        let mut root_item = OutlineItem::new("root".to_string(), None);
+        root_item.children = ["notes", "notes (2)", "notes", "notes"]
+            .map(|title| OutlineItem::new(title.to_string(), None))
+            .to_vec();
+
+        let renamed = root_item.disambiguate_descendant_titles();
+
+        let titles: Vec<&str> = root_item
+            .children
+            .iter()
+            .map(|child| child.title.as_str())
+            .collect();
+        assert_eq!(titles, vec!["notes", "notes (2)", "notes (3)", "notes (4)"]);
+        assert_eq!(renamed.len(), 2);
+    }
+
+    #[test]
+    fn hierarchical_numbers() {
+        let mut chapter = OutlineItem::new("chapter".to_string(), None);
+        chapter.children = vec![
+            OutlineItem::new("a.pdf".to_string(), None),
+            OutlineItem::new("b.pdf".to_string(), None),
+        ];
+        let mut root_item = OutlineItem::new("root".to_string(), None);
+        root_item.children = vec![OutlineItem::new("intro.pdf".to_string(), None), chapter];
+
+        root_item.number_descendants();
+
+        assert_eq!(root_item.title, "root");
+        assert_eq!(root_item.children[0].title, "1 intro.pdf");
+        assert_eq!(root_item.children[1].title, "2 chapter");
+        assert_eq!(root_item.children[1].children[1].title, "2.2 b.pdf");
+    }
+
+    #[test]
+    fn counts_of_open_and_closed_items() -> Result<()> {
+        let mut doc = test_support::get_basic_pdf_doc("doc_name", 3)?;
+        let pages: Vec<ObjectId> = doc.get_pages().values().cloned().collect();
+
+        let mut closed_item = OutlineItem::new("closed".to_string(), None);
+        closed_item.open = false;
+        closed_item.children = vec![
+            OutlineItem::new("p2".to_string(), Some(pages[1])),
+            OutlineItem::new("p3".to_string(), Some(pages[2])),
+        ];
+        let mut root_item = OutlineItem::new("root".to_string(), None);
+        root_item.children = vec![
+            OutlineItem::new("p1".to_string(), Some(pages[0])),
+            closed_item,
+        ];
+
+        let outlines_id = build_outlines(&mut doc, &[root_item], TitleEncoding::Auto)?
+            .ok_or(anyhow!("No outlines built"))?;
+
+        let outlines = doc.get_dictionary(outlines_id)?;
+        assert_eq!(outlines.get(b"Count")?.as_i64()?, 3);
+
+        let root_item_id = outlines.get(b"First")?.as_reference()?;
+        let root_item = doc.get_dictionary(root_item_id)?;
+        assert_eq!(root_item.get(b"Count")?.as_i64()?, 2);
+
+        let closed_item_id = root_item.get(b"Last")?.as_reference()?;
+        let closed_item = doc.get_dictionary(closed_item_id)?;
+        assert_eq!(closed_item.get(b"Count")?.as_i64()?, -2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn bookmarks_attached_by_source() -> Result<()> {
+        let mut doc = test_support::get_basic_pdf_doc("doc_name", 3)?;
+        let pages: Vec<ObjectId> = doc.get_pages().values().cloned().collect();
+
+        let mut closed_item = OutlineItem::new("closed".to_string(), None).with_source("closed");
+        closed_item.children.push(
+            OutlineItem::new("inner.pdf".to_string(), Some(pages[1]))
+                .with_source("closed/inner.pdf"),
+        );
+        closed_item.open = false;
+        let mut root_item = OutlineItem::new("root".to_string(), None).with_source("");
+        root_item.children = vec![
+            OutlineItem::new("open.pdf".to_string(), Some(pages[0])).with_source("open.pdf"),
+            closed_item,
+        ];
+
+        let (outlines_id, bookmark_ids) =
+            build_outlines_with_ids(&mut doc, &[root_item], TitleEncoding::Auto)?
+                .ok_or(anyhow!("No outlines built"))?;
+        assert_eq!(bookmark_ids.len(), 4);
+
+        // A bookmark getting its first child is left open
+        let open_id = bookmark_ids[Path::new("open.pdf")];
+        let heading_id =
+            append_child_bookmark(&mut doc, open_id, "Heading", pages[0], TitleEncoding::Auto)?;
+        assert_eq!(
+            doc.get_dictionary(open_id)?.get(b"First")?.as_reference()?,
+            heading_id
+        );
+        assert_eq!(doc.get_dictionary(open_id)?.get(b"Count")?.as_i64()?, 1);
+
+        // Under a closed bookmark the new one is hidden
+        let closed_id = bookmark_ids[Path::new("closed")];
+        assert_eq!(doc.get_dictionary(closed_id)?.get(b"Count")?.as_i64()?, -1);
+        append_child_bookmark(&mut doc, closed_id, "Hidden", pages[1], TitleEncoding::Auto)?;
+        assert_eq!(doc.get_dictionary(closed_id)?.get(b"Count")?.as_i64()?, -2);
+
+        // root, open.pdf, Heading and closed are visible
+        assert_eq!(doc.get_dictionary(outlines_id)?.get(b"Count")?.as_i64()?, 4);
+
+        Ok(())
+    }
+
+    #[test]
+    fn styles_by_kind_and_level() -> Result<()> {
+        let mut top_item = OutlineItem::new(String::new(), None);
+        let mut directory_item = OutlineItem::new("directory".to_string(), None);
+        directory_item
+            .children
+            .push(OutlineItem::new("file.pdf".to_string(), Some((1, 0))));
+        top_item.children.push(directory_item.clone());
+        top_item.children.push(directory_item);
+
+        let styles = BookmarkStyles {
+            directories: Some(BookmarkStyle::parse("bold")?),
+            files: None,
+            per_level: BTreeMap::from([(2, BookmarkStyle::parse("italic,#ff0000")?)]),
+        };
+        top_item.set_descendant_styles(&styles, 1);
+
+        assert_eq!(top_item.children[1].format, BOLD_TEXT_FORMAT);
+        let file_item = &top_item.children[1].children[0];
+        assert_eq!(file_item.format, ITALIC_TEXT_FORMAT);
+        assert_eq!(file_item.color, [1.0, 0.0, 0.0]);
+        assert!(BookmarkStyle::parse("#12345").is_err());
+        assert!(BookmarkStyle::parse("underlined").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn open_depth_collapses_the_deeper_items() -> Result<()> {
+        let mut doc = test_support::get_basic_pdf_doc("doc", 2)?;
+        let pages: Vec<ObjectId> = doc.get_pages().into_values().collect();
+
+        let mut inner_item = OutlineItem::new("inner".to_string(), None).with_source("outer/inner");
+        inner_item.children = vec![
+            OutlineItem::new("a.pdf".to_string(), Some(pages[0])),
+            OutlineItem::new("b.pdf".to_string(), Some(pages[1])),
+        ];
+        let mut outer_item = OutlineItem::new("outer".to_string(), None).with_source("outer");
+        outer_item.children.push(inner_item);
+        let mut top_item = OutlineItem::new(String::new(), None);
+        top_item.children.push(outer_item);
+
+        top_item.set_descendant_open_depth(1, 1);
+        let outer_item = &top_item.children[0];
+        assert!(outer_item.open);
+        assert!(!outer_item.children[0].open);
+        assert!(!outer_item.children[0].children[0].open);
+
+        let (outlines_id, bookmark_ids) =
+            build_outlines_with_ids(&mut doc, &top_item.children, TitleEncoding::Auto)?
+                .ok_or(anyhow!("No outlines built"))?;
+        // outer shows inner, whose two files are hidden
+        let outer_id = bookmark_ids[Path::new("outer")];
+        let inner_id = bookmark_ids[Path::new("outer/inner")];
+        assert_eq!(doc.get_dictionary(outer_id)?.get(b"Count")?.as_i64()?, 1);
+        assert_eq!(doc.get_dictionary(inner_id)?.get(b"Count")?.as_i64()?, -2);
+        assert_eq!(doc.get_dictionary(outlines_id)?.get(b"Count")?.as_i64()?, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn dest_views_per_level() -> Result<()> {
+        let doc = test_support::get_basic_pdf_doc("doc", 2)?;
+        let page_ids: Vec<ObjectId> = doc.get_pages().into_values().collect();
+
+        let mut top_item = OutlineItem::new(String::new(), None);
+        let mut directory_item = OutlineItem::new("directory".to_string(), None);
+        directory_item
+            .children
+            .push(OutlineItem::new("file.pdf".to_string(), Some(page_ids[1])));
+        top_item.children.push(directory_item);
+        top_item.set_descendant_dest_views(
+            &DestViews {
+                default: DestView::Fit,
+                per_level: BTreeMap::from([(2, DestView::InheritZoom)]),
+                zoom: None,
+            },
+            1,
+        );
+
+        assert_eq!(top_item.children[0].dest_view, DestView::Fit);
+        assert_eq!(
+            top_item.children[0].children[0].dest_view,
+            DestView::InheritZoom
+        );
+        let media_box = page_tree::media_box(&doc, page_ids[1]);
+        let dest = DestView::InheritZoom.dest(page_ids[1], media_box, None);
+        let dest = dest.as_array()?;
+        assert_eq!(dest.len(), 5);
+        assert_eq!(dest[1].as_name()?, b"XYZ");
+        assert!(
+            dest[2..]
+                .iter()
+                .all(|coordinate| matches!(coordinate, Object::Null))
+        );
+
+        let dest = DestView::TopLeft.dest(page_ids[1], media_box, Some(1.5));
+        let dest = dest.as_array()?;
+        assert_eq!(dest[1].as_name()?, b"XYZ");
+        assert_eq!(dest[3].as_float()?, media_box[3]);
+        assert_eq!(dest[4].as_float()?, 1.5);
+        let dest = DestView::FitWidth.dest(page_ids[1], media_box, None);
+        let dest = dest.as_array()?;
+        assert_eq!(dest[1].as_name()?, b"FitH");
+        assert_eq!(dest[2].as_float()?, media_box[3]);
+
+        Ok(())
+    }
+}
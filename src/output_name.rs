@@ -0,0 +1,185 @@
+use anyhow::{Result, anyhow};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Template of the name of the output, placed next to the input directory.
+pub const DEFAULT_OUTPUT_TEMPLATE: &str = "{dirname}-united.pdf";
+const SECONDS_PER_DAY: u64 = 86_400;
+
+/// Values of the placeholders of an output template.
+#[derive(Debug, Clone)]
+pub struct OutputNameFields {
+    /// `{dirname}`: the name of the input directory.
+    pub dirname: String,
+    /// `{date}`: the date of the run as `YYYY-MM-DD`.
+    pub date: String,
+    /// `{count}`: the number of merged files.
+    pub file_count: usize,
+}
+
+/// Fill the placeholders of the template, which must give a file name (not a path).
+pub fn render_output_template(template: &str, fields: &OutputNameFields) -> Result<String> {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        let end = rest[start..].find('}').ok_or(anyhow!(
+            "The placeholder at '{}' of the output template is not closed",
+            &rest[start..]
+        ))? + start;
+
+        match &rest[start + 1..end] {
+            "dirname" => rendered.push_str(&fields.dirname),
+            "date" => rendered.push_str(&fields.date),
+            "count" => rendered.push_str(&fields.file_count.to_string()),
+            placeholder => {
+                return Err(anyhow!(
+                    "Unknown placeholder '{{{placeholder}}}' in the output template \
+                    (available: {{dirname}}, {{date}}, {{count}})"
+                ));
+            }
+        }
+        rest = &rest[end + 1..];
+    }
+    rendered.push_str(rest);
+
+    if rendered.is_empty() || rendered.contains(['/', '\\']) {
+        return Err(anyhow!(
+            "The output template must give a file name, not '{rendered}'"
+        ));
+    }
+
+    Ok(rendered)
+}
+
+/// File name pattern matching every name the template can give, with `*` in place
+/// of the placeholders.
+pub fn output_template_pattern(template: &str) -> String {
+    let mut pattern = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let (Some(start), Some(end)) = (rest.find('{'), rest.find('}')) {
+        if end < start {
+            break;
+        }
+        pattern.push_str(&rest[..start]);
+        pattern.push('*');
+        rest = &rest[end + 1..];
+    }
+    pattern.push_str(rest);
+
+    pattern
+}
+
+/// The path if no file is there, otherwise the first free one among `name (2).pdf`,
+/// `name (3).pdf`, ... as file managers do for repeated copies.
+pub fn first_free_numbered_path(path: impl AsRef<Path>) -> Result<PathBuf> {
+    let path = path.as_ref();
+    if !std::fs::exists(path)? {
+        return Ok(path.to_path_buf());
+    }
+
+    let stem = path
+        .file_stem()
+        .ok_or(anyhow!(
+            "The path '{}' does not name a file",
+            path.display()
+        ))?
+        .to_string_lossy();
+    let extension = path
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+
+    for number in 2.. {
+        let numbered_path = path.with_file_name(format!("{stem} ({number}){extension}"));
+        if !std::fs::exists(&numbered_path)? {
+            return Ok(numbered_path);
+        }
+    }
+    unreachable!("the numbers of the copies are unbounded")
+}
+
+/// Today's date (UTC) as `YYYY-MM-DD`.
+pub fn today() -> String {
+    let (year, month, day) = today_civil();
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+/// Today's date (UTC) as `(year, month, day)`.
+pub fn today_civil() -> (i64, u32, u32) {
+    let days_since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since_epoch| since_epoch.as_secs() / SECONDS_PER_DAY);
+    civil_from_days(days_since_epoch as i64)
+}
+
+/// The proleptic Gregorian date of the day counted from 1970-01-01
+/// (H. Hinnant's `civil_from_days`).
+fn civil_from_days(days_since_epoch: i64) -> (i64, u32, u32) {
+    let z = days_since_epoch + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support;
+
+    #[test]
+    fn render_and_match_templates() -> Result<()> {
+        let fields = OutputNameFields {
+            dirname: "lectures".to_string(),
+            date: "2024-02-29".to_string(),
+            file_count: 12,
+        };
+
+        let rendered = render_output_template("{dirname}-{date}-{count}.pdf", &fields)?;
+        assert_eq!(rendered, "lectures-2024-02-29-12.pdf");
+        assert!(render_output_template("{dirname}-{profile}.pdf", &fields).is_err());
+        assert!(render_output_template("out/{dirname}.pdf", &fields).is_err());
+
+        assert_eq!(
+            output_template_pattern(DEFAULT_OUTPUT_TEMPLATE),
+            "*-united.pdf"
+        );
+        assert_eq!(output_template_pattern("{dirname}-{date}.pdf"), "*-*.pdf");
+
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(19_782), (2024, 2, 29));
+
+        Ok(())
+    }
+
+    #[test]
+    fn numbered_copies() -> Result<()> {
+        let test_dir = PathBuf::from(test_support::get_virgin_test_dir("numbered_copies")?);
+
+        let output_path = test_dir.join("lectures-united.pdf");
+        assert_eq!(first_free_numbered_path(&output_path)?, output_path);
+
+        std::fs::write(&output_path, b"%PDF-1.7")?;
+        std::fs::write(test_dir.join("lectures-united (2).pdf"), b"%PDF-1.7")?;
+        assert_eq!(
+            first_free_numbered_path(&output_path)?,
+            test_dir.join("lectures-united (3).pdf")
+        );
+
+        Ok(())
+    }
+}
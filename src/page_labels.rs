@@ -0,0 +1,92 @@
+use crate::report::MergeReport;
+use crate::text::{TitleEncoding, encode_text_string};
+use anyhow::Result;
+use lopdf::{Document, Object, dictionary};
+
+/// Set the `/PageLabels` of the catalog so that the pages of each source file are labelled
+/// with the title of its bookmark followed by the number of the page within the file (e.g.
+/// "Invoice-3 2"). The generated pages which are not part of a file (e.g. a table of
+/// contents) are numbered in lowercase roman numerals.
+pub fn set_page_labels(
+    doc: &mut Document,
+    report: &MergeReport,
+    encoding: TitleEncoding,
+) -> Result<()> {
+    let num_pages = doc.get_pages().len() as u32;
+    let mut nums = Vec::new();
+    // Index of the first page not covered by the ranges added so far
+    let mut next_index = 0;
+
+    for source in &report.sources {
+        let first_index = source.first_page - 1;
+        if first_index > next_index {
+            nums.push(Object::Integer(next_index.into()));
+            nums.push(generated_pages_label());
+        }
+
+        let title = source
+            .title
+            .clone()
+            .unwrap_or_else(|| source.path.display().to_string());
+        nums.push(Object::Integer(first_index.into()));
+        nums.push(Object::Dictionary(dictionary! {
+            "S" => "D",
+            "P" => encode_text_string(&format!("{title} "), encoding),
+        }));
+        next_index = source.last_page;
+    }
+    if next_index < num_pages {
+        nums.push(Object::Integer(next_index.into()));
+        nums.push(generated_pages_label());
+    }
+
+    if nums.is_empty() {
+        return Ok(());
+    }
+    let page_labels_id = doc.add_object(dictionary! { "Nums" => nums });
+    doc.catalog_mut()?
+        .set("PageLabels", Object::Reference(page_labels_id));
+
+    Ok(())
+}
+
+fn generated_pages_label() -> Object {
+    Object::Dictionary(dictionary! { "S" => "r" })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support;
+
+    #[test]
+    fn labels_restart_at_each_file() -> Result<()> {
+        let mut doc = test_support::get_basic_pdf_doc("doc", 5)?;
+        let report: MergeReport = serde_json::from_str(
+            r#"{"root": "archive", "sources": [
+                {"path": "a.pdf", "size": 1, "modified": null, "page_count": 2,
+                 "first_page": 2, "last_page": 3, "title": "Invoice-3"},
+                {"path": "b.pdf", "size": 1, "modified": null, "page_count": 1,
+                 "first_page": 4, "last_page": 4}
+            ]}"#,
+        )?;
+        set_page_labels(&mut doc, &report, TitleEncoding::Auto)?;
+
+        let page_labels_id = doc.catalog()?.get(b"PageLabels")?.as_reference()?;
+        let nums = doc
+            .get_dictionary(page_labels_id)?
+            .get(b"Nums")?
+            .as_array()?;
+        let starts: Vec<i64> = nums
+            .iter()
+            .step_by(2)
+            .map(Object::as_i64)
+            .collect::<lopdf::Result<_>>()?;
+        assert_eq!(starts, vec![0, 1, 3, 4]);
+        let invoice_label = nums[3].as_dict()?;
+        assert_eq!(invoice_label.get(b"P")?.as_str()?, b"Invoice-3 ");
+        assert_eq!(nums[5].as_dict()?.get(b"P")?.as_str()?, b"b.pdf ");
+
+        Ok(())
+    }
+}
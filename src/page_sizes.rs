@@ -0,0 +1,130 @@
+use crate::page_tree;
+use crate::report::MergeReport;
+use lopdf::{Document, ObjectId};
+use std::collections::BTreeMap;
+
+const POINTS_PER_MM: f32 = 72.0 / 25.4;
+/// Tolerance in millimetres when recognising a standard format.
+const FORMAT_TOLERANCE_MM: f32 = 3.0;
+/// Standard formats as (name, short side, long side) in millimetres.
+const STANDARD_FORMATS: [(&str, f32, f32); 6] = [
+    ("A3", 297.0, 420.0),
+    ("A4", 210.0, 297.0),
+    ("A5", 148.0, 210.0),
+    ("Letter", 215.9, 279.4),
+    ("Legal", 215.9, 355.6),
+    ("Tabloid", 279.4, 431.8),
+];
+
+/// The format of the page as shown, e.g. "A4 portrait" or "200x300 mm landscape",
+/// taking into account its `/Rotate`.
+pub fn page_format(doc: &Document, page_id: ObjectId) -> String {
+    let [llx, lly, urx, ury] = page_tree::media_box(doc, page_id);
+    let (mut width, mut height) = ((urx - llx).abs(), (ury - lly).abs());
+
+    let rotate = page_tree::inherited_page_attribute(doc, page_id, b"Rotate")
+        .and_then(|rotate| rotate.as_i64().ok())
+        .unwrap_or(0);
+    if rotate.rem_euclid(180) == 90 {
+        std::mem::swap(&mut width, &mut height);
+    }
+
+    let (short_side, long_side) = (
+        width.min(height) / POINTS_PER_MM,
+        width.max(height) / POINTS_PER_MM,
+    );
+    let name = STANDARD_FORMATS
+        .iter()
+        .find(|(_name, format_short, format_long)| {
+            (short_side - format_short).abs() <= FORMAT_TOLERANCE_MM
+                && (long_side - format_long).abs() <= FORMAT_TOLERANCE_MM
+        })
+        .map(|(name, _short, _long)| name.to_string())
+        .unwrap_or_else(|| format!("{:.0}x{:.0} mm", short_side, long_side));
+    let orientation = if width > height {
+        "landscape"
+    } else {
+        "portrait"
+    };
+
+    format!("{name} {orientation}")
+}
+
+/// How many pages of the document have each format.
+pub fn page_format_counts(doc: &Document) -> BTreeMap<String, u32> {
+    let mut counts = BTreeMap::new();
+    for page_id in doc.get_pages().into_values() {
+        *counts.entry(page_format(doc, page_id)).or_insert(0) += 1;
+    }
+    counts
+}
+
+/// The most common page format among all the sources of the report, if any.
+pub fn dominant_format(report: &MergeReport) -> Option<String> {
+    let mut total_counts: BTreeMap<&str, u32> = BTreeMap::new();
+    for source in &report.sources {
+        for (format, count) in &source.page_formats {
+            *total_counts.entry(format).or_insert(0) += count;
+        }
+    }
+
+    total_counts
+        .into_iter()
+        .max_by_key(|(_format, count)| *count)
+        .map(|(format, _count)| format.to_string())
+}
+
+/// A warning for every source with pages in a format other than the dominant one.
+pub fn mixed_format_warnings(report: &MergeReport) -> Vec<String> {
+    let Some(dominant_format) = dominant_format(report) else {
+        return Vec::new();
+    };
+
+    report
+        .sources
+        .iter()
+        .filter_map(|source| {
+            let deviating: Vec<String> = source
+                .page_formats
+                .iter()
+                .filter(|(format, _count)| **format != dominant_format)
+                .map(|(format, count)| format!("{count} pages {format}"))
+                .collect();
+
+            (!deviating.is_empty()).then(|| {
+                format!(
+                    "'{}' deviates from the dominant format ({dominant_format}): {}",
+                    source.path.display(),
+                    deviating.join(", ")
+                )
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use lopdf::{Object, dictionary};
+
+    #[test]
+    fn formats_with_rotation() -> anyhow::Result<()> {
+        let mut doc = Document::with_version("1.7");
+        let mut add_page = |media_box: [f32; 4], rotate: i64| {
+            doc.add_object(dictionary! {
+                "Type" => "Page",
+                "MediaBox" => media_box.iter().map(|&c| Object::Real(c)).collect::<Vec<_>>(),
+                "Rotate" => rotate,
+            })
+        };
+        let a4_portrait = add_page([0.0, 0.0, 595.0, 842.0], 0);
+        let a4_rotated = add_page([0.0, 0.0, 595.0, 842.0], 90);
+        let custom = add_page([0.0, 0.0, 400.0, 300.0], 0);
+
+        assert_eq!(page_format(&doc, a4_portrait), "A4 portrait");
+        assert_eq!(page_format(&doc, a4_rotated), "A4 landscape");
+        assert_eq!(page_format(&doc, custom), "106x141 mm landscape");
+
+        Ok(())
+    }
+}
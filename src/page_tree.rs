@@ -0,0 +1,488 @@
+use anyhow::{Result, anyhow};
+use lopdf::{Document, Object, ObjectId, dictionary};
+use std::collections::HashSet;
+
+/// US Letter, the default media box of the PDF reference.
+pub const DEFAULT_MEDIA_BOX: [f32; 4] = [0.0, 0.0, 612.0, 792.0];
+/// Attributes a page may inherit from its ancestor Pages nodes.
+const INHERITABLE_PAGE_ATTRIBUTES: [&[u8]; 4] = [b"Resources", b"MediaBox", b"CropBox", b"Rotate"];
+/// Number of kids of each Pages node of a balanced page tree.
+pub const BALANCED_TREE_FAN_OUT: usize = 32;
+/// From this number of pages on, `PageTreeLayout::Auto` builds a balanced page tree.
+pub const BALANCED_TREE_THRESHOLD: usize = 50_000;
+/// Guard against cyclic `/Parent` chains in broken documents.
+const MAX_PAGE_TREE_DEPTH: usize = 64;
+
+/// Shape of the page tree of the output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum PageTreeLayout {
+    /// Graft the page tree of each input under intermediate nodes of at most
+    /// `BALANCED_TREE_FAN_OUT` kids, balancing huge outputs
+    #[default]
+    Auto,
+    /// Always graft the page tree of each input under the root
+    Grafted,
+    /// Every page directly under the root
+    Flat,
+    /// A multi-level tree with a fixed fan-out, for fast navigation in huge outputs
+    Balanced,
+}
+
+/// Give the page tree of the document the layout. The page tree is left untouched for
+/// `Grafted`.
+pub fn arrange_page_tree(doc: &mut Document, layout: PageTreeLayout) -> Result<()> {
+    match layout {
+        PageTreeLayout::Auto if doc.get_pages().len() >= BALANCED_TREE_THRESHOLD => {
+            balance_page_tree(doc, BALANCED_TREE_FAN_OUT)
+        }
+        PageTreeLayout::Auto => group_root_kids(doc, BALANCED_TREE_FAN_OUT),
+        PageTreeLayout::Grafted => Ok(()),
+        PageTreeLayout::Flat => flatten_page_tree(doc),
+        PageTreeLayout::Balanced => balance_page_tree(doc, BALANCED_TREE_FAN_OUT),
+    }
+}
+
+pub fn root_pages_id(doc: &Document) -> Result<ObjectId> {
+    Ok(doc.catalog()?.get(b"Pages")?.as_reference()?)
+}
+
+/// Get an attribute of the page, looking it up among the ancestor Pages nodes if the page
+/// inherits it (see the PDF reference, section 7.7.3.4).
+pub fn inherited_page_attribute(doc: &Document, page_id: ObjectId, key: &[u8]) -> Option<Object> {
+    let mut node_id = page_id;
+
+    for _ in 0..MAX_PAGE_TREE_DEPTH {
+        let node = doc.get_dictionary(node_id).ok()?;
+        if let Ok(value) = node.get(key) {
+            return Some(value.clone());
+        }
+        node_id = node.get(b"Parent").and_then(Object::as_reference).ok()?;
+    }
+
+    None
+}
+
+/// The media box of the page as `[llx, lly, urx, ury]`.
+pub fn media_box(doc: &Document, page_id: ObjectId) -> [f32; 4] {
+    let Some(media_box) = inherited_page_attribute(doc, page_id, b"MediaBox") else {
+        return DEFAULT_MEDIA_BOX;
+    };
+
+    let coordinates: Option<Vec<f32>> = doc
+        .dereference(&media_box)
+        .ok()
+        .and_then(|(_id, media_box)| media_box.as_array().ok())
+        .map(|array| array.iter().map(object_to_f32).collect())
+        .unwrap_or(None);
+
+    match coordinates.as_deref() {
+        Some(&[llx, lly, urx, ury]) => [llx, lly, urx, ury],
+        _ => DEFAULT_MEDIA_BOX,
+    }
+}
+
+pub fn object_to_f32(object: &Object) -> Option<f32> {
+    match object {
+        Object::Integer(integer) => Some(*integer as f32),
+        Object::Real(real) => Some(*real),
+        _ => None,
+    }
+}
+
+/// Insert the pages, which must already have the root Pages node as `/Parent`, at the
+/// beginning of the document.
+pub fn prepend_pages(doc: &mut Document, page_ids: &[ObjectId]) -> Result<()> {
+    let root_pages_id = root_pages_id(doc)?;
+    let root_pages = doc.get_object_mut(root_pages_id)?.as_dict_mut()?;
+
+    let kids = root_pages.get_mut(b"Kids")?.as_array_mut()?;
+    let previous_kids = std::mem::take(kids);
+    kids.extend(page_ids.iter().map(|&page_id| Object::Reference(page_id)));
+    kids.extend(previous_kids);
+
+    let count = root_pages.get(b"Count")?.as_i64()? + page_ids.len() as i64;
+    root_pages.set("Count", Object::Integer(count));
+
+    Ok(())
+}
+
+/// Insert the page before the other page, under the same Pages node, and update the
+/// `/Count` of the ancestors. The page may inherit attributes from its new parent.
+pub fn insert_page_before(
+    doc: &mut Document,
+    page_id: ObjectId,
+    next_page_id: ObjectId,
+) -> Result<()> {
+    let parent_id = doc
+        .get_dictionary(next_page_id)?
+        .get(b"Parent")?
+        .as_reference()?;
+    doc.get_dictionary_mut(page_id)?
+        .set("Parent", Object::Reference(parent_id));
+
+    let kids = doc
+        .get_dictionary_mut(parent_id)?
+        .get_mut(b"Kids")?
+        .as_array_mut()?;
+    let index = kids
+        .iter()
+        .position(|kid| kid.as_reference().ok() == Some(next_page_id))
+        .ok_or(anyhow!(
+            "The page {next_page_id:?} is not among the kids of its parent"
+        ))?;
+    kids.insert(index, Object::Reference(page_id));
+
+    let mut node_id = Some(parent_id);
+    for _ in 0..MAX_PAGE_TREE_DEPTH {
+        let Some(current_id) = node_id else {
+            break;
+        };
+        let node = doc.get_dictionary_mut(current_id)?;
+        let count = node.get(b"Count")?.as_i64()? + 1;
+        node.set("Count", Object::Integer(count));
+        node_id = node.get(b"Parent").and_then(Object::as_reference).ok();
+    }
+
+    Ok(())
+}
+
+/// Recompute the `/Count` of every Pages node and make the `/Parent` of every node point to
+/// the node listing it among its `/Kids`, as the page trees of the inputs may carry stale
+/// values. Returns a description of each fix.
+pub fn fix_page_tree(doc: &mut Document) -> Result<Vec<String>> {
+    let mut fixes = Vec::new();
+    let mut visited = HashSet::new();
+    fix_page_tree_node(doc, root_pages_id(doc)?, 0, &mut visited, &mut fixes)?;
+    Ok(fixes)
+}
+
+/// Fix the subtree of the node, returning the number of pages it contains.
+fn fix_page_tree_node(
+    doc: &mut Document,
+    node_id: ObjectId,
+    depth: usize,
+    visited: &mut HashSet<ObjectId>,
+    fixes: &mut Vec<String>,
+) -> Result<i64> {
+    if depth > MAX_PAGE_TREE_DEPTH || !visited.insert(node_id) {
+        return Err(anyhow!(
+            "The page tree is cyclic or deeper than {MAX_PAGE_TREE_DEPTH} levels at {node_id:?}"
+        ));
+    }
+
+    let node = doc.get_dictionary(node_id)?;
+    if !node.has_type(b"Pages") {
+        return Ok(1);
+    }
+
+    let kid_ids: Vec<ObjectId> = node
+        .get(b"Kids")?
+        .as_array()?
+        .iter()
+        .map(Object::as_reference)
+        .collect::<lopdf::Result<_>>()?;
+
+    let mut count = 0;
+    for kid_id in kid_ids {
+        let kid = doc.get_object_mut(kid_id)?.as_dict_mut()?;
+        if kid.get(b"Parent").and_then(Object::as_reference).ok() != Some(node_id) {
+            kid.set("Parent", Object::Reference(node_id));
+            fixes.push(format!("Set the parent of {kid_id:?} to {node_id:?}"));
+        }
+        count += fix_page_tree_node(doc, kid_id, depth + 1, visited, fixes)?;
+    }
+
+    let node = doc.get_object_mut(node_id)?.as_dict_mut()?;
+    let stale_count = node.get(b"Count").and_then(Object::as_i64).ok();
+    if stale_count != Some(count) {
+        node.set("Count", Object::Integer(count));
+        fixes.push(format!(
+            "Set the page count of {node_id:?} from {stale_count:?} to {count}"
+        ));
+    }
+
+    Ok(count)
+}
+
+/// Make every page a direct kid of the root Pages node, in page order, copying into the
+/// pages the attributes they inherited from the intermediate Pages nodes, which are removed.
+pub fn flatten_page_tree(doc: &mut Document) -> Result<()> {
+    let page_ids = detach_pages(doc)?;
+
+    let root_pages = doc.get_object_mut(root_pages_id(doc)?)?.as_dict_mut()?;
+    root_pages.set(
+        "Kids",
+        Object::Array(page_ids.iter().map(|&id| Object::Reference(id)).collect()),
+    );
+    root_pages.set("Count", Object::Integer(page_ids.len() as i64));
+
+    Ok(())
+}
+
+/// Rebuild the page tree with at most `fan_out` kids per node and all the pages at the
+/// same depth, so that a viewer reaches any page in a logarithmic number of steps.
+pub fn balance_page_tree(doc: &mut Document, fan_out: usize) -> Result<()> {
+    if fan_out < 2 {
+        return Err(anyhow!(
+            "The fan-out of a balanced page tree must be at least 2"
+        ));
+    }
+
+    let pages: Vec<(ObjectId, i64)> = detach_pages(doc)?
+        .into_iter()
+        .map(|page_id| (page_id, 1))
+        .collect();
+
+    build_upper_levels(doc, pages, fan_out)
+}
+
+/// Put the kids of the root Pages node (the grafted page trees of the inputs) under
+/// intermediate nodes of at most `fan_out` kids, leaving the subtrees untouched.
+pub fn group_root_kids(doc: &mut Document, fan_out: usize) -> Result<()> {
+    if fan_out < 2 {
+        return Err(anyhow!(
+            "The fan-out of a balanced page tree must be at least 2"
+        ));
+    }
+
+    let root_kids = doc
+        .get_dictionary(root_pages_id(doc)?)?
+        .get(b"Kids")?
+        .as_array()?
+        .iter()
+        .map(|kid| {
+            let kid_id = kid.as_reference()?;
+            let kid_dict = doc.get_dictionary(kid_id)?;
+            let count = match kid_dict.get(b"Count") {
+                Ok(count) if kid_dict.has_type(b"Pages") => count.as_i64()?,
+                _ => 1,
+            };
+            Ok((kid_id, count))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    build_upper_levels(doc, root_kids, fan_out)
+}
+
+/// Build the Pages nodes above the given nodes (with their page counts), `fan_out` kids per
+/// node, until the root Pages node can hold the top level.
+fn build_upper_levels(
+    doc: &mut Document,
+    mut level: Vec<(ObjectId, i64)>,
+    fan_out: usize,
+) -> Result<()> {
+    let root_pages_id = root_pages_id(doc)?;
+
+    // Ids and page counts of the nodes of the level being built
+    while level.len() > fan_out {
+        let mut parent_level = Vec::with_capacity(level.len().div_ceil(fan_out));
+        for kids in level.chunks(fan_out) {
+            let count = kids.iter().map(|&(_id, count)| count).sum();
+            let node_id = doc.add_object(dictionary! {
+                "Type" => "Pages",
+                "Parent" => root_pages_id,
+                "Kids" => kids.iter().map(|&(id, _count)| Object::Reference(id)).collect::<Vec<_>>(),
+                "Count" => count,
+            });
+            for &(kid_id, _count) in kids {
+                doc.get_object_mut(kid_id)?
+                    .as_dict_mut()?
+                    .set("Parent", Object::Reference(node_id));
+            }
+            parent_level.push((node_id, count));
+        }
+        level = parent_level;
+    }
+
+    let root_pages = doc.get_object_mut(root_pages_id)?.as_dict_mut()?;
+    root_pages.set(
+        "Kids",
+        Object::Array(
+            level
+                .iter()
+                .map(|&(id, _count)| Object::Reference(id))
+                .collect(),
+        ),
+    );
+    root_pages.set(
+        "Count",
+        Object::Integer(level.iter().map(|&(_id, count)| count).sum()),
+    );
+
+    Ok(())
+}
+
+/// Take the pages out of the page tree, in page order, removing the intermediate Pages
+/// nodes after copying into the pages the attributes they inherited from them. The pages
+/// are left with the root Pages node as `/Parent`, whose `/Kids` still need to be set.
+fn detach_pages(doc: &mut Document) -> Result<Vec<ObjectId>> {
+    let root_pages_id = root_pages_id(doc)?;
+    let page_ids: Vec<ObjectId> = doc.get_pages().into_values().collect();
+
+    let mut intermediate_node_ids = HashSet::new();
+    for &page_id in &page_ids {
+        let mut node_id = page_id;
+        for _ in 0..MAX_PAGE_TREE_DEPTH {
+            let Ok(parent_id) = doc
+                .get_dictionary(node_id)?
+                .get(b"Parent")
+                .and_then(Object::as_reference)
+            else {
+                break;
+            };
+            if parent_id == root_pages_id {
+                break;
+            }
+            intermediate_node_ids.insert(parent_id);
+            node_id = parent_id;
+        }
+
+        let inherited_attributes: Vec<(&[u8], Object)> = INHERITABLE_PAGE_ATTRIBUTES
+            .into_iter()
+            .filter_map(|key| Some((key, inherited_page_attribute(doc, page_id, key)?)))
+            .collect();
+        let page = doc.get_object_mut(page_id)?.as_dict_mut()?;
+        for (key, value) in inherited_attributes {
+            page.set(key, value);
+        }
+        page.set("Parent", Object::Reference(root_pages_id));
+    }
+
+    for node_id in intermediate_node_ids {
+        doc.objects.remove(&node_id);
+    }
+
+    Ok(page_ids)
+}
+
+/// The id of the page with the given number (starting from 1).
+pub fn page_id_by_number(doc: &Document, page_number: u32) -> Result<ObjectId> {
+    doc.get_pages()
+        .get(&page_number)
+        .copied()
+        .ok_or(anyhow!("The document has no page number {page_number}"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support;
+
+    #[test]
+    fn stale_counts_and_parents_are_fixed() -> Result<()> {
+        let mut doc = test_support::get_basic_pdf_doc("doc_name", 3)?;
+        let root_pages_id = root_pages_id(&doc)?;
+        let first_page_id = page_id_by_number(&doc, 1)?;
+
+        doc.get_object_mut(root_pages_id)?
+            .as_dict_mut()?
+            .set("Count", Object::Integer(7));
+        doc.get_object_mut(first_page_id)?
+            .as_dict_mut()?
+            .remove(b"Parent");
+
+        let fixes = fix_page_tree(&mut doc)?;
+
+        assert_eq!(fixes.len(), 2);
+        assert_eq!(
+            doc.get_dictionary(root_pages_id)?.get(b"Count")?.as_i64()?,
+            3
+        );
+        assert!(fix_page_tree(&mut doc)?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn nested_page_trees_are_flattened() -> Result<()> {
+        let mut doc = test_support::get_basic_pdf_doc("doc_name", 3)?;
+        let root_pages_id = root_pages_id(&doc)?;
+        let page_ids: Vec<ObjectId> = doc.get_pages().into_values().collect();
+
+        // Move the last two pages under an intermediate node carrying their media box
+        let intermediate_id = doc.add_object(dictionary! {
+            "Type" => "Pages",
+            "Parent" => root_pages_id,
+            "Kids" => vec![page_ids[1].into(), page_ids[2].into()],
+            "Count" => 2,
+            "MediaBox" => vec![0.into(), 0.into(), 100.into(), 200.into()],
+        });
+        for &page_id in &page_ids[1..] {
+            let page = doc.get_object_mut(page_id)?.as_dict_mut()?;
+            page.set("Parent", intermediate_id);
+            page.remove(b"MediaBox");
+        }
+        doc.get_object_mut(root_pages_id)?
+            .as_dict_mut()?
+            .set("Kids", vec![page_ids[0].into(), intermediate_id.into()]);
+
+        flatten_page_tree(&mut doc)?;
+
+        assert!(!doc.objects.contains_key(&intermediate_id));
+        assert_eq!(doc.get_pages().into_values().collect::<Vec<_>>(), page_ids);
+        assert_eq!(media_box(&doc, page_ids[2]), [0.0, 0.0, 100.0, 200.0]);
+        assert!(fix_page_tree(&mut doc)?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn balanced_page_tree() -> Result<()> {
+        let mut doc = test_support::get_basic_pdf_doc("doc_name", 10)?;
+        let page_ids: Vec<ObjectId> = doc.get_pages().into_values().collect();
+
+        balance_page_tree(&mut doc, 3)?;
+
+        // 10 pages -> 4 nodes -> 2 nodes under the root
+        let root_pages = doc.get_dictionary(root_pages_id(&doc)?)?;
+        assert_eq!(root_pages.get(b"Kids")?.as_array()?.len(), 2);
+        assert_eq!(root_pages.get(b"Count")?.as_i64()?, 10);
+        assert_eq!(doc.get_pages().into_values().collect::<Vec<_>>(), page_ids);
+        assert!(fix_page_tree(&mut doc)?.is_empty());
+
+        // Few pages stay below the root
+        let mut doc = test_support::get_basic_pdf_doc("doc_name", 3)?;
+        balance_page_tree(&mut doc, 3)?;
+        let root_pages = doc.get_dictionary(root_pages_id(&doc)?)?;
+        assert_eq!(root_pages.get(b"Kids")?.as_array()?.len(), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn grafted_trees_are_grouped() -> Result<()> {
+        let mut doc = test_support::get_basic_pdf_doc("doc_name", 7)?;
+        let root_pages_id = root_pages_id(&doc)?;
+        let page_ids: Vec<ObjectId> = doc.get_pages().into_values().collect();
+
+        // The first two pages as a grafted page tree, the others directly below the root
+        let grafted_id = doc.add_object(lopdf::dictionary! {
+            "Type" => "Pages",
+            "Parent" => root_pages_id,
+            "Kids" => vec![page_ids[0].into(), page_ids[1].into()],
+            "Count" => 2,
+        });
+        for &page_id in &page_ids[..2] {
+            doc.get_object_mut(page_id)?
+                .as_dict_mut()?
+                .set("Parent", grafted_id);
+        }
+        let mut root_kids = vec![Object::Reference(grafted_id)];
+        root_kids.extend(page_ids[2..].iter().map(|&id| Object::Reference(id)));
+        doc.get_object_mut(root_pages_id)?
+            .as_dict_mut()?
+            .set("Kids", root_kids);
+
+        group_root_kids(&mut doc, 2)?;
+
+        // 6 kids -> 3 nodes -> 2 nodes under the root
+        let root_pages = doc.get_dictionary(root_pages_id)?;
+        assert_eq!(root_pages.get(b"Kids")?.as_array()?.len(), 2);
+        assert_eq!(root_pages.get(b"Count")?.as_i64()?, 7);
+        assert!(doc.objects.contains_key(&grafted_id));
+        assert_eq!(doc.get_pages().into_values().collect::<Vec<_>>(), page_ids);
+        assert!(fix_page_tree(&mut doc)?.is_empty());
+
+        Ok(())
+    }
+}
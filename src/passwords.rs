@@ -0,0 +1,149 @@
+use crate::{source, utils};
+use anyhow::{Result, anyhow};
+use lopdf::Document;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Passwords of the encrypted source files. In the TOML file every key is a path relative
+/// to the input directory, or a glob pattern of such paths (see `utils::matches_path_glob`),
+/// and its value the password, e.g. `"scans/**/*.pdf" = "s3cret"`. A path takes precedence
+/// over the patterns, and a longer pattern over a shorter one.
+#[derive(Clone, Default)]
+pub struct PasswordMap {
+    paths: BTreeMap<PathBuf, String>,
+    /// Sorted by decreasing length of the pattern.
+    patterns: Vec<(String, String)>,
+}
+
+impl PasswordMap {
+    pub fn load(passwords_path: impl AsRef<Path>) -> Result<Self> {
+        let content = std::fs::read_to_string(passwords_path.as_ref())?;
+        Self::parse(&content)
+    }
+
+    pub fn parse(content: &str) -> Result<Self> {
+        let entries: BTreeMap<String, String> = toml::from_str(content)
+            .map_err(|err| anyhow!("The password file is not a table of strings: {err}"))?;
+        let mut password_map = PasswordMap::default();
+
+        for (key, password) in entries {
+            if source::is_glob_pattern(&key) {
+                password_map.patterns.push((key, password));
+            } else {
+                password_map
+                    .paths
+                    .insert(utils::path_from_portable(&key), password);
+            }
+        }
+        password_map
+            .patterns
+            .sort_by_key(|(pattern, _)| std::cmp::Reverse(pattern.len()));
+
+        Ok(password_map)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty() && self.patterns.is_empty()
+    }
+
+    /// The password of the file, by its path relative to the root.
+    pub fn password_for(
+        &self,
+        file_path: impl AsRef<Path>,
+        root: impl AsRef<Path>,
+    ) -> Option<&str> {
+        let file_path = file_path.as_ref();
+        let relative_path = file_path.strip_prefix(root.as_ref()).unwrap_or(file_path);
+
+        self.paths
+            .get(relative_path)
+            .or_else(|| {
+                self.patterns
+                    .iter()
+                    .find(|(pattern, _)| utils::matches_path_glob(pattern, relative_path))
+                    .map(|(_, password)| password)
+            })
+            .map(String::as_str)
+    }
+
+    /// Decrypt the document if it is encrypted. The documents which do not open without a
+    /// password are an error unless the map has theirs.
+    pub fn decrypt(
+        &self,
+        doc: &mut Document,
+        file_path: impl AsRef<Path>,
+        root: impl AsRef<Path>,
+    ) -> Result<()> {
+        if !doc.is_encrypted() {
+            return Ok(());
+        }
+        let file_path = file_path.as_ref();
+        let Some(password) = self.password_for(file_path, root) else {
+            return Err(anyhow!(
+                "The document '{}' is encrypted and the password file has no password for it",
+                file_path.display()
+            ));
+        };
+
+        doc.decrypt(password).map_err(|err| {
+            anyhow!(
+                "The document '{}' could not be decrypted with its password: {err}",
+                file_path.display()
+            )
+        })
+    }
+}
+
+// The passwords are left out of the logs of the options
+impl std::fmt::Debug for PasswordMap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PasswordMap")
+            .field("paths", &self.paths.keys().collect::<Vec<_>>())
+            .field(
+                "patterns",
+                &self
+                    .patterns
+                    .iter()
+                    .map(|(pattern, _)| pattern)
+                    .collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn paths_before_longer_patterns() -> Result<()> {
+        let password_map = PasswordMap::parse(
+            r#"
+            "scans/invoice.pdf" = "exact"
+            "scans/*.pdf" = "scans"
+            "**/*.pdf" = "any"
+            "#,
+        )?;
+        let root = Path::new("/archive");
+
+        assert_eq!(
+            password_map.password_for(root.join("scans").join("invoice.pdf"), root),
+            Some("exact")
+        );
+        assert_eq!(
+            password_map.password_for(root.join("scans").join("receipt.pdf"), root),
+            Some("scans")
+        );
+        assert_eq!(
+            password_map.password_for(root.join("notes.pdf"), root),
+            Some("any")
+        );
+        assert_eq!(
+            password_map.password_for(root.join("notes.txt"), root),
+            None
+        );
+        assert!(PasswordMap::parse("\"a.pdf\" = 1").is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,418 @@
+//! What a merge will do before doing it: the entries of a merge described explicitly by a
+//! plan file (`MergePlan`), and the tree a merge of directories would produce
+//! (`PlannedEntry`, see `merge::plan_merge`).
+
+use anyhow::{Result, anyhow};
+use lopdf::Document;
+use serde::Deserialize;
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// A merge described entry by entry instead of by a directory tree: which files, in which
+/// order, under which titles and nesting.
+///
+/// ```yaml
+/// entries:
+///   - title: Part I
+///     children:
+///       - path: intro.pdf
+///         title: Introduction
+///       - path: scans/chapter1.pdf
+///         pages: 3-10,12
+///   - path: appendix.pdf
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MergePlan {
+    /// The directory against which the relative paths of the entries are resolved.
+    #[serde(skip)]
+    pub base_dir: PathBuf,
+    pub entries: Vec<PlanEntry>,
+}
+
+/// A file to merge, or a section grouping the entries below it (or both: the children of
+/// a file are nested under its bookmark).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PlanEntry {
+    pub path: Option<PathBuf>,
+    /// Title of the bookmark, by default the name of the file.
+    pub title: Option<String>,
+    /// The pages of the file to merge, e.g. `1-3,5,8-` (all by default).
+    pub pages: Option<String>,
+    #[serde(default)]
+    pub children: Vec<PlanEntry>,
+}
+
+impl MergePlan {
+    /// Load the plan (YAML), whose relative paths are relative to its directory.
+    pub fn load(plan_path: impl AsRef<Path>) -> Result<Self> {
+        let plan_path = plan_path.as_ref();
+        let file = std::fs::File::open(plan_path)?;
+        let mut plan: MergePlan = serde_yaml::from_reader(std::io::BufReader::new(file))
+            .map_err(|err| anyhow!("Invalid merge plan '{}': {err}", plan_path.display()))?;
+        plan.base_dir = plan_path
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+
+        plan.check_entries(&plan.entries)?;
+        Ok(plan)
+    }
+
+    fn check_entries(&self, entries: &[PlanEntry]) -> Result<()> {
+        for entry in entries {
+            if entry.path.is_none() && entry.title.is_none() {
+                return Err(anyhow!(
+                    "An entry of the merge plan has neither path nor title"
+                ));
+            }
+            if entry.path.is_none() && entry.pages.is_some() {
+                return Err(anyhow!(
+                    "The section '{}' of the merge plan has pages but no path",
+                    entry.title.as_deref().unwrap_or_default()
+                ));
+            }
+            self.check_entries(&entry.children)?;
+        }
+        Ok(())
+    }
+}
+
+impl MergePlan {
+    /// The plan merging the files in the order of the list, nested under a section for
+    /// each directory between their common ancestor and them. A directory whose files are
+    /// not consecutive in the list gets a section for each run of them.
+    pub fn from_file_list(file_paths: &[PathBuf]) -> Result<Self> {
+        let file_paths = file_paths
+            .iter()
+            .map(std::path::absolute)
+            .collect::<std::io::Result<Vec<_>>>()?;
+        let parent_dirs: Vec<&Path> = file_paths
+            .iter()
+            .map(|file_path| file_path.parent().unwrap_or(file_path))
+            .collect();
+        let base_dir = crate::utils::common_ancestor(&parent_dirs);
+        let relative_paths = file_paths
+            .iter()
+            .map(|file_path| Ok(file_path.strip_prefix(&base_dir)?.to_path_buf()))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self::from_relative_paths(base_dir, &relative_paths))
+    }
+
+    /// As `from_file_list`, with the paths relative to the given base directory.
+    pub fn from_relative_paths(base_dir: PathBuf, relative_paths: &[PathBuf]) -> Self {
+        let mut entries = Vec::new();
+        for relative_path in relative_paths {
+            let mut siblings = &mut entries;
+            if let Some(relative_dir) = relative_path.parent() {
+                for dir_name in relative_dir.iter() {
+                    let dir_title = dir_name.to_string_lossy().to_string();
+                    let continues_section = siblings.last().is_some_and(|last: &PlanEntry| {
+                        last.path.is_none() && last.title.as_deref() == Some(&dir_title)
+                    });
+                    if !continues_section {
+                        siblings.push(PlanEntry {
+                            path: None,
+                            title: Some(dir_title),
+                            pages: None,
+                            children: Vec::new(),
+                        });
+                    }
+                    let section_index = siblings.len() - 1;
+                    siblings = &mut siblings[section_index].children;
+                }
+            }
+            siblings.push(PlanEntry {
+                path: Some(relative_path.to_path_buf()),
+                title: None,
+                pages: None,
+                children: Vec::new(),
+            });
+        }
+
+        MergePlan { base_dir, entries }
+    }
+}
+
+/// Read a list of file paths, one per line or separated by NUL characters (as written by
+/// `find -print0`). The empty entries are ignored.
+pub fn read_file_list(mut reader: impl Read, nul_separated: bool) -> Result<Vec<PathBuf>> {
+    let mut content = String::new();
+    reader.read_to_string(&mut content)?;
+
+    let separator = if nul_separated { '\0' } else { '\n' };
+    Ok(content
+        .split(separator)
+        .map(|line| line.trim_end_matches('\r'))
+        .filter(|line| !line.trim().is_empty())
+        .map(PathBuf::from)
+        .collect())
+}
+
+/// Parse a list of page ranges such as `1-3,5,8-` (`8-` up to the last page) into the
+/// page numbers of a document with `page_count` pages.
+pub fn parse_page_ranges(ranges: &str, page_count: u32) -> Result<BTreeSet<u32>> {
+    let parse_page = |page: &str| {
+        page.trim()
+            .parse::<u32>()
+            .map_err(|_| anyhow!("'{page}' is not a page number in the page ranges '{ranges}'"))
+    };
+
+    let mut page_numbers = BTreeSet::new();
+    for range in ranges.split(',') {
+        let (first, last) = match range.split_once('-') {
+            Some((first, "")) => (parse_page(first)?, page_count),
+            Some((first, last)) => (parse_page(first)?, parse_page(last)?),
+            None => {
+                let page = parse_page(range)?;
+                (page, page)
+            }
+        };
+        if first == 0 || first > last || last > page_count {
+            return Err(anyhow!(
+                "The page range '{}' is not within the {page_count} pages of the document",
+                range.trim()
+            ));
+        }
+        page_numbers.extend(first..=last);
+    }
+
+    Ok(page_numbers)
+}
+
+/// Remove from the document the pages not in the ranges, and the objects only they used.
+pub fn keep_pages(doc: &mut Document, ranges: &str) -> Result<()> {
+    let page_count = doc.get_pages().len() as u32;
+    let kept_pages = parse_page_ranges(ranges, page_count)?;
+    let deleted_pages: Vec<u32> = (1..=page_count)
+        .filter(|page_number| !kept_pages.contains(page_number))
+        .collect();
+
+    doc.delete_pages(&deleted_pages);
+    doc.prune_objects();
+    Ok(())
+}
+
+const INDENT: &str = "  ";
+
+/// A file or directory which a merge would add to the output, as planned by `plan_merge`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlannedEntry {
+    pub title: String,
+    pub path: PathBuf,
+    /// Pages of the file, or of all the files below the directory.
+    pub page_count: u32,
+    /// Why the pages of the file could not be counted: the merge would likely fail on it.
+    pub error: Option<String>,
+    /// The entries of a directory, empty for a file.
+    pub children: Vec<PlannedEntry>,
+}
+
+impl PlannedEntry {
+    pub fn file(path: impl AsRef<Path>, page_count: Result<u32>) -> Self {
+        let path = path.as_ref();
+        let (page_count, error) = match page_count {
+            Ok(page_count) => (page_count, None),
+            Err(err) => (0, Some(err.to_string())),
+        };
+
+        PlannedEntry {
+            title: file_name(path),
+            path: path.to_path_buf(),
+            page_count,
+            error,
+            children: Vec::new(),
+        }
+    }
+
+    pub fn directory(path: impl AsRef<Path>, children: Vec<PlannedEntry>) -> Self {
+        let path = path.as_ref();
+
+        PlannedEntry {
+            title: file_name(path),
+            path: path.to_path_buf(),
+            page_count: children.iter().map(|child| child.page_count).sum(),
+            error: None,
+            children,
+        }
+    }
+
+    pub fn is_directory(&self) -> bool {
+        !self.children.is_empty()
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|file_name| file_name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+/// The planned bookmark tree, one entry per line, with the pages of each entry and the
+/// pages it would occupy in the output, then the totals.
+pub fn format_plan(planned_entries: &[PlannedEntry]) -> Result<String> {
+    fn write_entries(
+        planned_entries: &[PlannedEntry],
+        depth: usize,
+        pages_before: &mut u32,
+        plan: &mut String,
+    ) -> Result<()> {
+        let indent = INDENT.repeat(depth);
+
+        for planned_entry in planned_entries {
+            let first_page = *pages_before + 1;
+            let last_page = *pages_before + planned_entry.page_count;
+            let page_range = match planned_entry.page_count {
+                0 => String::new(),
+                1 => format!(" (p. {first_page})"),
+                _ => format!(" (p. {first_page}-{last_page})"),
+            };
+
+            if planned_entry.is_directory() {
+                writeln!(
+                    plan,
+                    "{indent}{}/  {}{page_range}",
+                    planned_entry.title,
+                    pages(planned_entry.page_count)
+                )?;
+                write_entries(&planned_entry.children, depth + 1, pages_before, plan)?;
+            } else {
+                match &planned_entry.error {
+                    Some(err) => {
+                        writeln!(plan, "{indent}{}  unreadable: {err}", planned_entry.title)?
+                    }
+                    None => writeln!(
+                        plan,
+                        "{indent}{}  {}{page_range}",
+                        planned_entry.title,
+                        pages(planned_entry.page_count)
+                    )?,
+                }
+                *pages_before = last_page;
+            }
+        }
+
+        Ok(())
+    }
+
+    let mut plan = String::new();
+    let mut total_pages = 0;
+    write_entries(planned_entries, 0, &mut total_pages, &mut plan)?;
+
+    let (num_files, num_unreadable) = count_files(planned_entries);
+    writeln!(plan, "Total: {num_files} files, {}", pages(total_pages))?;
+    if num_unreadable > 0 {
+        writeln!(plan, "Unreadable files: {num_unreadable}")?;
+    }
+
+    Ok(plan)
+}
+
+fn pages(page_count: u32) -> String {
+    match page_count {
+        1 => "1 page".to_string(),
+        page_count => format!("{page_count} pages"),
+    }
+}
+
+/// The number of files, and of the files whose pages could not be counted.
+fn count_files(planned_entries: &[PlannedEntry]) -> (usize, usize) {
+    planned_entries
+        .iter()
+        .map(|planned_entry| {
+            if planned_entry.is_directory() {
+                count_files(&planned_entry.children)
+            } else {
+                (1, usize::from(planned_entry.error.is_some()))
+            }
+        })
+        .fold(
+            (0, 0),
+            |(files, unreadable), (entry_files, entry_unreadable)| {
+                (files + entry_files, unreadable + entry_unreadable)
+            },
+        )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn page_ranges() -> Result<()> {
+        assert_eq!(
+            parse_page_ranges("1-3, 5,8-", 9)?,
+            BTreeSet::from([1, 2, 3, 5, 8, 9])
+        );
+        assert!(parse_page_ranges("0-2", 9).is_err());
+        assert!(parse_page_ranges("4-12", 9).is_err());
+        assert!(parse_page_ranges("three", 9).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn plan_from_file_list() -> Result<()> {
+        let file_list = "/archive/2024/q1/a.pdf\n/archive/2024/q1/b.pdf\n\n\
+                         /archive/2024/summary.pdf\n/archive/2024/q1/c.pdf\n";
+        let file_paths = read_file_list(file_list.as_bytes(), false)?;
+        assert_eq!(file_paths.len(), 4);
+
+        let plan = MergePlan::from_file_list(&file_paths)?;
+        assert_eq!(plan.base_dir, std::path::absolute("/archive/2024")?);
+        let titles: Vec<Option<&str>> = plan
+            .entries
+            .iter()
+            .map(|entry| entry.title.as_deref())
+            .collect();
+        assert_eq!(titles, vec![Some("q1"), None, Some("q1")]);
+        assert_eq!(plan.entries[0].children.len(), 2);
+        assert_eq!(
+            plan.entries[0].children[1].path.as_deref(),
+            Some(Path::new("q1/b.pdf"))
+        );
+
+        let nul_separated = read_file_list("a.pdf\0dir/b.pdf\0".as_bytes(), true)?;
+        assert_eq!(
+            nul_separated,
+            vec![PathBuf::from("a.pdf"), PathBuf::from("dir/b.pdf")]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn plan_with_cumulative_pages() -> Result<()> {
+        let planned_entries = vec![PlannedEntry::directory(
+            "lectures",
+            vec![
+                PlannedEntry::file("lectures/intro.pdf", Ok(2)),
+                PlannedEntry::directory(
+                    "lectures/week1",
+                    vec![
+                        PlannedEntry::file("lectures/week1/a.pdf", Ok(1)),
+                        PlannedEntry::file("lectures/week1/b.pdf", Err(anyhow!("no trailer"))),
+                    ],
+                ),
+                PlannedEntry::file("lectures/outro.pdf", Ok(3)),
+            ],
+        )];
+
+        assert_eq!(
+            format_plan(&planned_entries)?,
+            "lectures/  6 pages (p. 1-6)\n\
+             \x20 intro.pdf  2 pages (p. 1-2)\n\
+             \x20 week1/  1 page (p. 3)\n\
+             \x20   a.pdf  1 page (p. 3)\n\
+             \x20   b.pdf  unreadable: no trailer\n\
+             \x20 outro.pdf  3 pages (p. 4-6)\n\
+             Total: 4 files, 6 pages\n\
+             Unreadable files: 1\n"
+        );
+
+        Ok(())
+    }
+}
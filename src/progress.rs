@@ -0,0 +1,244 @@
+use indicatif::{ProgressBar, ProgressStyle};
+use log::{error, info, warn};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How often the watchdog looks at the progress, unless the heartbeat interval or the stall
+/// timeout is shorter.
+const DEFAULT_WATCHDOG_TICK: Duration = Duration::from_secs(1);
+/// Layout of the progress bar drawn by `Progress::with_bar`.
+const BAR_TEMPLATE: &str = "{bar:30} {pos}/{len} files, {msg}";
+/// Exit code of the process aborted because of a stall.
+pub const STALL_EXIT_CODE: i32 = 75;
+
+/// What the watchdog does when the merge has not progressed for the stall timeout.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum StallAction {
+    /// Log a warning (once per stall) and keep waiting
+    Warn,
+    /// Log an error and terminate the process, as a blocked read cannot be interrupted
+    #[default]
+    Abort,
+}
+
+/// Shared record of the current phase and file of the merge, updated by the merging code
+/// and read by the watchdog.
+#[derive(Debug, Clone, Default)]
+pub struct Progress(Arc<Mutex<ProgressState>>);
+
+#[derive(Debug, Default)]
+struct ProgressState {
+    phase: &'static str,
+    current: Option<PathBuf>,
+    steps: usize,
+    last_step: Option<Instant>,
+    bar: Option<ProgressBar>,
+    pages_merged: u32,
+}
+
+impl Progress {
+    /// A record which also draws on stderr (when it is a terminal) a bar of the files merged
+    /// out of the files found so far, with the pages merged and the current file.
+    pub fn with_bar() -> Self {
+        let bar = ProgressBar::new(0);
+        if let Ok(style) = ProgressStyle::with_template(BAR_TEMPLATE) {
+            bar.set_style(style);
+        }
+        let progress = Progress::default();
+        progress.lock().bar = Some(bar);
+        progress
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, ProgressState> {
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Record that the merge moved on to the phase, working on the given path if any.
+    pub fn step(&self, phase: &'static str, current: Option<&Path>) {
+        let mut state = self.lock();
+        state.phase = phase;
+        state.current = current.map(Path::to_path_buf);
+        state.steps += 1;
+        state.last_step = Some(Instant::now());
+        state.update_bar_message();
+    }
+
+    /// Record that the traversal found more files to merge.
+    pub fn files_found(&self, count: usize) {
+        if let Some(bar) = &self.lock().bar {
+            bar.inc_length(count as u64);
+        }
+    }
+
+    /// Record that a file with the given number of pages was merged.
+    pub fn file_merged(&self, page_count: u32) {
+        let mut state = self.lock();
+        state.pages_merged += page_count;
+        if let Some(bar) = &state.bar {
+            bar.inc(1);
+        }
+        state.update_bar_message();
+    }
+
+    /// Record that a file was left out of the merge because of an error.
+    pub fn file_skipped(&self) {
+        if let Some(bar) = &self.lock().bar {
+            bar.inc(1);
+        }
+    }
+
+    /// Remove the progress bar, if any, from the terminal.
+    pub fn finish(&self) {
+        if let Some(bar) = self.lock().bar.take() {
+            bar.finish_and_clear();
+        }
+    }
+
+    fn describe(&self) -> (String, Duration) {
+        let state = self.lock();
+        let idle = state
+            .last_step
+            .map_or(Duration::ZERO, |last_step| last_step.elapsed());
+        let current = state
+            .current
+            .as_ref()
+            .map(|current| format!(" on '{}'", current.display()))
+            .unwrap_or_default();
+
+        (
+            format!(
+                "phase '{}'{current}, {} steps done",
+                state.phase, state.steps
+            ),
+            idle,
+        )
+    }
+}
+
+impl ProgressState {
+    fn update_bar_message(&self) {
+        let Some(bar) = &self.bar else {
+            return;
+        };
+        let current = self
+            .current
+            .as_ref()
+            .and_then(|current| current.file_name())
+            .map(|file_name| format!(" ({})", file_name.to_string_lossy()))
+            .unwrap_or_default();
+        bar.set_message(format!("{} pages{current}", self.pages_merged));
+    }
+}
+
+/// Run the job while a watchdog thread logs a heartbeat with the current phase and file every
+/// `heartbeat_interval`, and reacts as `stall_action` says if no step is recorded for
+/// `stall_timeout`. Without interval and timeout the job is just run.
+pub fn watch<T>(
+    progress: &Progress,
+    heartbeat_interval: Option<Duration>,
+    stall_timeout: Option<Duration>,
+    stall_action: StallAction,
+    job: impl FnOnce() -> T,
+) -> T {
+    if heartbeat_interval.is_none() && stall_timeout.is_none() {
+        return job();
+    }
+
+    progress.step("start", None);
+    let tick = [heartbeat_interval, stall_timeout]
+        .into_iter()
+        .flatten()
+        .fold(DEFAULT_WATCHDOG_TICK, Duration::min);
+    let (done_sender, done_receiver) = mpsc::channel::<()>();
+
+    std::thread::scope(|scope| {
+        scope.spawn(move || {
+            let mut last_heartbeat = Instant::now();
+            let mut stall_reported = false;
+
+            // The sender is dropped when the job ends, which disconnects the channel
+            while let Err(RecvTimeoutError::Timeout) = done_receiver.recv_timeout(tick) {
+                let (description, idle) = progress.describe();
+
+                if let Some(interval) = heartbeat_interval
+                    && last_heartbeat.elapsed() >= interval
+                {
+                    info!("Still merging: {description}");
+                    last_heartbeat = Instant::now();
+                }
+
+                match stall_timeout {
+                    Some(timeout) if idle >= timeout => match stall_action {
+                        StallAction::Warn if !stall_reported => {
+                            warn!("No progress for {}s: {description}", idle.as_secs());
+                            stall_reported = true;
+                        }
+                        StallAction::Warn => {}
+                        StallAction::Abort => {
+                            error!(
+                                "No progress for {}s, aborting: {description}",
+                                idle.as_secs()
+                            );
+                            std::process::exit(STALL_EXIT_CODE);
+                        }
+                    },
+                    _ => stall_reported = false,
+                }
+            }
+        });
+
+        let output = job();
+        drop(done_sender);
+        output
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bar_counts_files_and_pages() {
+        let progress = Progress::with_bar();
+        progress.files_found(2);
+        progress.files_found(1);
+        progress.step("merge", Some(Path::new("lectures/week1.pdf")));
+        progress.file_merged(4);
+        progress.file_skipped();
+
+        let state = progress.lock();
+        let bar = state.bar.as_ref().unwrap();
+        assert_eq!((bar.position(), bar.length()), (2, Some(3)));
+        assert_eq!(bar.message(), "4 pages (week1.pdf)");
+    }
+
+    #[test]
+    fn stall_is_measured_from_the_last_step() {
+        let progress = Progress::default();
+        progress.step("load", Some(Path::new("slow.pdf")));
+        std::thread::sleep(Duration::from_millis(20));
+
+        let (description, idle) = progress.describe();
+        assert_eq!(description, "phase 'load' on 'slow.pdf', 1 steps done");
+        assert!(idle >= Duration::from_millis(20));
+
+        // With `Warn` the watchdog lets the stalled job run to its end
+        let output = watch(
+            &progress,
+            None,
+            Some(Duration::from_millis(5)),
+            StallAction::Warn,
+            || {
+                std::thread::sleep(Duration::from_millis(30));
+                progress.step("merge", None);
+                "merged"
+            },
+        );
+        assert_eq!(output, "merged");
+        assert!(progress.describe().1 < Duration::from_millis(20));
+    }
+}
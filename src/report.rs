@@ -0,0 +1,422 @@
+use crate::outline::TitleMapping;
+use crate::source::{self, EntryKind};
+use anyhow::Result;
+use log::warn;
+use lopdf::ObjectId;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Record of a merge run, serialisable as JSON.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MergeReport {
+    /// The input directory of the run.
+    pub root: PathBuf,
+    /// The merged files, in the order in which they appear in the output.
+    pub sources: Vec<SourceRecord>,
+    /// Problems encountered which did not prevent the merge.
+    #[serde(default)]
+    pub warnings: Vec<String>,
+    /// The traversed directories, to skip listing them again when they did not change.
+    #[serde(default)]
+    pub directories: Vec<DirectoryRecord>,
+    /// The bookmarks renamed because a sibling had the same title.
+    #[serde(default)]
+    pub title_mappings: Vec<TitleMapping>,
+    /// The files left out of the merge because of an error.
+    #[serde(default)]
+    pub skipped: Vec<SkippedRecord>,
+    /// The id in the output of the bookmark of each source file and directory, by path
+    /// relative to the input directory (see `outline::append_child_bookmark`).
+    #[serde(default)]
+    pub bookmark_ids: BTreeMap<PathBuf, ObjectId>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SkippedRecord {
+    /// Path of the file relative to the input directory.
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DirectoryRecord {
+    /// Path of the directory relative to the input directory (empty for the input directory).
+    pub path: PathBuf,
+    /// Modification time of the directory in seconds since the Unix epoch, if available.
+    pub modified: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SourceRecord {
+    /// Path of the file relative to the input directory.
+    pub path: PathBuf,
+    /// Size of the file in bytes.
+    pub size: u64,
+    /// Modification time of the file in seconds since the Unix epoch, if available.
+    pub modified: Option<u64>,
+    pub page_count: u32,
+    /// Number (starting from 1) of the first page of the file in the output.
+    #[serde(default)]
+    pub first_page: u32,
+    /// Number of the last page of the file in the output.
+    #[serde(default)]
+    pub last_page: u32,
+    /// Title of the bookmark of the file, as resolved in the outline (after numbering and
+    /// disambiguation of the siblings).
+    #[serde(default)]
+    pub title: Option<String>,
+    /// The `/PageMode` of the catalog of the file, if any.
+    #[serde(default)]
+    pub page_mode: Option<String>,
+    /// Number of pages of the file for each format (e.g. "A4 portrait").
+    #[serde(default)]
+    pub page_formats: BTreeMap<String, u32>,
+    /// First and last Bates identifier of the pages of the file, if they were stamped.
+    #[serde(default)]
+    pub bates_range: Option<(String, String)>,
+    /// The file held digital signatures, which the merge invalidated.
+    #[serde(default)]
+    pub signed: bool,
+}
+
+impl MergeReport {
+    pub fn new(root: impl AsRef<Path>) -> Self {
+        MergeReport {
+            root: root.as_ref().to_path_buf(),
+            sources: Vec::new(),
+            warnings: Vec::new(),
+            directories: Vec::new(),
+            title_mappings: Vec::new(),
+            skipped: Vec::new(),
+            bookmark_ids: BTreeMap::new(),
+        }
+    }
+
+    /// Record that the file is left out of the merge because of the error.
+    pub fn record_skipped(&mut self, source_path: impl AsRef<Path>, err: &impl std::fmt::Display) {
+        let source_path = source_path.as_ref();
+        warn!("Skip '{}': {err}", source_path.display());
+
+        self.skipped.push(SkippedRecord {
+            path: source_path
+                .strip_prefix(&self.root)
+                .unwrap_or(source_path)
+                .to_path_buf(),
+            reason: err.to_string(),
+        });
+    }
+
+    /// Record the file (given by its full path) as merged.
+    pub fn record_source(&mut self, source_path: impl AsRef<Path>, page_count: u32) -> Result<()> {
+        let (size, modified) = file_fingerprint(source_path.as_ref())?;
+        self.record_source_with_fingerprint(source_path, page_count, size, modified);
+        Ok(())
+    }
+
+    /// As `record_source`, with the size and modification time given instead of read from
+    /// the file system (e.g. for the members of an archive).
+    pub fn record_source_with_fingerprint(
+        &mut self,
+        source_path: impl AsRef<Path>,
+        page_count: u32,
+        size: u64,
+        modified: Option<u64>,
+    ) {
+        let source_path = source_path.as_ref();
+        let first_page = self
+            .sources
+            .iter()
+            .map(|source| source.page_count)
+            .sum::<u32>()
+            + 1;
+
+        self.sources.push(SourceRecord {
+            path: source_path
+                .strip_prefix(&self.root)
+                .unwrap_or(source_path)
+                .to_path_buf(),
+            size,
+            modified,
+            page_count,
+            first_page,
+            last_page: first_page + page_count.saturating_sub(1),
+            title: None,
+            page_mode: None,
+            page_formats: BTreeMap::new(),
+            bates_range: None,
+            signed: false,
+        });
+    }
+
+    /// Record the directory (given by its full path) as traversed.
+    pub fn record_directory(&mut self, directory_path: impl AsRef<Path>) -> Result<()> {
+        let directory_path = directory_path.as_ref();
+        let (_size, modified) = file_fingerprint(directory_path)?;
+
+        self.directories.push(DirectoryRecord {
+            path: directory_path
+                .strip_prefix(&self.root)
+                .unwrap_or(directory_path)
+                .to_path_buf(),
+            modified,
+        });
+
+        Ok(())
+    }
+
+    /// Account for pages inserted at the beginning of the output.
+    pub fn shift_pages(&mut self, num_inserted_pages: u32) {
+        self.shift_pages_from(1, num_inserted_pages);
+    }
+
+    /// Account for pages inserted before the page with the given number.
+    pub fn shift_pages_from(&mut self, page_number: u32, num_inserted_pages: u32) {
+        for source in &mut self.sources {
+            if source.first_page >= page_number {
+                source.first_page += num_inserted_pages;
+                source.last_page += num_inserted_pages;
+            }
+        }
+    }
+
+    /// Record the titles of the bookmarks of the files (given by their full paths).
+    pub fn record_titles<'a>(&mut self, titles: impl IntoIterator<Item = (&'a Path, &'a str)>) {
+        let mut titles: BTreeMap<&Path, &str> = titles
+            .into_iter()
+            .map(|(source_path, title)| {
+                (
+                    source_path.strip_prefix(&self.root).unwrap_or(source_path),
+                    title,
+                )
+            })
+            .collect();
+
+        for source in &mut self.sources {
+            if let Some(title) = titles.remove(source.path.as_path()) {
+                source.title = Some(title.to_string());
+            }
+        }
+    }
+
+    pub fn save(&self, report_path: impl AsRef<Path>) -> Result<()> {
+        let file = std::fs::File::create(report_path.as_ref())?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+
+    pub fn load(report_path: impl AsRef<Path>) -> Result<Self> {
+        let file = std::fs::File::open(report_path.as_ref())?;
+        Ok(serde_json::from_reader(std::io::BufReader::new(file))?)
+    }
+
+    /// Write the skipped files in the format of a skip list, each one preceded by its
+    /// reason as a comment, so that the next runs can exclude them upfront.
+    pub fn save_skipped_list(&self, skipped_list_path: impl AsRef<Path>) -> Result<()> {
+        let mut file = std::io::BufWriter::new(std::fs::File::create(skipped_list_path.as_ref())?);
+        for skipped in &self.skipped {
+            writeln!(file, "# {}", skipped.reason.replace(['\n', '\r'], " "))?;
+            writeln!(file, "{}", skipped.path.display())?;
+        }
+        file.flush()?;
+        Ok(())
+    }
+}
+
+/// Files which a re-merge of a tree would add, remove or update with respect to a previous run.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TreeChanges {
+    pub added: Vec<PathBuf>,
+    pub removed: Vec<PathBuf>,
+    pub modified: Vec<PathBuf>,
+}
+
+impl TreeChanges {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+}
+
+/// Compare the files currently in the tree with the ones recorded in the report. A file
+/// counts as modified if its size or modification time differ from the recorded ones.
+///
+/// If `trust_directory_mtimes` is set, a directory whose modification time did not change
+/// since the report is not listed again, and its files are taken as unchanged without
+/// reading their metadata: much faster on large trees, but files rewritten in place (rather
+/// than replaced) in such directories are not detected.
+pub fn diff_tree_against_report(
+    root: impl AsRef<Path>,
+    previous_report: &MergeReport,
+    trust_directory_mtimes: bool,
+) -> Result<TreeChanges> {
+    let root = root.as_ref();
+
+    let mut previous_sources: BTreeMap<&Path, &SourceRecord> = previous_report
+        .sources
+        .iter()
+        .map(|source| (source.path.as_path(), source))
+        .collect();
+
+    let unchanged_directories: HashSet<&Path> = if trust_directory_mtimes {
+        previous_report
+            .directories
+            .iter()
+            .filter(|directory| {
+                file_fingerprint(root.join(&directory.path))
+                    .is_ok_and(|(_size, modified)| modified == directory.modified)
+            })
+            .map(|directory| directory.path.as_path())
+            .collect()
+    } else {
+        HashSet::new()
+    };
+
+    let mut changes = TreeChanges::default();
+
+    for (relative_path, trusted) in list_tree_files(root, previous_report, &unchanged_directories)?
+    {
+        match previous_sources.remove(relative_path.as_path()) {
+            None => changes.added.push(relative_path),
+            Some(_previous_source) if trusted => {}
+            Some(previous_source) => {
+                let fingerprint = file_fingerprint(root.join(&relative_path))?;
+                if fingerprint != (previous_source.size, previous_source.modified) {
+                    changes.modified.push(relative_path);
+                }
+            }
+        }
+    }
+
+    changes.removed = previous_sources
+        .into_keys()
+        .map(|path| path.to_path_buf())
+        .collect();
+
+    Ok(changes)
+}
+
+/// All the files in the tree, as paths relative to the root, sorted. The content of the
+/// unchanged directories is taken from the report, and their files are flagged as trusted.
+fn list_tree_files(
+    root: &Path,
+    previous_report: &MergeReport,
+    unchanged_directories: &HashSet<&Path>,
+) -> Result<Vec<(PathBuf, bool)>> {
+    let is_child_of = |path: &Path, directory: &Path| path.parent() == Some(directory);
+
+    let mut files = Vec::new();
+    let mut directories = vec![PathBuf::new()];
+
+    while let Some(directory) = directories.pop() {
+        if unchanged_directories.contains(directory.as_path()) {
+            files.extend(
+                previous_report
+                    .sources
+                    .iter()
+                    .filter(|source| is_child_of(&source.path, &directory))
+                    .map(|source| (source.path.clone(), true)),
+            );
+            directories.extend(
+                previous_report
+                    .directories
+                    .iter()
+                    .filter(|subdirectory| is_child_of(&subdirectory.path, &directory))
+                    .map(|subdirectory| subdirectory.path.clone()),
+            );
+            continue;
+        }
+
+        for entry in std::fs::read_dir(root.join(&directory))? {
+            let entry = entry?;
+            let relative_path = directory.join(entry.file_name());
+            match source::entry_kind(entry.path()) {
+                EntryKind::File => files.push((relative_path, false)),
+                EntryKind::Directory => directories.push(relative_path),
+                EntryKind::Special(_kind) => {}
+            }
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+fn file_fingerprint(path: impl AsRef<Path>) -> Result<(u64, Option<u64>)> {
+    let metadata = std::fs::metadata(path.as_ref())?;
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|since_epoch| since_epoch.as_secs());
+
+    Ok((metadata.len(), modified))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support;
+
+    #[test]
+    fn changes_since_report() -> Result<()> {
+        let test_dir = test_support::get_virgin_test_dir("changes_since_report")?;
+        let root = format!("{test_dir}/root_pdfs");
+        let identity_function = |n: u8| n;
+        test_support::generate_fn_tree_with_levels(&root, 2, 2, 0, 1, &identity_function)?;
+
+        let mut report = MergeReport::new(&root);
+        for (relative_path, _trusted) in
+            list_tree_files(Path::new(&root), &report, &HashSet::new())?
+        {
+            report.record_source(Path::new(&root).join(relative_path), 1)?;
+        }
+        assert!(diff_tree_against_report(&root, &report, false)?.is_empty());
+
+        let removed = PathBuf::from("L2S1/pdf_doc1.pdf");
+        std::fs::remove_file(Path::new(&root).join(&removed))?;
+        let added = PathBuf::from("L2S1/new_doc.pdf");
+        std::fs::write(Path::new(&root).join(&added), b"%PDF-1.7")?;
+
+        let changes = diff_tree_against_report(&root, &report, false)?;
+        assert_eq!(changes.added, vec![added]);
+        assert_eq!(changes.removed, vec![removed]);
+        assert!(changes.modified.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn unchanged_directories_are_trusted() -> Result<()> {
+        let test_dir = test_support::get_virgin_test_dir("unchanged_directories_are_trusted")?;
+        let root = format!("{test_dir}/root_pdfs");
+        let identity_function = |n: u8| n;
+        test_support::generate_fn_tree_with_levels(&root, 2, 2, 0, 1, &identity_function)?;
+
+        let mut report = MergeReport::new(&root);
+        report.record_directory(&root)?;
+        for entry in std::fs::read_dir(&root)? {
+            report.record_directory(entry?.path())?;
+        }
+        for (relative_path, _trusted) in
+            list_tree_files(Path::new(&root), &report, &HashSet::new())?
+        {
+            report.record_source(Path::new(&root).join(relative_path), 1)?;
+        }
+
+        // Rewriting a file in place leaves the modification time of its directory alone
+        let rewritten = PathBuf::from("L2S1/pdf_doc1.pdf");
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(Path::new(&root).join(&rewritten))?;
+        file.write_all(b"\n% rewritten")?;
+        drop(file);
+
+        let changes = diff_tree_against_report(&root, &report, false)?;
+        assert_eq!(changes.modified, vec![rewritten]);
+        assert!(diff_tree_against_report(&root, &report, true)?.is_empty());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,255 @@
+use anyhow::Result;
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use std::collections::HashSet;
+
+/// What to do with the source files holding digital signatures, which the merge invalidates.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum SignedPolicy {
+    /// Abort the merge
+    #[default]
+    Error,
+    /// Remove the signature fields and their widgets from the pages
+    Strip,
+    /// Merge the file, keeping the appearance of the signatures, and report it
+    Warn,
+}
+
+/// A terminal signature field of the form of a document.
+struct SignatureField {
+    id: ObjectId,
+    /// The field holds a signature, not only the place for one.
+    signed: bool,
+}
+
+/// Whether a field of the form of the document holds a signature.
+pub fn is_signed(doc: &Document) -> Result<bool> {
+    Ok(signature_fields(doc)?.iter().any(|field| field.signed))
+}
+
+/// Remove the signature fields from the form of the document and, unless `keep_appearances`,
+/// their widgets from the pages. The form, with the permissions (`/Perms`) bound to the
+/// signatures, is dropped once no field is left, otherwise the other fields are kept.
+/// Returns the number of signature fields removed.
+pub fn strip_signature_fields(doc: &mut Document, keep_appearances: bool) -> Result<usize> {
+    let fields = signature_fields(doc)?;
+    if fields.is_empty() {
+        return Ok(0);
+    }
+    let field_ids: HashSet<ObjectId> = fields.iter().map(|field| field.id).collect();
+
+    if !keep_appearances {
+        // The widgets are either the fields themselves or their kids
+        let mut widget_ids = field_ids.clone();
+        for field_id in &field_ids {
+            if let Ok(kids) = doc
+                .get_dictionary(*field_id)
+                .and_then(|field| field.get(b"Kids"))
+                .and_then(Object::as_array)
+            {
+                widget_ids.extend(kids.iter().filter_map(|kid| kid.as_reference().ok()));
+            }
+        }
+        for page_id in doc.get_pages().into_values() {
+            remove_annotations(doc, page_id, &widget_ids)?;
+        }
+    }
+
+    let top_fields = acro_form(doc)?
+        .and_then(|acro_form| acro_form.get(b"Fields").ok())
+        .and_then(|top_fields| doc.dereference(top_fields).ok())
+        .and_then(|(_, top_fields)| top_fields.as_array().ok())
+        .cloned()
+        .unwrap_or_default();
+    let kept_fields = retain_fields(doc, top_fields, &field_ids)?;
+
+    if kept_fields.is_empty() {
+        let catalog = doc.catalog_mut()?;
+        catalog.remove(b"AcroForm");
+        catalog.remove(b"Perms");
+    } else {
+        let acro_form_id = match doc.catalog()?.get(b"AcroForm")? {
+            Object::Reference(acro_form_id) => Some(*acro_form_id),
+            _ => None,
+        };
+        let acro_form = match acro_form_id {
+            Some(acro_form_id) => doc.get_dictionary_mut(acro_form_id)?,
+            None => doc.catalog_mut()?.get_mut(b"AcroForm")?.as_dict_mut()?,
+        };
+        acro_form.set("Fields", kept_fields);
+        acro_form.remove(b"SigFlags");
+    }
+
+    Ok(fields.len())
+}
+
+/// The fields left once the removed ones are taken out of them and of their descendants,
+/// dropping the parent fields left without kid fields.
+fn retain_fields(
+    doc: &mut Document,
+    field_refs: Vec<Object>,
+    removed_ids: &HashSet<ObjectId>,
+) -> Result<Vec<Object>> {
+    let mut kept_fields = Vec::new();
+    for field_ref in field_refs {
+        let Ok(field_id) = field_ref.as_reference() else {
+            kept_fields.push(field_ref);
+            continue;
+        };
+        if removed_ids.contains(&field_id) {
+            continue;
+        }
+        let kids = doc
+            .get_dictionary(field_id)
+            .and_then(|field| field.get(b"Kids"))
+            .and_then(Object::as_array)
+            .cloned()
+            .unwrap_or_default();
+        // The kids without a partial name are widgets, not fields
+        let (child_fields, widgets): (Vec<Object>, Vec<Object>) =
+            kids.into_iter().partition(|kid| {
+                kid.as_reference()
+                    .and_then(|kid_id| doc.get_dictionary(kid_id))
+                    .is_ok_and(|kid| kid.has(b"T"))
+            });
+        if !child_fields.is_empty() {
+            let kept_children = retain_fields(doc, child_fields, removed_ids)?;
+            if kept_children.is_empty() && widgets.is_empty() {
+                continue;
+            }
+            let kids: Vec<Object> = kept_children.into_iter().chain(widgets).collect();
+            doc.get_dictionary_mut(field_id)?.set("Kids", kids);
+        }
+        kept_fields.push(field_ref);
+    }
+
+    Ok(kept_fields)
+}
+
+fn acro_form(doc: &Document) -> Result<Option<&Dictionary>> {
+    let Ok(acro_form) = doc.catalog()?.get(b"AcroForm") else {
+        return Ok(None);
+    };
+    let (_, acro_form) = doc.dereference(acro_form)?;
+    Ok(Some(acro_form.as_dict()?))
+}
+
+fn signature_fields(doc: &Document) -> Result<Vec<SignatureField>> {
+    let mut fields = Vec::new();
+    if let Some(top_fields) = acro_form(doc)?
+        .and_then(|acro_form| acro_form.get(b"Fields").ok())
+        .and_then(|top_fields| doc.dereference(top_fields).ok())
+        .and_then(|(_, top_fields)| top_fields.as_array().ok())
+    {
+        collect_signature_fields(doc, top_fields, None, &mut fields);
+    }
+
+    Ok(fields)
+}
+
+/// Collect the terminal signature fields among the fields and their descendants, the field
+/// type being inherited from the parent fields.
+fn collect_signature_fields(
+    doc: &Document,
+    field_refs: &[Object],
+    inherited_type: Option<&[u8]>,
+    fields: &mut Vec<SignatureField>,
+) {
+    for field_ref in field_refs {
+        let Ok(field_id) = field_ref.as_reference() else {
+            continue;
+        };
+        let Ok(field) = doc.get_dictionary(field_id) else {
+            continue;
+        };
+        let field_type = field
+            .get(b"FT")
+            .and_then(Object::as_name)
+            .ok()
+            .or(inherited_type);
+
+        // The kids without a partial name are widgets, not fields
+        let child_fields: Vec<Object> = field
+            .get(b"Kids")
+            .and_then(Object::as_array)
+            .map(|kids| {
+                kids.iter()
+                    .filter(|kid| {
+                        kid.as_reference()
+                            .and_then(|kid_id| doc.get_dictionary(kid_id))
+                            .is_ok_and(|kid| kid.has(b"T"))
+                    })
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        if !child_fields.is_empty() {
+            collect_signature_fields(doc, &child_fields, field_type, fields);
+        } else if field_type == Some(b"Sig".as_slice()) {
+            fields.push(SignatureField {
+                id: field_id,
+                signed: field.has(b"V"),
+            });
+        }
+    }
+}
+
+fn remove_annotations(
+    doc: &mut Document,
+    page_id: ObjectId,
+    annotation_ids: &HashSet<ObjectId>,
+) -> Result<()> {
+    let annots_id = match doc.get_dictionary(page_id)?.get(b"Annots") {
+        Ok(Object::Reference(annots_id)) => Some(*annots_id),
+        Ok(_) => None,
+        Err(_) => return Ok(()),
+    };
+    let annots = match annots_id {
+        Some(annots_id) => doc.get_object_mut(annots_id)?,
+        None => doc.get_dictionary_mut(page_id)?.get_mut(b"Annots")?,
+    };
+    annots.as_array_mut()?.retain(|annot| {
+        !annot
+            .as_reference()
+            .is_ok_and(|annot_id| annotation_ids.contains(&annot_id))
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support;
+
+    #[test]
+    fn signed_fields_are_stripped() -> Result<()> {
+        let mut doc = test_support::get_basic_pdf_doc("doc", 1)?;
+        assert!(!is_signed(&doc)?);
+        let page_id = test_support::add_signed_form(&mut doc, false)?;
+        assert!(is_signed(&doc)?);
+
+        assert_eq!(strip_signature_fields(&mut doc, false)?, 1);
+        assert!(!doc.catalog()?.has(b"AcroForm"));
+        let annots = doc.get_dictionary(page_id)?.get(b"Annots")?.as_array()?;
+        assert!(annots.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn other_fields_are_kept() -> Result<()> {
+        let mut doc = test_support::get_basic_pdf_doc("doc", 1)?;
+        let page_id = test_support::add_signed_form(&mut doc, true)?;
+
+        assert_eq!(strip_signature_fields(&mut doc, false)?, 1);
+        assert!(!is_signed(&doc)?);
+        let acro_form = acro_form(&doc)?.ok_or(anyhow::anyhow!("The form was dropped"))?;
+        assert_eq!(acro_form.get(b"Fields")?.as_array()?.len(), 1);
+        assert!(!acro_form.has(b"SigFlags"));
+        let annots = doc.get_dictionary(page_id)?.get(b"Annots")?.as_array()?;
+        assert_eq!(annots.len(), 1);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,131 @@
+use crate::utils;
+use anyhow::{Result, anyhow};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+const HASH_PREFIX: &str = "sha256:";
+const COMMENT_PREFIX: char = '#';
+
+/// Files to exclude from the merge, e.g. because they are known to be broken. In the
+/// skip-list file every line is either a path relative to the input directory or the
+/// SHA-256 of the content of a file in the form `sha256:<hex digest>`. Empty lines and
+/// lines starting with '#' are ignored.
+#[derive(Debug, Clone, Default)]
+pub struct SkipList {
+    paths: HashSet<PathBuf>,
+    hashes: HashSet<String>,
+}
+
+impl SkipList {
+    pub fn load(skip_list_path: impl AsRef<Path>) -> Result<Self> {
+        let content = std::fs::read_to_string(skip_list_path.as_ref())?;
+        Self::parse(&content)
+    }
+
+    pub fn parse(content: &str) -> Result<Self> {
+        let mut skip_list = SkipList::default();
+
+        for line in content.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with(COMMENT_PREFIX) {
+                continue;
+            }
+
+            match line.strip_prefix(HASH_PREFIX) {
+                Some(hex_digest) => {
+                    if hex_digest.len() != 64 || !hex_digest.chars().all(|c| c.is_ascii_hexdigit())
+                    {
+                        return Err(anyhow!(
+                            "The line '{line}' of the skip list is not a valid SHA-256 digest"
+                        ));
+                    }
+                    skip_list.hashes.insert(hex_digest.to_ascii_lowercase());
+                }
+                None => {
+                    skip_list.paths.insert(utils::path_from_portable(line));
+                }
+            }
+        }
+
+        Ok(skip_list)
+    }
+
+    /// Exclude also the file at the path, relative to the input directory.
+    pub fn insert_path(&mut self, relative_path: impl AsRef<Path>) {
+        self.paths.insert(relative_path.as_ref().to_path_buf());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty() && self.hashes.is_empty()
+    }
+
+    /// Whether some files are listed by the hash of their content.
+    pub fn has_hashes(&self) -> bool {
+        !self.hashes.is_empty()
+    }
+
+    /// Whether the file is listed, by its path relative to the root or by the hash of its content.
+    pub fn excludes(&self, file_path: impl AsRef<Path>, root: impl AsRef<Path>) -> Result<bool> {
+        let file_path = file_path.as_ref();
+        let relative_path = file_path.strip_prefix(root.as_ref()).unwrap_or(file_path);
+
+        if self.paths.contains(relative_path) {
+            return Ok(true);
+        }
+        if self.hashes.is_empty() {
+            return Ok(false);
+        }
+
+        Ok(self
+            .hashes
+            .contains(&sha256_hex(&std::fs::read(file_path)?)))
+    }
+
+    /// Append the paths (relative to the input directory) to the skip-list file, creating it if needed.
+    pub fn append_paths(
+        skip_list_path: impl AsRef<Path>,
+        relative_paths: &[impl AsRef<Path>],
+    ) -> Result<()> {
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(skip_list_path.as_ref())?;
+
+        for relative_path in relative_paths {
+            writeln!(file, "{}", relative_path.as_ref().display())?;
+        }
+
+        Ok(())
+    }
+}
+
+pub fn sha256_hex(content: &[u8]) -> String {
+    Sha256::digest(content)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_paths_hashes_and_comments() -> Result<()> {
+        let empty_file_hash = sha256_hex(b"");
+        let content = format!(
+            "# broken scans\n\nlectures/week3.pdf\n{HASH_PREFIX}{}\n",
+            empty_file_hash.to_ascii_uppercase()
+        );
+
+        let skip_list = SkipList::parse(&content)?;
+        assert!(skip_list.paths.contains(Path::new("lectures/week3.pdf")));
+        assert!(skip_list.hashes.contains(&empty_file_hash));
+        assert_eq!(skip_list.paths.len() + skip_list.hashes.len(), 2);
+
+        assert!(SkipList::parse("sha256:not-a-digest").is_err());
+
+        Ok(())
+    }
+}
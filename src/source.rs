@@ -0,0 +1,246 @@
+//! The files and directories found in the input trees: what kind of entry each is, whether
+//! a file is a PDF file and how many pages it has, without loading it.
+
+use anyhow::Result;
+use lazy_static::lazy_static;
+use lopdf::Document;
+use regex::bytes::Regex;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+lazy_static! {
+    static ref ROOT_REFERENCE: Regex = Regex::new(r"/Root\s+(\d+)\s+(\d+)\s+R").unwrap();
+    static ref PAGES_REFERENCE: Regex = Regex::new(r"/Pages\s+(\d+)\s+(\d+)\s+R").unwrap();
+    static ref COUNT_ENTRY: Regex = Regex::new(r"/Count\s+(\d+)").unwrap();
+}
+
+/// Report the number of pages of the PDF file without parsing the whole document: the
+/// trailer is followed to the Catalog and then to the root Pages node, whose `/Count` is read.
+/// Falls back to a full parse if an object on the way cannot be found in plain form
+/// (e.g. because it lives in a compressed object stream).
+pub fn quick_page_count(pdf_file_path: impl AsRef<Path>) -> Result<u32> {
+    let buffer = std::fs::read(pdf_file_path.as_ref())?;
+
+    match scan_root_pages_count(&buffer) {
+        Some(page_count) => Ok(page_count),
+        None => Ok(Document::load_mem(&buffer)?.get_pages().len() as u32),
+    }
+}
+
+fn scan_root_pages_count(buffer: &[u8]) -> Option<u32> {
+    // In incrementally updated files the last occurrences are the valid ones.
+    let last_reference = |regex: &Regex, haystack: &[u8]| -> Option<(u32, u16)> {
+        let captures = regex.captures_iter(haystack).last()?;
+        let id = str::from_utf8(&captures[1]).ok()?.parse().ok()?;
+        let generation = str::from_utf8(&captures[2]).ok()?.parse().ok()?;
+        Some((id, generation))
+    };
+
+    let find_object_body = |(id, generation): (u32, u16)| -> Option<&[u8]> {
+        let object_regex = Regex::new(&format!(
+            r"(?s-u)(?:^|\D){id}\s+{generation}\s+obj\b(.*?)\bendobj"
+        ))
+        .ok()?;
+        let captures = object_regex.captures_iter(buffer).last()?;
+        Some(captures.get(1)?.as_bytes())
+    };
+
+    let catalog_id = last_reference(&ROOT_REFERENCE, buffer)?;
+    let catalog_body = find_object_body(catalog_id)?;
+    let pages_id = last_reference(&PAGES_REFERENCE, catalog_body)?;
+    let pages_body = find_object_body(pages_id)?;
+
+    let count_captures = COUNT_ENTRY.captures(pages_body)?;
+    str::from_utf8(&count_captures[1]).ok()?.parse().ok()
+}
+
+/// Bytes at the beginning of a file searched for the `%PDF-` header, which some writers
+/// precede with garbage.
+const PDF_HEADER_SEARCH_LEN: usize = 1024;
+
+/// Whether the file is a PDF file: named `*.pdf` (in any case) or starting with the
+/// `%PDF-` header.
+pub fn is_pdf_file(path: impl AsRef<Path>) -> Result<bool> {
+    let path = path.as_ref();
+    if path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("pdf"))
+    {
+        return Ok(true);
+    }
+
+    let mut beginning = Vec::with_capacity(PDF_HEADER_SEARCH_LEN);
+    File::open(path)?
+        .take(PDF_HEADER_SEARCH_LEN as u64)
+        .read_to_end(&mut beginning)?;
+    Ok(beginning.windows(5).any(|window| window == b"%PDF-"))
+}
+
+/// What an entry of a directory is, following the symbolic links.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Directory,
+    /// FIFO, socket, device or broken symbolic link: nothing to merge, and reading it may block.
+    Special(&'static str),
+}
+
+pub fn entry_kind(path: impl AsRef<Path>) -> EntryKind {
+    let Ok(metadata) = std::fs::metadata(path.as_ref()) else {
+        return EntryKind::Special("broken symbolic link");
+    };
+    let file_type = metadata.file_type();
+    if file_type.is_file() {
+        return EntryKind::File;
+    }
+    if file_type.is_dir() {
+        return EntryKind::Directory;
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        if file_type.is_fifo() {
+            return EntryKind::Special("FIFO");
+        }
+        if file_type.is_socket() {
+            return EntryKind::Special("socket");
+        }
+        if file_type.is_block_device() || file_type.is_char_device() {
+            return EntryKind::Special("device file");
+        }
+    }
+
+    EntryKind::Special("special file")
+}
+
+/// Whether the path leads (through symbolic links) to a regular file.
+pub fn is_file(path: impl AsRef<Path>) -> bool {
+    entry_kind(path) == EntryKind::File
+}
+
+/// Size in bytes of the file, or of all the files below the directory.
+pub fn tree_size(path: impl AsRef<Path>) -> Result<u64> {
+    let path = path.as_ref();
+    let metadata = std::fs::metadata(path)?;
+    if !metadata.is_dir() {
+        return Ok(metadata.len());
+    }
+
+    let mut size = 0;
+    for entry in std::fs::read_dir(path)? {
+        size += tree_size(entry?.path())?;
+    }
+    Ok(size)
+}
+
+/// Whether the input is a glob pattern (with `*` or `?`) rather than a path.
+pub fn is_glob_pattern(input: &str) -> bool {
+    input.contains(['*', '?'])
+}
+
+/// The files matching the glob pattern (see `utils::matches_path_glob`), in natural order
+/// of their paths. The pattern is matched below its longest leading part without wildcards,
+/// e.g. `reports/2024` for `reports/2024/**/*.pdf`. Symbolic links to directories are not
+/// followed.
+pub fn expand_glob(pattern: &str) -> Result<Vec<PathBuf>> {
+    let components: Vec<&str> = pattern.split(['/', '\\']).collect();
+    let literal_len = components
+        .iter()
+        .position(|component| is_glob_pattern(component))
+        .unwrap_or(components.len());
+    let base_dir = match components[..literal_len].join("/") {
+        base_dir if base_dir.is_empty() && pattern.starts_with(['/', '\\']) => "/".to_string(),
+        base_dir if base_dir.is_empty() => ".".to_string(),
+        base_dir => base_dir,
+    };
+    let base_dir = crate::utils::path_from_portable(&base_dir);
+    let relative_pattern = components[literal_len..].join("/");
+
+    let mut matched_paths = Vec::new();
+    let mut directories = vec![base_dir.clone()];
+    while let Some(directory) = directories.pop() {
+        for entry in std::fs::read_dir(&directory)? {
+            let path = entry?.path();
+            if std::fs::symlink_metadata(&path)?.is_dir() {
+                directories.push(path);
+            } else if is_file(&path)
+                && crate::utils::matches_path_glob(&relative_pattern, path.strip_prefix(&base_dir)?)
+            {
+                matched_paths.push(path);
+            }
+        }
+    }
+
+    matched_paths.sort_by(|path, other_path| {
+        let names = |path: &Path| -> Vec<String> {
+            path.iter()
+                .map(|name| name.to_string_lossy().to_string())
+                .collect()
+        };
+        let (names, other_names) = (names(path), names(other_path));
+        names
+            .iter()
+            .zip(&other_names)
+            .map(|(name, other_name)| crate::utils::natural_cmp(name, other_name))
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or_else(|| names.len().cmp(&other_names.len()))
+    });
+
+    Ok(matched_paths)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support;
+
+    #[test]
+    fn quick_page_count_of_classic_and_modern_files() -> Result<()> {
+        let test_dir =
+            test_support::get_virgin_test_dir("quick_page_count_of_classic_and_modern_files")?;
+        let classic_path = format!("{test_dir}/classic.pdf");
+        let modern_path = format!("{test_dir}/modern.pdf");
+
+        let mut document = test_support::get_basic_pdf_doc("doc_name", 7)?;
+        document.save(&classic_path)?;
+
+        let mut buffer = Vec::new();
+        document.save_modern(&mut buffer)?;
+        std::fs::write(&modern_path, buffer)?;
+
+        assert_eq!(quick_page_count(&classic_path)?, 7);
+        assert_eq!(quick_page_count(&modern_path)?, 7);
+
+        Ok(())
+    }
+
+    #[test]
+    fn glob_expansion() -> Result<()> {
+        let test_dir = test_support::get_virgin_test_dir("glob_expansion")?;
+        for relative_path in [
+            "2024/q10/b.pdf",
+            "2024/q2/a.pdf",
+            "2024/q2/notes.txt",
+            "2023/c.pdf",
+        ] {
+            let path = Path::new(&test_dir).join(relative_path);
+            std::fs::create_dir_all(path.parent().unwrap_or(Path::new(&test_dir)))?;
+            std::fs::write(path, b"%PDF-1.7")?;
+        }
+
+        assert!(is_glob_pattern("reports/**/*.pdf"));
+        assert!(!is_glob_pattern("reports/2024"));
+        let matched_paths = expand_glob(&format!("{test_dir}/2024/**/*.pdf"))?;
+        assert_eq!(
+            matched_paths,
+            vec![
+                Path::new(&test_dir).join("2024/q2/a.pdf"),
+                Path::new(&test_dir).join("2024/q10/b.pdf"),
+            ]
+        );
+
+        Ok(())
+    }
+}
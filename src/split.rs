@@ -0,0 +1,330 @@
+use crate::outline::{self, OutlineEntry, OutlineItem, TocNode, read_outline};
+use crate::report::MergeReport;
+use crate::text::TitleEncoding;
+use anyhow::{Result, anyhow};
+use log::{info, warn};
+use lopdf::Document;
+use std::path::{Path, PathBuf};
+
+/// Explode a merged document into one file per outline entry of the given level (1 for the
+/// top-level entries). Each file contains the pages from its entry up to the next file, and is
+/// saved under the directories named after its ancestor entries. Entries above the level
+/// without children become files as well. Returns the paths of the files written.
+pub fn split_by_outline_level(
+    pdf_path: impl AsRef<Path>,
+    level: usize,
+    into_dir: impl AsRef<Path>,
+) -> Result<Vec<PathBuf>> {
+    if level == 0 {
+        return Err(anyhow!(
+            "The split level starts from 1 (the top-level bookmarks)"
+        ));
+    }
+
+    let doc = Document::load(pdf_path.as_ref())?;
+    let num_pages = doc.get_pages().len() as u32;
+    let entries = read_outline(&doc)?;
+    if entries.is_empty() {
+        return Err(anyhow!(
+            "The document '{}' has no outline to split along",
+            pdf_path.as_ref().display()
+        ));
+    }
+
+    let split_points = find_split_points(&entries, level);
+
+    let mut written_paths = Vec::with_capacity(split_points.len());
+    for (index, (relative_path, first_page)) in split_points.iter().enumerate() {
+        let last_page = split_points
+            .get(index + 1)
+            .map_or(num_pages, |(_next_path, next_first_page)| {
+                next_first_page - 1
+            });
+
+        if last_page < *first_page {
+            warn!(
+                "'{}' does not span any page on its own, therefore it is not written",
+                relative_path.display()
+            );
+            continue;
+        }
+
+        let output_path = into_dir.as_ref().join(relative_path);
+        if std::fs::exists(&output_path)? {
+            return Err(anyhow!(
+                "A file '{}' is already present",
+                output_path.display()
+            ));
+        }
+        if let Some(parent_dir) = output_path.parent() {
+            std::fs::create_dir_all(parent_dir)?;
+        }
+
+        let mut part = extract_pages(&doc, *first_page, last_page)?;
+        part.save(&output_path)?;
+        info!(
+            "Pages {first_page}-{last_page} saved as '{}'",
+            output_path.display()
+        );
+        written_paths.push(output_path);
+    }
+
+    Ok(written_paths)
+}
+
+/// Split the merged document into parts of at most `max_pages` pages, cutting only where a
+/// source file of the report starts: a file longer than the limit makes a part on its own.
+/// The pages before the first file (e.g. a table of contents) stay with it. Each part keeps
+/// the bookmarks pointing to its pages.
+pub fn split_by_page_count(
+    doc: &Document,
+    report: &MergeReport,
+    max_pages: u32,
+    encoding: TitleEncoding,
+) -> Result<Vec<Document>> {
+    if max_pages == 0 {
+        return Err(anyhow!("The parts must have at least one page"));
+    }
+
+    let num_pages = doc.get_pages().len() as u32;
+    let file_first_pages: Vec<u32> = report
+        .sources
+        .iter()
+        .map(|source| source.first_page)
+        .collect();
+    let toc_nodes = outline::outline_tree(&read_outline(doc)?);
+
+    part_page_ranges(&file_first_pages, num_pages, max_pages)
+        .into_iter()
+        .map(|(first_page, last_page)| {
+            if last_page - first_page + 1 > max_pages {
+                warn!(
+                    "The pages {first_page}-{last_page} exceed the limit of {max_pages} pages, \
+                    but belong to the same file"
+                );
+            }
+            let mut part = extract_pages(doc, first_page, last_page)?;
+            let part_nodes = truncate_toc_nodes(&toc_nodes, first_page, last_page);
+            let items = OutlineItem::from_toc_nodes(part_nodes, &part.get_pages());
+            if let Some(outlines_id) = outline::build_outlines(&mut part, &items, encoding)? {
+                part.catalog_mut()?
+                    .set("Outlines", lopdf::Object::Reference(outlines_id));
+            }
+            Ok(part)
+        })
+        .collect()
+}
+
+/// The first and last page of each part, packing the files greedily. The files are given
+/// by their first page.
+fn part_page_ranges(file_first_pages: &[u32], num_pages: u32, max_pages: u32) -> Vec<(u32, u32)> {
+    // The pages between two cuts are never separated
+    let mut cuts: Vec<u32> = file_first_pages
+        .iter()
+        .copied()
+        .filter(|&first_page| first_page > 1 && first_page <= num_pages)
+        .collect();
+    cuts.dedup();
+    cuts.push(num_pages + 1);
+
+    let mut ranges = Vec::new();
+    let mut part_first_page = 1;
+    let mut part_end = 1;
+    for cut in cuts {
+        if cut - part_first_page > max_pages && part_end > part_first_page {
+            ranges.push((part_first_page, part_end - 1));
+            part_first_page = part_end;
+        }
+        part_end = cut;
+    }
+    if part_end > part_first_page {
+        ranges.push((part_first_page, part_end - 1));
+    }
+
+    ranges
+}
+
+/// The nodes with a destination in the page range or with descendants in it, their pages
+/// renumbered from the first page of the range.
+fn truncate_toc_nodes(nodes: &[TocNode], first_page: u32, last_page: u32) -> Vec<TocNode> {
+    nodes
+        .iter()
+        .filter_map(|node| {
+            let children = truncate_toc_nodes(&node.children, first_page, last_page);
+            let page = node
+                .page
+                .filter(|page| (first_page..=last_page).contains(page))
+                .map(|page| page - first_page + 1);
+            (page.is_some() || !children.is_empty()).then(|| TocNode {
+                title: node.title.clone(),
+                page,
+                children,
+            })
+        })
+        .collect()
+}
+
+/// The relative output path and first page of every entry where the document is split.
+fn find_split_points(entries: &[OutlineEntry], level: usize) -> Vec<(PathBuf, u32)> {
+    let mut ancestor_titles: Vec<&str> = Vec::new();
+    let mut split_points = Vec::new();
+
+    for (index, entry) in entries.iter().enumerate() {
+        ancestor_titles.truncate(entry.level - 1);
+
+        let is_leaf = entries
+            .get(index + 1)
+            .is_none_or(|next_entry| next_entry.level <= entry.level);
+
+        if entry.level == level || (entry.level < level && is_leaf) {
+            match entry.page {
+                Some(first_page) => {
+                    let mut relative_path: PathBuf = ancestor_titles
+                        .iter()
+                        .map(|title| sanitize_file_name(title))
+                        .collect();
+                    relative_path.push(format!("{}.pdf", sanitize_file_name(&entry.title)));
+                    split_points.push((relative_path, first_page));
+                }
+                None => warn!(
+                    "The destination of the bookmark '{}' could not be resolved, therefore it is not split",
+                    entry.title
+                ),
+            }
+        }
+
+        ancestor_titles.push(&entry.title);
+    }
+
+    split_points
+}
+
+/// A copy of the document containing only the pages in the range, without outline and
+/// named destinations (which would point to pages no longer present).
+fn extract_pages(doc: &Document, first_page: u32, last_page: u32) -> Result<Document> {
+    let mut part = doc.clone();
+
+    let pages_to_delete: Vec<u32> = part
+        .get_pages()
+        .into_keys()
+        .filter(|page_number| !(first_page..=last_page).contains(page_number))
+        .collect();
+    part.delete_pages(&pages_to_delete);
+
+    let catalog = part.catalog_mut()?;
+    catalog.remove(b"Outlines");
+    catalog.remove(b"Names");
+
+    part.prune_objects();
+    part.renumber_objects();
+
+    Ok(part)
+}
+
+/// Turn a bookmark title into a file name: path separators are replaced, and a `.pdf`
+/// extension is stripped (it is added back for files).
+fn sanitize_file_name(title: &str) -> String {
+    let title = title.trim();
+    let title = match title.len().checked_sub(4) {
+        Some(stem_len)
+            if title.is_char_boundary(stem_len)
+                && title[stem_len..].eq_ignore_ascii_case(".pdf") =>
+        {
+            &title[..stem_len]
+        }
+        _ => title,
+    };
+
+    let sanitized: String = title
+        .chars()
+        .map(|c| {
+            if matches!(c, '/' | '\\' | '\0') {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+
+    match sanitized.as_str() {
+        "" | "." | ".." => "_".to_string(),
+        _ => sanitized,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parts_are_cut_between_files() {
+        // Files of 3, 2, 6 and 1 pages after 1 page of contents
+        let file_first_pages = [2, 5, 7, 13];
+        assert_eq!(
+            part_page_ranges(&file_first_pages, 13, 5),
+            vec![(1, 4), (5, 6), (7, 12), (13, 13)]
+        );
+        assert_eq!(part_page_ranges(&file_first_pages, 13, 20), vec![(1, 13)]);
+    }
+
+    #[test]
+    fn truncated_toc_keeps_the_ancestors() {
+        let node = |title: &str, page, children| TocNode {
+            title: title.to_string(),
+            page,
+            children,
+        };
+        let nodes = vec![node(
+            "root",
+            Some(1),
+            vec![
+                node("a.pdf", Some(1), vec![]),
+                node("chapter", None, vec![node("b.pdf", Some(4), vec![])]),
+            ],
+        )];
+
+        assert_eq!(
+            truncate_toc_nodes(&nodes, 3, 5),
+            vec![node(
+                "root",
+                None,
+                vec![node("chapter", None, vec![node("b.pdf", Some(2), vec![])])]
+            )]
+        );
+    }
+
+    #[test]
+    fn split_points_at_levels_2_and_3() {
+        let entry = |level, title: &str, page| OutlineEntry {
+            level,
+            title: title.to_string(),
+            page: Some(page),
+        };
+        let entries = vec![
+            entry(1, "root", 1),
+            entry(2, "intro.pdf", 1),
+            entry(2, "chapter", 3),
+            entry(3, "section.pdf", 3),
+            entry(3, "exercises.pdf", 5),
+        ];
+
+        let split_points = find_split_points(&entries, 3);
+        assert_eq!(
+            split_points,
+            vec![
+                (PathBuf::from("root/intro.pdf"), 1),
+                (PathBuf::from("root/chapter/section.pdf"), 3),
+                (PathBuf::from("root/chapter/exercises.pdf"), 5),
+            ]
+        );
+
+        let split_points = find_split_points(&entries, 2);
+        assert_eq!(
+            split_points,
+            vec![
+                (PathBuf::from("root/intro.pdf"), 1),
+                (PathBuf::from("root/chapter.pdf"), 3),
+            ]
+        );
+    }
+}
@@ -0,0 +1,464 @@
+use crate::locale::Locale;
+use crate::page_tree;
+use crate::report::MergeReport;
+use crate::text::transliterate_to_ascii;
+use anyhow::{Result, anyhow};
+use lopdf::content::{Content, Operation};
+use lopdf::{Dictionary, Document, Object, ObjectId, Stream, dictionary};
+
+/// Name of the font of the stamps in the resources of the pages, unlikely to clash with
+/// the ones of the source files.
+const STAMP_FONT_NAME: &str = "PdfuniteStampFont";
+/// Names of the watermark and of its transparency in the resources of the pages.
+const WATERMARK_XOBJECT_NAME: &str = "PdfuniteWatermark";
+const WATERMARK_GSTATE_NAME: &str = "PdfuniteWatermarkGS";
+const WATERMARK_GRAY: f32 = 0.5;
+const MARGIN: f32 = 20.0;
+const SOURCE_FOOTER_FONT_SIZE: f32 = 8.0;
+/// Rough width of a Helvetica character relative to the font size, to align the stamps.
+const AVERAGE_CHAR_WIDTH: f32 = 0.5;
+
+/// Where a stamp is placed on the page.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum StampPosition {
+    #[default]
+    BottomCenter,
+    BottomLeft,
+    BottomRight,
+    TopCenter,
+    TopLeft,
+    TopRight,
+}
+
+/// The running page number stamped on every page of the output.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PageNumberStamp {
+    pub position: StampPosition,
+    /// Text of the stamp, where `{page}` is replaced by the page number and `{total}` by
+    /// the number of pages.
+    pub format: String,
+    pub font_size: f32,
+}
+
+impl Default for PageNumberStamp {
+    fn default() -> Self {
+        PageNumberStamp {
+            position: StampPosition::default(),
+            format: "{page}".to_string(),
+            font_size: 10.0,
+        }
+    }
+}
+
+/// Sequential identifiers ("Bates numbers") stamped on every page: the prefix followed by
+/// the number, padded with zeros to `width` digits (e.g. `ACME000042`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatesNumbering {
+    pub prefix: String,
+    /// Number of the first page of the output.
+    pub start: u64,
+    pub width: usize,
+    pub position: StampPosition,
+    pub font_size: f32,
+}
+
+impl BatesNumbering {
+    /// Parse `PREFIX:START:WIDTH` (e.g. `ACME:1:6`), the stamps being at the bottom right.
+    pub fn parse(spec: &str) -> Result<Self> {
+        let mut fields = spec.rsplitn(3, ':');
+        let (Some(width), Some(start), Some(prefix)) =
+            (fields.next(), fields.next(), fields.next())
+        else {
+            return Err(anyhow!("'{spec}' is not in the form PREFIX:START:WIDTH"));
+        };
+
+        Ok(BatesNumbering {
+            prefix: prefix.to_string(),
+            start: start
+                .trim()
+                .parse()
+                .map_err(|_| anyhow!("'{start}' is not a valid Bates start number"))?,
+            width: width
+                .trim()
+                .parse()
+                .map_err(|_| anyhow!("'{width}' is not a valid number of digits"))?,
+            position: StampPosition::BottomRight,
+            font_size: 10.0,
+        })
+    }
+
+    /// The identifier of the page of the output with the number (starting from 1).
+    pub fn identifier(&self, page_number: u32) -> String {
+        let number = self.start + u64::from(page_number.saturating_sub(1));
+        format!("{}{number:0width$}", self.prefix, width = self.width)
+    }
+}
+
+/// Stamp the Bates identifier on every page of the document and record the range of the
+/// identifiers of each source file in the report.
+pub fn stamp_bates_numbers(
+    doc: &mut Document,
+    bates: &BatesNumbering,
+    report: &mut MergeReport,
+) -> Result<()> {
+    let font_id = add_stamp_font(doc);
+
+    for (page_number, page_id) in doc.get_pages() {
+        stamp_text(
+            doc,
+            page_id,
+            &bates.identifier(page_number),
+            bates.position,
+            bates.font_size,
+            font_id,
+        )?;
+    }
+
+    for source in &mut report.sources {
+        source.bates_range = Some((
+            bates.identifier(source.first_page),
+            bates.identifier(source.last_page),
+        ));
+    }
+
+    Ok(())
+}
+
+/// A text drawn across every page, e.g. "CONFIDENTIAL".
+#[derive(Debug, Clone, PartialEq)]
+pub struct Watermark {
+    pub text: String,
+    /// From 0 (invisible) to 1 (opaque).
+    pub opacity: f32,
+    /// Counterclockwise angle of the text in degrees.
+    pub angle: f32,
+    pub font_size: f32,
+}
+
+impl Watermark {
+    pub fn new(text: String) -> Self {
+        Watermark {
+            text,
+            opacity: 0.2,
+            angle: 45.0,
+            font_size: 60.0,
+        }
+    }
+}
+
+/// Draw the watermark at the center of every page of the document, over its content. The
+/// text and its transparency are shared by all the pages, as a Form XObject and an ExtGState.
+pub fn stamp_watermark(doc: &mut Document, watermark: &Watermark) -> Result<()> {
+    let text = transliterate_to_ascii(&watermark.text);
+    let width = text.chars().count() as f32 * watermark.font_size * AVERAGE_CHAR_WIDTH;
+    let height = watermark.font_size;
+
+    let font_id = add_stamp_font(doc);
+    let form_operations = vec![
+        Operation::new("BT", vec![]),
+        Operation::new("g", vec![Object::Real(WATERMARK_GRAY)]),
+        Operation::new(
+            "Tf",
+            vec![STAMP_FONT_NAME.into(), Object::Real(watermark.font_size)],
+        ),
+        // The baseline leaves room for the descenders
+        Operation::new("Td", vec![0.into(), Object::Real(height * 0.2)]),
+        Operation::new("Tj", vec![Object::string_literal(text)]),
+        Operation::new("ET", vec![]),
+    ];
+    let form_id = doc.add_object(Stream::new(
+        dictionary! {
+            "Type" => "XObject",
+            "Subtype" => "Form",
+            "BBox" => vec![0.into(), 0.into(), Object::Real(width), Object::Real(height)],
+            "Resources" => dictionary! {
+                "Font" => dictionary! {
+                    STAMP_FONT_NAME => Object::Reference(font_id),
+                },
+            },
+        },
+        Content {
+            operations: form_operations,
+        }
+        .encode()?,
+    ));
+    let gstate_id = doc.add_object(dictionary! {
+        "Type" => "ExtGState",
+        "ca" => Object::Real(watermark.opacity),
+        "CA" => Object::Real(watermark.opacity),
+    });
+
+    let (sin, cos) = watermark.angle.to_radians().sin_cos();
+    for page_id in doc.get_pages().into_values() {
+        let [llx, lly, urx, ury] = page_tree::media_box(doc, page_id);
+        let (center_x, center_y) = ((llx + urx) / 2.0, (lly + ury) / 2.0);
+        // Rotate around the center of the page, then center the text on it
+        let operations = vec![
+            Operation::new("gs", vec![WATERMARK_GSTATE_NAME.into()]),
+            Operation::new(
+                "cm",
+                [cos, sin, -sin, cos, center_x, center_y]
+                    .into_iter()
+                    .map(Object::Real)
+                    .collect(),
+            ),
+            Operation::new(
+                "cm",
+                vec![
+                    1.into(),
+                    0.into(),
+                    0.into(),
+                    1.into(),
+                    Object::Real(-width / 2.0),
+                    Object::Real(-height / 2.0),
+                ],
+            ),
+            Operation::new("Do", vec![WATERMARK_XOBJECT_NAME.into()]),
+        ];
+        add_page_resource(doc, page_id, "XObject", WATERMARK_XOBJECT_NAME, form_id)?;
+        add_page_resource(doc, page_id, "ExtGState", WATERMARK_GSTATE_NAME, gstate_id)?;
+        append_page_content(doc, page_id, operations)?;
+    }
+
+    Ok(())
+}
+
+/// Stamp on every page of the source files of the report the path of the file (relative
+/// to the input directory) and the number of the page within the file, e.g.
+/// `invoices/march.pdf, p. 2/5`. The generated pages are left blank.
+pub fn stamp_source_footers(
+    doc: &mut Document,
+    report: &MergeReport,
+    position: StampPosition,
+) -> Result<()> {
+    let pages = doc.get_pages();
+    let font_id = add_stamp_font(doc);
+
+    for source in &report.sources {
+        for page_number in source.first_page..=source.last_page {
+            let Some(&page_id) = pages.get(&page_number) else {
+                continue;
+            };
+            let footer = format!(
+                "{}, p. {}/{}",
+                source.path.display(),
+                page_number - source.first_page + 1,
+                source.page_count
+            );
+            stamp_text(
+                doc,
+                page_id,
+                &footer,
+                position,
+                SOURCE_FOOTER_FONT_SIZE,
+                font_id,
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Stamp the page number on every page of the document, counting from its first page, with
+/// the numbers written according to the locale.
+pub fn stamp_page_numbers(
+    doc: &mut Document,
+    stamp: &PageNumberStamp,
+    locale: &Locale,
+) -> Result<()> {
+    let pages = doc.get_pages();
+    let total = locale.format_integer(pages.len() as i64);
+    let font_id = add_stamp_font(doc);
+
+    for (page_number, page_id) in pages {
+        let text = stamp
+            .format
+            .replace("{page}", &locale.format_integer(page_number.into()))
+            .replace("{total}", &total);
+        stamp_text(
+            doc,
+            page_id,
+            &text,
+            stamp.position,
+            stamp.font_size,
+            font_id,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Add the font used by the stamps to the document, returning its id.
+pub fn add_stamp_font(doc: &mut Document) -> ObjectId {
+    doc.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type1",
+        "BaseFont" => "Helvetica",
+        "Encoding" => "WinAnsiEncoding",
+    })
+}
+
+/// Write the text at the position of the page, over its content. The rotation of the page
+/// is not taken into account.
+pub fn stamp_text(
+    doc: &mut Document,
+    page_id: ObjectId,
+    text: &str,
+    position: StampPosition,
+    font_size: f32,
+    font_id: ObjectId,
+) -> Result<()> {
+    let text = transliterate_to_ascii(text);
+    let [llx, lly, urx, ury] = page_tree::media_box(doc, page_id);
+    let text_width = text.chars().count() as f32 * font_size * AVERAGE_CHAR_WIDTH;
+
+    let left = match position {
+        StampPosition::BottomLeft | StampPosition::TopLeft => llx + MARGIN,
+        StampPosition::BottomCenter | StampPosition::TopCenter => {
+            llx + (urx - llx - text_width) / 2.0
+        }
+        StampPosition::BottomRight | StampPosition::TopRight => urx - MARGIN - text_width,
+    };
+    let baseline = match position {
+        StampPosition::BottomLeft | StampPosition::BottomCenter | StampPosition::BottomRight => {
+            lly + MARGIN
+        }
+        StampPosition::TopLeft | StampPosition::TopCenter | StampPosition::TopRight => {
+            ury - MARGIN - font_size
+        }
+    };
+
+    let operations = vec![
+        Operation::new("BT", vec![]),
+        Operation::new("Tf", vec![STAMP_FONT_NAME.into(), Object::Real(font_size)]),
+        Operation::new("Td", vec![Object::Real(left), Object::Real(baseline)]),
+        Operation::new("Tj", vec![Object::string_literal(text)]),
+        Operation::new("ET", vec![]),
+    ];
+    add_page_resource(doc, page_id, "Font", STAMP_FONT_NAME, font_id)?;
+    append_page_content(doc, page_id, operations)
+}
+
+/// Draw the operations over the content of the page, in a clean graphics state: the
+/// original content is enclosed in `q`/`Q`.
+pub fn append_page_content(
+    doc: &mut Document,
+    page_id: ObjectId,
+    operations: Vec<Operation>,
+) -> Result<()> {
+    let contents = doc.get_dictionary(page_id)?.get(b"Contents").ok().cloned();
+    let mut content_ids: Vec<Object> = match contents {
+        Some(Object::Array(content_ids)) => content_ids,
+        Some(content_id @ Object::Reference(_)) => vec![content_id],
+        _ => Vec::new(),
+    };
+
+    let save_state_id = doc.add_object(Stream::new(dictionary! {}, b"q\n".to_vec()));
+    // The original content may not end with a white-space
+    let mut stamp_content = b"\nQ\n".to_vec();
+    stamp_content.extend(Content { operations }.encode()?);
+    let stamp_id = doc.add_object(Stream::new(dictionary! {}, stamp_content));
+
+    content_ids.insert(0, Object::Reference(save_state_id));
+    content_ids.push(Object::Reference(stamp_id));
+    doc.get_dictionary_mut(page_id)?
+        .set("Contents", content_ids);
+
+    Ok(())
+}
+
+/// Make the resource (e.g. a font, of kind `Font`) available to the content of the page
+/// under the name, giving the page its own resources (a copy of the inherited ones, if any).
+pub fn add_page_resource(
+    doc: &mut Document,
+    page_id: ObjectId,
+    kind: &str,
+    name: &str,
+    resource_id: ObjectId,
+) -> Result<()> {
+    let mut resources = resolved_dictionary(
+        doc,
+        page_tree::inherited_page_attribute(doc, page_id, b"Resources"),
+    );
+    let mut resources_of_kind =
+        resolved_dictionary(doc, resources.get(kind.as_bytes()).ok().cloned());
+    resources_of_kind.set(name, Object::Reference(resource_id));
+    resources.set(kind, resources_of_kind);
+
+    doc.get_dictionary_mut(page_id)?.set("Resources", resources);
+
+    Ok(())
+}
+
+/// A copy of the dictionary, given inline or by reference, or an empty one.
+fn resolved_dictionary(doc: &Document, object: Option<Object>) -> Dictionary {
+    match object {
+        Some(Object::Dictionary(dictionary)) => dictionary,
+        Some(Object::Reference(id)) => doc.get_dictionary(id).cloned().unwrap_or_default(),
+        _ => Dictionary::new(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support;
+
+    #[test]
+    fn page_numbers_are_stamped() -> Result<()> {
+        let mut doc = test_support::get_basic_pdf_doc("doc", 2)?;
+        let stamp = PageNumberStamp {
+            format: "{page} / {total}".to_string(),
+            ..Default::default()
+        };
+        stamp_page_numbers(&mut doc, &stamp, &Locale::default())?;
+
+        let pages = doc.get_pages();
+        let content = String::from_utf8_lossy(&doc.get_page_content(pages[&2])?).to_string();
+        assert!(content.starts_with("q\n"));
+        assert!(content.contains("(2 / 2)"));
+
+        let resources = doc
+            .get_dictionary(pages[&2])?
+            .get(b"Resources")?
+            .as_dict()?;
+        let fonts = resources.get(b"Font")?.as_dict()?;
+        assert!(fonts.has(b"F1"));
+        assert!(fonts.has(STAMP_FONT_NAME.as_bytes()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn watermark_is_shared_by_the_pages() -> Result<()> {
+        let mut doc = test_support::get_basic_pdf_doc("doc", 2)?;
+        stamp_watermark(&mut doc, &Watermark::new("CONFIDENTIAL".to_string()))?;
+
+        let xobject_ids = doc
+            .get_pages()
+            .into_values()
+            .map(|page_id| {
+                let resources = doc.get_dictionary(page_id)?.get(b"Resources")?.as_dict()?;
+                let xobjects = resources.get(b"XObject")?.as_dict()?;
+                Ok(xobjects
+                    .get(WATERMARK_XOBJECT_NAME.as_bytes())?
+                    .as_reference()?)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(xobject_ids.len(), 2);
+        assert_eq!(xobject_ids[0], xobject_ids[1]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn bates_identifiers() -> Result<()> {
+        let bates = BatesNumbering::parse("CASE:12-:41:6")?;
+        assert_eq!(bates.prefix, "CASE:12-");
+        assert_eq!(bates.identifier(1), "CASE:12-000041");
+        assert_eq!(bates.identifier(10), "CASE:12-000050");
+        assert!(BatesNumbering::parse("CASE:6").is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,315 @@
+use lopdf::{Object, StringFormat};
+use unicode_normalization::UnicodeNormalization;
+use unicode_normalization::char::is_combining_mark;
+
+const UTF16_BE_BOM: [u8; 2] = [0xFE, 0xFF];
+const PLACEHOLDER_CHAR: u8 = b'?';
+
+/// How the text strings generated by the tool (bookmark titles, ToC entries, metadata)
+/// are encoded in the output document.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum TitleEncoding {
+    /// PDFDocEncoding if all the characters are representable, UTF-16BE otherwise
+    #[default]
+    Auto,
+    /// Always UTF-16BE (with byte order mark)
+    Utf16,
+    /// Always PDFDocEncoding, non-representable characters are replaced by '?'
+    Pdfdoc,
+    /// ASCII only, accented characters are transliterated (e.g. 'é' becomes 'e'),
+    /// for old viewers which do not handle anything else
+    AsciiTranslit,
+}
+
+/// Encode the text as a PDF text string (see the PDF reference, section 7.9.2.2).
+pub fn encode_text_string(text: &str, encoding: TitleEncoding) -> Object {
+    match encoding {
+        TitleEncoding::Auto => match to_pdf_doc_encoding(text) {
+            Some(bytes) => Object::String(bytes, StringFormat::Literal),
+            None => to_utf16_be(text),
+        },
+        TitleEncoding::Utf16 => to_utf16_be(text),
+        TitleEncoding::Pdfdoc => Object::String(
+            text.chars()
+                .map(|c| pdf_doc_encoding_byte(c).unwrap_or(PLACEHOLDER_CHAR))
+                .collect(),
+            StringFormat::Literal,
+        ),
+        TitleEncoding::AsciiTranslit => Object::String(
+            transliterate_to_ascii(text).into_bytes(),
+            StringFormat::Literal,
+        ),
+    }
+}
+
+fn to_utf16_be(text: &str) -> Object {
+    let bytes = UTF16_BE_BOM
+        .into_iter()
+        .chain(text.encode_utf16().flat_map(|unit| unit.to_be_bytes()))
+        .collect();
+    Object::String(bytes, StringFormat::Hexadecimal)
+}
+
+/// Decode a PDF text string, encoded either in UTF-16BE (with byte order mark) or in PDFDocEncoding.
+pub fn decode_text_string(bytes: &[u8]) -> String {
+    match bytes.strip_prefix(&UTF16_BE_BOM) {
+        Some(utf16_bytes) => {
+            let units: Vec<u16> = utf16_bytes
+                .chunks_exact(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        None => bytes
+            .iter()
+            .map(|&byte| pdf_doc_encoding_char(byte))
+            .collect(),
+    }
+}
+
+fn to_pdf_doc_encoding(text: &str) -> Option<Vec<u8>> {
+    text.chars().map(pdf_doc_encoding_byte).collect()
+}
+
+/// The characters of PDFDocEncoding outside of ASCII and Latin-1 (see the PDF reference, annex D.2).
+const PDF_DOC_ENCODING_SPECIALS: [(char, u8); 32] = [
+    ('\u{2022}', 0x80),
+    ('\u{2020}', 0x81),
+    ('\u{2021}', 0x82),
+    ('\u{2026}', 0x83),
+    ('\u{2014}', 0x84),
+    ('\u{2013}', 0x85),
+    ('\u{0192}', 0x86),
+    ('\u{2044}', 0x87),
+    ('\u{2039}', 0x88),
+    ('\u{203A}', 0x89),
+    ('\u{2212}', 0x8A),
+    ('\u{2030}', 0x8B),
+    ('\u{201E}', 0x8C),
+    ('\u{201C}', 0x8D),
+    ('\u{201D}', 0x8E),
+    ('\u{2018}', 0x8F),
+    ('\u{2019}', 0x90),
+    ('\u{201A}', 0x91),
+    ('\u{2122}', 0x92),
+    ('\u{FB01}', 0x93),
+    ('\u{FB02}', 0x94),
+    ('\u{0141}', 0x95),
+    ('\u{0152}', 0x96),
+    ('\u{0160}', 0x97),
+    ('\u{0178}', 0x98),
+    ('\u{017D}', 0x99),
+    ('\u{0131}', 0x9A),
+    ('\u{0142}', 0x9B),
+    ('\u{0153}', 0x9C),
+    ('\u{0161}', 0x9D),
+    ('\u{017E}', 0x9E),
+    ('\u{20AC}', 0xA0),
+];
+
+/// The byte of the character in PDFDocEncoding, if any.
+fn pdf_doc_encoding_byte(c: char) -> Option<u8> {
+    match c {
+        '\t' | '\n' | '\r' | ' '..='~' => Some(c as u8),
+        // Latin-1, but for the soft hyphen which is undefined in PDFDocEncoding
+        '\u{00A1}'..='\u{00AC}' | '\u{00AE}'..='\u{00FF}' => Some(c as u8),
+        _ => PDF_DOC_ENCODING_SPECIALS
+            .iter()
+            .find(|(special_char, _byte)| *special_char == c)
+            .map(|(_special_char, byte)| *byte),
+    }
+}
+
+/// The character of the byte in PDFDocEncoding, or the replacement character if undefined.
+fn pdf_doc_encoding_char(byte: u8) -> char {
+    match byte {
+        0x80..=0xA0 => PDF_DOC_ENCODING_SPECIALS
+            .iter()
+            .find(|(_special_char, special_byte)| *special_byte == byte)
+            .map_or(char::REPLACEMENT_CHARACTER, |(special_char, _byte)| {
+                *special_char
+            }),
+        0xAD => char::REPLACEMENT_CHARACTER,
+        _ => byte as char,
+    }
+}
+
+/// Replace every non ASCII character with its closest ASCII representation, or '?'.
+pub fn transliterate_to_ascii(text: &str) -> String {
+    text.nfkd()
+        .filter(|&c| !is_combining_mark(c))
+        .map(|c| match c {
+            c if c.is_ascii() => c.to_string(),
+            'ß' => "ss".to_string(),
+            'æ' => "ae".to_string(),
+            'Æ' => "AE".to_string(),
+            'œ' => "oe".to_string(),
+            'Œ' => "OE".to_string(),
+            'ø' => "o".to_string(),
+            'Ø' => "O".to_string(),
+            'ł' => "l".to_string(),
+            'Ł' => "L".to_string(),
+            'đ' => "d".to_string(),
+            'Đ' => "D".to_string(),
+            'þ' => "th".to_string(),
+            'Þ' => "TH".to_string(),
+            '‘' | '’' => "'".to_string(),
+            '“' | '”' | '„' => "\"".to_string(),
+            '–' | '—' => "-".to_string(),
+            '…' => "...".to_string(),
+            _ => (PLACEHOLDER_CHAR as char).to_string(),
+        })
+        .collect()
+}
+
+/// Turn the file name (without extension) into a name usable in URLs as named destination:
+/// lowercase ASCII letters and digits, with any other run of characters collapsed into a '-'.
+pub fn normalize_dest_name(file_name: &str) -> String {
+    let stem = file_name
+        .rsplit_once('.')
+        .map_or(file_name, |(stem, _extension)| stem);
+
+    transliterate_to_ascii(stem)
+        .to_ascii_lowercase()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Changes making the bookmark titles taken from file and directory names readable.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PrettifyTitles {
+    /// Drop the `.pdf` extension of the file names.
+    pub strip_extension: bool,
+    /// Drop the numeric prefixes used to sort the entries, e.g. `01_` or `3 - `.
+    pub strip_sort_prefix: bool,
+    /// Replace the runs of underscores and dashes with a space.
+    pub spaces_for_separators: bool,
+    /// Capitalise the first letter of every word, leaving the others as they are.
+    pub title_case: bool,
+}
+
+impl PrettifyTitles {
+    pub fn all() -> Self {
+        PrettifyTitles {
+            strip_extension: true,
+            strip_sort_prefix: true,
+            spaces_for_separators: true,
+            title_case: true,
+        }
+    }
+
+    /// The title for the file or directory name. A change which would leave the title
+    /// empty is not applied.
+    pub fn apply(&self, name: &str) -> String {
+        let mut title = name.to_string();
+
+        if self.strip_extension
+            && let Some((stem, extension)) = title.rsplit_once('.')
+            && extension.eq_ignore_ascii_case("pdf")
+            && !stem.is_empty()
+        {
+            title = stem.to_string();
+        }
+
+        if self.strip_sort_prefix {
+            let without_digits = title.trim_start_matches(|c: char| c.is_ascii_digit());
+            let without_prefix =
+                without_digits.trim_start_matches(|c: char| c.is_whitespace() || "._-".contains(c));
+            if without_digits.len() < title.len()
+                && without_prefix.len() < without_digits.len()
+                && !without_prefix.is_empty()
+            {
+                title = without_prefix.to_string();
+            }
+        }
+
+        if self.spaces_for_separators {
+            let spaced = title
+                .split(['_', '-'])
+                .filter(|word| !word.is_empty())
+                .collect::<Vec<_>>()
+                .join(" ");
+            if !spaced.is_empty() {
+                title = spaced;
+            }
+        }
+
+        if self.title_case {
+            let mut at_word_start = true;
+            title = title
+                .chars()
+                .flat_map(|c| {
+                    let capitalise = at_word_start;
+                    at_word_start = c.is_whitespace();
+                    let mut converted = vec![c];
+                    if capitalise {
+                        converted = c.to_uppercase().collect();
+                    }
+                    converted
+                })
+                .collect();
+        }
+
+        title
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn encodings_of_accented_and_cjk_titles() -> Result<()> {
+        let accented = encode_text_string("Übungen", TitleEncoding::Auto);
+        assert_eq!(accented.as_str()?, b"\xDCbungen");
+
+        let cjk = encode_text_string("講義", TitleEncoding::Auto);
+        assert_eq!(cjk.as_str()?, [0xFE, 0xFF, 0x8B, 0x1B, 0x7F, 0xA9]);
+
+        let cjk_in_pdf_doc = encode_text_string("講義", TitleEncoding::Pdfdoc);
+        assert_eq!(cjk_in_pdf_doc.as_str()?, b"??");
+
+        let transliterated = encode_text_string("Straße Łódź", TitleEncoding::AsciiTranslit);
+        assert_eq!(transliterated.as_str()?, b"Strasse Lodz");
+
+        Ok(())
+    }
+
+    #[test]
+    fn decode_what_was_encoded() {
+        for title in ["Übungen – Blatt 3", "講義ノート", "plain"] {
+            let encoded = encode_text_string(title, TitleEncoding::Auto);
+            let Object::String(bytes, _format) = encoded else {
+                panic!("Text strings are encoded as strings");
+            };
+            assert_eq!(decode_text_string(&bytes), title);
+        }
+    }
+
+    #[test]
+    fn normalized_dest_names() {
+        assert_eq!(
+            normalize_dest_name("Invoice 2023_07.pdf"),
+            "invoice-2023-07"
+        );
+        assert_eq!(normalize_dest_name("  Übung (3).PDF"), "ubung-3");
+    }
+
+    #[test]
+    fn prettified_titles() {
+        let all = PrettifyTitles::all();
+        assert_eq!(all.apply("01_lecture_notes-SDEs.pdf"), "Lecture Notes SDEs");
+        assert_eq!(all.apply("3 - übungen.PDF"), "Übungen");
+        assert_eq!(all.apply("2024.pdf"), "2024");
+        assert_eq!(all.apply("scan.djvu"), "Scan.djvu");
+
+        let only_extension = PrettifyTitles {
+            strip_extension: true,
+            ..Default::default()
+        };
+        assert_eq!(only_extension.apply("01_notes.pdf"), "01_notes");
+    }
+}
@@ -0,0 +1,146 @@
+use crate::outline::TocNode;
+use anyhow::{Result, anyhow};
+use std::fmt::Write;
+use std::path::Path;
+
+const MARKDOWN_INDENT: &str = "  ";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum TocFormat {
+    /// Nested Markdown list
+    Md,
+    Json,
+    Yaml,
+    /// Outline Processor Markup Language, read by outliners and e-readers
+    Opml,
+}
+
+impl TocFormat {
+    /// The format given by the extension of the file name (`md`, `json`, `yaml` or `opml`).
+    pub fn from_path(path: impl AsRef<Path>) -> Result<TocFormat> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase());
+
+        match extension.as_deref() {
+            Some("md" | "markdown") => Ok(TocFormat::Md),
+            Some("json") => Ok(TocFormat::Json),
+            Some("yaml" | "yml") => Ok(TocFormat::Yaml),
+            Some("opml") => Ok(TocFormat::Opml),
+            _ => Err(anyhow!(
+                "Cannot tell the format of the ToC file '{}' from its extension \
+                (use .md, .json, .yaml or .opml)",
+                path.display()
+            )),
+        }
+    }
+}
+
+/// Serialise the outline tree in the given format.
+pub fn export_toc(nodes: &[TocNode], format: TocFormat) -> Result<String> {
+    match format {
+        TocFormat::Md => {
+            let mut markdown = String::new();
+            write_markdown_list(nodes, 0, &mut markdown)?;
+            Ok(markdown)
+        }
+        TocFormat::Json => Ok(serde_json::to_string_pretty(nodes)?),
+        TocFormat::Yaml => Ok(serde_yaml::to_string(nodes)?),
+        TocFormat::Opml => {
+            let mut opml = String::from(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<opml version=\"2.0\">\n<head></head>\n<body>\n",
+            );
+            write_opml_outlines(nodes, 1, &mut opml)?;
+            opml.push_str("</body>\n</opml>\n");
+            Ok(opml)
+        }
+    }
+}
+
+fn write_opml_outlines(nodes: &[TocNode], depth: usize, opml: &mut String) -> Result<()> {
+    let indent = MARKDOWN_INDENT.repeat(depth);
+
+    for node in nodes {
+        write!(
+            opml,
+            "{indent}<outline text=\"{}\"",
+            escape_xml(&node.title)
+        )?;
+        if let Some(page) = node.page {
+            write!(opml, " page=\"{page}\"")?;
+        }
+        if node.children.is_empty() {
+            writeln!(opml, "/>")?;
+        } else {
+            writeln!(opml, ">")?;
+            write_opml_outlines(&node.children, depth + 1, opml)?;
+            writeln!(opml, "{indent}</outline>")?;
+        }
+    }
+
+    Ok(())
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn write_markdown_list(nodes: &[TocNode], depth: usize, markdown: &mut String) -> Result<()> {
+    let indent = MARKDOWN_INDENT.repeat(depth);
+
+    for node in nodes {
+        match node.page {
+            Some(page) => writeln!(markdown, "{indent}- {} (p. {page})", node.title)?,
+            None => writeln!(markdown, "{indent}- {}", node.title)?,
+        }
+        write_markdown_list(&node.children, depth + 1, markdown)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::outline::{OutlineEntry, outline_tree};
+
+    #[test]
+    fn markdown_of_nested_outline() -> Result<()> {
+        let entries = vec![
+            OutlineEntry {
+                level: 1,
+                title: "root".to_string(),
+                page: Some(1),
+            },
+            OutlineEntry {
+                level: 2,
+                title: "intro.pdf".to_string(),
+                page: Some(1),
+            },
+            OutlineEntry {
+                level: 1,
+                title: "appendix".to_string(),
+                page: None,
+            },
+        ];
+
+        let markdown = export_toc(&outline_tree(&entries), TocFormat::Md)?;
+        assert_eq!(
+            markdown,
+            "- root (p. 1)\n  - intro.pdf (p. 1)\n- appendix\n"
+        );
+
+        let opml = export_toc(&outline_tree(&entries), TocFormat::Opml)?;
+        assert!(opml.contains(
+            "  <outline text=\"root\" page=\"1\">\n    <outline text=\"intro.pdf\" page=\"1\"/>\n  </outline>\n"
+        ));
+        assert_eq!(TocFormat::from_path("toc.OPML")?, TocFormat::Opml);
+        assert!(TocFormat::from_path("toc.txt").is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,233 @@
+use crate::outline::{DestView, OutlineItem};
+use crate::page_tree;
+use crate::text::transliterate_to_ascii;
+use anyhow::Result;
+use lopdf::content::{Content, Operation};
+use lopdf::{Document, Object, ObjectId, Stream, dictionary};
+use std::collections::HashMap;
+
+const PAGE_WIDTH: f32 = 595.0;
+const PAGE_HEIGHT: f32 = 842.0;
+const MARGIN: f32 = 56.0;
+const HEADING: &str = "Contents";
+const HEADING_FONT_SIZE: f32 = 18.0;
+/// Space taken by the heading on the first page.
+const HEADING_HEIGHT: f32 = 40.0;
+const FONT_SIZE: f32 = 11.0;
+const LINE_HEIGHT: f32 = 16.0;
+const INDENT_PER_LEVEL: f32 = 14.0;
+/// Rough width of a Helvetica character relative to the font size, to lay out the leaders.
+const AVERAGE_CHAR_WIDTH: f32 = 0.5;
+const FONT_NAME: &str = "F1";
+
+/// A line of the table of contents.
+struct TocLine {
+    title: String,
+    level: usize,
+    page_id: ObjectId,
+}
+
+/// Insert at the beginning of the document pages with a printed table of contents of the
+/// outline items: one line per item, indented by level, with dotted leaders to the number
+/// of its page and a link to it. Only the directories are expanded, not the bookmarks
+/// within a file. Returns the number of pages added.
+pub fn prepend_toc_pages(doc: &mut Document, items: &[OutlineItem]) -> Result<u32> {
+    let mut lines = Vec::new();
+    collect_toc_lines(items, 0, &mut lines);
+    if lines.is_empty() {
+        return Ok(0);
+    }
+
+    let first_page_capacity =
+        ((PAGE_HEIGHT - 2.0 * MARGIN - HEADING_HEIGHT) / LINE_HEIGHT) as usize;
+    let page_capacity = ((PAGE_HEIGHT - 2.0 * MARGIN) / LINE_HEIGHT) as usize;
+    let mut pages_lines = vec![&lines[..first_page_capacity.min(lines.len())]];
+    if lines.len() > first_page_capacity {
+        pages_lines.extend(lines[first_page_capacity..].chunks(page_capacity));
+    }
+    let num_toc_pages = pages_lines.len() as u32;
+
+    // The pages of the document come after the ones of the ToC
+    let page_numbers: HashMap<ObjectId, u32> = doc
+        .get_pages()
+        .into_iter()
+        .map(|(page_number, page_id)| (page_id, page_number + num_toc_pages))
+        .collect();
+
+    let root_pages_id = page_tree::root_pages_id(doc)?;
+    let font_id = doc.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type1",
+        "BaseFont" => "Helvetica",
+        "Encoding" => "WinAnsiEncoding",
+    });
+
+    let toc_page_ids: Vec<ObjectId> = pages_lines
+        .iter()
+        .enumerate()
+        .map(|(index, page_lines)| {
+            add_toc_page(
+                doc,
+                page_lines,
+                index == 0,
+                &page_numbers,
+                font_id,
+                root_pages_id,
+            )
+        })
+        .collect::<Result<_>>()?;
+
+    page_tree::prepend_pages(doc, &toc_page_ids)?;
+
+    Ok(num_toc_pages)
+}
+
+fn collect_toc_lines(items: &[OutlineItem], level: usize, lines: &mut Vec<TocLine>) {
+    for item in items {
+        let Some(page_id) = item.first_page() else {
+            continue;
+        };
+        lines.push(TocLine {
+            title: transliterate_to_ascii(&item.title),
+            level,
+            page_id,
+        });
+        // The items with a page of their own are files, whose bookmarks are left out
+        if item.dest_page.is_none() {
+            collect_toc_lines(&item.children, level + 1, lines);
+        }
+    }
+}
+
+fn add_toc_page(
+    doc: &mut Document,
+    lines: &[TocLine],
+    with_heading: bool,
+    page_numbers: &HashMap<ObjectId, u32>,
+    font_id: ObjectId,
+    root_pages_id: ObjectId,
+) -> Result<ObjectId> {
+    let char_width = FONT_SIZE * AVERAGE_CHAR_WIDTH;
+    let right = PAGE_WIDTH - MARGIN;
+    let mut operations = Vec::new();
+    let mut annotation_ids = Vec::with_capacity(lines.len());
+
+    let mut baseline = PAGE_HEIGHT - MARGIN - FONT_SIZE;
+    if with_heading {
+        operations.extend(text_operations(
+            MARGIN,
+            PAGE_HEIGHT - MARGIN - HEADING_FONT_SIZE,
+            HEADING_FONT_SIZE,
+            HEADING.to_string(),
+        ));
+        baseline -= HEADING_HEIGHT;
+    }
+
+    for line in lines {
+        let left = MARGIN + line.level as f32 * INDENT_PER_LEVEL;
+        let page_number = page_numbers
+            .get(&line.page_id)
+            .map(u32::to_string)
+            .unwrap_or_default();
+        let number_width = page_number.len() as f32 * char_width;
+
+        // Title truncated to leave room for at least a few leader dots
+        let max_title_chars = ((right - left - number_width) / char_width) as usize;
+        let title: String = line
+            .title
+            .chars()
+            .take(max_title_chars.saturating_sub(4))
+            .collect();
+        let title_width = title.chars().count() as f32 * char_width;
+        let num_dots = ((right - left - title_width - number_width) / char_width) as usize;
+        let text = format!(
+            "{title} {} {page_number}",
+            ".".repeat(num_dots.saturating_sub(2))
+        );
+        operations.extend(text_operations(left, baseline, FONT_SIZE, text));
+
+        let media_box = page_tree::media_box(doc, line.page_id);
+        annotation_ids.push(doc.add_object(dictionary! {
+            "Type" => "Annot",
+            "Subtype" => "Link",
+            "Rect" => vec![
+                Object::Real(left),
+                Object::Real(baseline - (LINE_HEIGHT - FONT_SIZE) / 2.0),
+                Object::Real(right),
+                Object::Real(baseline + FONT_SIZE),
+            ],
+            "Border" => vec![0.into(), 0.into(), 0.into()],
+            "Dest" => DestView::Fit.dest(line.page_id, media_box, None),
+        }));
+
+        baseline -= LINE_HEIGHT;
+    }
+
+    let content_id = doc.add_object(Stream::new(
+        dictionary! {},
+        Content { operations }.encode()?,
+    ));
+
+    Ok(doc.add_object(dictionary! {
+        "Type" => "Page",
+        "Parent" => Object::Reference(root_pages_id),
+        "MediaBox" => vec![0.into(), 0.into(), Object::Real(PAGE_WIDTH), Object::Real(PAGE_HEIGHT)],
+        "Contents" => Object::Reference(content_id),
+        "Resources" => dictionary! {
+            "Font" => dictionary! {
+                FONT_NAME => Object::Reference(font_id),
+            },
+        },
+        "Annots" => annotation_ids.into_iter().map(Object::Reference).collect::<Vec<_>>(),
+    }))
+}
+
+fn text_operations(left: f32, baseline: f32, font_size: f32, text: String) -> [Operation; 5] {
+    [
+        Operation::new("BT", vec![]),
+        Operation::new("Tf", vec![FONT_NAME.into(), Object::Real(font_size)]),
+        Operation::new("Td", vec![Object::Real(left), Object::Real(baseline)]),
+        Operation::new("Tj", vec![Object::string_literal(text)]),
+        Operation::new("ET", vec![]),
+    ]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support;
+
+    #[test]
+    fn toc_pages_link_to_the_files() -> Result<()> {
+        let mut doc = test_support::get_basic_pdf_doc("doc", 3)?;
+        let page_ids: Vec<ObjectId> = doc.get_pages().into_values().collect();
+
+        let mut directory_item = OutlineItem::new("chapter".to_string(), None);
+        let mut file_item = OutlineItem::new("part.pdf".to_string(), Some(page_ids[2]));
+        file_item
+            .children
+            .push(OutlineItem::new("Section".to_string(), Some(page_ids[2])));
+        directory_item.children.push(file_item);
+        let items = vec![
+            OutlineItem::new("intro.pdf".to_string(), Some(page_ids[0])),
+            directory_item,
+        ];
+
+        assert_eq!(prepend_toc_pages(&mut doc, &items)?, 1);
+
+        let pages = doc.get_pages();
+        assert_eq!(pages.len(), 4);
+        let toc_page = doc.get_dictionary(pages[&1])?;
+        let annots = toc_page.get(b"Annots")?.as_array()?;
+        assert_eq!(annots.len(), 3);
+        let last_link = doc.get_dictionary(annots[2].as_reference()?)?;
+        let dest = last_link.get(b"Dest")?.as_array()?;
+        assert_eq!(dest[0].as_reference()?, page_ids[2]);
+
+        let content = String::from_utf8_lossy(&doc.get_page_content(pages[&1])?).to_string();
+        assert!(content.contains("part.pdf ...."));
+        assert!(content.contains(" 4)"));
+
+        Ok(())
+    }
+}
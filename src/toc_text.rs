@@ -0,0 +1,159 @@
+use lazy_static::lazy_static;
+use lopdf::Document;
+use regex::Regex;
+use std::collections::HashMap;
+
+/// Pages at the beginning of a document searched for a printed table of contents.
+const MAX_TOC_SEARCH_PAGES: u32 = 12;
+/// Lines of the form "title ..... page" a page needs to be taken for a ToC page.
+const MIN_TOC_LINES_PER_PAGE: usize = 3;
+/// Largest difference tried between the printed and the physical page numbers
+/// (front matter not counted by the printed numbering).
+const MAX_PAGE_OFFSET: u32 = 40;
+/// Entries whose title is looked up in the text of the pages to find the page offset.
+const OFFSET_PROBE_ENTRIES: usize = 8;
+
+lazy_static! {
+    /// A title, then dot leaders or spaces, then the printed page number at the end of the line.
+    static ref TOC_LINE: Regex =
+        Regex::new(r"^\s*(?P<title>\S.*?\S)(?:\s*[.·…_ ]{2,}\s*|\s+)(?P<page>\d{1,4})\s*$").unwrap();
+}
+
+/// An entry of a printed table of contents.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TocTextEntry {
+    pub title: String,
+    /// Number (starting from 1) of the page of the document the entry refers to.
+    pub page_number: u32,
+}
+
+/// Detect a printed table of contents among the first pages of a document without
+/// outline and turn its lines into entries pointing to the physical pages. This is a
+/// heuristic for scanned or exported books: it finds nothing when the text of the pages
+/// cannot be extracted (e.g. images without OCR layer).
+pub fn detect_toc_entries(doc: &Document) -> Vec<TocTextEntry> {
+    let pages = doc.get_pages();
+    let page_count = pages.len() as u32;
+    let page_text = |page_number: u32| doc.extract_text(&[page_number]).unwrap_or_default();
+
+    let mut toc_lines = Vec::new();
+    let mut last_toc_page = None;
+    for page_number in 1..=page_count.min(MAX_TOC_SEARCH_PAGES) {
+        let lines = parse_toc_lines(&page_text(page_number));
+        if lines.len() >= MIN_TOC_LINES_PER_PAGE {
+            toc_lines.extend(lines);
+            last_toc_page = Some(page_number);
+        } else if last_toc_page.is_some() {
+            break;
+        }
+    }
+    let Some(last_toc_page) = last_toc_page else {
+        return Vec::new();
+    };
+
+    let mut body_texts = HashMap::new();
+    let offset = best_page_offset(&toc_lines, |page_number| {
+        (page_number > last_toc_page && page_number <= page_count).then(|| {
+            body_texts
+                .entry(page_number)
+                .or_insert_with(|| page_text(page_number))
+                .clone()
+        })
+    });
+
+    let mut previous_page_number = 0;
+    toc_lines
+        .into_iter()
+        .filter_map(|(title, printed_page)| {
+            let page_number = printed_page + offset;
+            // The printed ToC lists the sections in page order: skip what does not fit
+            (page_number > last_toc_page
+                && page_number <= page_count
+                && page_number >= previous_page_number)
+                .then(|| {
+                    previous_page_number = page_number;
+                    TocTextEntry { title, page_number }
+                })
+        })
+        .collect()
+}
+
+/// The lines of the text looking like entries of a table of contents, as (title, printed page).
+fn parse_toc_lines(text: &str) -> Vec<(String, u32)> {
+    text.lines()
+        .filter_map(|line| {
+            let captures = TOC_LINE.captures(line)?;
+            let title = captures["title"]
+                .trim_end_matches(['.', '·', '…', '_', ' '])
+                .to_string();
+            let printed_page = captures["page"].parse().ok()?;
+            title
+                .chars()
+                .any(char::is_alphabetic)
+                .then_some((title, printed_page))
+        })
+        .collect()
+}
+
+/// The offset to add to the printed page numbers to get the physical ones, chosen as the
+/// one for which most probed titles appear on their page (0 if no title is found).
+fn best_page_offset(
+    toc_lines: &[(String, u32)],
+    mut page_text: impl FnMut(u32) -> Option<String>,
+) -> u32 {
+    let normalise = |text: &str| {
+        text.split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .to_lowercase()
+    };
+
+    let mut best = (0, 0);
+    for offset in 0..=MAX_PAGE_OFFSET {
+        let found_titles = toc_lines
+            .iter()
+            .take(OFFSET_PROBE_ENTRIES)
+            .filter(|(title, printed_page)| {
+                page_text(printed_page + offset)
+                    .is_some_and(|text| normalise(&text).contains(&normalise(title)))
+            })
+            .count();
+        if found_titles > best.1 {
+            best = (offset, found_titles);
+        }
+    }
+
+    best.0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn toc_lines_and_page_offset() {
+        let toc_page = "Contents\n\
+            1 Introduction ........ 1\n\
+            2 Getting started · · · 7\n\
+            Appendix A   23\n\
+            Figure 3 shows the 2 cases\n";
+
+        let lines = parse_toc_lines(toc_page);
+        assert_eq!(
+            lines,
+            vec![
+                ("1 Introduction".to_string(), 1),
+                ("2 Getting started".to_string(), 7),
+                ("Appendix A".to_string(), 23),
+            ]
+        );
+
+        // The numbering starts on the fifth physical page
+        let offset = best_page_offset(&lines, |page_number| match page_number {
+            5 => Some("1  Introduction\nThis book...".to_string()),
+            11 => Some("2 Getting\nstarted".to_string()),
+            _ => Some(String::new()),
+        });
+        assert_eq!(offset, 4);
+    }
+}
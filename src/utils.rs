@@ -1,139 +1,216 @@
 use anyhow::{Result, anyhow};
-use lopdf::{Document, Object, ObjectId, dictionary};
-use std::path::Path;
-use std::process::Command;
-
-/// Uses `qpdf --check`, `pdfinfo` and `pdftotext -layout` to validate the PDF file.
-pub fn validate_pdf(pdf_file_path: impl AsRef<Path>) -> Result<()> {
-    let pdf_file_path = pdf_file_path.as_ref();
-
-    // The processes are spawned in order of speed, from the slowest to the fastest.
-    let qpdf_out = Command::new("qpdf")
-        .arg("--check")
-        .arg(pdf_file_path)
-        .output()?;
-
-    let pdftotext_out = Command::new("pdftotext")
-        .arg("-layout")
-        .arg(pdf_file_path)
-        .output()?;
-
-    let pdfinfo_out = Command::new("pdfinfo").arg(pdf_file_path).output()?;
-
-    if !pdfinfo_out.status.success() {
-        return Err(anyhow!(
-            "`pdfinfo {}` returned with exit code {:?}: stdout [[{}]], stderr: [[{}]]",
-            pdf_file_path.display(),
-            pdfinfo_out.status.code(),
-            str::from_utf8(&pdfinfo_out.stdout)?,
-            str::from_utf8(&pdfinfo_out.stderr)?
-        ));
+use lopdf::Document;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+/// Turn a path written by a user in a list or manifest into a native path, accepting both
+/// `/` and `\` as separators regardless of the platform.
+pub fn path_from_portable(portable_path: &str) -> PathBuf {
+    let mut path = PathBuf::new();
+    let mut rest = portable_path;
+
+    // Keep the root of absolute paths (`/...`, `C:\...`), while `C:...` stays drive-relative
+    let drive_len = match rest.as_bytes() {
+        [letter, b':', ..] if cfg!(windows) && letter.is_ascii_alphabetic() => 2,
+        _ => 0,
+    };
+    if drive_len > 0 {
+        path.push(&rest[..drive_len]);
+        rest = &rest[drive_len..];
     }
-
-    if !pdfinfo_out.status.success() {
-        return Err(anyhow!(
-            "`pdftotext {}` returned with exit code {:?}: stdout [[{}]], stderr: [[{}]]",
-            pdf_file_path.display(),
-            pdftotext_out.status.code(),
-            str::from_utf8(&pdftotext_out.stdout)?,
-            str::from_utf8(&pdftotext_out.stderr)?
-        ));
+    if rest.starts_with(['/', '\\']) {
+        path.push(std::path::MAIN_SEPARATOR_STR);
     }
 
-    if !qpdf_out.status.success() {
-        return Err(anyhow!(
-            "`qpdf {}` returned with exit code {:?}: stdout [[{}]], stderr: [[{}]]",
-            pdf_file_path.display(),
-            qpdf_out.status.code(),
-            str::from_utf8(&qpdf_out.stdout)?,
-            str::from_utf8(&qpdf_out.stderr)?
-        ));
+    path.extend(
+        rest.split(['/', '\\'])
+            .filter(|component| !component.is_empty()),
+    );
+    path
+}
+
+/// The path as shown in messages: native separators and, on Windows, without the `\\?\`
+/// prefix added by `canonicalize`.
+pub fn display_path(path: impl AsRef<Path>) -> String {
+    let displayed = path.as_ref().display().to_string();
+
+    if cfg!(windows)
+        && let Some(unc_path) = displayed.strip_prefix(r"\\?\UNC\")
+    {
+        return format!(r"\\{unc_path}");
+    }
+    if cfg!(windows)
+        && let Some(plain_path) = displayed.strip_prefix(r"\\?\")
+    {
+        return plain_path.to_string();
     }
 
-    Ok(())
+    displayed
 }
 
-/// Generates an a tree of directories of `num_levels` where the last level is pdf files.
-/// The first generation has `num_siblings_this_level` children, and then each generation
-/// applies recursively the function `siblings_fn` on the `num_siblings_this_level` input
-/// to determine the future number of children. The parameter `constant_num_lateral_leaves`
-/// tells how many pdf_files have to be created on each floor (but the last one), in
-/// addition to the siblings.
-///
-/// Use with caution: if for example recursive_fn(n):=n, and we have no 'lateral leaves'
-/// then we have an n-tree. An n-tree with L levels has sum(k=0, k=L) {n^k} nodes!
-/// Furthermore if each pdf has p pages, this means p*(n^L) pdf pages in total!
-pub fn generate_fn_tree_with_levels(
-    root_pdfs: impl AsRef<Path>,
-    num_levels: u8,
-    num_siblings_this_level: u8,
-    constant_num_lateral_leaves: u8,
-    pages_per_pdf: u8,
-    siblings_fn: &impl Fn(u8) -> u8,
-) -> Result<()> {
-    let root_pdfs = root_pdfs.as_ref();
+/// The longest path of which all the paths are descendants (or which they are).
+pub fn common_ancestor(paths: &[impl AsRef<Path>]) -> PathBuf {
+    let Some((first_path, other_paths)) = paths.split_first() else {
+        return PathBuf::new();
+    };
 
-    if std::fs::exists(root_pdfs)? {
-        return Err(anyhow!(
-            "The path '{}' exists already!",
-            root_pdfs.display()
-        ));
+    let mut ancestor = first_path.as_ref().to_path_buf();
+    for path in other_paths {
+        while !path.as_ref().starts_with(&ancestor) {
+            if !ancestor.pop() {
+                return PathBuf::new();
+            }
+        }
     }
+    ancestor
+}
 
-    if num_levels == 0 {
-        return Ok(());
+/// Compare two names so that the runs of digits compare by their numeric value
+/// (`page2` before `page10`), and the rest by character.
+pub fn natural_cmp(name: &str, other_name: &str) -> std::cmp::Ordering {
+    let (mut chars, mut other_chars) = (name.chars().peekable(), other_name.chars().peekable());
+
+    loop {
+        match (chars.peek(), other_chars.peek()) {
+            (None, None) => return std::cmp::Ordering::Equal,
+            (None, Some(_)) => return std::cmp::Ordering::Less,
+            (Some(_), None) => return std::cmp::Ordering::Greater,
+            (Some(c), Some(other_c)) if c.is_ascii_digit() && other_c.is_ascii_digit() => {
+                let take_number = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+                    let mut digits = String::new();
+                    while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                        digits.push(digit);
+                    }
+                    digits
+                };
+                let (number, other_number) =
+                    (take_number(&mut chars), take_number(&mut other_chars));
+                let (trimmed, other_trimmed) = (
+                    number.trim_start_matches('0'),
+                    other_number.trim_start_matches('0'),
+                );
+                let ordering = trimmed
+                    .len()
+                    .cmp(&other_trimmed.len())
+                    .then_with(|| trimmed.cmp(other_trimmed))
+                    .then_with(|| number.len().cmp(&other_number.len()));
+                if ordering.is_ne() {
+                    return ordering;
+                }
+            }
+            (Some(c), Some(other_c)) => {
+                let ordering = c.cmp(other_c);
+                if ordering.is_ne() {
+                    return ordering;
+                }
+                chars.next();
+                other_chars.next();
+            }
+        }
     }
+}
 
-    if num_siblings_this_level == 0 {
-        return Err(anyhow!(
-            "The siblings to generate are {} and the levels still to create are {}",
-            num_siblings_this_level,
-            num_levels
-        ));
+/// Whether the file name matches the pattern, where `*` stands for any sequence of
+/// characters and `?` for any single character.
+pub fn matches_file_name_pattern(pattern: &str, file_name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let file_name: Vec<char> = file_name.chars().collect();
+    let (mut pattern_index, mut name_index) = (0, 0);
+    // Position of the last `*` and of the name character it is currently matched up to
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while name_index < file_name.len() {
+        match pattern.get(pattern_index) {
+            Some('*') => {
+                backtrack = Some((pattern_index, name_index));
+                pattern_index += 1;
+            }
+            Some(&c) if c == '?' || c == file_name[name_index] => {
+                pattern_index += 1;
+                name_index += 1;
+            }
+            _ => match backtrack {
+                Some((star_index, star_name_index)) => {
+                    backtrack = Some((star_index, star_name_index + 1));
+                    pattern_index = star_index + 1;
+                    name_index = star_name_index + 1;
+                }
+                None => return false,
+            },
+        }
     }
 
-    std::fs::create_dir(root_pdfs)?;
-
-    if num_levels == 1 {
-        for sibling in 1..=num_siblings_this_level {
-            let pdf_name = format!("pdf_doc{}.pdf", sibling);
-            let pdf_path = format!("{}/{}", root_pdfs.display(), pdf_name);
+    pattern[pattern_index..].iter().all(|&c| c == '*')
+}
 
-            let mut pdf_doc = get_basic_pdf_doc(&pdf_name, pages_per_pdf)?;
+/// Save the document through a temporary file in the same directory, renamed to the path
+/// once complete, so that an interrupted save never leaves a truncated file at the path.
+/// An existing file at the path is replaced.
+pub fn save_atomically(doc: &mut Document, path: impl AsRef<Path>) -> Result<()> {
+    write_atomically(path, |writer| Ok(doc.save_to(writer)?))
+}
 
-            let mut buffer = Vec::new();
-            pdf_doc.save_modern(&mut buffer)?;
-            std::fs::write(pdf_path, &buffer)?;
-        }
-    } else {
-        for sibling in 1..=num_siblings_this_level {
-            let sibling_path = format!("{}/L{}S{}", root_pdfs.display(), num_levels, sibling);
-            if let Err(err) = generate_fn_tree_with_levels(
-                sibling_path,
-                num_levels.saturating_sub(1),
-                siblings_fn(num_siblings_this_level),
-                constant_num_lateral_leaves,
-                pages_per_pdf,
-                siblings_fn,
-            ) {
-                // If encountering any error, the function tries to clean up after itself
-                std::fs::remove_dir_all(root_pdfs)?;
-                return Err(err);
-            }
-        }
-        for lateral_leaf in 1..=constant_num_lateral_leaves {
-            let pdf_name = format!("lateral_pdf_doc{}.pdf", lateral_leaf);
-            let pdf_path = format!("{}/{}", root_pdfs.display(), pdf_name);
+/// Write the file as `save_atomically` does, with the content given by the function.
+pub fn write_atomically(
+    path: impl AsRef<Path>,
+    write_content: impl FnOnce(&mut BufWriter<File>) -> Result<()>,
+) -> Result<()> {
+    let path = path.as_ref();
+    let file_name = path.file_name().ok_or(anyhow!(
+        "The path '{}' does not name a file",
+        path.display()
+    ))?;
+    let temp_path = path.with_file_name(format!(
+        ".{}.{}.tmp",
+        file_name.to_string_lossy(),
+        std::process::id()
+    ));
+
+    let written = (|| -> Result<()> {
+        let mut writer = BufWriter::new(File::create(&temp_path)?);
+        write_content(&mut writer)?;
+        writer.into_inner()?.sync_all()?;
+        std::fs::rename(&temp_path, path)?;
+        Ok(())
+    })();
 
-            let mut pdf_doc = get_basic_pdf_doc(&pdf_name, pages_per_pdf)?;
+    if written.is_err() {
+        let _ = std::fs::remove_file(&temp_path);
+    }
+    written
+}
 
-            let mut buffer = Vec::new();
-            pdf_doc.save_modern(&mut buffer)?;
-            std::fs::write(pdf_path, &buffer)?;
+/// Whether the relative path matches the glob pattern, where `**` stands for any number
+/// (even none) of path components and the other components are file name patterns (see
+/// `matches_file_name_pattern`). Both `/` and `\` separate the components of the pattern.
+pub fn matches_path_glob(pattern: &str, relative_path: impl AsRef<Path>) -> bool {
+    fn matches_components(pattern: &[&str], path: &[String]) -> bool {
+        match pattern.split_first() {
+            None => path.is_empty(),
+            Some((&"**", rest)) => {
+                (0..=path.len()).any(|skipped| matches_components(rest, &path[skipped..]))
+            }
+            Some((component_pattern, rest)) => {
+                path.split_first().is_some_and(|(component, path_rest)| {
+                    matches_file_name_pattern(component_pattern, component)
+                        && matches_components(rest, path_rest)
+                })
+            }
         }
     }
 
-    Ok(())
+    let pattern: Vec<&str> = pattern
+        .split(['/', '\\'])
+        .filter(|component| !component.is_empty())
+        .collect();
+    let path: Vec<String> = relative_path
+        .as_ref()
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy().to_string())
+        .collect();
+
+    matches_components(&pattern, &path)
 }
 
 pub fn get_catalog_children_names(doc: &Document) -> Result<Vec<String>> {
@@ -147,116 +224,106 @@ pub fn get_catalog_children_names(doc: &Document) -> Result<Vec<String>> {
     Ok(catalog_children_names)
 }
 
-/// Get a PDF file with minimal features
-pub fn get_basic_pdf_doc(doc_name: &str, num_pages: u8) -> Result<Document> {
-    if doc_name.contains('/') {
-        return Err(anyhow!(
-            "The document name provided contains a '/', not allowed!"
-        ));
-    }
-
-    let mut doc = Document::with_version("1.7");
-
-    let pages_root_id = doc.new_object_id();
-
-    let font_id = doc.add_object(dictionary! {
-        "Type" => "Font",
-        "Subtype" => "Type1",
-        "BaseFont" => "Courier",
-    });
-
-    let resources_id = doc.add_object(dictionary! {
-            "Font" => dictionary! {
-                "F1" => font_id,
-            },
-
-    });
-
-    let pages_ids: Vec<_> = (1..=num_pages)
-        .map(|page_number| {
-            append_random_page_to_doc(page_number, num_pages, doc_name, &pages_root_id, &mut doc)
-        })
-        .collect::<Result<_>>()?;
-
-    let pages = dictionary! {
-        "Type" => "Pages",
-        "Kids" => pages_ids.iter().map(|&page_id| page_id.into()).collect::<Vec<_>>(),
-        "Count" => num_pages,
-        "Resources" => resources_id,
-        "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()],
-    };
-
-    doc.objects.insert(pages_root_id, Object::Dictionary(pages));
-    doc.max_id += 1;
-
-    let catalog_id = doc.add_object(dictionary! {
-        "Type" => "Catalog",
-        "Pages" => pages_root_id,
-    });
-
-    doc.trailer.set("Root", catalog_id);
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support;
 
-    Ok(doc)
-}
+    #[test]
+    fn portable_paths_are_native() {
+        let native = Path::new("lectures").join("week 3").join("notes.pdf");
+        assert_eq!(path_from_portable("lectures\\week 3/notes.pdf"), native);
+        assert_eq!(path_from_portable("lectures//week 3\\notes.pdf"), native);
+    }
 
-fn append_random_page_to_doc(
-    page_number: u8,
-    total_num_pages: u8,
-    doc_name: &str,
-    pages_id: &ObjectId,
-    doc: &mut Document,
-) -> Result<ObjectId> {
-    use lopdf::{
-        Stream,
-        content::{Content, Operation},
-    };
+    #[test]
+    fn common_ancestor_of_roots() {
+        let roots = ["/archive/2023/invoices", "/archive/2024", "/archive/2023"];
+        assert_eq!(common_ancestor(&roots), PathBuf::from("/archive"));
+        assert_eq!(
+            common_ancestor(&roots[..1]),
+            PathBuf::from("/archive/2023/invoices")
+        );
+    }
 
-    let page_title = format!("Page {page_number} of {total_num_pages}");
-    let random_text = craft_random_text_of_len(20);
-
-    let content = Content {
-        operations: vec![
-            Operation::new("BT", vec![]),
-            Operation::new("Td", vec![50.into(), 600.into()]),
-            Operation::new("TL", vec![50.into()]),
-            Operation::new("Tf", vec!["F1".into(), 46.into()]),
-            Operation::new("Tj", vec![Object::string_literal(doc_name)]),
-            Operation::new("Tf", vec!["F1".into(), 36.into()]),
-            Operation::new("'", vec![Object::string_literal(page_title)]),
-            Operation::new("Tf", vec!["F1".into(), 20.into()]),
-            Operation::new("'", vec![Object::string_literal(random_text)]),
-            Operation::new("ET", vec![]),
-        ],
-    };
+    #[test]
+    fn natural_order() {
+        let mut names = vec![
+            "page10.png",
+            "page2.png",
+            "page02.png",
+            "page1.png",
+            "cover.png",
+        ];
+        names.sort_by(|name, other_name| natural_cmp(name, other_name));
+        assert_eq!(
+            names,
+            vec![
+                "cover.png",
+                "page1.png",
+                "page2.png",
+                "page02.png",
+                "page10.png"
+            ]
+        );
+    }
 
-    let content_id = doc.add_object(Stream::new(dictionary! {}, content.encode()?));
+    #[test]
+    fn file_name_patterns() {
+        assert!(matches_file_name_pattern(
+            "*-united.pdf",
+            "notes-united.pdf"
+        ));
+        assert!(matches_file_name_pattern("*-united.pdf", "-united.pdf"));
+        assert!(!matches_file_name_pattern(
+            "*-united.pdf",
+            "notes-united.pdf.bak"
+        ));
+        assert!(matches_file_name_pattern(
+            "archive-????.pdf",
+            "archive-2024.pdf"
+        ));
+        assert!(!matches_file_name_pattern(
+            "archive-????.pdf",
+            "archive-24.pdf"
+        ));
+        assert!(matches_file_name_pattern("*a*b", "xaayb"));
+    }
 
-    let page_id = doc.add_object(dictionary! {
-        "Type" => "Page",
-        "Parent" => *pages_id,
-        "Contents" => content_id,
-    });
+    #[test]
+    fn atomic_save_replaces_the_file() -> Result<()> {
+        let test_dir = test_support::get_virgin_test_dir("atomic_save_replaces_the_file")?;
+        let output_path = format!("{test_dir}/output.pdf");
+        std::fs::write(&output_path, b"previous output")?;
 
-    Ok(page_id)
-}
+        let mut document = test_support::get_basic_pdf_doc("doc_name", 2)?;
+        save_atomically(&mut document, &output_path)?;
 
-pub fn craft_random_text_of_len(char_length: usize) -> String {
-    use rand::distr::{SampleString, StandardUniform};
-    let random_string: String = StandardUniform.sample_string(&mut rand::rng(), char_length);
-    random_string
-}
+        assert_eq!(Document::load(&output_path)?.get_pages().len(), 2);
+        assert_eq!(std::fs::read_dir(test_dir)?.count(), 1);
 
-#[cfg(test)]
-mod test {
-    use super::*;
+        Ok(())
+    }
 
     #[test]
-    fn get_doc_10_pages() -> Result<()> {
-        let document = get_basic_pdf_doc("doc_name", 10)?;
-        let len = document.get_pages().len();
-
-        assert_eq!(len, 10);
-
-        Ok(())
+    fn path_globs() {
+        assert!(matches_path_glob("**/report*.pdf", "report-2024.pdf"));
+        assert!(matches_path_glob(
+            "**/report*.pdf",
+            Path::new("q1").join("report.pdf")
+        ));
+        assert!(!matches_path_glob("**/report*.pdf", "q1"));
+        assert!(matches_path_glob(
+            "**/drafts/**",
+            Path::new("q1").join("drafts")
+        ));
+        assert!(matches_path_glob(
+            "**/drafts/**",
+            Path::new("drafts").join("old").join("a.pdf")
+        ));
+        assert!(!matches_path_glob(
+            "drafts/*.pdf",
+            Path::new("q1").join("drafts").join("a.pdf")
+        ));
     }
 }
@@ -0,0 +1,92 @@
+//! Checks of the input files and of the merged document: the catalog features a source may
+//! use, and the references left dangling by an import.
+
+use crate::error::MergeError;
+use anyhow::{Result, anyhow};
+use lazy_static::lazy_static;
+use lopdf::{Document, Object, ObjectId};
+use std::path::Path;
+
+lazy_static! {
+    static ref ALLOWED_CATALOG_CHILDREN_FOR_INPUT_PDF: Vec<String> = [
+        "Type", "Version", "Pages", "PageMode", "Outlines", "Metadata"
+    ]
+    .map(|not_owned| not_owned.to_string())
+    .into_iter()
+    .collect();
+}
+
+/// Fail with `MergeError::UnsupportedCatalogFeature` if the catalog of the source file has
+/// entries which the merge would silently drop (e.g. forms or embedded files).
+pub fn check_supported_catalog(doc: &Document, path: impl AsRef<Path>) -> Result<()> {
+    for (child_name, _child_object) in doc.catalog()? {
+        let child_name = String::from_utf8(child_name.to_vec())?;
+
+        if !ALLOWED_CATALOG_CHILDREN_FOR_INPUT_PDF.contains(&child_name) {
+            return Err(MergeError::UnsupportedCatalogFeature {
+                path: path.as_ref().to_path_buf(),
+                feature: child_name,
+            }
+            .into());
+        }
+    }
+
+    Ok(())
+}
+
+/// Verify that every reference contained in the given objects points to an object of the document.
+pub fn check_references_resolve(doc: &Document, object_ids: &[ObjectId]) -> Result<()> {
+    fn collect_references(object: &Object, references: &mut Vec<ObjectId>) {
+        match object {
+            Object::Reference(id) => references.push(*id),
+            Object::Array(array) => array
+                .iter()
+                .for_each(|child| collect_references(child, references)),
+            Object::Dictionary(dict) => dict
+                .iter()
+                .for_each(|(_key, child)| collect_references(child, references)),
+            Object::Stream(stream) => stream
+                .dict
+                .iter()
+                .for_each(|(_key, child)| collect_references(child, references)),
+            _ => {}
+        }
+    }
+
+    for object_id in object_ids {
+        let mut references = Vec::new();
+        collect_references(doc.get_object(*object_id)?, &mut references);
+
+        if let Some(dangling) = references
+            .iter()
+            .find(|reference| !doc.objects.contains_key(reference))
+        {
+            return Err(anyhow!(
+                "the object {object_id:?} references the missing object {dangling:?}"
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support;
+
+    #[test]
+    fn dangling_references_are_found() -> Result<()> {
+        let mut doc = test_support::get_basic_pdf_doc("doc_name", 1)?;
+        let page_id = *doc.get_pages().values().next().unwrap();
+        check_references_resolve(&doc, &[page_id])?;
+
+        let missing_id = (doc.max_id + 1, 0);
+        doc.get_object_mut(page_id)?
+            .as_dict_mut()?
+            .set("Thumb", Object::Reference(missing_id));
+        assert!(check_references_resolve(&doc, &[page_id]).is_err());
+
+        Ok(())
+    }
+}